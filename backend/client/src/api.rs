@@ -0,0 +1,232 @@
+use crate::error::ClientError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Tokens of a signed-in user, from login, `/auth/2fa/verify` or `/auth/refresh`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Session {
+    pub token: String,
+    /// Trade in with [`ApiClient::refresh`] before `expires_in` seconds
+    pub refresh_token: String,
+    pub expires_in: u64,
+    pub user_id: String,
+    pub roles: Vec<String>,
+}
+
+/// The password was right, but the account has a second factor
+#[derive(Debug, Clone, Deserialize)]
+pub struct SecondFactorChallenge {
+    /// Present with a code to [`ApiClient::verify_2fa`] within `expires_in` seconds
+    pub twofa_token: String,
+    pub expires_in: u64,
+    /// The account has a passkey, which may stand in for the code
+    pub passkey: bool,
+}
+
+/// Response of `POST /auth/login`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Session(Session),
+    SecondFactor(SecondFactorChallenge),
+}
+
+/// Response of `POST /channels`
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreatedChannel {
+    pub channel_id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct LoginRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct TwoFactorRequest<'a> {
+    twofa_token: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovery_code: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct RefreshRequest<'a> {
+    refresh_token: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// HTTP API client
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    base_url: String,
+    http: reqwest::Client,
+    token: Option<String>,
+}
+
+impl ApiClient {
+    /// Create a client for a server such as `http://127.0.0.1:3000`
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+            token: None,
+        }
+    }
+
+    /// Use an existing JWT instead of logging in
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// WebSocket signaling URL of the server
+    pub fn signaling_url(&self) -> String {
+        let url = if let Some(rest) = self.base_url.strip_prefix("https://") {
+            format!("wss://{}", rest)
+        } else if let Some(rest) = self.base_url.strip_prefix("http://") {
+            format!("ws://{}", rest)
+        } else {
+            self.base_url.clone()
+        };
+        format!("{}/ws", url)
+    }
+
+    /// Log in and keep the token for later requests. Accounts with a second
+    /// factor get a challenge instead, answered with [`ApiClient::verify_2fa`].
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<LoginResponse, ClientError> {
+        let response = self
+            .http
+            .post(format!("{}/auth/login", self.base_url))
+            .json(&LoginRequest { username, password })
+            .send()
+            .await?;
+        let login: LoginResponse = Self::parse(response).await?;
+        if let LoginResponse::Session(session) = &login {
+            self.token = Some(session.token.clone());
+        }
+        Ok(login)
+    }
+
+    /// Finish a login with an authenticator code and keep the token
+    pub async fn verify_2fa(&mut self, twofa_token: &str, code: &str) -> Result<Session, ClientError> {
+        self.second_factor(&TwoFactorRequest { twofa_token, code: Some(code), recovery_code: None }).await
+    }
+
+    /// Finish a login with one of the account's one-time recovery codes
+    pub async fn verify_recovery_code(&mut self, twofa_token: &str, recovery_code: &str) -> Result<Session, ClientError> {
+        self.second_factor(&TwoFactorRequest { twofa_token, code: None, recovery_code: Some(recovery_code) }).await
+    }
+
+    async fn second_factor(&mut self, request: &TwoFactorRequest<'_>) -> Result<Session, ClientError> {
+        let response = self
+            .http
+            .post(format!("{}/auth/2fa/verify", self.base_url))
+            .json(request)
+            .send()
+            .await?;
+        self.keep_session(response).await
+    }
+
+    /// Trade a refresh token for a new session and keep its token. The old
+    /// refresh token stops working.
+    pub async fn refresh(&mut self, refresh_token: &str) -> Result<Session, ClientError> {
+        let response = self
+            .http
+            .post(format!("{}/auth/refresh", self.base_url))
+            .json(&RefreshRequest { refresh_token })
+            .send()
+            .await?;
+        self.keep_session(response).await
+    }
+
+    async fn keep_session(&mut self, response: reqwest::Response) -> Result<Session, ClientError> {
+        let session: Session = Self::parse(response).await?;
+        self.token = Some(session.token.clone());
+        Ok(session)
+    }
+
+    /// Create a channel; `privacy` is `Public`, `Private` or `InviteOnly`
+    pub async fn create_channel(&self, name: &str, privacy: &str) -> Result<CreatedChannel, ClientError> {
+        let response = self
+            .authorized(self.http.post(format!("{}/channels", self.base_url)))
+            .json(&json!({ "name": name, "privacy": privacy }))
+            .send()
+            .await?;
+        Self::parse(response).await
+    }
+
+    /// Join a channel, with an invite token for invite-only channels
+    pub async fn join_channel(&self, channel_id: &str, join_token: Option<&str>) -> Result<(), ClientError> {
+        let response = self
+            .authorized(self.http.post(format!("{}/channels/{}/join", self.base_url, channel_id)))
+            .json(&json!({ "join_token": join_token }))
+            .send()
+            .await?;
+        Self::check(response).await.map(|_| ())
+    }
+
+    fn authorized(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response, ClientError> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let message = match response.json::<ErrorResponse>().await {
+            Ok(body) => body.error,
+            Err(_) => status.canonical_reason().unwrap_or("Unknown error").to_string(),
+        };
+        Err(ClientError::Api {
+            status: status.as_u16(),
+            message,
+        })
+    }
+
+    async fn parse<T: for<'de> Deserialize<'de>>(response: reqwest::Response) -> Result<T, ClientError> {
+        let response = Self::check(response).await?;
+        Ok(response.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signaling_url() {
+        assert_eq!(ApiClient::new("http://127.0.0.1:3000/").signaling_url(), "ws://127.0.0.1:3000/ws");
+        assert_eq!(ApiClient::new("https://fleet.example").signaling_url(), "wss://fleet.example/ws");
+    }
+
+    #[test]
+    fn test_login_response_variants() {
+        let session: LoginResponse = serde_json::from_str(
+            r#"{"token":"a","refresh_token":"r","expires_in":900,"user_id":"u","roles":["user"]}"#,
+        )
+        .unwrap();
+        assert!(matches!(session, LoginResponse::Session(Session { ref refresh_token, .. }) if refresh_token == "r"));
+
+        let challenge: LoginResponse = serde_json::from_str(
+            r#"{"twofa_required":true,"twofa_token":"t","expires_in":300,"passkey":false}"#,
+        )
+        .unwrap();
+        assert!(matches!(challenge, LoginResponse::SecondFactor(SecondFactorChallenge { ref twofa_token, .. }) if twofa_token == "t"));
+    }
+}
@@ -0,0 +1,704 @@
+//! Voice load generator.
+//!
+//! Connects N simulated clients through the real HTTP and UDP handshakes,
+//! streams synthetic Opus-sized frames between them and prints a JSON report
+//! of forwarding latency, loss and CPU usage.
+//!
+//! ```text
+//! cargo run --release -p whisper-fleet-client --features loadgen --bin loadgen -- \
+//!     --clients 400 --clients-per-channel 8 --duration-secs 120 --server-pid $(pidof main)
+//! ```
+
+use clap::Parser;
+use futures::stream::{self, StreamExt};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::{interval, sleep_until};
+use tracing::{info, warn};
+use whisper_fleet_client::{ApiClient, ClientError, LoginResponse, VoiceClient, VoiceEvent};
+
+/// Opus frame duration
+const FRAME_INTERVAL: Duration = Duration::from_millis(20);
+/// How long receivers keep listening after the senders stop
+const DRAIN: Duration = Duration::from_secs(1);
+/// TOC byte of a 20 ms fullband CELT frame, so taps see plausible Opus
+const FRAME_TOC: u8 = 0xFC;
+/// TOC + sender index (4 bytes) + send time in µs (8 bytes)
+const FRAME_HEADER: usize = 13;
+/// Connection errors kept verbatim in the report
+const MAX_REPORTED_ERRORS: usize = 10;
+
+#[derive(Parser, Debug, Clone)]
+#[command(name = "loadgen", about = "Simulate voice clients against a Whisper Fleet server")]
+struct Args {
+    /// HTTP API base URL
+    #[arg(long, default_value = "http://127.0.0.1:3000")]
+    api: String,
+    /// UDP voice server address
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    voice: SocketAddr,
+    #[arg(long, default_value_t = 100)]
+    clients: usize,
+    #[arg(long, default_value_t = 10)]
+    clients_per_channel: usize,
+    #[arg(long, default_value_t = 60)]
+    duration_secs: u64,
+    /// Fraction of time each client is talking
+    #[arg(long, default_value_t = 0.3)]
+    talk_ratio: f64,
+    /// Mean length of a talk spurt in ms
+    #[arg(long, default_value_t = 1500)]
+    talk_spurt_ms: u64,
+    /// Uplink loss to simulate, as a fraction of frames (0.0-1.0)
+    #[arg(long, default_value_t = 0.0)]
+    loss: f64,
+    /// Maximum random delay added to each frame before it is sent, in ms
+    #[arg(long, default_value_t = 0)]
+    jitter_ms: u64,
+    /// Payload size of each frame (80 bytes is 32 kbps Opus)
+    #[arg(long, default_value_t = 80)]
+    frame_bytes: usize,
+    /// Secret used to mint test JWTs; the server must sign with `secrets.jwt_secret`
+    /// and the default token issuer and audience
+    #[arg(long, default_value = "your-secret-key")]
+    jwt_secret: String,
+    /// File of `username:password` lines to log in with instead of minting tokens
+    #[arg(long)]
+    credentials: Option<PathBuf>,
+    /// Server process to sample CPU usage from (Linux only)
+    #[arg(long)]
+    server_pid: Option<u32>,
+    /// Logins and handshakes in flight at once while ramping up
+    #[arg(long, default_value_t = 50)]
+    connect_concurrency: usize,
+    /// Write the report to a file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+impl Args {
+    fn validate(&self) -> Result<(), String> {
+        if self.clients == 0 {
+            return Err("--clients must be at least 1".to_string());
+        }
+        if self.clients_per_channel < 2 {
+            return Err("--clients-per-channel must be at least 2".to_string());
+        }
+        if !(self.talk_ratio > 0.0 && self.talk_ratio <= 1.0) {
+            return Err("--talk-ratio must be in (0, 1]".to_string());
+        }
+        if !(0.0..1.0).contains(&self.loss) {
+            return Err("--loss must be in [0, 1)".to_string());
+        }
+        if self.frame_bytes < FRAME_HEADER {
+            return Err(format!("--frame-bytes must be at least {}", FRAME_HEADER));
+        }
+        Ok(())
+    }
+}
+
+/// Key ID, issuer and audience of tokens the server signs with its shared secret
+const TOKEN_KID: &str = "default";
+const TOKEN_ISSUER: &str = "whisper-fleet";
+const TOKEN_AUDIENCE: &str = "whisper-fleet";
+
+#[derive(Debug, Serialize)]
+struct Claims {
+    sub: String,
+    name: String,
+    roles: Vec<String>,
+    iss: String,
+    aud: String,
+    exp: usize,
+    iat: usize,
+    jti: String,
+    sid: String,
+}
+
+/// Machine-readable result of one run
+#[derive(Debug, Serialize)]
+struct LoadReport {
+    version: &'static str,
+    started_at: u64,
+    config: ReportConfig,
+    clients: ClientReport,
+    frames: FrameReport,
+    latency_ms: Option<LatencyReport>,
+    server_cpu: Option<CpuReport>,
+    loadgen_cpu: Option<CpuReport>,
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReportConfig {
+    clients: usize,
+    clients_per_channel: usize,
+    channels: usize,
+    duration_secs: u64,
+    talk_ratio: f64,
+    loss: f64,
+    jitter_ms: u64,
+    frame_bytes: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ClientReport {
+    requested: usize,
+    connected: usize,
+    failed: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct FrameReport {
+    /// Frames produced while talking, including simulated losses
+    generated: u64,
+    simulated_loss: u64,
+    sent: u64,
+    send_errors: u64,
+    /// Sent frames times the other connected members of the sender's channel
+    expected_deliveries: u64,
+    received: u64,
+    lost: u64,
+    loss_ratio: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyReport {
+    mean: f64,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct CpuReport {
+    /// Percent of one core
+    mean_percent: f64,
+    peak_percent: f64,
+    samples: usize,
+}
+
+/// What one simulated client observed
+#[derive(Debug, Default)]
+struct ClientOutcome {
+    generated: u64,
+    simulated_loss: u64,
+    sent: u64,
+    send_errors: u64,
+    received: u64,
+    /// Forwarding latencies in µs
+    latencies: Vec<u32>,
+}
+
+/// A connected client and the channel group it belongs to
+struct Participant {
+    index: u32,
+    group: usize,
+    voice: VoiceClient,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = Args::parse();
+    if let Err(e) = args.validate() {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+
+    let report = match run(Arc::new(args.clone())).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Load test failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let json = serde_json::to_string_pretty(&report).expect("report is serializable");
+    match &args.output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, json) {
+                eprintln!("Failed to write {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        None => println!("{}", json),
+    }
+}
+
+async fn run(args: Arc<Args>) -> Result<LoadReport, String> {
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut errors = Vec::new();
+
+    let tokens = obtain_tokens(&args).await?;
+    info!("Obtained {} tokens", tokens.len());
+
+    let groups: Vec<Vec<usize>> = (0..args.clients)
+        .collect::<Vec<_>>()
+        .chunks(args.clients_per_channel)
+        .map(|chunk| chunk.to_vec())
+        .collect();
+    let channel_ids = create_channels(&args, &tokens, &groups, &mut errors).await;
+    info!("Prepared {} channels", channel_ids.iter().flatten().count());
+
+    // Voice handshakes, ramped up in bounded batches
+    let attempts = groups.iter().enumerate().flat_map(|(group, members)| {
+        let channel_id = channel_ids[group].clone();
+        members.iter().map(move |&index| (group, index, channel_id.clone()))
+    });
+    let results: Vec<(usize, usize, Result<VoiceClient, String>)> = stream::iter(attempts)
+        .map(|(group, index, channel_id)| {
+            let token = tokens[index].clone();
+            let voice = args.voice;
+            async move {
+                let result = match channel_id {
+                    Some(channel_id) => VoiceClient::connect(voice, &token, &channel_id)
+                        .await
+                        .map_err(|e| format!("client {}: {}", index, e)),
+                    None => Err(format!("client {}: channel setup failed", index)),
+                };
+                (group, index, result)
+            }
+        })
+        .buffer_unordered(args.connect_concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut participants = Vec::new();
+    let mut failed = 0;
+    for (group, index, result) in results {
+        match result {
+            Ok(voice) => participants.push(Participant {
+                index: index as u32,
+                group,
+                voice,
+            }),
+            Err(e) => {
+                failed += 1;
+                if errors.len() < MAX_REPORTED_ERRORS {
+                    errors.push(e);
+                }
+            }
+        }
+    }
+    let connected = participants.len();
+    info!("{} clients connected, {} failed", connected, failed);
+    if connected == 0 {
+        return Err(format!("no client could connect: {}", errors.join("; ")));
+    }
+
+    let mut connected_per_group = vec![0u64; groups.len()];
+    for participant in &participants {
+        connected_per_group[participant.group] += 1;
+    }
+
+    // Stream
+    let epoch = Instant::now();
+    let deadline = epoch + Duration::from_secs(args.duration_secs);
+    let sampler = tokio::spawn(sample_cpu(args.server_pid, deadline));
+
+    let handles: Vec<_> = participants
+        .into_iter()
+        .map(|participant| {
+            let args = args.clone();
+            let group = participant.group;
+            let handle = tokio::spawn(run_client(participant.index, participant.voice, args, epoch, deadline));
+            (group, handle)
+        })
+        .collect();
+
+    let mut frames = FrameReport::default();
+    let mut latencies = Vec::new();
+    for (group, handle) in handles {
+        let outcome = match handle.await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                warn!("Client task failed: {}", e);
+                continue;
+            }
+        };
+        frames.generated += outcome.generated;
+        frames.simulated_loss += outcome.simulated_loss;
+        frames.sent += outcome.sent;
+        frames.send_errors += outcome.send_errors;
+        frames.expected_deliveries += outcome.sent * (connected_per_group[group] - 1);
+        frames.received += outcome.received;
+        latencies.extend(outcome.latencies);
+    }
+    frames.lost = frames.expected_deliveries.saturating_sub(frames.received);
+    frames.loss_ratio = if frames.expected_deliveries > 0 {
+        frames.lost as f64 / frames.expected_deliveries as f64
+    } else {
+        0.0
+    };
+
+    let (server_cpu, loadgen_cpu) = sampler.await.unwrap_or((None, None));
+
+    Ok(LoadReport {
+        version: env!("CARGO_PKG_VERSION"),
+        started_at,
+        config: ReportConfig {
+            clients: args.clients,
+            clients_per_channel: args.clients_per_channel,
+            channels: groups.len(),
+            duration_secs: args.duration_secs,
+            talk_ratio: args.talk_ratio,
+            loss: args.loss,
+            jitter_ms: args.jitter_ms,
+            frame_bytes: args.frame_bytes,
+        },
+        clients: ClientReport {
+            requested: args.clients,
+            connected,
+            failed,
+        },
+        frames,
+        latency_ms: latency_report(&mut latencies),
+        server_cpu,
+        loadgen_cpu,
+        errors,
+    })
+}
+
+/// One token per client, minted or obtained by logging in
+async fn obtain_tokens(args: &Args) -> Result<Vec<String>, String> {
+    let Some(path) = &args.credentials else {
+        return (0..args.clients)
+            .map(|index| mint_token(&args.jwt_secret, &user_id(index)))
+            .collect();
+    };
+
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let credentials: Vec<(String, String)> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(username, password)| (username.to_string(), password.to_string()))
+        .collect();
+    if credentials.len() < args.clients {
+        // Clients sharing an account would share a jitter buffer on the server
+        return Err(format!(
+            "{} has {} accounts, {} clients need distinct ones",
+            path.display(),
+            credentials.len(),
+            args.clients
+        ));
+    }
+
+    stream::iter(credentials.into_iter().take(args.clients))
+        .map(|(username, password)| async move {
+            let mut api = ApiClient::new(&args.api);
+            api.login(&username, &password)
+                .await
+                .map_err(|e| format!("login as {}: {}", username, e))
+                .and_then(|login| match login {
+                    LoginResponse::Session(session) => Ok(session.token),
+                    LoginResponse::SecondFactor(_) => Err(format!("login as {}: account has two-factor authentication", username)),
+                })
+        })
+        .buffered(args.connect_concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect()
+}
+
+/// Short enough to fit the 8-byte user ID of packet headers
+fn user_id(index: usize) -> String {
+    format!("lg{:06}", index)
+}
+
+fn mint_token(secret: &str, user_id: &str) -> Result<String, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as usize)
+        .unwrap_or(0);
+    let claims = Claims {
+        sub: user_id.to_string(),
+        name: user_id.to_string(),
+        roles: vec!["user".to_string()],
+        iss: TOKEN_ISSUER.to_string(),
+        aud: TOKEN_AUDIENCE.to_string(),
+        exp: now + 24 * 3600,
+        iat: now,
+        // Minted tokens are never revoked, so IDs only need to be distinct
+        jti: format!("{}-{}", user_id, now),
+        sid: format!("{}-{}", user_id, now),
+    };
+    let header = Header {
+        kid: Some(TOKEN_KID.to_string()),
+        ..Header::default()
+    };
+    encode(&header, &claims, &EncodingKey::from_secret(secret.as_ref()))
+        .map_err(|e| format!("failed to mint token: {}", e))
+}
+
+/// Create one public channel per group and join the other members to it
+async fn create_channels(
+    args: &Args,
+    tokens: &[String],
+    groups: &[Vec<usize>],
+    errors: &mut Vec<String>,
+) -> Vec<Option<String>> {
+    let results: Vec<Result<String, ClientError>> = stream::iter(groups.iter().enumerate())
+        .map(|(group, members)| async move {
+            let owner = ApiClient::new(&args.api).with_token(tokens[members[0]].clone());
+            let channel = owner.create_channel(&format!("loadgen-{}", group), "Public").await?;
+            for &member in &members[1..] {
+                ApiClient::new(&args.api)
+                    .with_token(tokens[member].clone())
+                    .join_channel(&channel.channel_id, None)
+                    .await?;
+            }
+            Ok(channel.channel_id)
+        })
+        .buffered(args.connect_concurrency.max(1))
+        .collect()
+        .await;
+
+    results
+        .into_iter()
+        .enumerate()
+        .map(|(group, result)| match result {
+            Ok(channel_id) => Some(channel_id),
+            Err(e) => {
+                if errors.len() < MAX_REPORTED_ERRORS {
+                    errors.push(format!("channel {}: {}", group, e));
+                }
+                None
+            }
+        })
+        .collect()
+}
+
+/// Random length of the next talk or silence period
+fn next_period(rng: &mut StdRng, mean_ms: f64) -> Duration {
+    let u: f64 = rng.gen();
+    Duration::from_secs_f64(-mean_ms * (1.0 - u).ln() / 1000.0)
+}
+
+async fn run_client(
+    index: u32,
+    mut voice: VoiceClient,
+    args: Arc<Args>,
+    epoch: Instant,
+    deadline: Instant,
+) -> ClientOutcome {
+    let mut rng = StdRng::from_entropy();
+    let mut outcome = ClientOutcome::default();
+
+    let mut filler = vec![0u8; args.frame_bytes - FRAME_HEADER];
+    rng.fill_bytes(&mut filler);
+
+    let talk_mean = args.talk_spurt_ms as f64;
+    let silence_mean = talk_mean * (1.0 - args.talk_ratio) / args.talk_ratio;
+    let mut talking = rng.gen_bool(args.talk_ratio);
+    let mut switch_at = Instant::now() + next_period(&mut rng, if talking { talk_mean } else { silence_mean });
+
+    // Frames waiting out their simulated jitter, by send time
+    let mut pending: BinaryHeap<Reverse<(Instant, u32)>> = BinaryHeap::new();
+    let mut ticker = interval(FRAME_INTERVAL);
+    let drain_until = deadline + DRAIN;
+
+    loop {
+        let next_send = pending.peek().map(|Reverse((at, _))| *at);
+        tokio::select! {
+            _ = ticker.tick(), if Instant::now() < deadline => {
+                let now = Instant::now();
+                if now >= switch_at && silence_mean > 0.0 {
+                    talking = !talking;
+                    switch_at = now + next_period(&mut rng, if talking { talk_mean } else { silence_mean });
+                }
+                if !talking {
+                    continue;
+                }
+
+                outcome.generated += 1;
+                let sequence = voice.next_sequence();
+                if args.loss > 0.0 && rng.gen_bool(args.loss) {
+                    outcome.simulated_loss += 1;
+                    continue;
+                }
+                let delay = if args.jitter_ms > 0 { rng.gen_range(0..=args.jitter_ms) } else { 0 };
+                pending.push(Reverse((now + Duration::from_millis(delay), sequence)));
+            }
+            _ = sleep_until(next_send.unwrap_or(drain_until).into()), if next_send.is_some() => {
+                let Some(Reverse((_, sequence))) = pending.pop() else { continue };
+                let payload = encode_frame(index, epoch.elapsed().as_micros() as u64, &filler);
+                match voice.send_voice_with_sequence(sequence, &payload).await {
+                    Ok(()) => outcome.sent += 1,
+                    Err(_) => outcome.send_errors += 1,
+                }
+            }
+            event = voice.recv() => match event {
+                Some(VoiceEvent::Frame(packet)) => {
+                    if let Some((_, sent_us)) = decode_frame(&packet.payload) {
+                        let latency = (epoch.elapsed().as_micros() as u64).saturating_sub(sent_us);
+                        outcome.received += 1;
+                        outcome.latencies.push(latency.min(u32::MAX as u64) as u32);
+                    }
+                }
+                Some(_) => {}
+                None => break,
+            },
+            _ = sleep_until(drain_until.into()) => break,
+        }
+    }
+
+    let _ = voice.leave().await;
+    outcome
+}
+
+/// Build a frame payload tagged with its sender and send time
+fn encode_frame(sender: u32, sent_us: u64, filler: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(FRAME_HEADER + filler.len());
+    payload.push(FRAME_TOC);
+    payload.extend_from_slice(&sender.to_be_bytes());
+    payload.extend_from_slice(&sent_us.to_be_bytes());
+    payload.extend_from_slice(filler);
+    payload
+}
+
+/// Sender and send time of a frame produced by [`encode_frame`]
+fn decode_frame(payload: &[u8]) -> Option<(u32, u64)> {
+    if payload.len() < FRAME_HEADER || payload[0] != FRAME_TOC {
+        return None;
+    }
+    let sender = u32::from_be_bytes(payload[1..5].try_into().ok()?);
+    let sent_us = u64::from_be_bytes(payload[5..13].try_into().ok()?);
+    Some((sender, sent_us))
+}
+
+fn latency_report(latencies_us: &mut [u32]) -> Option<LatencyReport> {
+    if latencies_us.is_empty() {
+        return None;
+    }
+    latencies_us.sort_unstable();
+    let percentile = |p: f64| {
+        let index = ((latencies_us.len() - 1) as f64 * p).round() as usize;
+        latencies_us[index] as f64 / 1000.0
+    };
+    let sum: u64 = latencies_us.iter().map(|&us| us as u64).sum();
+    Some(LatencyReport {
+        mean: sum as f64 / latencies_us.len() as f64 / 1000.0,
+        p50: percentile(0.50),
+        p90: percentile(0.90),
+        p99: percentile(0.99),
+        max: latencies_us[latencies_us.len() - 1] as f64 / 1000.0,
+    })
+}
+
+/// Total user + system CPU ticks from a `/proc/<pid>/stat` line
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    // The command name may contain spaces, so fields are counted after its ')'
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+fn read_cpu_ticks(pid: Option<u32>) -> Option<u64> {
+    let path = match pid {
+        Some(pid) => format!("/proc/{}/stat", pid),
+        None => "/proc/self/stat".to_string(),
+    };
+    parse_cpu_ticks(&std::fs::read_to_string(path).ok()?)
+}
+
+/// Sample server and load generator CPU usage once a second until the deadline
+async fn sample_cpu(server_pid: Option<u32>, deadline: Instant) -> (Option<CpuReport>, Option<CpuReport>) {
+    // SAFETY: sysconf has no preconditions
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_sec <= 0 {
+        return (None, None);
+    }
+    let ticks_per_sec = ticks_per_sec as f64;
+
+    let mut server_samples = Vec::new();
+    let mut loadgen_samples = Vec::new();
+    let mut last_server = server_pid.and_then(|pid| read_cpu_ticks(Some(pid)));
+    let mut last_loadgen = read_cpu_ticks(None);
+    let mut last_at = Instant::now();
+    let mut ticker = interval(Duration::from_secs(1));
+    ticker.tick().await;
+
+    while Instant::now() < deadline {
+        ticker.tick().await;
+        let elapsed = last_at.elapsed().as_secs_f64();
+        last_at = Instant::now();
+
+        let server = server_pid.and_then(|pid| read_cpu_ticks(Some(pid)));
+        if let (Some(before), Some(after)) = (last_server, server) {
+            server_samples.push((after - before) as f64 / ticks_per_sec / elapsed * 100.0);
+        }
+        last_server = server;
+
+        let loadgen = read_cpu_ticks(None);
+        if let (Some(before), Some(after)) = (last_loadgen, loadgen) {
+            loadgen_samples.push((after - before) as f64 / ticks_per_sec / elapsed * 100.0);
+        }
+        last_loadgen = loadgen;
+    }
+
+    (cpu_report(&server_samples), cpu_report(&loadgen_samples))
+}
+
+fn cpu_report(samples: &[f64]) -> Option<CpuReport> {
+    if samples.is_empty() {
+        return None;
+    }
+    Some(CpuReport {
+        mean_percent: samples.iter().sum::<f64>() / samples.len() as f64,
+        peak_percent: samples.iter().cloned().fold(0.0, f64::max),
+        samples: samples.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let payload = encode_frame(42, 123_456_789, &[7; 67]);
+        assert_eq!(payload.len(), 80);
+        assert_eq!(decode_frame(&payload), Some((42, 123_456_789)));
+        assert_eq!(decode_frame(&[0xF8]), None);
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut latencies: Vec<u32> = (1..=100).map(|ms| ms * 1000).collect();
+        let report = latency_report(&mut latencies).unwrap();
+        assert_eq!(report.p50, 51.0);
+        assert_eq!(report.p99, 99.0);
+        assert_eq!(report.max, 100.0);
+        assert!(latency_report(&mut []).is_none());
+    }
+
+    #[test]
+    fn test_parse_cpu_ticks() {
+        let stat = "1234 (my server) S 1 1234 1234 0 -1 4194560 500 0 0 0 250 50 0 0 20 0 8 0 100 0 0";
+        assert_eq!(parse_cpu_ticks(stat), Some(300));
+        assert_eq!(parse_cpu_ticks("garbage"), None);
+    }
+}
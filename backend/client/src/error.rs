@@ -0,0 +1,28 @@
+use whisper_fleet_protocol::PacketError;
+
+/// Client errors
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("API error ({status}): {message}")]
+    Api { status: u16, message: String },
+    #[error("WebSocket error: {0}")]
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("Packet error: {0}")]
+    Packet(#[from] PacketError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Handshake timed out")]
+    HandshakeTimeout,
+    #[error("Server rejected the connection: {0}")]
+    Rejected(String),
+    #[error("Connection closed")]
+    Closed,
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for ClientError {
+    fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
+        ClientError::WebSocket(Box::new(error))
+    }
+}
@@ -0,0 +1,40 @@
+//! Async client for the Whisper Fleet backend.
+//!
+//! - [`ApiClient`] logs in and manages channels over HTTP
+//! - [`SignalingClient`] speaks the `/ws` signaling protocol
+//! - [`VoiceClient`] performs the UDP handshake and sends/receives voice
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), whisper_fleet_client::ClientError> {
+//! use whisper_fleet_client::{ApiClient, LoginResponse, SignalingClient, VoiceClient};
+//!
+//! // A registered account; passwords are at least 10 characters and not a common one
+//! let mut api = ApiClient::new("http://127.0.0.1:3000");
+//! let login = match api.login("ace", "tailwind over the harbour").await? {
+//!     LoginResponse::Session(session) => session,
+//!     LoginResponse::SecondFactor(challenge) => api.verify_2fa(&challenge.twofa_token, "123456").await?,
+//! };
+//! let channel = api.create_channel("ops", "Public").await?;
+//!
+//! let signaling = SignalingClient::connect(&api.signaling_url(), &login.token).await?;
+//! signaling.join_channel(&channel.channel_id)?;
+//!
+//! let mut voice = VoiceClient::connect("127.0.0.1:8080".parse().unwrap(), &login.token, &channel.channel_id).await?;
+//! voice.send_voice(&[0xFC, 0x00]).await?;
+//! while let Some(event) = voice.recv().await {
+//!     println!("{:?}", event);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod api;
+pub mod error;
+pub mod signaling;
+pub mod voice;
+
+pub use api::{ApiClient, CreatedChannel, LoginResponse, SecondFactorChallenge, Session};
+pub use error::ClientError;
+pub use signaling::SignalingClient;
+pub use voice::{VoiceClient, VoiceConfig, VoiceEvent};
+pub use whisper_fleet_protocol as protocol;
@@ -0,0 +1,96 @@
+use crate::error::ClientError;
+use futures::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, warn};
+use whisper_fleet_protocol::WsMessage;
+
+/// Client for the `/ws` signaling WebSocket
+pub struct SignalingClient {
+    outgoing: mpsc::UnboundedSender<WsMessage>,
+    incoming: mpsc::UnboundedReceiver<WsMessage>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl SignalingClient {
+    /// Connect and authenticate with a JWT
+    pub async fn connect(url: &str, token: &str) -> Result<Self, ClientError> {
+        let (stream, _) = tokio_tungstenite::connect_async(format!("{}?token={}", url, token)).await?;
+        let (mut sink, mut source) = stream.split();
+
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<WsMessage>();
+        let (incoming_tx, incoming) = mpsc::unbounded_channel::<WsMessage>();
+
+        let writer = tokio::spawn(async move {
+            while let Some(msg) = outgoing_rx.recv().await {
+                let text = match serde_json::to_string(&msg) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        warn!("Failed to encode signaling message: {}", e);
+                        continue;
+                    }
+                };
+                if sink.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            let _ = sink.close().await;
+        });
+
+        let reader = tokio::spawn(async move {
+            while let Some(msg) = source.next().await {
+                match msg {
+                    Ok(Message::Text(text)) => match serde_json::from_str::<WsMessage>(&text) {
+                        Ok(msg) => {
+                            if incoming_tx.send(msg).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => debug!("Ignoring unknown signaling message: {}", e),
+                    },
+                    Ok(Message::Close(_)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+        });
+
+        Ok(Self {
+            outgoing,
+            incoming,
+            tasks: vec![writer, reader],
+        })
+    }
+
+    /// Send a raw signaling message
+    pub fn send(&self, msg: WsMessage) -> Result<(), ClientError> {
+        self.outgoing.send(msg).map_err(|_| ClientError::Closed)
+    }
+
+    pub fn join_channel(&self, channel_id: &str) -> Result<(), ClientError> {
+        self.send(WsMessage::JoinChannel {
+            channel_id: channel_id.to_string(),
+        })
+    }
+
+    pub fn leave_channel(&self) -> Result<(), ClientError> {
+        self.send(WsMessage::LeaveChannel)
+    }
+
+    pub fn set_mute(&self, muted: bool) -> Result<(), ClientError> {
+        self.send(if muted { WsMessage::Mute } else { WsMessage::Unmute })
+    }
+
+    /// Next message from the server, `None` once the connection is closed
+    pub async fn recv(&mut self) -> Option<WsMessage> {
+        self.incoming.recv().await
+    }
+}
+
+impl Drop for SignalingClient {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use crate::error::ClientError;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, timeout};
+use tracing::{debug, warn};
+use whisper_fleet_protocol::{AudioPacket, BitrateHint, PacketType, ReceiverReport, VoicePacket};
+
+/// Voice connection settings
+#[derive(Debug, Clone)]
+pub struct VoiceConfig {
+    /// How long to wait for the server's ack to each handshake attempt
+    pub handshake_timeout: Duration,
+    pub handshake_attempts: u32,
+    pub heartbeat_interval: Duration,
+    /// Events buffered before voice frames are dropped
+    pub event_capacity: usize,
+}
+
+impl Default for VoiceConfig {
+    fn default() -> Self {
+        Self {
+            handshake_timeout: Duration::from_secs(2),
+            handshake_attempts: 3,
+            heartbeat_interval: Duration::from_secs(5),
+            event_capacity: 512,
+        }
+    }
+}
+
+/// Something received from the voice server
+#[derive(Debug, Clone)]
+pub enum VoiceEvent {
+    /// Voice frame forwarded from another channel member
+    Frame(VoicePacket),
+    /// Target bitrate the encoder should use
+    BitrateHint(BitrateHint),
+    /// Heartbeat round trip completed
+    HeartbeatAck { rtt: Duration },
+    /// Error packet from the server
+    Error(String),
+}
+
+/// UDP voice connection to one channel
+pub struct VoiceClient {
+    socket: Arc<UdpSocket>,
+    user_id: String,
+    channel_id: String,
+    sequence: AtomicU32,
+    rtt: Arc<Mutex<Option<Duration>>>,
+    events: mpsc::Receiver<VoiceEvent>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl VoiceClient {
+    /// Connect with the default settings
+    pub async fn connect(server: SocketAddr, token: &str, channel_id: &str) -> Result<Self, ClientError> {
+        Self::connect_with_config(server, token, channel_id, VoiceConfig::default()).await
+    }
+
+    /// Bind a local socket and perform the handshake
+    pub async fn connect_with_config(
+        server: SocketAddr,
+        token: &str,
+        channel_id: &str,
+        config: VoiceConfig,
+    ) -> Result<Self, ClientError> {
+        let bind_addr = if server.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.connect(server).await?;
+
+        let handshake = AudioPacket::json_handshake(token.to_string(), channel_id.to_string()).to_bytes()?;
+        let user_id = Self::handshake(&socket, &handshake, &config).await?;
+        debug!("Voice handshake with {} complete as {}", server, user_id);
+
+        let socket = Arc::new(socket);
+        let rtt = Arc::new(Mutex::new(None));
+        let pending_heartbeats = Arc::new(Mutex::new(HashMap::new()));
+        let (events_tx, events) = mpsc::channel(config.event_capacity);
+
+        let receiver = tokio::spawn(Self::receive_loop(
+            socket.clone(),
+            events_tx,
+            pending_heartbeats.clone(),
+            rtt.clone(),
+        ));
+        let heartbeat = tokio::spawn(Self::heartbeat_loop(
+            socket.clone(),
+            user_id.clone(),
+            channel_id.to_string(),
+            config.heartbeat_interval,
+            pending_heartbeats,
+        ));
+
+        Ok(Self {
+            socket,
+            user_id,
+            channel_id: channel_id.to_string(),
+            sequence: AtomicU32::new(0),
+            rtt,
+            events,
+            tasks: vec![receiver, heartbeat],
+        })
+    }
+
+    /// Send the handshake until the server acks it, returning the header user ID
+    async fn handshake(socket: &UdpSocket, handshake: &[u8], config: &VoiceConfig) -> Result<String, ClientError> {
+        let mut buf = vec![0u8; 2048];
+        for attempt in 1..=config.handshake_attempts {
+            socket.send(handshake).await?;
+
+            let deadline = Instant::now() + config.handshake_timeout;
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let len = match timeout(remaining, socket.recv(&mut buf)).await {
+                    Ok(result) => result?,
+                    Err(_) => break,
+                };
+                match AudioPacket::from_bytes(&buf[..len]) {
+                    Ok(packet) if packet.header.packet_type == PacketType::Ack => {
+                        return Ok(packet.header.user_id_str());
+                    }
+                    Ok(packet) if packet.header.packet_type == PacketType::Error => {
+                        return Err(ClientError::Rejected(packet.error_message.unwrap_or_default()));
+                    }
+                    _ => continue,
+                }
+            }
+            debug!("Voice handshake attempt {} timed out", attempt);
+        }
+        Err(ClientError::HandshakeTimeout)
+    }
+
+    async fn receive_loop(
+        socket: Arc<UdpSocket>,
+        events: mpsc::Sender<VoiceEvent>,
+        pending_heartbeats: Arc<Mutex<HashMap<u32, Instant>>>,
+        rtt: Arc<Mutex<Option<Duration>>>,
+    ) {
+        let mut buf = vec![0u8; 2048];
+        loop {
+            let len = match socket.recv(&mut buf).await {
+                Ok(len) => len,
+                Err(e) => {
+                    warn!("Voice socket error: {}", e);
+                    break;
+                }
+            };
+            let data = &buf[..len];
+
+            // The server never sends handshakes, so type 0x01 is always voice
+            if VoicePacket::is_voice_type(data[0]) {
+                match VoicePacket::from_bytes(data) {
+                    // Frames are dropped rather than stalling the socket
+                    Ok(packet) => {
+                        let _ = events.try_send(VoiceEvent::Frame(packet));
+                    }
+                    Err(e) => debug!("Malformed voice packet: {}", e),
+                }
+                continue;
+            }
+
+            let packet = match AudioPacket::from_bytes(data) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    debug!("Malformed control packet: {}", e);
+                    continue;
+                }
+            };
+            let event = match packet.header.packet_type {
+                PacketType::Ack => {
+                    let sent_at = pending_heartbeats.lock().unwrap().remove(&packet.header.sequence);
+                    match sent_at {
+                        Some(sent_at) => {
+                            let measured = sent_at.elapsed();
+                            *rtt.lock().unwrap() = Some(measured);
+                            VoiceEvent::HeartbeatAck { rtt: measured }
+                        }
+                        None => continue, // Repeated handshake ack
+                    }
+                }
+                PacketType::BitrateHint => match packet.bitrate_hint {
+                    Some(hint) => VoiceEvent::BitrateHint(hint),
+                    None => continue,
+                },
+                PacketType::Error => VoiceEvent::Error(packet.error_message.unwrap_or_default()),
+                _ => continue,
+            };
+            if events.send(event).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn heartbeat_loop(
+        socket: Arc<UdpSocket>,
+        user_id: String,
+        channel_id: String,
+        period: Duration,
+        pending_heartbeats: Arc<Mutex<HashMap<u32, Instant>>>,
+    ) {
+        let mut ticker = interval(period);
+        let mut sequence: u32 = 0;
+        loop {
+            ticker.tick().await;
+            // Sequence 0 is the handshake ack
+            sequence = sequence.wrapping_add(1).max(1);
+
+            let mut packet = AudioPacket::heartbeat(&user_id, &channel_id);
+            packet.header.sequence = sequence;
+            let data = match packet.to_bytes() {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+
+            {
+                let mut pending = pending_heartbeats.lock().unwrap();
+                // Unanswered heartbeats older than a few periods are lost
+                pending.retain(|_, sent_at| sent_at.elapsed() < period * 4);
+                pending.insert(sequence, Instant::now());
+            }
+            if let Err(e) = socket.send(&data).await {
+                warn!("Failed to send heartbeat: {}", e);
+            }
+        }
+    }
+
+    /// User ID as carried in packet headers
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    pub fn channel_id(&self) -> &str {
+        &self.channel_id
+    }
+
+    /// Last measured heartbeat round-trip time
+    pub fn rtt(&self) -> Option<Duration> {
+        *self.rtt.lock().unwrap()
+    }
+
+    fn now_ms() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+
+    /// Send one Opus frame, returning its sequence number
+    pub async fn send_voice(&self, payload: &[u8]) -> Result<u32, ClientError> {
+        let sequence = self.next_sequence();
+        self.send_voice_with_sequence(sequence, payload).await?;
+        Ok(sequence)
+    }
+
+    /// Reserve the sequence number of the next frame.
+    /// A reserved number that is never sent looks like a lost packet to the server.
+    pub fn next_sequence(&self) -> u32 {
+        self.sequence.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Send one Opus frame with a sequence number from [`next_sequence`](Self::next_sequence)
+    pub async fn send_voice_with_sequence(&self, sequence: u32, payload: &[u8]) -> Result<(), ClientError> {
+        let packet = VoicePacket::voice(sequence, Self::now_ms(), payload.to_vec());
+        self.socket.send(&packet.to_bytes()).await?;
+        Ok(())
+    }
+
+    /// Send every simulcast layer of one frame, as `(layer, payload)` pairs
+    pub async fn send_simulcast(&self, layers: &[(u8, &[u8])]) -> Result<u32, ClientError> {
+        let sequence = self.next_sequence();
+        let timestamp = Self::now_ms();
+        for (layer, payload) in layers {
+            let packet = VoicePacket::simulcast(*layer, sequence, timestamp, payload.to_vec());
+            self.socket.send(&packet.to_bytes()).await?;
+        }
+        Ok(sequence)
+    }
+
+    pub async fn set_mute(&self, muted: bool) -> Result<(), ClientError> {
+        self.send_control(AudioPacket::set_mute(&self.user_id, &self.channel_id, muted)).await
+    }
+
+    /// Report downlink quality so the server can adapt bitrates
+    pub async fn send_receiver_report(&self, report: ReceiverReport) -> Result<(), ClientError> {
+        self.send_control(AudioPacket::receiver_report(&self.user_id, &self.channel_id, report)).await
+    }
+
+    async fn send_control(&self, packet: AudioPacket) -> Result<(), ClientError> {
+        self.socket.send(&packet.to_bytes()?).await?;
+        Ok(())
+    }
+
+    /// Next event from the server, `None` once the connection is closed
+    pub async fn recv(&mut self) -> Option<VoiceEvent> {
+        self.events.recv().await
+    }
+
+    /// Leave the channel and close the connection
+    pub async fn leave(self) -> Result<(), ClientError> {
+        self.send_control(AudioPacket::leave_channel(&self.user_id, &self.channel_id)).await
+    }
+}
+
+impl Drop for VoiceClient {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn fake_server() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        (socket, addr)
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_voice() {
+        let (server, addr) = fake_server().await;
+
+        let server_task = tokio::spawn(async move {
+            let mut buf = vec![0u8; 2048];
+
+            // First attempt is lost, second is acked
+            server.recv_from(&mut buf).await.unwrap();
+            let (len, client) = server.recv_from(&mut buf).await.unwrap();
+            let handshake = AudioPacket::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(handshake.header.packet_type, PacketType::Handshake);
+            assert_eq!(handshake.handshake_data.unwrap().token, "token");
+
+            let ack = AudioPacket::ack("alice", "chan", 0).to_bytes().unwrap();
+            server.send_to(&ack, client).await.unwrap();
+
+            // Forward a frame to the client and read back one it sends
+            server.send_to(&VoicePacket::voice(7, 0, vec![1, 2, 3]).to_bytes(), client).await.unwrap();
+            loop {
+                let (len, _) = server.recv_from(&mut buf).await.unwrap();
+                if let Ok(packet) = VoicePacket::from_bytes(&buf[..len]) {
+                    return packet;
+                }
+            }
+        });
+
+        let config = VoiceConfig {
+            handshake_timeout: Duration::from_millis(100),
+            ..VoiceConfig::default()
+        };
+        let mut client = VoiceClient::connect_with_config(addr, "token", "chan", config).await.unwrap();
+        assert_eq!(client.user_id(), "alice");
+
+        match client.recv().await.unwrap() {
+            VoiceEvent::Frame(packet) => assert_eq!(packet.sequence_number, 7),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        client.send_voice(&[9, 9]).await.unwrap();
+        let sent = server_task.await.unwrap();
+        assert_eq!(sent.sequence_number, 0);
+        assert_eq!(sent.payload, vec![9, 9]);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let (_server, addr) = fake_server().await;
+        let config = VoiceConfig {
+            handshake_timeout: Duration::from_millis(20),
+            handshake_attempts: 2,
+            ..VoiceConfig::default()
+        };
+
+        let result = VoiceClient::connect_with_config(addr, "token", "chan", config).await;
+        assert!(matches!(result, Err(ClientError::HandshakeTimeout)));
+    }
+}
@@ -0,0 +1,18 @@
+-- Control-plane state shared by backend instances
+CREATE TABLE fleet_channels (
+    id TEXT PRIMARY KEY,
+    data JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Users connected to a channel over WebSocket, by the instance holding the socket
+CREATE TABLE fleet_presence (
+    user_id TEXT PRIMARY KEY,
+    channel_id TEXT NOT NULL,
+    username TEXT NOT NULL,
+    is_muted BOOLEAN NOT NULL DEFAULT false,
+    instance_id TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_fleet_presence_channel ON fleet_presence(channel_id);
@@ -0,0 +1,21 @@
+-- Refresh tokens by hash; used ones are kept until they expire to catch reuse
+CREATE TABLE fleet_refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    roles TEXT[] NOT NULL DEFAULT '{}',
+    expires_at BIGINT NOT NULL,
+    used BOOLEAN NOT NULL DEFAULT false
+);
+
+CREATE INDEX idx_fleet_refresh_tokens_session ON fleet_refresh_tokens(session_id);
+CREATE INDEX idx_fleet_refresh_tokens_expires ON fleet_refresh_tokens(expires_at);
+
+-- Revoked access tokens and sessions, until their tokens would have expired
+CREATE TABLE fleet_revocations (
+    id TEXT PRIMARY KEY,
+    data JSONB NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+
+CREATE INDEX idx_fleet_revocations_expires ON fleet_revocations(expires_at);
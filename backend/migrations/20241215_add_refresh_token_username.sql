@@ -0,0 +1,2 @@
+-- Access tokens carry the username, so refreshing needs it too
+ALTER TABLE fleet_refresh_tokens ADD COLUMN username TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,30 @@
+-- Accounts created before self-service registration count as verified
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE users ADD COLUMN verification_token TEXT;
+ALTER TABLE users ADD COLUMN verification_token_expiry TIMESTAMPTZ;
+
+-- Usernames and email addresses are unique regardless of case; accounts are
+-- found and linked by email address. Accounts that differ only in case have
+-- to be renamed or merged by hand first, so stop and name them.
+DO $$
+DECLARE
+    conflicts TEXT;
+BEGIN
+    SELECT string_agg(names, '; ') INTO conflicts FROM (
+        SELECT 'username ' || string_agg(username, ', ' ORDER BY created_at) AS names
+        FROM users GROUP BY lower(username) HAVING count(*) > 1
+        UNION ALL
+        SELECT 'email ' || string_agg(email, ', ' ORDER BY created_at)
+        FROM users GROUP BY lower(email) HAVING count(*) > 1
+    ) duplicates;
+    IF conflicts IS NOT NULL THEN
+        RAISE EXCEPTION 'Accounts differ only in case: %', conflicts
+            USING HINT = 'Rename or remove all but one account of each, then restart';
+    END IF;
+END $$;
+
+CREATE UNIQUE INDEX idx_users_username_lower ON users (lower(username));
+CREATE UNIQUE INDEX idx_users_email_lower ON users (lower(email));
+-- Lookups go through lower(email), and the column's UNIQUE constraint has its own index
+DROP INDEX idx_users_email;
+CREATE INDEX idx_users_verification_token ON users (verification_token);
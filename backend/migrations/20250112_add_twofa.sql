@@ -0,0 +1,8 @@
+-- users.twofa_secret now holds the encrypted TOTP secret, pending until it
+-- is confirmed with a code; secrets stored before encryption are dropped
+UPDATE users SET twofa_secret = NULL;
+ALTER TABLE users ADD COLUMN twofa_enabled BOOLEAN NOT NULL DEFAULT false;
+-- Time step of the last accepted code, so no code works twice
+ALTER TABLE users ADD COLUMN twofa_last_step BIGINT;
+-- Hashes of the unused recovery codes
+ALTER TABLE users ADD COLUMN twofa_recovery_codes TEXT[] NOT NULL DEFAULT '{}';
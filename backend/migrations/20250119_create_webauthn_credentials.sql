@@ -0,0 +1,22 @@
+-- Passkeys and security keys registered with WebAuthn
+CREATE TABLE webauthn_credentials (
+    -- Credential ID, base64url
+    id TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- Public key and signature counter, as stored by webauthn-rs
+    passkey JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMPTZ
+);
+CREATE INDEX webauthn_credentials_user_id ON webauthn_credentials (user_id);
+
+-- Registrations and sign-ins waiting for the authenticator's answer; each
+-- is finished at most once
+CREATE TABLE webauthn_ceremonies (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    state JSONB NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
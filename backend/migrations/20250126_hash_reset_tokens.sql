@@ -0,0 +1,3 @@
+-- users.reset_token now holds the hash of a mailed reset token
+UPDATE users SET reset_token = NULL, reset_token_expiry = NULL;
+CREATE INDEX idx_users_reset_token ON users (reset_token);
@@ -0,0 +1,22 @@
+-- Accounts at OAuth/OIDC providers that sign in as a user
+CREATE TABLE oauth_identities (
+    provider TEXT NOT NULL,
+    -- The provider's stable ID for the account, `sub` for OIDC
+    subject TEXT NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    email TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMPTZ,
+    PRIMARY KEY (provider, subject)
+);
+CREATE INDEX oauth_identities_user_id ON oauth_identities (user_id);
+
+-- Logins sent to a provider and not back yet; each is finished at most once
+CREATE TABLE oauth_logins (
+    -- Hash of the state parameter
+    state TEXT PRIMARY KEY,
+    provider TEXT NOT NULL,
+    pkce_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,2 @@
+-- DN of the directory entry of users who sign in with LDAP; NULL for local accounts
+ALTER TABLE users ADD COLUMN directory_dn TEXT;
@@ -0,0 +1,13 @@
+//! Wire formats shared by the Whisper Fleet backend and its clients.
+//!
+//! `packet` covers the UDP control and voice packets, `signaling` the JSON
+//! messages exchanged over the `/ws` WebSocket.
+
+pub mod packet;
+pub mod signaling;
+
+pub use packet::{
+    AudioPacket, BitrateHint, HandshakeData, PacketError, PacketHeader, PacketType,
+    ReceiverReport, VoicePacket,
+};
+pub use signaling::{UserInfo, WsMessage};
@@ -0,0 +1,880 @@
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+/// Error message of a handshake refused because the channel is full
+pub const CHANNEL_FULL_ERROR: &str = "channel_full";
+
+/// Packet types for different audio operations
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PacketType {
+    /// Initial handshake packet with JWT token
+    Handshake = 0x01,
+    /// Audio data packet
+    Audio = 0x02,
+    /// Join channel request
+    JoinChannel = 0x03,
+    /// Leave channel request
+    LeaveChannel = 0x04,
+    /// Mute/unmute request
+    SetMute = 0x05,
+    /// Heartbeat to keep connection alive
+    Heartbeat = 0x06,
+    /// Error response
+    Error = 0x07,
+    /// Acknowledgment
+    Ack = 0x08,
+    /// Server-to-client target bitrate hint
+    BitrateHint = 0x09,
+    /// Client-to-server downlink quality report
+    ReceiverReport = 0x0A,
+}
+
+impl PacketType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(PacketType::Handshake),
+            0x02 => Some(PacketType::Audio),
+            0x03 => Some(PacketType::JoinChannel),
+            0x04 => Some(PacketType::LeaveChannel),
+            0x05 => Some(PacketType::SetMute),
+            0x06 => Some(PacketType::Heartbeat),
+            0x07 => Some(PacketType::Error),
+            0x08 => Some(PacketType::Ack),
+            0x09 => Some(PacketType::BitrateHint),
+            0x0A => Some(PacketType::ReceiverReport),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Packet header structure (21 bytes)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PacketHeader {
+    /// Packet type
+    pub packet_type: PacketType,
+    /// Sequence number for ordering
+    pub sequence: u32,
+    /// User ID (8 bytes)
+    pub user_id: [u8; 8],
+    /// Channel ID (4 bytes)
+    pub channel_id: [u8; 4],
+    /// Timestamp
+    pub timestamp: u32,
+}
+
+impl PacketHeader {
+    pub const SIZE: usize = 21; // 1 + 4 + 8 + 4 + 4
+
+    /// Create a new packet header
+    pub fn new(
+        packet_type: PacketType,
+        sequence: u32,
+        user_id: &str,
+        channel_id: &str,
+        timestamp: u32,
+    ) -> Self {
+        let mut user_id_bytes = [0u8; 8];
+        let mut channel_id_bytes = [0u8; 4];
+        
+        user_id_bytes[..user_id.len().min(8)].copy_from_slice(&user_id.as_bytes()[..user_id.len().min(8)]);
+        channel_id_bytes[..channel_id.len().min(4)].copy_from_slice(&channel_id.as_bytes()[..channel_id.len().min(4)]);
+
+        Self {
+            packet_type,
+            sequence,
+            user_id: user_id_bytes,
+            channel_id: channel_id_bytes,
+            timestamp,
+        }
+    }
+
+    /// Serialize header to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::SIZE);
+        buf.write_u8(self.packet_type.to_u8()).unwrap();
+        buf.write_u32::<BigEndian>(self.sequence).unwrap();
+        buf.extend_from_slice(&self.user_id);
+        buf.extend_from_slice(&self.channel_id);
+        buf.write_u32::<BigEndian>(self.timestamp).unwrap();
+        buf
+    }
+
+    /// Deserialize header from bytes
+    pub fn from_bytes(data: &[u8]) -> Result<Self, PacketError> {
+        if data.len() < Self::SIZE {
+            return Err(PacketError::InvalidSize);
+        }
+
+        let mut cursor = Cursor::new(data);
+        
+        let packet_type_byte = cursor.read_u8()?;
+        let packet_type = PacketType::from_u8(packet_type_byte)
+            .ok_or(PacketError::InvalidPacketType)?;
+        
+        let sequence = cursor.read_u32::<BigEndian>()?;
+        
+        let mut user_id = [0u8; 8];
+        cursor.read_exact(&mut user_id)?;
+        
+        let mut channel_id = [0u8; 4];
+        cursor.read_exact(&mut channel_id)?;
+        
+        let timestamp = cursor.read_u32::<BigEndian>()?;
+
+        Ok(Self {
+            packet_type,
+            sequence,
+            user_id,
+            channel_id,
+            timestamp,
+        })
+    }
+
+    /// Get user ID as string
+    pub fn user_id_str(&self) -> String {
+        String::from_utf8_lossy(&self.user_id)
+            .trim_matches('\0')
+            .to_string()
+    }
+
+    /// Get channel ID as string
+    pub fn channel_id_str(&self) -> String {
+        String::from_utf8_lossy(&self.channel_id)
+            .trim_matches('\0')
+            .to_string()
+    }
+}
+
+/// JSON handshake structure for UDP authentication
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandshakeData {
+    pub token: String,
+    pub channel_id: String,
+}
+
+/// Target bitrate hint sent to senders
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BitrateHint {
+    /// Bitrate the sender should encode at, in bits per second
+    pub target_bps: u32,
+    /// Hard cap for the channel; senders above it are throttled
+    pub max_bps: u32,
+}
+
+/// Downlink quality report sent by listeners
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReceiverReport {
+    /// Fraction of voice packets lost since the last report, in 1/256 units
+    pub fraction_lost: u8,
+    /// Round-trip time measured from heartbeat acks, in ms
+    pub rtt_ms: u16,
+    /// Interarrival jitter, in ms
+    pub jitter_ms: u16,
+}
+
+impl ReceiverReport {
+    /// Loss as a fraction between 0.0 and 1.0
+    pub fn loss(&self) -> f32 {
+        self.fraction_lost as f32 / 256.0
+    }
+}
+
+/// Audio packet structure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioPacket {
+    /// Packet header
+    pub header: PacketHeader,
+    /// JWT token for authentication (handshake packets)
+    pub jwt_token: Option<String>,
+    /// JSON handshake data (for new handshake format)
+    pub handshake_data: Option<HandshakeData>,
+    /// Audio data (audio packets)
+    pub audio_data: Option<Vec<u8>>,
+    /// Mute state (set mute packets)
+    pub mute_state: Option<bool>,
+    /// Error message (error packets)
+    pub error_message: Option<String>,
+    /// Bitrate hint (bitrate hint packets)
+    pub bitrate_hint: Option<BitrateHint>,
+    /// Receiver report (receiver report packets)
+    pub receiver_report: Option<ReceiverReport>,
+}
+
+impl AudioPacket {
+    /// Create a handshake packet (legacy format)
+    pub fn handshake(jwt_token: String, user_id: &str, channel_id: &str) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::Handshake,
+                0,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: Some(jwt_token),
+            handshake_data: None,
+            audio_data: None,
+            mute_state: None,
+            error_message: None,
+            bitrate_hint: None,
+            receiver_report: None,
+        }
+    }
+
+    /// Create a JSON handshake packet (new format)
+    pub fn json_handshake(token: String, channel_id: String) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::Handshake,
+                0,
+                "", // user_id will be extracted from JWT
+                &channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: Some(HandshakeData { token, channel_id }),
+            audio_data: None,
+            mute_state: None,
+            error_message: None,
+            bitrate_hint: None,
+            receiver_report: None,
+        }
+    }
+
+    /// Create an audio packet
+    pub fn audio(
+        sequence: u32,
+        user_id: &str,
+        channel_id: &str,
+        audio_data: Vec<u8>,
+    ) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::Audio,
+                sequence,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: None,
+            audio_data: Some(audio_data),
+            mute_state: None,
+            error_message: None,
+            bitrate_hint: None,
+            receiver_report: None,
+        }
+    }
+
+    /// Create a join channel packet
+    pub fn join_channel(user_id: &str, channel_id: &str) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::JoinChannel,
+                0,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: None,
+            audio_data: None,
+            mute_state: None,
+            error_message: None,
+            bitrate_hint: None,
+            receiver_report: None,
+        }
+    }
+
+    /// Create a leave channel packet
+    pub fn leave_channel(user_id: &str, channel_id: &str) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::LeaveChannel,
+                0,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: None,
+            audio_data: None,
+            mute_state: None,
+            error_message: None,
+            bitrate_hint: None,
+            receiver_report: None,
+        }
+    }
+
+    /// Create a set mute packet
+    pub fn set_mute(user_id: &str, channel_id: &str, mute: bool) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::SetMute,
+                0,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: None,
+            audio_data: None,
+            mute_state: Some(mute),
+            error_message: None,
+            bitrate_hint: None,
+            receiver_report: None,
+        }
+    }
+
+    /// Create a heartbeat packet
+    pub fn heartbeat(user_id: &str, channel_id: &str) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::Heartbeat,
+                0,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: None,
+            audio_data: None,
+            mute_state: None,
+            error_message: None,
+            bitrate_hint: None,
+            receiver_report: None,
+        }
+    }
+
+    /// Create an error packet
+    pub fn error(user_id: &str, channel_id: &str, error_message: String) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::Error,
+                0,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: None,
+            audio_data: None,
+            mute_state: None,
+            error_message: Some(error_message),
+            bitrate_hint: None,
+            receiver_report: None,
+        }
+    }
+
+    /// Create an error packet refusing a handshake to a full channel; the
+    /// header sequence carries the waitlist position, 0 without a waitlist
+    pub fn channel_full(user_id: &str, channel_id: &str, position: Option<u32>) -> Self {
+        let mut packet = Self::error(user_id, channel_id, CHANNEL_FULL_ERROR.to_string());
+        packet.header.sequence = position.unwrap_or(0);
+        packet
+    }
+
+    /// Waitlist position of a `channel_full` error packet
+    pub fn waitlist_position(&self) -> Option<u32> {
+        match (&self.header.packet_type, self.error_message.as_deref()) {
+            (PacketType::Error, Some(CHANNEL_FULL_ERROR)) if self.header.sequence > 0 => Some(self.header.sequence),
+            _ => None,
+        }
+    }
+
+    /// Create an acknowledgment packet
+    pub fn ack(user_id: &str, channel_id: &str, sequence: u32) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::Ack,
+                sequence,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: None,
+            audio_data: None,
+            mute_state: None,
+            error_message: None,
+            bitrate_hint: None,
+            receiver_report: None,
+        }
+    }
+
+    /// Create a bitrate hint packet
+    pub fn bitrate_hint(user_id: &str, channel_id: &str, hint: BitrateHint) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::BitrateHint,
+                0,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: None,
+            audio_data: None,
+            mute_state: None,
+            error_message: None,
+            bitrate_hint: Some(hint),
+            receiver_report: None,
+        }
+    }
+
+    /// Create a receiver report packet
+    pub fn receiver_report(user_id: &str, channel_id: &str, report: ReceiverReport) -> Self {
+        Self {
+            header: PacketHeader::new(
+                PacketType::ReceiverReport,
+                0,
+                user_id,
+                channel_id,
+                chrono::Utc::now().timestamp() as u32,
+            ),
+            jwt_token: None,
+            handshake_data: None,
+            audio_data: None,
+            mute_state: None,
+            error_message: None,
+            bitrate_hint: None,
+            receiver_report: Some(report),
+        }
+    }
+
+    /// Serialize packet to bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PacketError> {
+        let mut buf = Vec::new();
+        
+        // Write header
+        buf.extend_from_slice(&self.header.to_bytes());
+        
+        // Write payload based on packet type
+        match self.header.packet_type {
+            PacketType::Handshake => {
+                if let Some(ref handshake) = self.handshake_data {
+                    // JSON handshake format
+                    let json = serde_json::to_string(handshake)
+                        .map_err(|_| PacketError::InvalidJson)?;
+                    let json_bytes = json.as_bytes();
+                    buf.write_u16::<BigEndian>(json_bytes.len() as u16)?;
+                    buf.extend_from_slice(json_bytes);
+                } else if let Some(ref token) = self.jwt_token {
+                    // Legacy format
+                    let token_bytes = token.as_bytes();
+                    buf.write_u16::<BigEndian>(token_bytes.len() as u16)?;
+                    buf.extend_from_slice(token_bytes);
+                } else {
+                    return Err(PacketError::MissingToken);
+                }
+            }
+            PacketType::Audio => {
+                if let Some(ref audio) = self.audio_data {
+                    buf.write_u16::<BigEndian>(audio.len() as u16)?;
+                    buf.extend_from_slice(audio);
+                } else {
+                    return Err(PacketError::MissingAudioData);
+                }
+            }
+            PacketType::SetMute => {
+                if let Some(mute) = self.mute_state {
+                    buf.write_u8(if mute { 1 } else { 0 })?;
+                } else {
+                    return Err(PacketError::MissingMuteState);
+                }
+            }
+            PacketType::Error => {
+                if let Some(ref error) = self.error_message {
+                    let error_bytes = error.as_bytes();
+                    buf.write_u16::<BigEndian>(error_bytes.len() as u16)?;
+                    buf.extend_from_slice(error_bytes);
+                } else {
+                    return Err(PacketError::MissingErrorMessage);
+                }
+            }
+            PacketType::BitrateHint => {
+                if let Some(hint) = self.bitrate_hint {
+                    buf.write_u32::<BigEndian>(hint.target_bps)?;
+                    buf.write_u32::<BigEndian>(hint.max_bps)?;
+                } else {
+                    return Err(PacketError::MissingBitrateHint);
+                }
+            }
+            PacketType::ReceiverReport => {
+                if let Some(report) = self.receiver_report {
+                    buf.write_u8(report.fraction_lost)?;
+                    buf.write_u16::<BigEndian>(report.rtt_ms)?;
+                    buf.write_u16::<BigEndian>(report.jitter_ms)?;
+                } else {
+                    return Err(PacketError::MissingReceiverReport);
+                }
+            }
+            _ => {
+                // Other packet types have no additional payload
+            }
+        }
+        
+        Ok(buf)
+    }
+
+    /// Deserialize packet from bytes
+    pub fn from_bytes(data: &[u8]) -> Result<Self, PacketError> {
+        if data.len() < PacketHeader::SIZE {
+            return Err(PacketError::InvalidSize);
+        }
+
+        let header = PacketHeader::from_bytes(&data[..PacketHeader::SIZE])?;
+        let mut cursor = Cursor::new(&data[PacketHeader::SIZE..]);
+        
+        let (jwt_token, handshake_data) = match header.packet_type {
+            PacketType::Handshake => {
+                let payload_len = cursor.read_u16::<BigEndian>()? as usize;
+                let mut payload_bytes = vec![0u8; payload_len];
+                cursor.read_exact(&mut payload_bytes)?;
+                let payload_str = String::from_utf8(payload_bytes).map_err(|_| PacketError::InvalidUtf8)?;
+                
+                // Try to parse as JSON handshake first
+                if let Ok(handshake) = serde_json::from_str::<HandshakeData>(&payload_str) {
+                    (None, Some(handshake))
+                } else {
+                    // Fall back to legacy format
+                    (Some(payload_str), None)
+                }
+            }
+            _ => (None, None),
+        };
+
+        let audio_data = match header.packet_type {
+            PacketType::Audio => {
+                let audio_len = cursor.read_u16::<BigEndian>()? as usize;
+                let mut audio_bytes = vec![0u8; audio_len];
+                cursor.read_exact(&mut audio_bytes)?;
+                Some(audio_bytes)
+            }
+            _ => None,
+        };
+
+        let mute_state = match header.packet_type {
+            PacketType::SetMute => {
+                let mute_byte = cursor.read_u8()?;
+                Some(mute_byte != 0)
+            }
+            _ => None,
+        };
+
+        let error_message = match header.packet_type {
+            PacketType::Error => {
+                let error_len = cursor.read_u16::<BigEndian>()? as usize;
+                let mut error_bytes = vec![0u8; error_len];
+                cursor.read_exact(&mut error_bytes)?;
+                Some(String::from_utf8(error_bytes).map_err(|_| PacketError::InvalidUtf8)?)
+            }
+            _ => None,
+        };
+
+        let bitrate_hint = match header.packet_type {
+            PacketType::BitrateHint => Some(BitrateHint {
+                target_bps: cursor.read_u32::<BigEndian>()?,
+                max_bps: cursor.read_u32::<BigEndian>()?,
+            }),
+            _ => None,
+        };
+
+        let receiver_report = match header.packet_type {
+            PacketType::ReceiverReport => Some(ReceiverReport {
+                fraction_lost: cursor.read_u8()?,
+                rtt_ms: cursor.read_u16::<BigEndian>()?,
+                jitter_ms: cursor.read_u16::<BigEndian>()?,
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            header,
+            jwt_token,
+            handshake_data,
+            audio_data,
+            mute_state,
+            error_message,
+            bitrate_hint,
+            receiver_report,
+        })
+    }
+}
+
+/// Binary Opus voice packet structure
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoicePacket {
+    /// Packet type (0x01 for voice data, 0x10 for simulcast voice data)
+    pub packet_type: u8,
+    /// Simulcast layer (0 = highest quality); always 0 for plain voice packets
+    pub layer: u8,
+    /// Monotonic sequence number (shared by all layers of the same frame)
+    pub sequence_number: u32,
+    /// UNIX timestamp in ms
+    pub timestamp: u64,
+    /// Opus-compressed audio data
+    pub payload: Vec<u8>,
+}
+
+impl VoicePacket {
+    /// Minimum header size (1 + 4 + 8 + 2 = 15 bytes)
+    pub const HEADER_SIZE: usize = 15;
+    /// Simulcast header size (1 + 1 + 4 + 8 + 2 = 16 bytes)
+    pub const SIMULCAST_HEADER_SIZE: usize = 16;
+    pub const VOICE_PACKET_TYPE: u8 = 0x01;
+    pub const SIMULCAST_PACKET_TYPE: u8 = 0x10;
+    /// Highest layer ID a sender may use
+    pub const MAX_LAYER: u8 = 2;
+
+    /// Create a plain voice packet
+    pub fn voice(sequence_number: u32, timestamp: u64, payload: Vec<u8>) -> Self {
+        Self {
+            packet_type: Self::VOICE_PACKET_TYPE,
+            layer: 0,
+            sequence_number,
+            timestamp,
+            payload,
+        }
+    }
+
+    /// Create a voice packet carrying one simulcast layer of a frame
+    pub fn simulcast(layer: u8, sequence_number: u32, timestamp: u64, payload: Vec<u8>) -> Self {
+        Self {
+            packet_type: Self::SIMULCAST_PACKET_TYPE,
+            layer,
+            sequence_number,
+            timestamp,
+            payload,
+        }
+    }
+
+    /// Check whether a datagram starts with a voice packet type byte
+    pub fn is_voice_type(packet_type: u8) -> bool {
+        packet_type == Self::VOICE_PACKET_TYPE || packet_type == Self::SIMULCAST_PACKET_TYPE
+    }
+
+    /// Parse a VoicePacket from raw bytes
+    pub fn from_bytes(data: &[u8]) -> Result<Self, PacketError> {
+        if data.len() < Self::HEADER_SIZE {
+            return Err(PacketError::InvalidVoicePacket("Packet too short".into()));
+        }
+        let packet_type = data[0];
+        let (layer, header_size) = match packet_type {
+            Self::VOICE_PACKET_TYPE => (0, Self::HEADER_SIZE),
+            Self::SIMULCAST_PACKET_TYPE => {
+                if data.len() < Self::SIMULCAST_HEADER_SIZE {
+                    return Err(PacketError::InvalidVoicePacket("Packet too short".into()));
+                }
+                (data[1], Self::SIMULCAST_HEADER_SIZE)
+            }
+            _ => return Err(PacketError::InvalidVoicePacket("Invalid packet type".into())),
+        };
+        if layer > Self::MAX_LAYER {
+            return Err(PacketError::InvalidVoicePacket("Invalid simulcast layer".into()));
+        }
+        let d = &data[header_size - Self::HEADER_SIZE..];
+        let sequence_number = u32::from_be_bytes([d[1], d[2], d[3], d[4]]);
+        let timestamp = u64::from_be_bytes([
+            d[5], d[6], d[7], d[8], d[9], d[10], d[11], d[12],
+        ]);
+        let payload_length = u16::from_be_bytes([d[13], d[14]]) as usize;
+        if data.len() != header_size + payload_length {
+            return Err(PacketError::InvalidVoicePacket("Payload length mismatch".into()));
+        }
+        let payload = data[header_size..].to_vec();
+        Ok(Self {
+            packet_type,
+            layer,
+            sequence_number,
+            timestamp,
+            payload,
+        })
+    }
+
+    /// Serialize VoicePacket to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::SIMULCAST_HEADER_SIZE + self.payload.len());
+        buf.push(self.packet_type);
+        if self.packet_type == Self::SIMULCAST_PACKET_TYPE {
+            buf.push(self.layer);
+        }
+        buf.extend_from_slice(&self.sequence_number.to_be_bytes());
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.extend_from_slice(&(self.payload.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+}
+
+/// Packet parsing errors
+#[derive(Debug, thiserror::Error)]
+pub enum PacketError {
+    #[error("Invalid packet size")]
+    InvalidSize,
+    #[error("Invalid packet type")]
+    InvalidPacketType,
+    #[error("Missing JWT token")]
+    MissingToken,
+    #[error("Missing audio data")]
+    MissingAudioData,
+    #[error("Missing mute state")]
+    MissingMuteState,
+    #[error("Missing error message")]
+    MissingErrorMessage,
+    #[error("Missing bitrate hint")]
+    MissingBitrateHint,
+    #[error("Missing receiver report")]
+    MissingReceiverReport,
+    #[error("Invalid UTF-8 encoding")]
+    InvalidUtf8,
+    #[error("Invalid JSON format")]
+    InvalidJson,
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid voice packet: {0}")]
+    InvalidVoicePacket(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_header_serialization() {
+        let header = PacketHeader::new(
+            PacketType::Audio,
+            12345,
+            "user123",
+            "chan1",
+            1234567890,
+        );
+
+        let bytes = header.to_bytes();
+        let deserialized = PacketHeader::from_bytes(&bytes).unwrap();
+
+        assert_eq!(header.packet_type, deserialized.packet_type);
+        assert_eq!(header.sequence, deserialized.sequence);
+        assert_eq!(header.user_id_str(), deserialized.user_id_str());
+        assert_eq!(header.channel_id_str(), deserialized.channel_id_str());
+        assert_eq!(header.timestamp, deserialized.timestamp);
+    }
+
+    #[test]
+    fn test_audio_packet_serialization() {
+        let packet = AudioPacket::audio(
+            12345,
+            "user123",
+            "chan1",
+            vec![1, 2, 3, 4, 5],
+        );
+
+        let bytes = packet.to_bytes().unwrap();
+        let deserialized = AudioPacket::from_bytes(&bytes).unwrap();
+
+        assert_eq!(packet.header.packet_type, deserialized.header.packet_type);
+        assert_eq!(packet.header.sequence, deserialized.header.sequence);
+        assert_eq!(packet.audio_data, deserialized.audio_data);
+    }
+
+    #[test]
+    fn test_handshake_packet_serialization() {
+        let packet = AudioPacket::handshake(
+            "jwt.token.here".to_string(),
+            "user123",
+            "chan1",
+        );
+
+        let bytes = packet.to_bytes().unwrap();
+        let deserialized = AudioPacket::from_bytes(&bytes).unwrap();
+
+        assert_eq!(packet.header.packet_type, deserialized.header.packet_type);
+        assert_eq!(packet.jwt_token, deserialized.jwt_token);
+    }
+
+    #[test]
+    fn test_set_mute_packet_serialization() {
+        let packet = AudioPacket::set_mute("user123", "chan1", true);
+
+        let bytes = packet.to_bytes().unwrap();
+        let deserialized = AudioPacket::from_bytes(&bytes).unwrap();
+
+        assert_eq!(packet.header.packet_type, deserialized.header.packet_type);
+        assert_eq!(packet.mute_state, deserialized.mute_state);
+    }
+
+    #[test]
+    fn test_error_packet_serialization() {
+        let packet = AudioPacket::error("user123", "chan1", "Test error".to_string());
+
+        let bytes = packet.to_bytes().unwrap();
+        let deserialized = AudioPacket::from_bytes(&bytes).unwrap();
+
+        assert_eq!(packet.header.packet_type, deserialized.header.packet_type);
+        assert_eq!(packet.error_message, deserialized.error_message);
+    }
+
+    #[test]
+    fn test_channel_full_packet_carries_position() {
+        let packet = AudioPacket::channel_full("user123", "chan1", Some(3));
+        let deserialized = AudioPacket::from_bytes(&packet.to_bytes().unwrap()).unwrap();
+        assert_eq!(deserialized.error_message.as_deref(), Some(CHANNEL_FULL_ERROR));
+        assert_eq!(deserialized.waitlist_position(), Some(3));
+
+        let packet = AudioPacket::channel_full("user123", "chan1", None);
+        assert_eq!(packet.waitlist_position(), None);
+    }
+
+    #[test]
+    fn test_bitrate_hint_packet_serialization() {
+        let hint = BitrateHint { target_bps: 24_000, max_bps: 64_000 };
+        let packet = AudioPacket::bitrate_hint("user123", "chan1", hint);
+
+        let bytes = packet.to_bytes().unwrap();
+        let deserialized = AudioPacket::from_bytes(&bytes).unwrap();
+
+        assert_eq!(deserialized.header.packet_type, PacketType::BitrateHint);
+        assert_eq!(deserialized.bitrate_hint, Some(hint));
+    }
+
+    #[test]
+    fn test_receiver_report_packet_serialization() {
+        let report = ReceiverReport { fraction_lost: 64, rtt_ms: 120, jitter_ms: 15 };
+        let packet = AudioPacket::receiver_report("user123", "chan1", report);
+
+        let bytes = packet.to_bytes().unwrap();
+        let deserialized = AudioPacket::from_bytes(&bytes).unwrap();
+
+        assert_eq!(deserialized.header.packet_type, PacketType::ReceiverReport);
+        assert_eq!(deserialized.receiver_report, Some(report));
+        assert_eq!(report.loss(), 0.25);
+    }
+
+    #[test]
+    fn test_voice_packet_serialization() {
+        let packet = VoicePacket::voice(42, 1_700_000_000_000, vec![9; 60]);
+
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), VoicePacket::HEADER_SIZE + 60);
+        assert_eq!(VoicePacket::from_bytes(&bytes).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_simulcast_voice_packet_serialization() {
+        let packet = VoicePacket::simulcast(2, 42, 1_700_000_000_000, vec![7; 30]);
+
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), VoicePacket::SIMULCAST_HEADER_SIZE + 30);
+        let deserialized = VoicePacket::from_bytes(&bytes).unwrap();
+        assert_eq!(deserialized.layer, 2);
+        assert_eq!(deserialized, packet);
+
+        let invalid = VoicePacket::simulcast(3, 42, 0, vec![7; 30]).to_bytes();
+        assert!(VoicePacket::from_bytes(&invalid).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+//! JSON messages exchanged over the `/ws` signaling WebSocket.
+
+use serde::{Deserialize, Serialize};
+
+/// WebSocket message types
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum WsMessage {
+    #[serde(rename = "join_channel")]
+    JoinChannel {
+        channel_id: String,
+    },
+    #[serde(rename = "leave_channel")]
+    LeaveChannel,
+    #[serde(rename = "mute")]
+    Mute,
+    #[serde(rename = "unmute")]
+    Unmute,
+    #[serde(rename = "user_joined")]
+    UserJoined {
+        user_id: String,
+        username: String,
+        is_muted: bool,
+    },
+    #[serde(rename = "user_left")]
+    UserLeft {
+        user_id: String,
+    },
+    #[serde(rename = "user_state_update")]
+    UserStateUpdate {
+        user_id: String,
+        is_muted: bool,
+    },
+    /// Restrictions imposed by a channel moderator, separate from the
+    /// user's own `is_muted`
+    #[serde(rename = "user_server_state")]
+    UserServerState {
+        user_id: String,
+        server_muted: bool,
+        server_deafened: bool,
+    },
+    /// Sent to a user a moderator removed from a channel; `reason` is
+    /// `kicked` or `banned`
+    #[serde(rename = "removed_from_channel")]
+    RemovedFromChannel {
+        channel_id: String,
+        reason: String,
+        message: String,
+    },
+    /// The join was refused because the channel is full; `position` is the
+    /// user's place in its waitlist, if it has one. Joining again keeps it.
+    #[serde(rename = "channel_full")]
+    ChannelFull {
+        channel_id: String,
+        limit: u32,
+        position: Option<u32>,
+    },
+    #[serde(rename = "role_changed")]
+    RoleChanged {
+        channel_id: String,
+        user_id: String,
+        role: String,
+    },
+    #[serde(rename = "error")]
+    Error {
+        message: String,
+    },
+    #[serde(rename = "channel_info")]
+    ChannelInfo {
+        channel_id: String,
+        users: Vec<UserInfo>,
+    },
+    #[serde(rename = "recording_state")]
+    RecordingState {
+        channel_id: String,
+        active: bool,
+        recording_id: Option<String>,
+        started_by: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub user_id: String,
+    pub username: String,
+    pub is_muted: bool,
+    pub is_speaking: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_are_tagged_by_type() {
+        let json = serde_json::to_string(&WsMessage::JoinChannel {
+            channel_id: "channel1".to_string(),
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"type":"join_channel","channel_id":"channel1"}"#);
+
+        let parsed: WsMessage = serde_json::from_str(r#"{"type":"mute"}"#).unwrap();
+        assert!(matches!(parsed, WsMessage::Mute));
+    }
+}
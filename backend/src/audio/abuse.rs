@@ -0,0 +1,185 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use tracing::warn;
+use crate::voice::security::{BlockList, BucketConfig, DosDetector, TokenBucketLimiter};
+
+/// UDP abuse protection configuration
+#[derive(Debug, Clone)]
+pub struct AbuseConfig {
+    /// Datagrams a second from one source address
+    pub addr_packets: BucketConfig,
+    /// Bytes a second from one source address
+    pub addr_bytes: BucketConfig,
+    /// Voice frames a second from one session, across its addresses
+    pub session_packets: BucketConfig,
+    /// Voice bytes a second from one session
+    pub session_bytes: BucketConfig,
+    /// Handshakes a second from one source address
+    pub handshakes: BucketConfig,
+    /// Limit violations from one IP within `dos_window` that get it blocked
+    pub dos_threshold: u32,
+    pub dos_window: Duration,
+    /// First block of an IP; each repeat offence doubles it
+    pub block_base: Duration,
+    /// Longest block
+    pub block_max: Duration,
+}
+
+impl Default for AbuseConfig {
+    fn default() -> Self {
+        Self {
+            // Three simulcast layers every 20ms plus control traffic
+            addr_packets: BucketConfig::new(300.0, 600.0),
+            addr_bytes: BucketConfig::new(256_000.0, 512_000.0),
+            session_packets: BucketConfig::new(200.0, 400.0),
+            session_bytes: BucketConfig::new(128_000.0, 256_000.0),
+            handshakes: BucketConfig::new(0.5, 5.0),
+            dos_threshold: 100,
+            dos_window: Duration::from_secs(10),
+            block_base: Duration::from_secs(60),
+            block_max: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Rate limits per source address and session, blocking IPs that keep
+/// exceeding them
+pub struct AbuseGuard {
+    addr_packets: TokenBucketLimiter<SocketAddr>,
+    addr_bytes: TokenBucketLimiter<SocketAddr>,
+    session_packets: TokenBucketLimiter<String>,
+    session_bytes: TokenBucketLimiter<String>,
+    handshakes: TokenBucketLimiter<SocketAddr>,
+    dos: DosDetector,
+    blocklist: BlockList,
+}
+
+impl AbuseGuard {
+    pub fn new(config: AbuseConfig) -> Self {
+        Self {
+            addr_packets: TokenBucketLimiter::new(config.addr_packets),
+            addr_bytes: TokenBucketLimiter::new(config.addr_bytes),
+            session_packets: TokenBucketLimiter::new(config.session_packets),
+            session_bytes: TokenBucketLimiter::new(config.session_bytes),
+            handshakes: TokenBucketLimiter::new(config.handshakes),
+            dos: DosDetector::new(config.dos_threshold, config.dos_window),
+            blocklist: BlockList::new(config.block_base, config.block_max),
+        }
+    }
+
+    /// Take on the rate limits of `config`; the DoS threshold and block
+    /// periods keep their startup values
+    pub fn set_limits(&self, config: &AbuseConfig) {
+        self.addr_packets.set_config(config.addr_packets);
+        self.addr_bytes.set_config(config.addr_bytes);
+        self.session_packets.set_config(config.session_packets);
+        self.session_bytes.set_config(config.session_bytes);
+        self.handshakes.set_config(config.handshakes);
+    }
+
+    /// Check any datagram against its source's budget; the error is the
+    /// reason it is dropped
+    pub fn check_datagram(&self, addr: SocketAddr, len: usize) -> Result<(), &'static str> {
+        if self.blocklist.is_blocked(&addr.ip()) {
+            return Err("blocked");
+        }
+        if !self.addr_packets.check(&addr, 1.0) || !self.addr_bytes.check(&addr, len as f64) {
+            self.violation(addr, "address rate limit");
+            return Err("rate_limited");
+        }
+        Ok(())
+    }
+
+    /// Check a handshake against its source's handshake budget
+    pub fn check_handshake(&self, addr: SocketAddr) -> Result<(), &'static str> {
+        if !self.handshakes.check(&addr, 1.0) {
+            self.violation(addr, "handshake rate limit");
+            return Err("handshake_limited");
+        }
+        Ok(())
+    }
+
+    /// Check a voice frame against its session's budget
+    pub fn check_session(&self, addr: SocketAddr, user_id: &str, len: usize) -> Result<(), &'static str> {
+        if !self.session_packets.check(user_id, 1.0) || !self.session_bytes.check(user_id, len as f64) {
+            self.violation(addr, "session rate limit");
+            return Err("session_rate_limited");
+        }
+        Ok(())
+    }
+
+    /// Count a limit violation, blocking the IP once they pile up
+    fn violation(&self, addr: SocketAddr, limit: &str) {
+        let ip = addr.ip().to_string();
+        if self.dos.check(&ip) {
+            self.dos.reset(&ip);
+            let period = self.blocklist.block(addr.ip(), limit);
+            warn!("Blocked {} for {:?} after repeatedly exceeding the {}", ip, period, limit);
+        }
+    }
+
+    pub fn blocklist(&self) -> &BlockList {
+        &self.blocklist
+    }
+
+    /// Forget idle buckets, old violations and forgiven offences
+    pub fn cleanup(&self, idle: Duration) {
+        self.addr_packets.retain_active(idle);
+        self.addr_bytes.retain_active(idle);
+        self.session_packets.retain_active(idle);
+        self.session_bytes.retain_active(idle);
+        self.handshakes.retain_active(idle);
+        self.dos.prune();
+        self.blocklist.prune();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeat_offenders_are_blocked() {
+        let guard = AbuseGuard::new(AbuseConfig {
+            handshakes: BucketConfig::new(0.0, 2.0),
+            dos_threshold: 3,
+            ..AbuseConfig::default()
+        });
+        let addr: SocketAddr = "203.0.113.7:5000".parse().unwrap();
+        let neighbour: SocketAddr = "203.0.113.8:5000".parse().unwrap();
+
+        assert!(guard.check_handshake(addr).is_ok());
+        assert!(guard.check_handshake(addr).is_ok());
+        for _ in 0..4 {
+            assert_eq!(guard.check_handshake(addr), Err("handshake_limited"));
+        }
+
+        // Every port of the address is blocked, other addresses are not
+        let other_port: SocketAddr = "203.0.113.7:6000".parse().unwrap();
+        assert_eq!(guard.check_datagram(other_port, 100), Err("blocked"));
+        assert!(guard.check_datagram(neighbour, 100).is_ok());
+        assert_eq!(guard.blocklist().list()[0].reason, "handshake rate limit");
+    }
+
+    #[test]
+    fn test_session_budget_spans_addresses() {
+        let guard = AbuseGuard::new(AbuseConfig {
+            session_bytes: BucketConfig::new(0.0, 1000.0),
+            ..AbuseConfig::default()
+        });
+        let first: SocketAddr = "198.51.100.1:5000".parse().unwrap();
+        let second: SocketAddr = "198.51.100.1:5001".parse().unwrap();
+
+        assert!(guard.check_session(first, "alice", 600).is_ok());
+        assert_eq!(guard.check_session(second, "alice", 600), Err("session_rate_limited"));
+        assert!(guard.check_session(second, "bob", 600).is_ok());
+
+        // Raised limits apply to existing sessions
+        guard.set_limits(&AbuseConfig {
+            session_bytes: BucketConfig::new(1_000_000.0, 2000.0),
+            ..AbuseConfig::default()
+        });
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(guard.check_session(second, "alice", 600).is_ok());
+    }
+}
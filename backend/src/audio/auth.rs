@@ -1,38 +1,27 @@
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use crate::routes::channels::{AppState as ChannelAppState, Channel, Role};
-
-/// JWT claims structure for audio authentication
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AudioClaims {
-    pub sub: String, // user_id
-    pub roles: Vec<String>,
-    pub exp: usize, // expiration time
-    pub iat: usize, // issued at
-}
+use crate::routes::channels::AppState as ChannelAppState;
+use crate::routes::user::UNVERIFIED_ROLE;
+use crate::tokens::TokenRef;
 
 /// Authenticated user session
 #[derive(Debug, Clone)]
 pub struct AudioSession {
     pub user_id: String,
     pub username: String,
-    pub roles: Vec<String>,
-    pub authenticated_at: Instant,
+    /// Token the session was opened with
+    pub token: TokenRef,
     pub last_activity: Instant,
 }
 
 impl AudioSession {
-    pub fn new(user_id: String, username: String, roles: Vec<String>) -> Self {
-        let now = Instant::now();
+    pub fn new(user_id: String, username: String, token: TokenRef) -> Self {
         Self {
             user_id,
             username,
-            roles,
-            authenticated_at: now,
-            last_activity: now,
+            token,
+            last_activity: Instant::now(),
         }
     }
 
@@ -48,37 +37,33 @@ impl AudioSession {
 /// Audio authentication manager
 pub struct AudioAuth {
     sessions: Arc<Mutex<HashMap<String, AudioSession>>>,
-    jwt_secret: String,
     session_timeout: Duration,
     channel_state: Arc<ChannelAppState>,
 }
 
 impl AudioAuth {
-    pub fn new(jwt_secret: String, channel_state: Arc<ChannelAppState>) -> Self {
+    /// Tokens are verified by the channel state's token service
+    pub fn new(channel_state: Arc<ChannelAppState>) -> Self {
         Self {
             sessions: Arc::new(Mutex::new(HashMap::new())),
-            jwt_secret,
             session_timeout: Duration::from_secs(3600), // 1 hour
             channel_state,
         }
     }
 
-    /// Authenticate a user with JWT token
+    /// Authenticate a user with JWT token; revoked tokens and accounts
+    /// without a verified email address are refused
     pub fn authenticate(&self, token: &str) -> Result<AudioSession, AuthError> {
         // Decode and validate JWT token
-        let token_data = decode::<AudioClaims>(
-            token,
-            &DecodingKey::from_secret(self.jwt_secret.as_ref()),
-            &Validation::default(),
-        )
-        .map_err(|_| AuthError::InvalidToken)?;
-
-        let claims = token_data.claims;
+        let claims = self.channel_state.tokens.verify(token).map_err(|_| AuthError::InvalidToken)?;
+        if claims.has_role(UNVERIFIED_ROLE) {
+            return Err(AuthError::EmailNotVerified);
+        }
+        let token = claims.token_ref();
         let user_id = claims.sub;
-        let username = self.get_username_by_id(&user_id)?;
 
         // Create new session
-        let session = AudioSession::new(user_id.clone(), username, claims.roles);
+        let session = AudioSession::new(user_id.clone(), claims.name, token);
 
         // Store session
         let mut sessions = self.sessions.lock().unwrap();
@@ -91,27 +76,31 @@ impl AudioAuth {
     pub fn authenticate_with_channel(&self, token: &str, channel_id: &str) -> Result<AudioSession, AuthError> {
         // First authenticate the JWT token
         let session = self.authenticate(token)?;
-        
-        // Verify user is a member of the specified channel
+        self.check_channel_membership(&session.user_id, channel_id)?;
+        Ok(session)
+    }
+
+    /// Verify a user may join a channel held in the local channel state
+    pub fn check_channel_membership(&self, user_id: &str, channel_id: &str) -> Result<(), AuthError> {
         let channels = self.channel_state.channels.lock().unwrap();
         let channel = channels.get(channel_id)
             .ok_or(AuthError::ChannelNotFound)?;
 
         // Check if user is banned
-        if channel.banned_users.iter().any(|banned| banned.user_id == session.user_id) {
+        if channel.banned_users.iter().any(|banned| banned.user_id == user_id) {
             return Err(AuthError::UserBanned);
         }
 
         // Check if user is a member (owner, moderator, or member)
-        let is_member = channel.owner == session.user_id ||
-                       channel.moderators.contains(&session.user_id) ||
-                       channel.members.contains(&session.user_id);
+        let is_member = channel.owner == user_id ||
+                       channel.moderators.iter().any(|moderator| moderator == user_id) ||
+                       channel.members.iter().any(|member| member == user_id);
 
         if !is_member {
             return Err(AuthError::NotChannelMember);
         }
 
-        Ok(session)
+        Ok(())
     }
 
     /// Get existing session for user
@@ -143,18 +132,6 @@ impl AudioAuth {
         sessions.retain(|_, session| !session.is_expired(self.session_timeout));
     }
 
-    /// Get username by user ID (placeholder implementation)
-    fn get_username_by_id(&self, user_id: &str) -> Result<String, AuthError> {
-        // In a real implementation, this would query a user database
-        // For now, we'll use the user_id as username
-        Ok(user_id.to_string())
-    }
-
-    /// Set session timeout
-    pub fn set_session_timeout(&mut self, timeout: Duration) {
-        self.session_timeout = timeout;
-    }
-
     /// Get session count
     pub fn session_count(&self) -> usize {
         self.sessions.lock().unwrap().len()
@@ -170,58 +147,57 @@ pub enum AuthError {
     SessionNotFound,
     #[error("Session expired")]
     SessionExpired,
-    #[error("User not found")]
-    UserNotFound,
-    #[error("Permission denied")]
-    PermissionDenied,
     #[error("Channel not found")]
     ChannelNotFound,
     #[error("User not a member of channel")]
     NotChannelMember,
     #[error("User is banned from channel")]
     UserBanned,
+    #[error("Email address not verified")]
+    EmailNotVerified,
+}
+
+impl AuthError {
+    /// Stable name used as a metrics label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::SessionNotFound => "session_not_found",
+            AuthError::SessionExpired => "session_expired",
+            AuthError::ChannelNotFound => "channel_not_found",
+            AuthError::NotChannelMember => "not_channel_member",
+            AuthError::UserBanned => "user_banned",
+            AuthError::EmailNotVerified => "email_not_verified",
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use jsonwebtoken::{encode, EncodingKey, Header};
-    use chrono::Utc;
-
-    fn create_test_token(user_id: &str) -> String {
-        let now = Utc::now();
-        let exp = (now + chrono::Duration::hours(1)).timestamp() as usize;
-        let iat = now.timestamp() as usize;
 
-        let claims = AudioClaims {
-            sub: user_id.to_string(),
-            roles: vec!["user".to_string()],
-            exp,
-            iat,
-        };
+    fn create_test_auth() -> AudioAuth {
+        AudioAuth::new(Arc::new(ChannelAppState::new()))
+    }
 
-        encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret("test-secret".as_ref()),
-        )
-        .unwrap()
+    fn create_test_token(auth: &AudioAuth, user_id: &str) -> String {
+        auth.channel_state.tokens.issue(user_id, &format!("{}_name", user_id), vec!["user".to_string()]).unwrap()
     }
 
     #[test]
     fn test_authentication() {
-        let auth = AudioAuth::new("test-secret".to_string());
-        let token = create_test_token("test_user");
+        let auth = create_test_auth();
+        let token = create_test_token(&auth, "test_user");
 
         let session = auth.authenticate(&token).unwrap();
         assert_eq!(session.user_id, "test_user");
-        assert_eq!(session.username, "test_user");
+        assert_eq!(session.username, "test_user_name");
     }
 
     #[test]
     fn test_session_management() {
-        let auth = AudioAuth::new("test-secret".to_string());
-        let token = create_test_token("test_user");
+        let auth = create_test_auth();
+        let token = create_test_token(&auth, "test_user");
 
         // Authenticate
         let session = auth.authenticate(&token).unwrap();
@@ -238,8 +214,18 @@ mod tests {
 
     #[test]
     fn test_invalid_token() {
-        let auth = AudioAuth::new("test-secret".to_string());
-        
+        let auth = create_test_auth();
+
         assert!(auth.authenticate("invalid.token.here").is_err());
     }
+
+    #[test]
+    fn test_unverified_users_cannot_join_voice() {
+        let auth = create_test_auth();
+        let roles = vec!["user".to_string(), UNVERIFIED_ROLE.to_string()];
+        let token = auth.channel_state.tokens.issue("new_user", "new_user", roles).unwrap();
+
+        assert!(matches!(auth.authenticate(&token), Err(AuthError::EmailNotVerified)));
+        assert_eq!(auth.session_count(), 0);
+    }
 } 
\ No newline at end of file
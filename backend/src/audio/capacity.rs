@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::routes::channels::Channel;
+use super::state::StateError;
+
+/// How long a waitlisted user keeps their place without asking again
+pub const WAITLIST_TTL: Duration = Duration::from_secs(60);
+
+/// How long after their last frame a user still counts as speaking
+pub const SPEAKER_HOLD: Duration = Duration::from_millis(500);
+
+struct WaitlistEntry {
+    user_id: String,
+    last_seen: Instant,
+}
+
+/// Users queued for a place in full channels, first come first served
+#[derive(Default)]
+pub struct Waitlists {
+    queues: Mutex<HashMap<String, Vec<WaitlistEntry>>>,
+}
+
+impl Waitlists {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Admit a user to the voice of a channel that `occupied` other users are in.
+    ///
+    /// Owners and moderators are always admitted. In a full channel with a
+    /// waitlist the user is queued, or keeps their place by asking again
+    /// within `WAITLIST_TTL`; the queue's head is admitted as places free up.
+    pub fn admit(&self, channel: &Channel, user_id: &str, occupied: usize) -> Result<(), StateError> {
+        let limit = match channel.max_participants {
+            Some(limit) if !channel.bypasses_limits(user_id) => limit,
+            _ => {
+                self.remove(&channel.id, user_id);
+                return Ok(());
+            }
+        };
+        let free = (limit as usize).saturating_sub(occupied);
+
+        if !channel.waitlist_enabled {
+            return if free > 0 {
+                Ok(())
+            } else {
+                Err(StateError::ChannelFull { limit, position: None })
+            };
+        }
+
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(channel.id.clone()).or_default();
+        let now = Instant::now();
+        queue.retain(|entry| now.duration_since(entry.last_seen) < WAITLIST_TTL);
+
+        let index = match queue.iter().position(|entry| entry.user_id == user_id) {
+            Some(index) => {
+                queue[index].last_seen = now;
+                index
+            }
+            None => {
+                queue.push(WaitlistEntry {
+                    user_id: user_id.to_string(),
+                    last_seen: now,
+                });
+                queue.len() - 1
+            }
+        };
+
+        if index < free {
+            queue.remove(index);
+            if queue.is_empty() {
+                queues.remove(&channel.id);
+            }
+            Ok(())
+        } else {
+            Err(StateError::ChannelFull {
+                limit,
+                position: Some(index as u32 + 1),
+            })
+        }
+    }
+
+    /// Give up a user's place in a channel's queue
+    pub fn remove(&self, channel_id: &str, user_id: &str) {
+        let mut queues = self.queues.lock().unwrap();
+        if let Some(queue) = queues.get_mut(channel_id) {
+            queue.retain(|entry| entry.user_id != user_id);
+            if queue.is_empty() {
+                queues.remove(channel_id);
+            }
+        }
+    }
+
+    /// Drop a channel's queue, e.g. once its waitlist is turned off
+    pub fn clear(&self, channel_id: &str) {
+        self.queues.lock().unwrap().remove(channel_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::channels::ChannelPrivacy;
+
+    fn channel(max_participants: u32, waitlist_enabled: bool) -> Channel {
+        Channel {
+            id: "chan1".to_string(),
+            name: "Ops".to_string(),
+            privacy: ChannelPrivacy::Public,
+            owner: "alice".to_string(),
+            moderators: vec!["alice".to_string()],
+            members: vec!["alice".to_string(), "bob".to_string(), "carol".to_string()],
+            banned_users: Vec::new(),
+            invite_tokens: HashMap::new(),
+            min_bitrate_bps: None,
+            max_bitrate_bps: None,
+            max_participants: Some(max_participants),
+            max_speakers: None,
+            waitlist_enabled,
+        }
+    }
+
+    #[test]
+    fn test_full_channel_rejects_without_waitlist() {
+        let waitlists = Waitlists::new();
+        let channel = channel(2, false);
+
+        assert!(waitlists.admit(&channel, "bob", 1).is_ok());
+        assert!(matches!(
+            waitlists.admit(&channel, "bob", 2),
+            Err(StateError::ChannelFull { limit: 2, position: None })
+        ));
+        // Moderators bypass the limit
+        assert!(waitlists.admit(&channel, "alice", 2).is_ok());
+    }
+
+    #[test]
+    fn test_waitlist_admits_in_order() {
+        let waitlists = Waitlists::new();
+        let channel = channel(1, true);
+
+        assert!(matches!(waitlists.admit(&channel, "bob", 1), Err(StateError::ChannelFull { position: Some(1), .. })));
+        assert!(matches!(waitlists.admit(&channel, "carol", 1), Err(StateError::ChannelFull { position: Some(2), .. })));
+
+        // A place frees up: Carol keeps waiting behind Bob
+        assert!(matches!(waitlists.admit(&channel, "carol", 0), Err(StateError::ChannelFull { position: Some(2), .. })));
+        assert!(waitlists.admit(&channel, "bob", 0).is_ok());
+        assert!(matches!(waitlists.admit(&channel, "carol", 1), Err(StateError::ChannelFull { position: Some(1), .. })));
+
+        waitlists.remove("chan1", "carol");
+        assert!(waitlists.queues.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::audio::packet::{BitrateHint, ReceiverReport};
+
+/// Congestion control configuration
+#[derive(Debug, Clone)]
+pub struct CongestionConfig {
+    /// Lowest bitrate ever hinted to a sender
+    pub min_bitrate_bps: u32,
+    /// Highest bitrate ever hinted to a sender
+    pub max_bitrate_bps: u32,
+    /// Starting estimate for a path with no reports yet
+    pub initial_bitrate_bps: u32,
+    /// Reports older than this are ignored when computing channel targets
+    pub report_timeout: Duration,
+    /// How far above the channel cap a sender may go before being throttled
+    pub throttle_tolerance: f32,
+    /// Minimum relative change before a new hint is sent
+    pub hint_threshold: f32,
+}
+
+impl Default for CongestionConfig {
+    fn default() -> Self {
+        Self {
+            min_bitrate_bps: 8_000,
+            max_bitrate_bps: 64_000,
+            initial_bitrate_bps: 32_000,
+            report_timeout: Duration::from_secs(10),
+            throttle_tolerance: 1.25,
+            hint_threshold: 0.1,
+        }
+    }
+}
+
+/// Per-channel bitrate bounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelBitrateLimits {
+    pub min_bps: u32,
+    pub max_bps: u32,
+}
+
+impl ChannelBitrateLimits {
+    /// Resolve optional channel settings against the server-wide bounds
+    pub fn resolve(min_bps: Option<u32>, max_bps: Option<u32>, config: &CongestionConfig) -> Self {
+        let max_bps = max_bps
+            .unwrap_or(config.max_bitrate_bps)
+            .clamp(config.min_bitrate_bps, config.max_bitrate_bps);
+        let min_bps = min_bps
+            .unwrap_or(config.min_bitrate_bps)
+            .clamp(config.min_bitrate_bps, max_bps);
+        Self { min_bps, max_bps }
+    }
+}
+
+/// Estimated downlink condition of one listener
+#[derive(Debug, Clone)]
+pub struct PathEstimate {
+    /// Smoothed loss fraction (0.0 - 1.0)
+    pub loss: f32,
+    /// Smoothed round-trip time in ms
+    pub rtt_ms: f32,
+    /// Smoothed jitter in ms
+    pub jitter_ms: f32,
+    /// Estimated available bandwidth in bits per second
+    pub available_bps: u32,
+    pub last_report: Instant,
+}
+
+impl PathEstimate {
+    /// EWMA weight given to each new report
+    const SMOOTHING: f32 = 0.3;
+    /// RTT above which the path is treated as queueing
+    const HIGH_RTT_MS: f32 = 400.0;
+
+    fn new(initial_bps: u32) -> Self {
+        Self {
+            loss: 0.0,
+            rtt_ms: 0.0,
+            jitter_ms: 0.0,
+            available_bps: initial_bps,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Fold a receiver report into the estimate.
+    ///
+    /// Loss-based control in the style of GCC: grow slowly while loss is
+    /// low, hold between 2% and 10%, and back off proportionally above that.
+    /// A high RTT is taken as a sign of standing queues and also backs off.
+    fn update(&mut self, report: &ReceiverReport, config: &CongestionConfig) {
+        let loss = report.loss();
+        self.loss += Self::SMOOTHING * (loss - self.loss);
+        self.rtt_ms += Self::SMOOTHING * (report.rtt_ms as f32 - self.rtt_ms);
+        self.jitter_ms += Self::SMOOTHING * (report.jitter_ms as f32 - self.jitter_ms);
+
+        let mut estimate = self.available_bps as f32;
+        if self.loss > 0.10 {
+            estimate *= 1.0 - 0.5 * self.loss;
+        } else if self.loss < 0.02 {
+            estimate *= 1.08;
+        }
+        if self.rtt_ms > Self::HIGH_RTT_MS {
+            estimate *= 0.85;
+        }
+
+        self.available_bps = (estimate as u32).clamp(config.min_bitrate_bps, config.max_bitrate_bps);
+        self.last_report = Instant::now();
+    }
+}
+
+/// Token bucket used to throttle senders exceeding the channel cap
+#[derive(Debug)]
+struct ThrottleBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Tracks listener path estimates and computes bitrate hints for senders
+pub struct CongestionController {
+    config: CongestionConfig,
+    paths: Mutex<HashMap<String, PathEstimate>>, // listener user_id -> estimate
+    buckets: Mutex<HashMap<(String, u8), ThrottleBucket>>, // (sender user_id, layer) -> bucket
+    channel_limits: Mutex<HashMap<String, ChannelBitrateLimits>>,
+    last_hints: Mutex<HashMap<String, BitrateHint>>, // channel_id -> last hint sent
+    last_simulcast_hints: Mutex<HashMap<String, BitrateHint>>, // channel_id -> last hint sent
+}
+
+impl CongestionController {
+    /// Burst allowance of the throttle bucket, in seconds of traffic at the cap
+    const BURST_SECONDS: f64 = 0.5;
+
+    pub fn new(config: CongestionConfig) -> Self {
+        Self {
+            config,
+            paths: Mutex::new(HashMap::new()),
+            buckets: Mutex::new(HashMap::new()),
+            channel_limits: Mutex::new(HashMap::new()),
+            last_hints: Mutex::new(HashMap::new()),
+            last_simulcast_hints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &CongestionConfig {
+        &self.config
+    }
+
+    /// Record a receiver report from a listener
+    pub fn on_receiver_report(&self, user_id: &str, report: &ReceiverReport) {
+        let mut paths = self.paths.lock().unwrap();
+        paths
+            .entry(user_id.to_string())
+            .or_insert_with(|| PathEstimate::new(self.config.initial_bitrate_bps))
+            .update(report, &self.config);
+    }
+
+    /// Get the current estimate for a listener
+    pub fn estimate(&self, user_id: &str) -> Option<PathEstimate> {
+        self.paths.lock().unwrap().get(user_id).cloned()
+    }
+
+    /// Replace the cached bitrate limits of a channel
+    pub fn set_channel_limits(&self, channel_id: &str, limits: ChannelBitrateLimits) {
+        self.channel_limits.lock().unwrap().insert(channel_id.to_string(), limits);
+    }
+
+    /// Get the cached bitrate limits of a channel, falling back to server bounds
+    pub fn channel_limits(&self, channel_id: &str) -> ChannelBitrateLimits {
+        self.channel_limits
+            .lock()
+            .unwrap()
+            .get(channel_id)
+            .copied()
+            .unwrap_or_else(|| ChannelBitrateLimits::resolve(None, None, &self.config))
+    }
+
+    /// Compute the bitrate senders in a channel should target.
+    ///
+    /// This is the lowest fresh estimate among the channel's listeners, so
+    /// that no listener receives more than its path can carry, bounded by
+    /// the channel limits.
+    pub fn target_bitrate(&self, listeners: &[String], limits: ChannelBitrateLimits) -> u32 {
+        let paths = self.paths.lock().unwrap();
+        listeners
+            .iter()
+            .filter_map(|user_id| paths.get(user_id))
+            .filter(|path| path.last_report.elapsed() <= self.config.report_timeout)
+            .map(|path| path.available_bps)
+            .min()
+            .unwrap_or(limits.max_bps)
+            .clamp(limits.min_bps, limits.max_bps)
+    }
+
+    /// Compute the bitrate simulcast senders should use for their top layer.
+    ///
+    /// Weak listeners are served lower layers, so the top layer only needs
+    /// to fit the best listener path.
+    pub fn peak_bitrate(&self, listeners: &[String], limits: ChannelBitrateLimits) -> u32 {
+        let paths = self.paths.lock().unwrap();
+        listeners
+            .iter()
+            .filter_map(|user_id| paths.get(user_id))
+            .filter(|path| path.last_report.elapsed() <= self.config.report_timeout)
+            .map(|path| path.available_bps)
+            .max()
+            .unwrap_or(limits.max_bps)
+            .clamp(limits.min_bps, limits.max_bps)
+    }
+
+    /// Build the hint for a channel, returning `None` if it has not changed
+    /// enough since the last hint to be worth sending.
+    pub fn next_hint(&self, channel_id: &str, listeners: &[String]) -> Option<BitrateHint> {
+        let limits = self.channel_limits(channel_id);
+        let hint = BitrateHint {
+            target_bps: self.target_bitrate(listeners, limits),
+            max_bps: limits.max_bps,
+        };
+        self.dedupe_hint(&self.last_hints, channel_id, hint)
+    }
+
+    /// Build the top-layer hint for simulcast senders in a channel
+    pub fn next_simulcast_hint(&self, channel_id: &str, listeners: &[String]) -> Option<BitrateHint> {
+        let limits = self.channel_limits(channel_id);
+        let hint = BitrateHint {
+            target_bps: self.peak_bitrate(listeners, limits),
+            max_bps: limits.max_bps,
+        };
+        self.dedupe_hint(&self.last_simulcast_hints, channel_id, hint)
+    }
+
+    fn dedupe_hint(
+        &self,
+        last_hints: &Mutex<HashMap<String, BitrateHint>>,
+        channel_id: &str,
+        hint: BitrateHint,
+    ) -> Option<BitrateHint> {
+        let mut last_hints = last_hints.lock().unwrap();
+        if let Some(last) = last_hints.get(channel_id) {
+            let change = (hint.target_bps as f32 - last.target_bps as f32).abs() / last.target_bps.max(1) as f32;
+            if change < self.config.hint_threshold && hint.max_bps == last.max_bps {
+                return None;
+            }
+        }
+        last_hints.insert(channel_id.to_string(), hint);
+        Some(hint)
+    }
+
+    /// Get the last hint sent to each channel
+    pub fn current_hints(&self) -> HashMap<String, BitrateHint> {
+        self.last_hints.lock().unwrap().clone()
+    }
+
+    /// Check whether a sender's frame fits within the channel cap.
+    ///
+    /// Senders that ignore hints and exceed the cap (plus tolerance) have
+    /// frames dropped until they fall back under it. Each simulcast layer
+    /// is held to the cap separately.
+    pub fn allow_frame(&self, user_id: &str, channel_id: &str, layer: u8, frame_bytes: usize) -> bool {
+        let cap_bps = self.channel_limits(channel_id).max_bps as f64 * self.config.throttle_tolerance as f64;
+        let rate = cap_bps / 8.0; // bytes per second
+        let capacity = rate * Self::BURST_SECONDS;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+        let bucket = buckets.entry((user_id.to_string(), layer)).or_insert(ThrottleBucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= frame_bytes as f64 {
+            bucket.tokens -= frame_bytes as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Forget users that are no longer connected and channels that are gone
+    pub fn retain(&self, user_ids: &HashSet<String>, channel_ids: &HashSet<String>) {
+        self.paths.lock().unwrap().retain(|user_id, _| user_ids.contains(user_id));
+        self.buckets.lock().unwrap().retain(|(user_id, _), _| user_ids.contains(user_id));
+        self.channel_limits.lock().unwrap().retain(|channel_id, _| channel_ids.contains(channel_id));
+        self.last_hints.lock().unwrap().retain(|channel_id, _| channel_ids.contains(channel_id));
+        self.last_simulcast_hints.lock().unwrap().retain(|channel_id, _| channel_ids.contains(channel_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(fraction_lost: u8, rtt_ms: u16) -> ReceiverReport {
+        ReceiverReport { fraction_lost, rtt_ms, jitter_ms: 0 }
+    }
+
+    #[test]
+    fn test_loss_lowers_estimate() {
+        let controller = CongestionController::new(CongestionConfig::default());
+
+        for _ in 0..5 {
+            controller.on_receiver_report("user1", &report(0, 50));
+            controller.on_receiver_report("user2", &report(128, 50)); // 50% loss
+        }
+
+        let good = controller.estimate("user1").unwrap();
+        let bad = controller.estimate("user2").unwrap();
+        assert!(good.available_bps > 32_000);
+        assert!(bad.available_bps < 32_000);
+    }
+
+    #[test]
+    fn test_target_is_bounded_minimum_of_listeners() {
+        let controller = CongestionController::new(CongestionConfig::default());
+        controller.on_receiver_report("user1", &report(0, 50));
+        controller.on_receiver_report("user2", &report(200, 50));
+
+        let listeners = vec!["user1".to_string(), "user2".to_string()];
+        let weakest = controller.estimate("user2").unwrap().available_bps;
+
+        let unbounded = ChannelBitrateLimits { min_bps: 8_000, max_bps: 64_000 };
+        assert_eq!(controller.target_bitrate(&listeners, unbounded), weakest);
+
+        let floor = ChannelBitrateLimits { min_bps: 30_000, max_bps: 64_000 };
+        assert_eq!(controller.target_bitrate(&listeners, floor), 30_000);
+
+        // Simulcast top layers only need to fit the strongest listener
+        let strongest = controller.estimate("user1").unwrap().available_bps;
+        assert_eq!(controller.peak_bitrate(&listeners, unbounded), strongest);
+
+        // No reports at all means senders may use the full channel cap
+        assert_eq!(controller.target_bitrate(&[], unbounded), 64_000);
+    }
+
+    #[test]
+    fn test_hint_only_sent_on_significant_change() {
+        let controller = CongestionController::new(CongestionConfig::default());
+        let listeners = vec!["user1".to_string()];
+
+        assert!(controller.next_hint("chan1", &listeners).is_some());
+        assert!(controller.next_hint("chan1", &listeners).is_none());
+
+        for _ in 0..5 {
+            controller.on_receiver_report("user1", &report(128, 50));
+        }
+        let hint = controller.next_hint("chan1", &listeners).unwrap();
+        assert!(hint.target_bps < 64_000);
+    }
+
+    #[test]
+    fn test_sender_over_cap_is_throttled() {
+        let controller = CongestionController::new(CongestionConfig::default());
+        controller.set_channel_limits("chan1", ChannelBitrateLimits { min_bps: 8_000, max_bps: 16_000 });
+
+        // Burst allowance is 0.5s at 20kbps (16kbps * 1.25) = 1250 bytes
+        let allowed = (0..20)
+            .filter(|_| controller.allow_frame("user1", "chan1", 0, 160))
+            .count();
+        assert_eq!(allowed, 7);
+
+        // Other simulcast layers have their own budget
+        assert!(controller.allow_frame("user1", "chan1", 1, 160));
+    }
+
+    #[test]
+    fn test_channel_limits_resolve() {
+        let config = CongestionConfig::default();
+        let limits = ChannelBitrateLimits::resolve(Some(4_000), Some(128_000), &config);
+        assert_eq!(limits, ChannelBitrateLimits { min_bps: 8_000, max_bps: 64_000 });
+
+        let limits = ChannelBitrateLimits::resolve(Some(48_000), Some(24_000), &config);
+        assert_eq!(limits, ChannelBitrateLimits { min_bps: 24_000, max_bps: 24_000 });
+    }
+}
@@ -2,8 +2,19 @@ pub mod server;
 pub mod packet;
 pub mod auth;
 pub mod state;
+pub mod congestion;
+pub mod simulcast;
+pub mod recording;
+pub mod tap;
+pub mod relay;
+pub mod sessions;
+pub mod capacity;
+pub mod abuse;
 
-pub use server::AudioServer;
-pub use packet::{AudioPacket, PacketType, PacketHeader};
+pub use server::{AudioServer, AudioServerConfig, ReloadHandle};
+pub use packet::{AudioPacket, PacketType};
 pub use auth::AudioAuth;
-pub use state::{UserState, ChannelState, AudioUserState}; 
\ No newline at end of file
+pub use state::AudioStateManager;
+pub use recording::RecordingConfig;
+pub use relay::RelayConfig;
+pub use abuse::AbuseConfig;
@@ -0,0 +1,823 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+use crate::audio::tap::{MediaTap, VoiceFrame};
+
+/// Opus frame duration the forwarder works with
+const FRAME_DURATION: Duration = Duration::from_millis(20);
+/// Samples per 20ms frame at the 48kHz Ogg Opus granule rate
+const SAMPLES_PER_FRAME: u64 = 960;
+/// TOC-only Opus packet (CELT fullband 20ms, zero-length frame) used to fill silence
+const SILENCE_PACKET: [u8; 1] = [0xF8];
+/// Packets gathered into one Ogg page (one second of audio)
+const PACKETS_PER_PAGE: usize = 50;
+/// Slots the mix holds back, waiting for other speakers' frames
+const MIX_DELAY_FRAMES: usize = 3;
+/// A speaker's timestamps are re-anchored once they drift this far from arrival times
+const MAX_DRIFT_FRAMES: i64 = 50;
+/// Frames waiting for the writer thread before the recording tap backs up
+const WRITER_QUEUE_FRAMES: usize = 256;
+
+/// Recording configuration
+#[derive(Debug, Clone)]
+pub struct RecordingConfig {
+    /// Directory finished recordings and spool files are written to
+    pub dir: PathBuf,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("recordings"),
+        }
+    }
+}
+
+/// Recording errors
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("Channel is already being recorded")]
+    AlreadyRecording,
+    #[error("Channel is not being recorded")]
+    NotRecording,
+    #[error("Recording not found")]
+    NotFound,
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Recording metadata, as listed to channel members. Kept next to the
+/// finished file as `<id>.json` so listings survive a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingInfo {
+    pub id: String,
+    pub channel_id: String,
+    pub started_by: String,
+    pub started_at: u64,
+    pub stopped_at: Option<u64>,
+    pub duration_ms: u64,
+    pub speakers: Vec<String>,
+    pub mixed_track: bool,
+    pub size_bytes: u64,
+}
+
+/// Ogg CRC32 (polynomial 0x04c11db7, no reflection, zero init)
+fn ogg_crc(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Build one Ogg page holding complete packets
+fn ogg_page(serial: u32, sequence: u32, granule: u64, header_type: u8, packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut segments = Vec::new();
+    for packet in packets {
+        segments.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+        segments.push((packet.len() % 255) as u8);
+    }
+
+    let mut page = Vec::with_capacity(27 + segments.len() + packets.iter().map(Vec::len).sum::<usize>());
+    page.extend_from_slice(b"OggS");
+    page.push(0); // Version
+    page.push(header_type);
+    page.extend_from_slice(&granule.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&[0; 4]); // CRC, filled in below
+    page.push(segments.len() as u8);
+    page.extend_from_slice(&segments);
+    for packet in packets {
+        page.extend_from_slice(packet);
+    }
+
+    let crc = ogg_crc(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+/// Read the next page from a spool file, returning its granule position and bytes
+fn read_page<R: Read>(reader: &mut R) -> io::Result<Option<(u64, Vec<u8>)>> {
+    let mut header = [0u8; 27];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut segments = vec![0u8; header[26] as usize];
+    reader.read_exact(&mut segments)?;
+    let mut body = vec![0u8; segments.iter().map(|&s| s as usize).sum()];
+    reader.read_exact(&mut body)?;
+
+    let granule = u64::from_le_bytes(header[6..14].try_into().unwrap());
+    let mut page = header.to_vec();
+    page.extend_from_slice(&segments);
+    page.extend_from_slice(&body);
+    Ok(Some((granule, page)))
+}
+
+/// One logical Ogg Opus stream, spooled to disk until the recording stops
+struct Track {
+    serial: u32,
+    label: String,
+    sequence: u32,
+    next_slot: u64,
+    packets: Vec<Vec<u8>>,
+    segments: usize,
+    spool_path: PathBuf,
+    spool: BufWriter<File>,
+}
+
+impl Track {
+    fn create(serial: u32, label: String, spool_path: PathBuf) -> io::Result<Self> {
+        let spool = BufWriter::new(File::create(&spool_path)?);
+        Ok(Self {
+            serial,
+            label,
+            sequence: 2, // Pages 0 and 1 are the OpusHead and OpusTags headers
+            next_slot: 0,
+            packets: Vec::new(),
+            segments: 0,
+            spool_path,
+            spool,
+        })
+    }
+
+    /// Place a frame at a timeline slot, filling any gap before it with silence.
+    ///
+    /// A late frame replaces the silence of its slot while that page is still
+    /// open; otherwise it is dropped, as are duplicates.
+    fn push(&mut self, slot: u64, payload: &[u8]) -> io::Result<()> {
+        if slot < self.next_slot {
+            let open_from = self.next_slot - self.packets.len() as u64;
+            if slot >= open_from {
+                let index = (slot - open_from) as usize;
+                let segments = self.segments - 1 + payload.len() / 255 + 1;
+                if self.packets[index] == SILENCE_PACKET && segments <= 255 {
+                    self.packets[index] = payload.to_vec();
+                    self.segments = segments;
+                }
+            }
+            return Ok(());
+        }
+        while self.next_slot < slot {
+            self.append(SILENCE_PACKET.to_vec())?;
+        }
+        self.append(payload.to_vec())
+    }
+
+    fn append(&mut self, packet: Vec<u8>) -> io::Result<()> {
+        let segments = packet.len() / 255 + 1;
+        if self.segments + segments > 255 {
+            self.flush_page(false)?;
+        }
+        self.segments += segments;
+        self.packets.push(packet);
+        self.next_slot += 1;
+
+        if self.packets.len() >= PACKETS_PER_PAGE {
+            self.flush_page(false)?;
+        }
+        Ok(())
+    }
+
+    fn flush_page(&mut self, eos: bool) -> io::Result<()> {
+        if self.packets.is_empty() && !eos {
+            return Ok(());
+        }
+        let granule = self.next_slot * SAMPLES_PER_FRAME;
+        let page = ogg_page(self.serial, self.sequence, granule, if eos { 0x04 } else { 0x00 }, &self.packets);
+        self.spool.write_all(&page)?;
+        self.sequence += 1;
+        self.packets.clear();
+        self.segments = 0;
+        Ok(())
+    }
+
+    fn head_page(&self) -> Vec<u8> {
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // Version
+        head.push(1); // Channel count
+        head.extend_from_slice(&0u16.to_le_bytes()); // Pre-skip, unknown for forwarded frames
+        head.extend_from_slice(&48_000u32.to_le_bytes()); // Input sample rate
+        head.extend_from_slice(&0i16.to_le_bytes()); // Output gain
+        head.push(0); // Mapping family
+        ogg_page(self.serial, 0, 0, 0x02, &[head])
+    }
+
+    fn tags_page(&self, channel_id: &str) -> Vec<u8> {
+        let vendor = b"whisper-fleet-link";
+        let comments = [format!("TITLE={}", self.label), format!("CHANNEL={}", channel_id)];
+
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in &comments {
+            tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            tags.extend_from_slice(comment.as_bytes());
+        }
+        ogg_page(self.serial, 1, 0, 0x00, &[tags])
+    }
+
+    /// Write the last page and close the spool
+    fn finish(&mut self, end_slot: u64) -> io::Result<()> {
+        // Pad with silence so every track ends at the same time
+        while self.next_slot < end_slot {
+            self.append(SILENCE_PACKET.to_vec())?;
+        }
+        self.flush_page(true)?;
+        self.spool.flush()
+    }
+}
+
+/// Active recording of one channel
+struct Recording {
+    info: RecordingInfo,
+    started: Instant,
+    spool_dir: PathBuf,
+    output_path: PathBuf,
+    tracks: BTreeMap<String, Track>, // speaker -> track
+    /// Switched mix: per slot, the largest frame of any speaker
+    mix: Option<Track>,
+    mix_pending: BTreeMap<u64, Vec<u8>>,
+    /// Per speaker, the slot and sender timestamp their frames are placed from
+    anchors: HashMap<String, (u64, u64)>,
+    opted_out: HashSet<String>,
+    next_serial: u32,
+}
+
+impl Recording {
+    fn create(dir: &Path, channel_id: &str, started_by: &str, mixed_track: bool) -> io::Result<Self> {
+        let id = Uuid::new_v4().to_string();
+        let spool_dir = dir.join(format!("{}.spool", id));
+        fs::create_dir_all(&spool_dir)?;
+
+        let mut recording = Self {
+            info: RecordingInfo {
+                id: id.clone(),
+                channel_id: channel_id.to_string(),
+                started_by: started_by.to_string(),
+                started_at: chrono::Utc::now().timestamp() as u64,
+                stopped_at: None,
+                duration_ms: 0,
+                speakers: Vec::new(),
+                mixed_track,
+                size_bytes: 0,
+            },
+            started: Instant::now(),
+            output_path: dir.join(format!("{}.ogg", id)),
+            spool_dir,
+            tracks: BTreeMap::new(),
+            mix: None,
+            mix_pending: BTreeMap::new(),
+            anchors: HashMap::new(),
+            opted_out: HashSet::new(),
+            next_serial: 1,
+        };
+
+        if mixed_track {
+            let serial = recording.allocate_serial();
+            recording.mix = Some(Track::create(serial, "mix".to_string(), recording.spool_dir.join("mix.pages"))?);
+        }
+        Ok(recording)
+    }
+
+    fn allocate_serial(&mut self) -> u32 {
+        let serial = self.next_serial;
+        self.next_serial += 1;
+        serial
+    }
+
+    fn slot_at(&self, at: Instant) -> u64 {
+        (at.saturating_duration_since(self.started).as_millis() / FRAME_DURATION.as_millis()) as u64
+    }
+
+    /// Timeline slot of a speaker's frame.
+    ///
+    /// Frames are placed by the sender's timestamp (ms) relative to the
+    /// speaker's first frame, so forwarder jitter neither shifts nor merges
+    /// them. Arrival time takes over again if the two clocks drift apart.
+    fn slot_of(&mut self, speaker_id: &str, forwarded_at: Instant, timestamp: u64) -> u64 {
+        let arrival = self.slot_at(forwarded_at);
+        let frame_ms = FRAME_DURATION.as_millis() as i64;
+        let anchor = self.anchors.entry(speaker_id.to_string()).or_insert((arrival, timestamp));
+        let slot = anchor.0 as i64 + (timestamp as i64 - anchor.1 as i64 + frame_ms / 2).div_euclid(frame_ms);
+        if (slot - arrival as i64).abs() > MAX_DRIFT_FRAMES {
+            *anchor = (arrival, timestamp);
+            return arrival;
+        }
+        slot.max(0) as u64
+    }
+
+    fn frame(&mut self, speaker_id: &str, slot: u64, payload: &[u8]) -> io::Result<()> {
+        if self.opted_out.contains(speaker_id) {
+            return Ok(());
+        }
+
+        if !self.tracks.contains_key(speaker_id) {
+            let serial = self.allocate_serial();
+            let spool_path = self.spool_dir.join(format!("{}.pages", serial));
+            self.tracks.insert(speaker_id.to_string(), Track::create(serial, speaker_id.to_string(), spool_path)?);
+            self.info.speakers.push(speaker_id.to_string());
+        }
+        self.tracks.get_mut(speaker_id).unwrap().push(slot, payload)?;
+
+        if let Some(mix) = self.mix.as_mut() {
+            let pending = self.mix_pending.entry(slot).or_default();
+            // Larger frames carry more signal in VBR Opus
+            if payload.len() > pending.len() {
+                *pending = payload.to_vec();
+            }
+            while self.mix_pending.len() > MIX_DELAY_FRAMES {
+                let (slot, payload) = self.mix_pending.pop_first().unwrap();
+                mix.push(slot, &payload)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Close all tracks and merge them into a grouped Ogg file
+    fn finish(mut self) -> io::Result<RecordingInfo> {
+        let elapsed_slot = self.slot_at(Instant::now());
+
+        if let Some(mix) = self.mix.as_mut() {
+            for (slot, payload) in std::mem::take(&mut self.mix_pending) {
+                mix.push(slot, &payload)?;
+            }
+        }
+
+        let mut tracks: Vec<Track> = self.tracks.into_values().collect();
+        if let Some(mix) = self.mix.take() {
+            tracks.insert(0, mix);
+        }
+        let end_slot = tracks.iter().map(|t| t.next_slot).max().unwrap_or(0).max(elapsed_slot);
+        for track in tracks.iter_mut() {
+            track.finish(end_slot)?;
+        }
+
+        let mut output = BufWriter::new(File::create(&self.output_path)?);
+        // Grouped streams: every BOS page first, then the remaining headers
+        for track in &tracks {
+            output.write_all(&track.head_page())?;
+        }
+        for track in &tracks {
+            output.write_all(&track.tags_page(&self.info.channel_id))?;
+        }
+
+        // Interleave data pages in timeline order
+        let mut readers = Vec::with_capacity(tracks.len());
+        for track in &tracks {
+            readers.push(BufReader::new(File::open(&track.spool_path)?));
+        }
+        let mut heads = Vec::with_capacity(readers.len());
+        for reader in readers.iter_mut() {
+            heads.push(read_page(reader)?);
+        }
+        loop {
+            let next = heads
+                .iter()
+                .enumerate()
+                .filter_map(|(index, head)| head.as_ref().map(|(granule, _)| (*granule, index)))
+                .min();
+            let index = match next {
+                Some((_, index)) => index,
+                None => break,
+            };
+            let (_, page) = heads[index].take().unwrap();
+            output.write_all(&page)?;
+            heads[index] = read_page(&mut readers[index])?;
+        }
+        output.flush()?;
+        drop(output);
+
+        if let Err(e) = fs::remove_dir_all(&self.spool_dir) {
+            warn!("Failed to remove recording spool {:?}: {}", self.spool_dir, e);
+        }
+
+        self.info.stopped_at = Some(chrono::Utc::now().timestamp() as u64);
+        self.info.duration_ms = end_slot * FRAME_DURATION.as_millis() as u64;
+        self.info.size_bytes = fs::metadata(&self.output_path)?.len();
+        fs::write(self.output_path.with_extension("json"), serde_json::to_vec_pretty(&self.info)?)?;
+        Ok(self.info)
+    }
+}
+
+/// Records channels to Ogg Opus, one logical stream per speaker.
+///
+/// Frames are written on a dedicated thread, so the forwarding path only
+/// reads `recording_channels` and the tap task only queues.
+pub struct RecordingManager {
+    config: RecordingConfig,
+    active: Arc<Mutex<HashMap<String, Recording>>>, // channel_id -> recording
+    recording_channels: RwLock<HashSet<String>>,
+    finished: Mutex<Vec<RecordingInfo>>,
+    writer: mpsc::Sender<VoiceFrame>,
+}
+
+impl RecordingManager {
+    pub fn new(config: RecordingConfig) -> Self {
+        let finished = Self::load_finished(&config.dir);
+        let active = Arc::new(Mutex::new(HashMap::new()));
+
+        // The thread ends when the manager, and with it the sender, is dropped
+        let (writer, mut frames) = mpsc::channel::<VoiceFrame>(WRITER_QUEUE_FRAMES);
+        let writer_active = Arc::clone(&active);
+        thread::Builder::new()
+            .name("recording-writer".to_string())
+            .spawn(move || {
+                while let Some(frame) = frames.blocking_recv() {
+                    write_frame(&writer_active, &frame);
+                }
+            })
+            .expect("failed to spawn recording writer thread");
+
+        Self {
+            config,
+            active,
+            recording_channels: RwLock::new(HashSet::new()),
+            finished: Mutex::new(finished),
+            writer,
+        }
+    }
+
+    /// Finished recordings left by earlier runs, oldest first
+    fn load_finished(dir: &Path) -> Vec<RecordingInfo> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => {
+                warn!("Failed to read recordings in {:?}: {}", dir, e);
+                return Vec::new();
+            }
+        };
+
+        let mut finished = Vec::new();
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let info: RecordingInfo = match fs::read(&path).map_err(serde_json::Error::io).and_then(|data| serde_json::from_slice(&data)) {
+                Ok(info) => info,
+                Err(e) => {
+                    warn!("Skipping recording metadata {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            if !dir.join(format!("{}.ogg", info.id)).is_file() {
+                warn!("Skipping recording {}: its file is missing", info.id);
+                continue;
+            }
+            finished.push(info);
+        }
+        finished.sort_by(|a, b| a.started_at.cmp(&b.started_at).then_with(|| a.id.cmp(&b.id)));
+        if !finished.is_empty() {
+            info!("Loaded {} finished recordings from {:?}", finished.len(), dir);
+        }
+        finished
+    }
+
+    /// Start recording a channel
+    pub fn start(&self, channel_id: &str, started_by: &str, mixed_track: bool) -> Result<RecordingInfo, RecordingError> {
+        let mut active = self.active.lock().unwrap();
+        if active.contains_key(channel_id) {
+            return Err(RecordingError::AlreadyRecording);
+        }
+
+        fs::create_dir_all(&self.config.dir)?;
+        let recording = Recording::create(&self.config.dir, channel_id, started_by, mixed_track)?;
+        let info = recording.info.clone();
+        active.insert(channel_id.to_string(), recording);
+        self.recording_channels.write().unwrap().insert(channel_id.to_string());
+
+        info!("Started recording {} of channel {} (by {})", info.id, channel_id, started_by);
+        Ok(info)
+    }
+
+    /// Stop recording a channel and write the final file
+    pub fn stop(&self, channel_id: &str) -> Result<RecordingInfo, RecordingError> {
+        let recording = self
+            .active
+            .lock()
+            .unwrap()
+            .remove(channel_id)
+            .ok_or(RecordingError::NotRecording)?;
+        self.recording_channels.write().unwrap().remove(channel_id);
+
+        let info = recording.finish()?;
+        info!("Stopped recording {} of channel {} ({} ms, {} speakers)",
+              info.id, channel_id, info.duration_ms, info.speakers.len());
+        self.finished.lock().unwrap().push(info.clone());
+        Ok(info)
+    }
+
+    /// Active recording of a channel, if any
+    pub fn active(&self, channel_id: &str) -> Option<RecordingInfo> {
+        self.active.lock().unwrap().get(channel_id).map(|recording| {
+            let mut info = recording.info.clone();
+            info.duration_ms = recording.started.elapsed().as_millis() as u64;
+            info
+        })
+    }
+
+    pub fn is_recording(&self, channel_id: &str) -> bool {
+        self.recording_channels.read().unwrap().contains(channel_id)
+    }
+
+    /// Exclude or re-include a participant in the active recording
+    pub fn set_consent(&self, channel_id: &str, user_id: &str, consent: bool) -> Result<(), RecordingError> {
+        let mut active = self.active.lock().unwrap();
+        let recording = active.get_mut(channel_id).ok_or(RecordingError::NotRecording)?;
+        if consent {
+            recording.opted_out.remove(user_id);
+        } else {
+            recording.opted_out.insert(user_id.to_string());
+        }
+        Ok(())
+    }
+
+    /// Finished recordings of a channel, oldest first
+    pub fn list(&self, channel_id: &str) -> Vec<RecordingInfo> {
+        self.finished
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|info| info.channel_id == channel_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Path of a finished recording
+    pub fn file_path(&self, channel_id: &str, recording_id: &str) -> Result<PathBuf, RecordingError> {
+        self.finished
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|info| info.channel_id == channel_id && info.id == recording_id)
+            .map(|info| self.config.dir.join(format!("{}.ogg", info.id)))
+            .ok_or(RecordingError::NotFound)
+    }
+}
+
+/// Recordings are fed from the forwarding path as a media tap
+#[async_trait]
+impl MediaTap for RecordingManager {
+    fn wants_channel(&self, channel_id: &str) -> bool {
+        self.is_recording(channel_id)
+    }
+
+    /// Waits while the writer is behind, so the tap's own queue drops frames
+    async fn on_frame(&self, frame: VoiceFrame) {
+        let _ = self.writer.send(frame).await;
+    }
+}
+
+/// Record one frame of a speaker, placed on the timeline by its sender timestamp
+fn write_frame(active: &Mutex<HashMap<String, Recording>>, frame: &VoiceFrame) {
+    let mut active = active.lock().unwrap();
+    if let Some(recording) = active.get_mut(&frame.channel_id) {
+        let slot = recording.slot_of(&frame.speaker_id, frame.forwarded_at, frame.timestamp);
+        if let Err(e) = recording.frame(&frame.speaker_id, slot, &frame.payload) {
+            debug!("Failed to record frame from {} in channel {}: {}", frame.speaker_id, frame.channel_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("wfl-recording-{}", Uuid::new_v4()))
+    }
+
+    fn parse_pages(data: &[u8]) -> Vec<(u32, u64, u8, Vec<u8>)> {
+        let mut pages = Vec::new();
+        let mut reader = data;
+        while let Some((granule, page)) = read_page(&mut reader).unwrap() {
+            let serial = u32::from_le_bytes(page[14..18].try_into().unwrap());
+            pages.push((serial, granule, page[5], page));
+        }
+        pages
+    }
+
+    #[test]
+    fn test_ogg_crc() {
+        assert_eq!(ogg_crc(b""), 0);
+        let page = ogg_page(1, 0, 0, 0x02, &[b"OpusHead".to_vec()]);
+        let mut zeroed = page.clone();
+        zeroed[22..26].copy_from_slice(&[0; 4]);
+        assert_eq!(u32::from_le_bytes(page[22..26].try_into().unwrap()), ogg_crc(&zeroed));
+    }
+
+    #[test]
+    fn test_tracks_are_aligned() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let mut recording = Recording::create(&dir, "channel1", "owner", true).unwrap();
+
+        // Alice speaks from the start, Bob joins a second later
+        for slot in 0..100 {
+            recording.frame("alice", slot, &[0xFC, 1, 2, 3]).unwrap();
+            if slot >= 50 {
+                recording.frame("bob", slot, &[0xFC, 1, 2, 3, 4, 5]).unwrap();
+            }
+        }
+        let output_path = recording.output_path.clone();
+        let info = recording.finish().unwrap();
+        assert_eq!(info.speakers, vec!["alice", "bob"]);
+
+        let data = fs::read(&output_path).unwrap();
+        assert_eq!(info.size_bytes, data.len() as u64);
+        let pages = parse_pages(&data);
+
+        // Mix, alice and bob: three BOS pages before anything else
+        assert!(pages[..3].iter().all(|(_, _, header_type, _)| *header_type == 0x02));
+        assert!(pages[3..].iter().all(|(_, _, header_type, _)| *header_type != 0x02));
+
+        // Every stream ends at the same granule
+        let last_granules: HashMap<u32, u64> = pages
+            .iter()
+            .filter(|(_, _, header_type, _)| header_type & 0x04 != 0)
+            .map(|(serial, granule, _, _)| (*serial, *granule))
+            .collect();
+        assert_eq!(last_granules.len(), 3);
+        assert!(last_granules.values().all(|&granule| granule == 100 * SAMPLES_PER_FRAME));
+
+        // Pages are written in timeline order
+        let data_granules: Vec<u64> = pages[6..].iter().map(|(_, granule, _, _)| *granule).collect();
+        assert!(data_granules.windows(2).all(|w| w[0] <= w[1]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Packets of one page, split at the lacing values
+    fn page_packets(page: &[u8]) -> Vec<Vec<u8>> {
+        let segments = &page[27..27 + page[26] as usize];
+        let mut body = &page[27 + segments.len()..];
+        let mut packets = Vec::new();
+        let mut packet = Vec::new();
+        for &segment in segments {
+            packet.extend_from_slice(&body[..segment as usize]);
+            body = &body[segment as usize..];
+            if segment < 255 {
+                packets.push(std::mem::take(&mut packet));
+            }
+        }
+        packets
+    }
+
+    #[test]
+    fn test_late_and_duplicate_frames_keep_their_slots() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let mut recording = Recording::create(&dir, "channel1", "owner", true).unwrap();
+        let payload = |slot: u64| vec![0xFC, slot as u8];
+
+        for slot in 0..120 {
+            match slot {
+                // Held back and delivered late: while its page is open, and after
+                20 | 60 => continue,
+                22 => recording.frame("alice", 20, &payload(20)).unwrap(),
+                110 => recording.frame("alice", 60, &payload(60)).unwrap(),
+                _ => {}
+            }
+            recording.frame("alice", slot, &payload(slot)).unwrap();
+            if slot == 10 {
+                recording.frame("alice", 10, &[0xFC, 0xFF]).unwrap();
+            }
+        }
+        let output_path = recording.output_path.clone();
+        let info = recording.finish().unwrap();
+        assert_eq!(info.duration_ms, 120 * FRAME_DURATION.as_millis() as u64);
+
+        let pages = parse_pages(&fs::read(&output_path).unwrap());
+        for serial in [1, 2] {
+            // Mix and alice: full pages end at 50 and 100 frames, the last at 120
+            let data: Vec<&(u32, u64, u8, Vec<u8>)> = pages[4..].iter().filter(|page| page.0 == serial).collect();
+            let granules: Vec<u64> = data.iter().map(|page| page.1).collect();
+            assert_eq!(granules, vec![50 * SAMPLES_PER_FRAME, 100 * SAMPLES_PER_FRAME, 120 * SAMPLES_PER_FRAME]);
+
+            let packets: Vec<Vec<u8>> = data.iter().flat_map(|page| page_packets(&page.3)).collect();
+            assert_eq!(packets.len(), 120);
+            assert_eq!(packets[10], payload(10));
+            assert_eq!(packets[11], payload(11));
+            assert_eq!(packets[20], payload(20));
+            assert_eq!(packets[60], SILENCE_PACKET);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_frames_are_placed_by_sender_timestamp() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let mut recording = Recording::create(&dir, "channel1", "owner", false).unwrap();
+        let at = |ms: u64| recording.started + Duration::from_millis(ms);
+        let (first, second, late, jumped) = (at(100), at(105), at(180), at(200));
+
+        assert_eq!(recording.slot_of("alice", first, 10_000), 5);
+        // Forwarded within the same slot, but sent 20ms later
+        assert_eq!(recording.slot_of("alice", second, 10_020), 6);
+        assert_eq!(recording.slot_of("alice", late, 10_040), 7);
+        // Bob's clock is his own
+        assert_eq!(recording.slot_of("bob", second, 500), 5);
+        // A jump in the sender's clock falls back to the arrival time
+        assert_eq!(recording.slot_of("alice", jumped, 99_000), 10);
+        assert_eq!(recording.slot_of("alice", jumped, 99_020), 11);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn frame(channel_id: &str, speaker_id: &str, payload: &[u8]) -> VoiceFrame {
+        VoiceFrame {
+            channel_id: channel_id.to_string(),
+            speaker_id: speaker_id.to_string(),
+            sequence_number: 50,
+            timestamp: 1_000,
+            layer: 0,
+            payload: Arc::from(payload),
+            forwarded_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_consent_and_lifecycle() {
+        let dir = test_dir();
+        let manager = RecordingManager::new(RecordingConfig { dir: dir.clone() });
+
+        let info = manager.start("channel1", "owner", false).unwrap();
+        assert!(matches!(manager.start("channel1", "owner", false), Err(RecordingError::AlreadyRecording)));
+        assert!(manager.is_recording("channel1"));
+        assert!(manager.wants_channel("channel1"));
+        assert!(!manager.wants_channel("channel2"));
+
+        manager.set_consent("channel1", "bob", false).unwrap();
+        write_frame(&manager.active, &frame("channel1", "alice", &[0xFC, 1]));
+        write_frame(&manager.active, &frame("channel1", "bob", &[0xFC, 2]));
+
+        let stopped = manager.stop("channel1").unwrap();
+        assert_eq!(stopped.id, info.id);
+        assert_eq!(stopped.speakers, vec!["alice"]);
+        assert!(matches!(manager.stop("channel1"), Err(RecordingError::NotRecording)));
+        assert!(!manager.wants_channel("channel1"));
+
+        assert_eq!(manager.list("channel1").len(), 1);
+        assert!(manager.file_path("channel1", &info.id).unwrap().exists());
+        assert!(matches!(manager.file_path("channel2", &info.id), Err(RecordingError::NotFound)));
+
+        // A new manager finds the recording again; stray metadata is skipped
+        fs::write(dir.join("broken.json"), b"{").unwrap();
+        let restarted = RecordingManager::new(RecordingConfig { dir: dir.clone() });
+        let listed = restarted.list("channel1");
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, info.id);
+        assert_eq!(listed[0].speakers, vec!["alice"]);
+        assert!(restarted.file_path("channel1", &info.id).unwrap().exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tap_frames_are_written_off_the_tap_task() {
+        let dir = test_dir();
+        let manager = RecordingManager::new(RecordingConfig { dir: dir.clone() });
+        manager.start("channel1", "owner", false).unwrap();
+
+        manager.on_frame(frame("channel1", "alice", &[0xFC, 1])).await;
+        for _ in 0..100 {
+            if manager.active("channel1").is_some_and(|info| !info.speakers.is_empty()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(manager.active("channel1").unwrap().speakers, vec!["alice"]);
+
+        let stopped = manager.stop("channel1").unwrap();
+        assert_eq!(stopped.speakers, vec!["alice"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,772 @@
+use crate::audio::auth::{AudioAuth, AuthError};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use ring::hmac;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::timeout_at;
+use tracing::{debug, info, warn};
+
+const MAGIC: &[u8; 4] = b"WFRL";
+const VERSION: u8 = 1;
+const TAG_LEN: usize = 32; // HMAC-SHA256
+
+/// Another backend instance of the fleet
+#[derive(Debug, Clone)]
+pub struct RelayPeer {
+    pub node_id: String,
+    pub addr: SocketAddr,
+}
+
+/// Server-to-server relay configuration
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    /// Unique name of this instance, e.g. "eu-1"
+    pub node_id: String,
+    pub bind_addr: String,
+    /// Shared secret authenticating relay datagrams; identical on every instance
+    pub secret: String,
+    pub peers: Vec<RelayPeer>,
+    /// How often local channels are announced to peers
+    pub announce_interval: Duration,
+    /// Peers stop receiving a channel this long after their last announcement of it
+    pub subscription_ttl: Duration,
+    /// How long a handshake waits for the owning instance to authorize it
+    pub authorize_timeout: Duration,
+    /// Datagrams stamped further from our clock than this are rejected
+    pub max_clock_skew: Duration,
+    /// Relayed frames queued for local fan-out before they are dropped
+    pub frame_queue_capacity: usize,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            node_id: "node-1".to_string(),
+            bind_addr: "0.0.0.0:8081".to_string(),
+            secret: String::new(),
+            peers: Vec::new(),
+            announce_interval: Duration::from_secs(1),
+            subscription_ttl: Duration::from_secs(5),
+            authorize_timeout: Duration::from_secs(1),
+            max_clock_skew: Duration::from_secs(10),
+            frame_queue_capacity: 1024,
+        }
+    }
+}
+
+impl RelayConfig {
+    /// Relay settings from `RELAY_*` environment variables; `None` unless `RELAY_PEERS` is set.
+    ///
+    /// `RELAY_PEERS` lists peers as `node=host:port`, comma separated, e.g.
+    /// `na-1=203.0.113.7:8081,ap-1=198.51.100.4:8081`.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let peers = match std::env::var("RELAY_PEERS") {
+            Ok(peers) if !peers.trim().is_empty() => parse_peers(&peers)?,
+            _ => return Ok(None),
+        };
+        let secret = std::env::var("RELAY_SECRET")
+            .map_err(|_| "RELAY_SECRET must be set when RELAY_PEERS is".to_string())?;
+        let defaults = Self::default();
+        Ok(Some(Self {
+            node_id: std::env::var("RELAY_NODE_ID").unwrap_or(defaults.node_id.clone()),
+            bind_addr: std::env::var("RELAY_BIND_ADDR").unwrap_or(defaults.bind_addr.clone()),
+            secret,
+            peers,
+            ..defaults
+        }))
+    }
+}
+
+/// Parse a `node=host:port,...` peer list
+fn parse_peers(value: &str) -> Result<Vec<RelayPeer>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (node_id, addr) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid relay peer '{}', expected node=host:port", entry))?;
+            let addr = addr
+                .parse()
+                .map_err(|e| format!("Invalid address of relay peer {}: {}", node_id, e))?;
+            Ok(RelayPeer {
+                node_id: node_id.to_string(),
+                addr,
+            })
+        })
+        .collect()
+}
+
+/// One voice frame of a speaker connected to another instance
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayFrame {
+    pub channel_id: String,
+    pub speaker_id: String,
+    pub sequence_number: u32,
+    pub timestamp: u64,
+    /// Every simulcast layer the speaker uploaded, so peers can pick per listener
+    pub layers: BTreeMap<u8, Vec<u8>>,
+}
+
+/// Answer of the owning instance to a membership check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    Allowed = 0,
+    NotMember = 1,
+    Banned = 2,
+    /// The channel does not live on the answering instance
+    NotFound = 3,
+}
+
+impl Decision {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Decision::Allowed),
+            1 => Some(Decision::NotMember),
+            2 => Some(Decision::Banned),
+            3 => Some(Decision::NotFound),
+            _ => None,
+        }
+    }
+}
+
+/// Relay errors
+#[derive(Debug, thiserror::Error)]
+pub enum RelayError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Datagram truncated")]
+    Truncated,
+    #[error("Not a relay datagram")]
+    BadMagic,
+    #[error("Unsupported relay version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Unknown relay message kind {0}")]
+    UnknownKind(u8),
+    #[error("Unknown peer {0}")]
+    UnknownPeer(String),
+    #[error("Bad signature")]
+    BadSignature,
+    #[error("Stale datagram")]
+    Stale,
+}
+
+/// Messages exchanged between instances
+#[derive(Debug, Clone, PartialEq)]
+enum RelayMessage {
+    /// Full set of channels the sender has local members in
+    Announce { channel_ids: Vec<String> },
+    Frame(RelayFrame),
+    AuthorizeRequest { request_id: u64, channel_id: String, user_id: String },
+    AuthorizeResponse { request_id: u64, decision: Decision },
+}
+
+impl RelayMessage {
+    fn kind(&self) -> u8 {
+        match self {
+            RelayMessage::Announce { .. } => 1,
+            RelayMessage::Frame(_) => 2,
+            RelayMessage::AuthorizeRequest { .. } => 3,
+            RelayMessage::AuthorizeResponse { .. } => 4,
+        }
+    }
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        match self {
+            RelayMessage::Announce { channel_ids } => {
+                buf.write_u16::<BigEndian>(channel_ids.len() as u16)?;
+                for channel_id in channel_ids {
+                    write_str(buf, channel_id)?;
+                }
+            }
+            RelayMessage::Frame(frame) => {
+                write_str(buf, &frame.channel_id)?;
+                write_str(buf, &frame.speaker_id)?;
+                buf.write_u32::<BigEndian>(frame.sequence_number)?;
+                buf.write_u64::<BigEndian>(frame.timestamp)?;
+                buf.write_u8(frame.layers.len() as u8)?;
+                for (layer, payload) in &frame.layers {
+                    buf.write_u8(*layer)?;
+                    buf.write_u16::<BigEndian>(payload.len() as u16)?;
+                    buf.extend_from_slice(payload);
+                }
+            }
+            RelayMessage::AuthorizeRequest { request_id, channel_id, user_id } => {
+                buf.write_u64::<BigEndian>(*request_id)?;
+                write_str(buf, channel_id)?;
+                write_str(buf, user_id)?;
+            }
+            RelayMessage::AuthorizeResponse { request_id, decision } => {
+                buf.write_u64::<BigEndian>(*request_id)?;
+                buf.write_u8(*decision as u8)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_body(kind: u8, cursor: &mut Cursor<&[u8]>) -> Result<Self, RelayError> {
+        let message = match kind {
+            1 => {
+                let count = cursor.read_u16::<BigEndian>()?;
+                let channel_ids = (0..count).map(|_| read_str(cursor)).collect::<Result<_, _>>()?;
+                RelayMessage::Announce { channel_ids }
+            }
+            2 => {
+                let channel_id = read_str(cursor)?;
+                let speaker_id = read_str(cursor)?;
+                let sequence_number = cursor.read_u32::<BigEndian>()?;
+                let timestamp = cursor.read_u64::<BigEndian>()?;
+                let count = cursor.read_u8()?;
+                let mut layers = BTreeMap::new();
+                for _ in 0..count {
+                    let layer = cursor.read_u8()?;
+                    let len = cursor.read_u16::<BigEndian>()? as usize;
+                    let mut payload = vec![0u8; len];
+                    cursor.read_exact(&mut payload)?;
+                    layers.insert(layer, payload);
+                }
+                RelayMessage::Frame(RelayFrame {
+                    channel_id,
+                    speaker_id,
+                    sequence_number,
+                    timestamp,
+                    layers,
+                })
+            }
+            3 => RelayMessage::AuthorizeRequest {
+                request_id: cursor.read_u64::<BigEndian>()?,
+                channel_id: read_str(cursor)?,
+                user_id: read_str(cursor)?,
+            },
+            4 => {
+                let request_id = cursor.read_u64::<BigEndian>()?;
+                let decision = cursor.read_u8()?;
+                RelayMessage::AuthorizeResponse {
+                    request_id,
+                    decision: Decision::from_u8(decision).ok_or(RelayError::UnknownKind(kind))?,
+                }
+            }
+            other => return Err(RelayError::UnknownKind(other)),
+        };
+        Ok(message)
+    }
+}
+
+fn write_str(buf: &mut Vec<u8>, value: &str) -> std::io::Result<()> {
+    let bytes = &value.as_bytes()[..value.len().min(u8::MAX as usize)];
+    buf.write_u8(bytes.len() as u8)?;
+    buf.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn read_str(cursor: &mut Cursor<&[u8]>) -> Result<String, RelayError> {
+    let len = cursor.read_u8()? as usize;
+    let mut bytes = vec![0u8; len];
+    cursor.read_exact(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Signed envelope:
+/// magic (4) + version (1) + kind (1) + sent at ms (8) + node id (1 + n) + body + HMAC-SHA256 (32)
+fn seal(key: &hmac::Key, node_id: &str, message: &RelayMessage) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(128);
+    buf.extend_from_slice(MAGIC);
+    buf.write_u8(VERSION)?;
+    buf.write_u8(message.kind())?;
+    buf.write_u64::<BigEndian>(now_ms())?;
+    write_str(&mut buf, node_id)?;
+    message.write_body(&mut buf)?;
+    let tag = hmac::sign(key, &buf);
+    buf.extend_from_slice(tag.as_ref());
+    Ok(buf)
+}
+
+/// Verify an envelope, returning the sender's node ID and the message
+fn open(
+    key: &hmac::Key,
+    data: &[u8],
+    max_clock_skew: Duration,
+) -> Result<(String, RelayMessage), RelayError> {
+    if data.len() < MAGIC.len() + 2 + 8 + 1 + TAG_LEN {
+        return Err(RelayError::Truncated);
+    }
+    if &data[..4] != MAGIC {
+        return Err(RelayError::BadMagic);
+    }
+    let (signed, tag) = data.split_at(data.len() - TAG_LEN);
+    hmac::verify(key, signed, tag).map_err(|_| RelayError::BadSignature)?;
+
+    let mut cursor = Cursor::new(signed);
+    cursor.set_position(4);
+    let version = cursor.read_u8().map_err(|_| RelayError::Truncated)?;
+    if version != VERSION {
+        return Err(RelayError::UnsupportedVersion(version));
+    }
+    let kind = cursor.read_u8().map_err(|_| RelayError::Truncated)?;
+    let sent_at = cursor.read_u64::<BigEndian>().map_err(|_| RelayError::Truncated)?;
+    if now_ms().abs_diff(sent_at) > max_clock_skew.as_millis() as u64 {
+        return Err(RelayError::Stale);
+    }
+    let node_id = read_str(&mut cursor).map_err(|_| RelayError::Truncated)?;
+    let message = RelayMessage::read_body(kind, &mut cursor).map_err(|e| match e {
+        RelayError::Io(_) => RelayError::Truncated,
+        other => other,
+    })?;
+    Ok((node_id, message))
+}
+
+/// Relay state of one peer
+#[derive(Debug)]
+struct PeerState {
+    addr: SocketAddr,
+    /// Channels the peer has members in, with the time of the last announcement
+    subscriptions: HashMap<String, Instant>,
+    last_seen: Option<Instant>,
+    frames_sent: u64,
+    frames_received: u64,
+}
+
+impl PeerState {
+    fn is_subscribed(&self, channel_id: &str, ttl: Duration) -> bool {
+        self.subscriptions
+            .get(channel_id)
+            .is_some_and(|announced_at| announced_at.elapsed() < ttl)
+    }
+}
+
+/// Per-peer relay statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayPeerStats {
+    pub node_id: String,
+    pub reachable: bool,
+    pub subscribed_channels: usize,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+}
+
+/// Relay statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayStats {
+    pub node_id: String,
+    pub peers: Vec<RelayPeerStats>,
+    /// Datagrams dropped for a bad signature, unknown sender or stale timestamp
+    pub rejected: u64,
+    /// Relayed frames dropped because local fan-out fell behind
+    pub dropped_frames: u64,
+}
+
+/// Cascading relay between backend instances.
+///
+/// Every instance sends each frame of its local speakers once to each peer
+/// with members in the channel; peers fan it out to their own listeners and
+/// never relay it further. Membership is decided by the instance whose
+/// channel state holds the channel.
+pub struct Relay {
+    config: RelayConfig,
+    key: hmac::Key,
+    socket: Arc<UdpSocket>,
+    auth: Arc<AudioAuth>,
+    peers: Mutex<HashMap<String, PeerState>>,
+    /// Channel ID -> node ID of the instance owning it
+    owners: Mutex<HashMap<String, String>>,
+    /// Authorization requests in flight, answered with (node ID, decision)
+    pending: Mutex<HashMap<u64, mpsc::UnboundedSender<(String, Decision)>>>,
+    next_request_id: AtomicU64,
+    frames_tx: mpsc::Sender<RelayFrame>,
+    rejected: AtomicU64,
+    dropped_frames: AtomicU64,
+}
+
+impl Relay {
+    /// Bind the relay socket and start receiving from peers.
+    /// Returns the relay and the frames of remote speakers to fan out locally.
+    pub async fn bind(
+        config: RelayConfig,
+        auth: Arc<AudioAuth>,
+    ) -> Result<(Arc<Self>, mpsc::Receiver<RelayFrame>), RelayError> {
+        let socket = Arc::new(UdpSocket::bind(&config.bind_addr).await?);
+        info!("Relay {} listening on {} with {} peers", config.node_id, socket.local_addr()?, config.peers.len());
+
+        let (frames_tx, frames_rx) = mpsc::channel(config.frame_queue_capacity);
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer| {
+                (
+                    peer.node_id.clone(),
+                    PeerState {
+                        addr: peer.addr,
+                        subscriptions: HashMap::new(),
+                        last_seen: None,
+                        frames_sent: 0,
+                        frames_received: 0,
+                    },
+                )
+            })
+            .collect();
+
+        let relay = Arc::new(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, config.secret.as_bytes()),
+            config,
+            socket,
+            auth,
+            peers: Mutex::new(peers),
+            owners: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            next_request_id: AtomicU64::new(1),
+            frames_tx,
+            rejected: AtomicU64::new(0),
+            dropped_frames: AtomicU64::new(0),
+        });
+
+        tokio::spawn(relay.clone().receive_loop());
+        Ok((relay, frames_rx))
+    }
+
+    async fn receive_loop(self: Arc<Self>) {
+        let mut buffer = vec![0u8; 65536];
+        loop {
+            let (len, addr) = match self.socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("Relay socket error: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.handle_datagram(&buffer[..len]).await {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                debug!("Rejected relay datagram from {}: {}", addr, e);
+            }
+        }
+    }
+
+    async fn handle_datagram(&self, data: &[u8]) -> Result<(), RelayError> {
+        let (node_id, message) = open(&self.key, data, self.config.max_clock_skew)?;
+
+        let reply_addr = {
+            let mut peers = self.peers.lock().unwrap();
+            let peer = peers.get_mut(&node_id).ok_or_else(|| RelayError::UnknownPeer(node_id.clone()))?;
+            peer.last_seen = Some(Instant::now());
+            if let RelayMessage::Frame(_) = message {
+                peer.frames_received += 1;
+            }
+            if let RelayMessage::Announce { channel_ids } = &message {
+                let now = Instant::now();
+                peer.subscriptions = channel_ids.iter().map(|channel_id| (channel_id.clone(), now)).collect();
+            }
+            peer.addr
+        };
+
+        match message {
+            RelayMessage::Announce { .. } => {}
+            RelayMessage::Frame(frame) => {
+                if self.frames_tx.try_send(frame).is_err() {
+                    self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            RelayMessage::AuthorizeRequest { request_id, channel_id, user_id } => {
+                let decision = match self.auth.check_channel_membership(&user_id, &channel_id) {
+                    Ok(()) => Decision::Allowed,
+                    Err(AuthError::ChannelNotFound) => Decision::NotFound,
+                    Err(AuthError::UserBanned) => Decision::Banned,
+                    Err(_) => Decision::NotMember,
+                };
+                self.send_to(reply_addr, &RelayMessage::AuthorizeResponse { request_id, decision })
+                    .await?;
+            }
+            RelayMessage::AuthorizeResponse { request_id, decision } => {
+                if let Some(tx) = self.pending.lock().unwrap().get(&request_id) {
+                    let _ = tx.send((node_id, decision));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_to(&self, addr: SocketAddr, message: &RelayMessage) -> Result<(), RelayError> {
+        let data = seal(&self.key, &self.config.node_id, message)?;
+        self.socket.send_to(&data, addr).await?;
+        Ok(())
+    }
+
+    /// Tell every peer which channels have members on this instance
+    pub async fn announce(&self, channel_ids: &HashSet<String>) {
+        let message = RelayMessage::Announce {
+            channel_ids: channel_ids.iter().cloned().collect(),
+        };
+        let addrs: Vec<SocketAddr> = self.peers.lock().unwrap().values().map(|peer| peer.addr).collect();
+        for addr in addrs {
+            if let Err(e) = self.send_to(addr, &message).await {
+                warn!("Failed to announce channels to relay peer {}: {}", addr, e);
+            }
+        }
+    }
+
+    /// Whether any peer has members in a channel
+    pub fn has_subscribers(&self, channel_id: &str) -> bool {
+        let ttl = self.config.subscription_ttl;
+        self.peers.lock().unwrap().values().any(|peer| {
+            peer.is_subscribed(channel_id, ttl)
+        })
+    }
+
+    /// Send a local speaker's frame once to every peer with members in its channel.
+    ///
+    /// Never blocks: the forwarder calls this while holding its locks, so a
+    /// full socket buffer drops the frame for that peer.
+    pub fn forward_frame(&self, frame: RelayFrame) {
+        let ttl = self.config.subscription_ttl;
+        let mut peers = self.peers.lock().unwrap();
+        let mut targets: Vec<&mut PeerState> = peers
+            .values_mut()
+            .filter(|peer| peer.is_subscribed(&frame.channel_id, ttl))
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+
+        let data = match seal(&self.key, &self.config.node_id, &RelayMessage::Frame(frame)) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to encode relay frame: {}", e);
+                return;
+            }
+        };
+        for peer in targets.iter_mut() {
+            match self.socket.try_send_to(&data, peer.addr) {
+                Ok(_) => peer.frames_sent += 1,
+                Err(e) => debug!("Failed to relay frame to {}: {}", peer.addr, e),
+            }
+        }
+    }
+
+    /// Ask the instance owning a channel whether a user may join it.
+    ///
+    /// Used for channels missing from the local channel state. The owner is
+    /// found by asking every peer and remembered for later handshakes.
+    pub async fn authorize(&self, channel_id: &str, user_id: &str) -> Result<(), AuthError> {
+        let known_owner = self.owners.lock().unwrap().get(channel_id).cloned();
+        let targets: Vec<(String, SocketAddr)> = {
+            let peers = self.peers.lock().unwrap();
+            peers
+                .iter()
+                .filter(|(node_id, _)| known_owner.as_ref().is_none_or(|owner| owner == *node_id))
+                .map(|(node_id, peer)| (node_id.clone(), peer.addr))
+                .collect()
+        };
+        if targets.is_empty() {
+            return Err(AuthError::ChannelNotFound);
+        }
+
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.pending.lock().unwrap().insert(request_id, tx);
+
+        let request = RelayMessage::AuthorizeRequest {
+            request_id,
+            channel_id: channel_id.to_string(),
+            user_id: user_id.to_string(),
+        };
+        for (_, addr) in &targets {
+            if let Err(e) = self.send_to(*addr, &request).await {
+                warn!("Failed to send authorization request to {}: {}", addr, e);
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + self.config.authorize_timeout;
+        let mut not_found = 0;
+        let result = loop {
+            let (node_id, decision) = match timeout_at(deadline, rx.recv()).await {
+                Ok(Some(answer)) => answer,
+                Ok(None) | Err(_) => {
+                    warn!("No relay peer authorized {} for channel {} in time", user_id, channel_id);
+                    break Err(AuthError::ChannelNotFound);
+                }
+            };
+            if decision == Decision::NotFound {
+                not_found += 1;
+                if not_found == targets.len() {
+                    // The channel moved or was deleted; ask everyone next time
+                    self.owners.lock().unwrap().remove(channel_id);
+                    break Err(AuthError::ChannelNotFound);
+                }
+                continue;
+            }
+
+            // Only the owner answers anything but NotFound
+            self.owners.lock().unwrap().insert(channel_id.to_string(), node_id);
+            break match decision {
+                Decision::Allowed => Ok(()),
+                Decision::Banned => Err(AuthError::UserBanned),
+                _ => Err(AuthError::NotChannelMember),
+            };
+        };
+        self.pending.lock().unwrap().remove(&request_id);
+        result
+    }
+
+    /// Get relay statistics
+    pub fn get_stats(&self) -> RelayStats {
+        let ttl = self.config.subscription_ttl;
+        let peers = self.peers.lock().unwrap();
+        RelayStats {
+            node_id: self.config.node_id.clone(),
+            peers: peers
+                .iter()
+                .map(|(node_id, peer)| RelayPeerStats {
+                    node_id: node_id.clone(),
+                    reachable: peer.last_seen.is_some_and(|seen| seen.elapsed() < ttl),
+                    subscribed_channels: peer
+                        .subscriptions
+                        .values()
+                        .filter(|announced_at| announced_at.elapsed() < ttl)
+                        .count(),
+                    frames_sent: peer.frames_sent,
+                    frames_received: peer.frames_received,
+                })
+                .collect(),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            dropped_frames: self.dropped_frames.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::channels::{AppState as ChannelAppState, Channel, ChannelPrivacy};
+
+    fn free_addr() -> SocketAddr {
+        std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    fn relay_config(node_id: &str, addr: SocketAddr, peer_id: &str, peer_addr: SocketAddr) -> RelayConfig {
+        RelayConfig {
+            node_id: node_id.to_string(),
+            bind_addr: addr.to_string(),
+            secret: "fleet-secret".to_string(),
+            peers: vec![RelayPeer {
+                node_id: peer_id.to_string(),
+                addr: peer_addr,
+            }],
+            ..RelayConfig::default()
+        }
+    }
+
+    fn channel_state(channel_id: &str, members: &[&str]) -> Arc<ChannelAppState> {
+        let state = ChannelAppState::new();
+        state.channels.lock().unwrap().insert(channel_id.to_string(), Channel {
+            id: channel_id.to_string(),
+            name: "Ops".to_string(),
+            privacy: ChannelPrivacy::Public,
+            owner: members[0].to_string(),
+            moderators: Vec::new(),
+            members: members.iter().map(|member| member.to_string()).collect(),
+            banned_users: Vec::new(),
+            invite_tokens: HashMap::new(),
+            min_bitrate_bps: None,
+            max_bitrate_bps: None,
+            max_participants: None,
+            max_speakers: None,
+            waitlist_enabled: false,
+        });
+        Arc::new(state)
+    }
+
+    #[test]
+    fn test_envelope_roundtrip_and_tampering() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"fleet-secret");
+        let frame = RelayMessage::Frame(RelayFrame {
+            channel_id: "chan1".to_string(),
+            speaker_id: "alice".to_string(),
+            sequence_number: 7,
+            timestamp: 140,
+            layers: BTreeMap::from([(0, vec![0xFC, 1, 2]), (2, vec![0xFC, 3])]),
+        });
+
+        let mut data = seal(&key, "eu-1", &frame).unwrap();
+        let (node_id, opened) = open(&key, &data, Duration::from_secs(10)).unwrap();
+        assert_eq!(node_id, "eu-1");
+        assert_eq!(opened, frame);
+
+        // Wrong secret and flipped payload bits both fail verification
+        let other_key = hmac::Key::new(hmac::HMAC_SHA256, b"other-secret");
+        assert!(matches!(open(&other_key, &data, Duration::from_secs(10)), Err(RelayError::BadSignature)));
+        data[20] ^= 0xFF;
+        assert!(matches!(open(&key, &data, Duration::from_secs(10)), Err(RelayError::BadSignature)));
+    }
+
+    #[test]
+    fn test_parse_peers() {
+        let peers = parse_peers("na-1=127.0.0.1:8081, ap-1=[::1]:9000").unwrap();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].node_id, "na-1");
+        assert_eq!(peers[1].addr, "[::1]:9000".parse().unwrap());
+
+        assert!(parse_peers("na-1").is_err());
+        assert!(parse_peers("na-1=not-an-address").is_err());
+    }
+
+    #[test]
+    fn test_stale_envelope_rejected() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"fleet-secret");
+        let mut data = seal(&key, "eu-1", &RelayMessage::Announce { channel_ids: Vec::new() }).unwrap();
+
+        // Re-sign with a timestamp an hour in the past
+        data.truncate(data.len() - TAG_LEN);
+        data[6..14].copy_from_slice(&(now_ms() - 3_600_000).to_be_bytes());
+        let tag = hmac::sign(&key, &data);
+        data.extend_from_slice(tag.as_ref());
+
+        assert!(matches!(open(&key, &data, Duration::from_secs(10)), Err(RelayError::Stale)));
+    }
+
+    #[tokio::test]
+    async fn test_authorization_and_frames_between_instances() {
+        let (eu_addr, na_addr) = (free_addr(), free_addr());
+        let eu_auth = Arc::new(AudioAuth::new(channel_state("chan1", &["alice", "bob"])));
+        let na_auth = Arc::new(AudioAuth::new(Arc::new(ChannelAppState::new())));
+
+        let (eu, _eu_frames) = Relay::bind(relay_config("eu-1", eu_addr, "na-1", na_addr), eu_auth).await.unwrap();
+        let (na, mut na_frames) = Relay::bind(relay_config("na-1", na_addr, "eu-1", eu_addr), na_auth).await.unwrap();
+
+        // The NA instance does not hold chan1, so EU decides membership
+        assert!(na.authorize("chan1", "bob").await.is_ok());
+        assert!(matches!(na.authorize("chan1", "mallory").await, Err(AuthError::NotChannelMember)));
+        assert!(matches!(na.authorize("chan2", "bob").await, Err(AuthError::ChannelNotFound)));
+
+        // EU only relays once NA has announced listeners in the channel
+        assert!(!eu.has_subscribers("chan1"));
+        na.announce(&HashSet::from(["chan1".to_string()])).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(eu.has_subscribers("chan1"));
+
+        let frame = RelayFrame {
+            channel_id: "chan1".to_string(),
+            speaker_id: "alice".to_string(),
+            sequence_number: 1,
+            timestamp: 20,
+            layers: BTreeMap::from([(0, vec![0xFC, 9])]),
+        };
+        eu.forward_frame(frame.clone());
+        let received = tokio::time::timeout(Duration::from_secs(1), na_frames.recv()).await.unwrap().unwrap();
+        assert_eq!(received, frame);
+        assert_eq!(eu.get_stats().peers[0].frames_sent, 1);
+        assert_eq!(na.get_stats().peers[0].frames_received, 1);
+    }
+}
@@ -1,17 +1,27 @@
 use crate::audio::{
-    AudioAuth, AudioPacket, PacketType, AudioStateManager, AudioSession,
-    packet::{PacketError, PacketHeader, HandshakeData, VoicePacket},
+    AudioAuth, AudioPacket, PacketType, AudioStateManager,
+    packet::{VoicePacket, BitrateHint},
     auth::AuthError,
-    state::{AudioUserState, ChannelState, Role},
+    congestion::{CongestionController, CongestionConfig, ChannelBitrateLimits},
+    simulcast::{LayerSelector, SimulcastConfig},
+    tap::{MediaTap, TapEvent, TapId, TapRegistry, VoiceFrame},
+    relay::{Relay, RelayConfig, RelayFrame},
+    sessions::VoiceSessions,
+    capacity::SPEAKER_HOLD,
+    abuse::{AbuseConfig, AbuseGuard},
+    state::StateError,
 };
-use crate::routes::channels::AppState as ChannelAppState;
-use std::collections::{HashMap, VecDeque};
+use crate::events::ChannelEvent;
+use crate::metrics::Metrics;
+use crate::routes::channels::{AppState as ChannelAppState, Role};
+use crate::tokens::TokenRef;
+use std::collections::{btree_map::Entry, BTreeMap, HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
-use tokio::sync::mpsc;
-use tokio::time::{interval, timeout};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::interval;
 use tracing::{debug, error, info, warn};
 
 /// Audio server configuration
@@ -27,7 +37,56 @@ pub struct AudioServerConfig {
     pub jitter_buffer_size: usize,
     pub jitter_buffer_window_ms: u64,
     pub frame_interval_ms: u64,
-    pub jwt_secret: String,
+    pub bitrate_hint_interval: Duration,
+    pub congestion: CongestionConfig,
+    pub simulcast: SimulcastConfig,
+    /// Queue length of each media tap before frames are dropped
+    pub tap_queue_capacity: usize,
+    /// Relay to other backend instances; `None` runs standalone
+    pub relay: Option<RelayConfig>,
+    /// Rate limits and automatic blocking of abusive addresses
+    pub abuse: AbuseConfig,
+}
+
+/// Settings that may change while the server runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LiveSettings {
+    pub user_timeout: Duration,
+    pub handshake_timeout: Duration,
+    /// Applied to jitter buffers created after a change
+    pub jitter_buffer_size: usize,
+    pub jitter_buffer_window_ms: u64,
+}
+
+impl LiveSettings {
+    pub fn from_config(config: &AudioServerConfig) -> Self {
+        Self {
+            user_timeout: config.user_timeout,
+            handshake_timeout: config.handshake_timeout,
+            jitter_buffer_size: config.jitter_buffer_size,
+            jitter_buffer_window_ms: config.jitter_buffer_window_ms,
+        }
+    }
+
+    pub(super) fn jitter_buffer(&self) -> JitterBuffer {
+        JitterBuffer::new(self.jitter_buffer_size, self.jitter_buffer_window_ms)
+    }
+}
+
+/// Applies reloaded configuration to a running server
+#[derive(Clone)]
+pub struct ReloadHandle {
+    live: Arc<RwLock<LiveSettings>>,
+    abuse: Arc<AbuseGuard>,
+}
+
+impl ReloadHandle {
+    /// Take on the timeouts, jitter settings and rate limits of `config`;
+    /// everything else needs a restart
+    pub fn apply(&self, config: &AudioServerConfig) {
+        *self.live.write().unwrap() = LiveSettings::from_config(config);
+        self.abuse.set_limits(&config.abuse);
+    }
 }
 
 /// Pending handshake information
@@ -39,17 +98,19 @@ struct PendingHandshake {
 }
 
 /// Jitter buffer entry for reordering packets
+///
+/// One entry per frame; simulcast senders contribute one payload per layer.
 #[derive(Debug, Clone)]
 struct JitterBufferEntry {
     sequence_number: u32,
     timestamp: u64,
-    payload: Vec<u8>,
+    layers: BTreeMap<u8, Vec<u8>>, // layer -> Opus payload
     received_at: Instant,
 }
 
 /// Jitter buffer for a single user
 #[derive(Debug)]
-struct JitterBuffer {
+pub(super) struct JitterBuffer {
     entries: VecDeque<JitterBufferEntry>,
     last_played_sequence: u32,
     max_size: usize,
@@ -57,7 +118,7 @@ struct JitterBuffer {
 }
 
 impl JitterBuffer {
-    fn new(max_size: usize, window_ms: u64) -> Self {
+    pub(super) fn new(max_size: usize, window_ms: u64) -> Self {
         Self {
             entries: VecDeque::with_capacity(max_size),
             last_played_sequence: 0,
@@ -87,7 +148,18 @@ impl JitterBuffer {
         // Insert in sequence order
         let insert_pos = self.entries.binary_search_by(|e| e.sequence_number.cmp(&entry.sequence_number));
         match insert_pos {
-            Ok(_) => false, // Duplicate sequence
+            Ok(pos) => {
+                // Another layer of a buffered frame, unless it is a duplicate
+                let existing = &mut self.entries[pos];
+                let mut added = false;
+                for (layer, payload) in entry.layers {
+                    if let Entry::Vacant(slot) = existing.layers.entry(layer) {
+                        slot.insert(payload);
+                        added = true;
+                    }
+                }
+                added
+            }
             Err(pos) => {
                 self.entries.insert(pos, entry);
                 true
@@ -138,6 +210,18 @@ pub struct VoiceConnectionState {
     pub last_active: Instant,
     pub channel_id: String,
     pub user_id: String,
+    /// Whether the sender has been uploading simulcast layers
+    pub simulcast: bool,
+    /// Unix time of the handshake
+    pub connected_at: u64,
+    /// Voice from this connection is dropped on arrival
+    pub server_muted: bool,
+    /// Nothing is forwarded to this connection
+    pub server_deafened: bool,
+    /// When the last voice frame from this connection was accepted
+    pub last_voice: Option<Instant>,
+    /// Token the handshake was made with
+    pub token: TokenRef,
 }
 
 impl Default for AudioServerConfig {
@@ -153,41 +237,16 @@ impl Default for AudioServerConfig {
             jitter_buffer_size: 20, // 20 entries (400ms at 20ms frames)
             jitter_buffer_window_ms: 400, // 400ms window
             frame_interval_ms: 20, // 20ms frame interval
-            jwt_secret: "your-secret-key".to_string(),
+            bitrate_hint_interval: Duration::from_secs(2),
+            congestion: CongestionConfig::default(),
+            simulcast: SimulcastConfig::default(),
+            tap_queue_capacity: 256, // ~5s of one speaker
+            relay: None,
+            abuse: AbuseConfig::default(),
         }
     }
 }
 
-/// Audio server event
-#[derive(Debug)]
-pub enum AudioServerEvent {
-    UserJoined {
-        user_id: String,
-        channel_id: String,
-        socket_addr: SocketAddr,
-    },
-    UserLeft {
-        user_id: String,
-        channel_id: String,
-        socket_addr: SocketAddr,
-    },
-    UserMuted {
-        user_id: String,
-        channel_id: String,
-        muted: bool,
-    },
-    AudioPacket {
-        from_user_id: String,
-        channel_id: String,
-        sequence: u32,
-        data: Vec<u8>,
-    },
-    Error {
-        socket_addr: SocketAddr,
-        error: String,
-    },
-}
-
 /// UDP Audio Streaming Server
 pub struct AudioServer {
     config: AudioServerConfig,
@@ -195,20 +254,60 @@ pub struct AudioServer {
     state_manager: Arc<AudioStateManager>,
     channel_state: Arc<ChannelAppState>,
     socket: Option<Arc<UdpSocket>>,
-    event_tx: Option<mpsc::UnboundedSender<AudioServerEvent>>,
-    event_rx: Option<mpsc::UnboundedReceiver<AudioServerEvent>>,
     pending_handshakes: Arc<Mutex<HashMap<SocketAddr, PendingHandshake>>>,
     voice_connections: Arc<Mutex<HashMap<SocketAddr, VoiceConnectionState>>>,
     jitter_buffers: Arc<Mutex<HashMap<String, JitterBuffer>>>,
+    congestion: Arc<CongestionController>,
+    layer_selector: Arc<LayerSelector>,
+    taps: Arc<TapRegistry>,
+    relay: Option<Arc<Relay>>,
+    metrics: Arc<Metrics>,
+    sessions: VoiceSessions,
+    abuse: Arc<AbuseGuard>,
+    live: Arc<RwLock<LiveSettings>>,
+}
+
+/// Bind a UDP socket with `buffer_size` byte kernel buffers; tokio's sockets
+/// can't resize them once bound
+async fn bind_udp(bind_addr: &str, buffer_size: usize) -> std::io::Result<UdpSocket> {
+    let addr = tokio::net::lookup_host(bind_addr)
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("No address for {}", bind_addr)))?;
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket2::Type::DGRAM, Some(socket2::Protocol::UDP))?;
+    socket.set_recv_buffer_size(buffer_size)?;
+    socket.set_send_buffer_size(buffer_size)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
 }
 
 impl AudioServer {
     /// Create a new audio server
     pub fn new(config: AudioServerConfig, channel_state: Arc<ChannelAppState>) -> Self {
-        let auth = Arc::new(AudioAuth::new(config.jwt_secret.clone(), channel_state.clone()));
+        let auth = Arc::new(AudioAuth::new(channel_state.clone()));
         let state_manager = Arc::new(AudioStateManager::new());
+        let congestion = Arc::new(CongestionController::new(config.congestion.clone()));
+        let layer_selector = Arc::new(LayerSelector::new(config.simulcast.clone()));
+        let taps = Arc::new(TapRegistry::new(config.tap_queue_capacity));
+        let abuse = Arc::new(AbuseGuard::new(config.abuse.clone()));
+        let live = Arc::new(RwLock::new(LiveSettings::from_config(&config)));
         
-        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let voice_connections = Arc::new(Mutex::new(HashMap::new()));
+        let jitter_buffers = Arc::new(Mutex::new(HashMap::new()));
+        let sessions = VoiceSessions {
+            voice_connections: voice_connections.clone(),
+            jitter_buffers: jitter_buffers.clone(),
+            state_manager: state_manager.clone(),
+            auth: auth.clone(),
+            congestion: congestion.clone(),
+            taps: taps.clone(),
+            channel_state: channel_state.clone(),
+            layer_selector: layer_selector.clone(),
+            socket: Arc::new(OnceLock::new()),
+            relay: Arc::new(OnceLock::new()),
+            live: live.clone(),
+        };
 
         Self {
             config,
@@ -216,25 +315,312 @@ impl AudioServer {
             state_manager,
             channel_state,
             socket: None,
-            event_tx: Some(event_tx),
-            event_rx: Some(event_rx),
             pending_handshakes: Arc::new(Mutex::new(HashMap::new())),
-            voice_connections: Arc::new(Mutex::new(HashMap::new())),
-            jitter_buffers: Arc::new(Mutex::new(HashMap::new())),
+            voice_connections,
+            jitter_buffers,
+            congestion,
+            layer_selector,
+            taps,
+            relay: None,
+            metrics: Arc::new(Metrics::new()),
+            sessions,
+            abuse,
+            live,
         }
     }
 
+    /// Report into `metrics` instead of a private registry
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Handle for inspecting and controlling live voice sessions
+    pub fn sessions(&self) -> VoiceSessions {
+        self.sessions.clone()
+    }
+
+    /// Rate limits and block list of the UDP socket
+    pub fn abuse_guard(&self) -> Arc<AbuseGuard> {
+        self.abuse.clone()
+    }
+
+    /// Handle for applying reloaded configuration while the server runs
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle {
+            live: self.live.clone(),
+            abuse: self.abuse.clone(),
+        }
+    }
+
+    /// Register an in-process consumer of forwarded voice frames
+    pub fn register_tap(&self, name: &str, tap: Arc<dyn MediaTap>) -> TapId {
+        self.taps.register(name, tap)
+    }
+
+    /// Unregister a media tap
+    #[allow(dead_code)] // for in-process consumers; the recorder stays registered for the server's lifetime
+    pub fn unregister_tap(&self, id: TapId) -> bool {
+        self.taps.unregister(id)
+    }
+
+    /// Channels with at least one local voice connection
+    fn local_channels(voice_connections: &Mutex<HashMap<SocketAddr, VoiceConnectionState>>) -> HashSet<String> {
+        voice_connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|conn| conn.channel_id.clone())
+            .collect()
+    }
+
+    /// Drop voice connections that have been silent longer than `user_timeout`,
+    /// telling taps the speaker left. Returns the users of dropped connections.
+    fn expire_idle_connections(
+        voice_connections: &Mutex<HashMap<SocketAddr, VoiceConnectionState>>,
+        taps: &TapRegistry,
+        user_timeout: Duration,
+        now: Instant,
+    ) -> Vec<String> {
+        let mut timed_out = Vec::new();
+        voice_connections.lock().unwrap().retain(|addr, conn| {
+            if now.duration_since(conn.last_active) > user_timeout {
+                debug!("Voice connection {} of {} timed out", addr, conn.user_id);
+                taps.publish_event(TapEvent::SpeakerLeft {
+                    channel_id: conn.channel_id.clone(),
+                    user_id: conn.user_id.clone(),
+                });
+                timed_out.push(conn.user_id.clone());
+                false
+            } else {
+                true
+            }
+        });
+        timed_out
+    }
+
+    /// Users other than `user_id` connected to a channel's voice
+    fn other_participants(
+        vc_map: &HashMap<SocketAddr, VoiceConnectionState>,
+        channel_id: &str,
+        user_id: &str,
+    ) -> HashSet<String> {
+        vc_map
+            .values()
+            .filter(|conn| conn.channel_id == channel_id && conn.user_id != user_id)
+            .map(|conn| conn.user_id.clone())
+            .collect()
+    }
+
+    /// Whether the sender at `addr` may be heard under its channel's speaker cap.
+    ///
+    /// Users already speaking keep their turn; owners and moderators are
+    /// never held back but do count towards the cap.
+    fn may_speak(
+        vc_map: &HashMap<SocketAddr, VoiceConnectionState>,
+        addr: SocketAddr,
+        channel_state: &ChannelAppState,
+    ) -> bool {
+        let sender = match vc_map.get(&addr) {
+            Some(sender) => sender,
+            None => return true,
+        };
+        let now = Instant::now();
+        let speaking = |conn: &VoiceConnectionState| {
+            conn.last_voice.is_some_and(|at| now.duration_since(at) < SPEAKER_HOLD)
+        };
+        if speaking(sender) {
+            return true;
+        }
+
+        // Only the first frame of a talk spurt looks up the channel
+        let max_speakers = match channel_state.channels.lock().unwrap().get(&sender.channel_id) {
+            Some(channel) if !channel.bypasses_limits(&sender.user_id) => channel.max_speakers,
+            _ => None,
+        };
+        let max_speakers = match max_speakers {
+            Some(max_speakers) => max_speakers,
+            None => return true,
+        };
+
+        let speakers: HashSet<&str> = vc_map
+            .values()
+            .filter(|conn| conn.channel_id == sender.channel_id && conn.user_id != sender.user_id && speaking(conn))
+            .map(|conn| conn.user_id.as_str())
+            .collect();
+        speakers.len() < max_speakers as usize
+    }
+
+    /// Apply moderation, membership and role changes to live connections
+    fn start_channel_event_task(&self) {
+        let mut events = self.channel_state.events.subscribe();
+        let voice_connections = self.voice_connections.clone();
+        let jitter_buffers = self.jitter_buffers.clone();
+        let state_manager = self.state_manager.clone();
+        let sessions = self.sessions.clone();
+        let live = self.live.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Audio server skipped {} channel events", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let (channel_id, user_id, server_muted, server_deafened) = match event {
+                    ChannelEvent::VoiceModerationChanged { channel_id, user_id, server_muted, server_deafened, .. } => {
+                        (channel_id, user_id, server_muted, server_deafened)
+                    }
+                    ChannelEvent::MemberRemoved { channel_id, user_id, reason, note, .. } => {
+                        // The client learns why from the error packet
+                        let message = reason.message(note.as_deref());
+                        sessions.remove_from_channel(&user_id, &channel_id, &message).await;
+                        continue;
+                    }
+                    ChannelEvent::RoleChanged { channel_id, user_id, role, .. } => {
+                        if state_manager.set_user_role(&user_id, &channel_id, role.clone()) {
+                            info!("Voice role of {} in {} is now {}", user_id, channel_id, role.as_str());
+                        }
+                        continue;
+                    }
+                    ChannelEvent::RecordingStarted { .. } | ChannelEvent::RecordingStopped { .. } => continue,
+                };
+
+                let mut applied = false;
+                for conn in voice_connections.lock().unwrap().values_mut() {
+                    if conn.user_id == user_id && conn.channel_id == channel_id {
+                        conn.server_muted = server_muted;
+                        conn.server_deafened = server_deafened;
+                        applied = true;
+                    }
+                }
+                if applied && server_muted {
+                    // Frames already buffered would otherwise play out
+                    if let Some(buffer) = jitter_buffers.lock().unwrap().get_mut(&user_id) {
+                        *buffer = live.read().unwrap().jitter_buffer();
+                    }
+                }
+                if applied {
+                    info!(
+                        "Voice of {} in {}: server muted {}, server deafened {}",
+                        user_id, channel_id, server_muted, server_deafened
+                    );
+                }
+            }
+        });
+    }
+
+    /// End live sessions once the token they were opened with is revoked
+    fn start_revocation_task(&self) {
+        let mut revocations = self.channel_state.tokens.subscribe_revocations();
+        let sessions = self.sessions.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let revocation = match revocations.recv().await {
+                    Ok(revocation) => revocation,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Audio server skipped {} token revocations", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                sessions.revoke(&revocation).await;
+            }
+        });
+    }
+
+    /// Announce local channels to relay peers and fan relayed frames out locally
+    fn start_relay_tasks(&self, relay: Arc<Relay>, mut relay_frames: mpsc::Receiver<RelayFrame>, socket: Arc<UdpSocket>) {
+        let voice_connections = self.voice_connections.clone();
+        let announce_relay = relay.clone();
+        let announce_interval = self.config.relay.as_ref().map(|config| config.announce_interval).unwrap_or(Duration::from_secs(1));
+
+        tokio::spawn(async move {
+            let mut interval = interval(announce_interval);
+            loop {
+                interval.tick().await;
+                let channels = Self::local_channels(&voice_connections);
+                announce_relay.announce(&channels).await;
+            }
+        });
+
+        let voice_connections = self.voice_connections.clone();
+        let congestion = self.congestion.clone();
+        let layer_selector = self.layer_selector.clone();
+        let taps = self.taps.clone();
+        let metrics = self.metrics.clone();
+
+        tokio::spawn(async move {
+            while let Some(frame) = relay_frames.recv().await {
+                // Remote speakers are never local, so every member listens
+                let listeners: Vec<(SocketAddr, String)> = voice_connections
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|(_, conn)| conn.channel_id == frame.channel_id && !conn.server_deafened)
+                    .map(|(addr, conn)| (*addr, conn.user_id.clone()))
+                    .collect();
+                if listeners.is_empty() {
+                    continue;
+                }
+
+                if taps.wants_channel(&frame.channel_id) {
+                    if let Some((&layer, payload)) = frame.layers.iter().next() {
+                        taps.publish_frame(VoiceFrame {
+                            channel_id: frame.channel_id.clone(),
+                            speaker_id: frame.speaker_id.clone(),
+                            sequence_number: frame.sequence_number,
+                            timestamp: frame.timestamp,
+                            layer,
+                            payload: Arc::from(payload.as_slice()),
+                            forwarded_at: Instant::now(),
+                        });
+                    }
+                }
+
+                let available: Vec<u8> = frame.layers.keys().copied().collect();
+                for (addr, listener_id) in listeners {
+                    let estimate = congestion.estimate(&listener_id);
+                    let layer = match layer_selector.select(&listener_id, &frame.speaker_id, &available, estimate.as_ref()) {
+                        Some(layer) => layer,
+                        None => continue,
+                    };
+                    let voice_packet = VoicePacket::voice(frame.sequence_number, frame.timestamp, frame.layers[&layer].clone());
+                    match socket.send_to(&voice_packet.to_bytes(), addr).await {
+                        Ok(_) => metrics.packet_forwarded("relay_in"),
+                        Err(e) => {
+                            warn!("Failed to forward relayed voice packet to {}: {}", addr, e);
+                            metrics.packet_dropped("send_failed");
+                        }
+                    }
+                }
+            }
+        });
+    }
+
     /// Start the audio server
     pub async fn start(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         info!("Starting UDP audio server on {}", self.config.bind_addr);
 
         // Bind UDP socket
-        let socket = UdpSocket::bind(&self.config.bind_addr).await?;
-        socket.set_recv_buffer_size(self.config.buffer_size)?;
-        socket.set_send_buffer_size(self.config.buffer_size)?;
+        let socket = bind_udp(&self.config.bind_addr, self.config.buffer_size).await?;
         
         self.socket = Some(Arc::new(socket));
         let socket = self.socket.as_ref().unwrap().clone();
+        let _ = self.sessions.socket.set(socket.clone());
+        self.start_channel_event_task();
+        self.start_revocation_task();
+
+        if let Some(relay_config) = self.config.relay.clone() {
+            let (relay, relay_frames) = Relay::bind(relay_config, self.auth.clone()).await?;
+            self.start_relay_tasks(relay.clone(), relay_frames, socket.clone());
+            let _ = self.sessions.relay.set(relay.clone());
+            self.relay = Some(relay);
+        }
 
         // Start background tasks
         let auth = self.auth.clone();
@@ -242,9 +628,14 @@ impl AudioServer {
         let pending_handshakes = self.pending_handshakes.clone();
         let voice_connections = self.voice_connections.clone();
         let jitter_buffers = self.jitter_buffers.clone();
+        let jitter_buffers_cleanup = jitter_buffers.clone();
+        let voice_connections_cleanup = voice_connections.clone();
+        let congestion = self.congestion.clone();
+        let layer_selector = self.layer_selector.clone();
+        let taps_cleanup = self.taps.clone();
+        let abuse_cleanup = self.abuse.clone();
         let cleanup_interval = self.config.cleanup_interval;
-        let user_timeout = self.config.user_timeout;
-        let handshake_timeout = self.config.handshake_timeout;
+        let live_cleanup = self.live.clone();
         let frame_interval = Duration::from_millis(self.config.frame_interval_ms);
 
         // Cleanup task
@@ -255,6 +646,8 @@ impl AudioServer {
                 
                 // Clean up expired sessions
                 auth.cleanup_expired_sessions();
+                abuse_cleanup.cleanup(cleanup_interval);
+                let LiveSettings { user_timeout, handshake_timeout, .. } = *live_cleanup.read().unwrap();
                 
                 // Clean up expired users
                 let removed_users = state_manager.cleanup();
@@ -275,11 +668,87 @@ impl AudioServer {
                 });
 
                 // Clean up old jitter buffers
-                let mut buffers = jitter_buffers.lock().unwrap();
-                buffers.retain(|user_id, buffer| {
+                let mut buffers = jitter_buffers_cleanup.lock().unwrap();
+                buffers.retain(|_, buffer| {
                     buffer.cleanup(500); // 500ms max age
                     !buffer.is_empty() || buffer.last_played_sequence > 0
                 });
+                drop(buffers);
+
+                let timed_out = Self::expire_idle_connections(&voice_connections_cleanup, &taps_cleanup, user_timeout, now);
+
+                // Forget congestion state and layer selections of departed users
+                let connections = voice_connections_cleanup.lock().unwrap();
+                let user_ids: HashSet<String> = connections.values().map(|c| c.user_id.clone()).collect();
+                let channel_ids: HashSet<String> = connections.values().map(|c| c.channel_id.clone()).collect();
+                drop(connections);
+                congestion.retain(&user_ids, &channel_ids);
+                for user_id in timed_out.iter().filter(|user_id| !user_ids.contains(*user_id)) {
+                    layer_selector.remove_user(user_id);
+                }
+            }
+        });
+
+        // Bitrate hint task
+        let voice_connections_hint = voice_connections.clone();
+        let channel_state_hint = self.channel_state.clone();
+        let congestion_hint = self.congestion.clone();
+        let socket_hint = socket.clone();
+        let hint_interval = self.config.bitrate_hint_interval;
+        let auth_hint = self.auth.clone();
+        let metrics_hint = self.metrics.clone();
+
+        tokio::spawn(async move {
+            let mut interval = interval(hint_interval);
+            loop {
+                interval.tick().await;
+
+                // Group live connections by channel
+                let mut channels: HashMap<String, Vec<(SocketAddr, String, bool)>> = HashMap::new();
+                for (addr, conn) in voice_connections_hint.lock().unwrap().iter() {
+                    channels
+                        .entry(conn.channel_id.clone())
+                        .or_default()
+                        .push((*addr, conn.user_id.clone(), conn.simulcast));
+                }
+
+                // Gauges are refreshed with the hints rather than on every change
+                metrics_hint.voice_sessions.set(auth_hint.session_count() as i64);
+                metrics_hint.set_voice_channel_users(channels.iter().map(|(channel_id, members)| (channel_id.as_str(), members.len())));
+
+                // Refresh cached channel limits so the forwarder never touches channel state
+                {
+                    let channel_configs = channel_state_hint.channels.lock().unwrap();
+                    for channel_id in channels.keys() {
+                        if let Some(channel) = channel_configs.get(channel_id) {
+                            congestion_hint.set_channel_limits(channel_id, ChannelBitrateLimits::resolve(
+                                channel.min_bitrate_bps,
+                                channel.max_bitrate_bps,
+                                congestion_hint.config(),
+                            ));
+                        }
+                    }
+                }
+
+                for (channel_id, members) in channels {
+                    let listeners: Vec<String> = members.iter().map(|(_, user_id, _)| user_id.clone()).collect();
+                    // Simulcast senders get a top-layer hint sized for the strongest
+                    // listener, since weaker listeners are moved to lower layers
+                    let hint = congestion_hint.next_hint(&channel_id, &listeners);
+                    let simulcast_hint = congestion_hint.next_simulcast_hint(&channel_id, &listeners);
+
+                    // Every member of the channel is a potential sender
+                    for (addr, user_id, simulcast) in members {
+                        let hint = match if simulcast { simulcast_hint } else { hint } {
+                            Some(hint) => hint,
+                            None => continue,
+                        };
+                        debug!("Bitrate hint for {} in channel {}: {} bps (cap {} bps)", user_id, channel_id, hint.target_bps, hint.max_bps);
+                        if let Err(e) = Self::send_bitrate_hint(&socket_hint, addr, &user_id, &channel_id, hint).await {
+                            warn!("Failed to send bitrate hint to {}: {}", addr, e);
+                        }
+                    }
+                }
             }
         });
 
@@ -287,12 +756,20 @@ impl AudioServer {
         let voice_connections_jb = voice_connections.clone();
         let jitter_buffers_jb = jitter_buffers.clone();
         let socket_jb = socket.clone();
+        let congestion_jb = self.congestion.clone();
+        let layer_selector_jb = self.layer_selector.clone();
+        let taps_jb = self.taps.clone();
+        let relay_jb = self.relay.clone();
+        let metrics_jb = self.metrics.clone();
         
         tokio::spawn(async move {
             let mut interval = interval(frame_interval);
             loop {
                 interval.tick().await;
-                
+
+                // Packets go out once the locks are released
+                let mut outgoing: Vec<(Vec<u8>, SocketAddr)> = Vec::new();
+                {
                 let mut buffers = jitter_buffers_jb.lock().unwrap();
                 let connections = voice_connections_jb.lock().unwrap();
                 
@@ -306,26 +783,70 @@ impl AudioServer {
                     if let Some(channel_id) = user_channel {
                         // Get next in-order packet
                         if let Some(entry) = buffer.pop_next() {
-                            // Create voice packet for forwarding
-                            let voice_packet = VoicePacket {
-                                packet_type: VoicePacket::VOICE_PACKET_TYPE,
-                                sequence_number: entry.sequence_number,
-                                timestamp: entry.timestamp,
-                                payload: entry.payload,
-                            };
-                            let packet_data = voice_packet.to_bytes();
-                            
-                            // Forward to all other users in the same channel
+                            let available: Vec<u8> = entry.layers.keys().copied().collect();
+
+                            // Taps receive the best layer the sender uploaded
+                            if taps_jb.wants_channel(channel_id) {
+                                if let Some((&layer, payload)) = entry.layers.iter().next() {
+                                    taps_jb.publish_frame(VoiceFrame {
+                                        channel_id: channel_id.clone(),
+                                        speaker_id: user_id.clone(),
+                                        sequence_number: entry.sequence_number,
+                                        timestamp: entry.timestamp,
+                                        layer,
+                                        payload: Arc::from(payload.as_slice()),
+                                        forwarded_at: Instant::now(),
+                                    });
+                                }
+                            }
+
+                            // Peers with members in the channel get every layer once
+                            if let Some(relay) = &relay_jb {
+                                if relay.has_subscribers(channel_id) {
+                                    relay.forward_frame(RelayFrame {
+                                        channel_id: channel_id.clone(),
+                                        speaker_id: user_id.clone(),
+                                        sequence_number: entry.sequence_number,
+                                        timestamp: entry.timestamp,
+                                        layers: entry.layers.clone(),
+                                    });
+                                    metrics_jb.packet_forwarded("relay_out");
+                                }
+                            }
+
+                            // Forward to all other users in the same channel, each
+                            // receiving the layer their path can carry
                             for (other_addr, other_conn) in connections.iter() {
-                                if other_conn.channel_id == *channel_id && other_conn.user_id != *user_id {
-                                    if let Err(e) = socket_jb.send_to(&packet_data, *other_addr).await {
-                                        warn!("Failed to forward voice packet to {}: {}", other_addr, e);
-                                    }
+                                if other_conn.channel_id == *channel_id && other_conn.user_id != *user_id && !other_conn.server_deafened {
+                                    let estimate = congestion_jb.estimate(&other_conn.user_id);
+                                    let layer = match layer_selector_jb.select(&other_conn.user_id, user_id, &available, estimate.as_ref()) {
+                                        Some(layer) => layer,
+                                        None => continue,
+                                    };
+
+                                    // Listeners always receive plain voice packets
+                                    let voice_packet = VoicePacket::voice(
+                                        entry.sequence_number,
+                                        entry.timestamp,
+                                        entry.layers[&layer].clone(),
+                                    );
+                                    outgoing.push((voice_packet.to_bytes(), *other_addr));
                                 }
                             }
                         }
                     }
                 }
+                }
+
+                for (bytes, addr) in outgoing {
+                    match socket_jb.send_to(&bytes, addr).await {
+                        Ok(_) => metrics_jb.packet_forwarded("local"),
+                        Err(e) => {
+                            warn!("Failed to forward voice packet to {}: {}", addr, e);
+                            metrics_jb.packet_dropped("send_failed");
+                        }
+                    }
+                }
             }
         });
 
@@ -335,78 +856,147 @@ impl AudioServer {
         loop {
             match socket.recv_from(&mut buffer).await {
                 Ok((len, addr)) => {
-                    let packet_data = &buffer[..len];
+                    // Shed blocked and flooding sources before spending a task on them
+                    if let Err(reason) = self.abuse.check_datagram(addr, len) {
+                        self.metrics.packet_dropped(reason);
+                        continue;
+                    }
+                    let packet_data = buffer[..len].to_vec();
                     
                     // Spawn task to handle packet
                     let auth = self.auth.clone();
                     let state_manager = self.state_manager.clone();
                     let channel_state = self.channel_state.clone();
                     let socket = socket.clone();
-                    let event_tx = self.event_tx.as_ref().unwrap().clone();
                     let pending_handshakes = self.pending_handshakes.clone();
                     let voice_connections = voice_connections.clone();
                     let jitter_buffers = jitter_buffers.clone();
+                    let congestion = self.congestion.clone();
+                    let layer_selector = self.layer_selector.clone();
+                    let taps = self.taps.clone();
+                    let relay = self.relay.clone();
+                    let metrics = self.metrics.clone();
+                    let abuse = self.abuse.clone();
+                    let live = self.live.clone();
 
                     tokio::spawn(async move {
-                        // Check for binary Opus packet (VoicePacket)
-                        if !packet_data.is_empty() && packet_data[0] == crate::audio::packet::VoicePacket::VOICE_PACKET_TYPE {
-                            use crate::audio::packet::VoicePacket;
-                            match VoicePacket::from_bytes(packet_data) {
-                                Ok(voice_packet) => {
-                                    // Look up connection state
-                                    let mut vc_map = voice_connections.lock().unwrap();
-                                    if let Some(state) = vc_map.get_mut(&addr) {
-                                        // Insert into jitter buffer instead of direct forwarding
-                                        let mut buffers = jitter_buffers.lock().unwrap();
-                                        let buffer = buffers.entry(state.user_id.clone()).or_insert_with(|| {
-                                            JitterBuffer::new(20, 400) // Use config values
-                                        });
-                                        
-                                        let entry = JitterBufferEntry {
-                                            sequence_number: voice_packet.sequence_number,
-                                            timestamp: voice_packet.timestamp,
-                                            payload: voice_packet.payload,
-                                            received_at: Instant::now(),
-                                        };
-                                        
-                                        if buffer.insert(entry) {
-                                            debug!("Inserted voice packet seq {} from {} into jitter buffer", 
-                                                   voice_packet.sequence_number, state.user_id);
-                                        } else {
-                                            debug!("Dropped voice packet seq {} from {} (duplicate/old)", 
-                                                   voice_packet.sequence_number, state.user_id);
-                                        }
-                                        
-                                        // Update sender state
-                                        state.last_sequence = voice_packet.sequence_number;
-                                        state.last_active = Instant::now();
-                                    } else {
-                                        warn!("Received voice packet from unauthenticated or unknown socket: {}", addr);
+                        // Voice packets share type byte 0x01 with handshakes, so a
+                        // datagram is only voice if it parses as one and comes from
+                        // an authenticated socket; anything else is a control packet
+                        let voice_packet = if !packet_data.is_empty() && VoicePacket::is_voice_type(packet_data[0]) {
+                            let known = voice_connections.lock().unwrap().contains_key(&addr);
+                            match VoicePacket::from_bytes(&packet_data) {
+                                Ok(voice_packet) if known => Some(voice_packet),
+                                Ok(_) => None,
+                                Err(e) => {
+                                    if known {
+                                        warn!("Malformed voice packet from {}: {}", addr, e);
+                                        metrics.packet_received("voice");
+                                        metrics.packet_dropped("malformed");
+                                        return;
                                     }
+                                    None
                                 }
-                                Err(e) => {
-                                    warn!("Malformed voice packet from {}: {}", addr, e);
+                            }
+                        } else {
+                            None
+                        };
+
+                        if let Some(voice_packet) = voice_packet {
+                            metrics.packet_received("voice");
+                            // Look up connection state
+                            let mut vc_map = voice_connections.lock().unwrap();
+                            let may_speak = Self::may_speak(&vc_map, addr, &channel_state);
+                            if let Some(state) = vc_map.get_mut(&addr) {
+                                if let Err(reason) = abuse.check_session(addr, &state.user_id, packet_data.len()) {
+                                    metrics.packet_dropped(reason);
+                                    return;
+                                }
+
+                                if state.server_muted {
+                                    // Still alive, just not heard
+                                    state.last_active = Instant::now();
+                                    metrics.packet_dropped("server_muted");
+                                    return;
+                                }
+
+                                if !may_speak {
+                                    state.last_active = Instant::now();
+                                    metrics.packet_dropped("speaker_cap");
+                                    return;
+                                }
+
+                                // Throttle senders that ignore bitrate hints
+                                if !congestion.allow_frame(&state.user_id, &state.channel_id, voice_packet.layer, voice_packet.payload.len()) {
+                                    debug!("Throttled voice packet seq {} from {} (over channel cap)",
+                                           voice_packet.sequence_number, state.user_id);
+                                    metrics.packet_dropped("throttled");
+                                    return;
                                 }
+
+                                // Insert into jitter buffer instead of direct forwarding
+                                let mut buffers = jitter_buffers.lock().unwrap();
+                                let buffer = buffers
+                                    .entry(state.user_id.clone())
+                                    .or_insert_with(|| live.read().unwrap().jitter_buffer());
+                                
+                                let entry = JitterBufferEntry {
+                                    sequence_number: voice_packet.sequence_number,
+                                    timestamp: voice_packet.timestamp,
+                                    layers: BTreeMap::from([(voice_packet.layer, voice_packet.payload)]),
+                                    received_at: Instant::now(),
+                                };
+                                
+                                if buffer.insert(entry) {
+                                    debug!("Inserted voice packet seq {} layer {} from {} into jitter buffer", 
+                                           voice_packet.sequence_number, voice_packet.layer, state.user_id);
+                                    metrics.jitter_buffer_depth.observe(buffer.len() as f64);
+                                } else {
+                                    debug!("Dropped voice packet seq {} layer {} from {} (duplicate/old)", 
+                                           voice_packet.sequence_number, voice_packet.layer, state.user_id);
+                                    metrics.packet_dropped("late");
+                                }
+                                
+                                if voice_packet.packet_type == VoicePacket::SIMULCAST_PACKET_TYPE {
+                                    state.simulcast = true;
+                                }
+                                
+                                // Update sender state
+                                state.last_sequence = voice_packet.sequence_number;
+                                state.last_active = Instant::now();
+                                state.last_voice = Some(state.last_active);
+                            } else {
+                                warn!("Received voice packet from unauthenticated or unknown socket: {}", addr);
+                                metrics.packet_dropped("unauthenticated");
                             }
                             return;
                         }
                         // Otherwise, handle as control packet
+                        metrics.packet_received("control");
+                        if packet_data.first() == Some(&PacketType::Handshake.to_u8()) {
+                            if let Err(reason) = abuse.check_handshake(addr) {
+                                metrics.packet_dropped(reason);
+                                return;
+                            }
+                        }
                         if let Err(e) = Self::handle_packet(
-                            packet_data,
+                            &packet_data,
                             addr,
                             &auth,
                             &state_manager,
                             &channel_state,
                             &socket,
-                            &event_tx,
                             &pending_handshakes,
+                            &voice_connections,
                             &jitter_buffers,
+                            &congestion,
+                            &layer_selector,
+                            &taps,
+                            &relay,
+                            &metrics,
+                            &live,
                         ).await {
                             error!("Error handling packet from {}: {}", addr, e);
-                            let _ = event_tx.send(AudioServerEvent::Error {
-                                socket_addr: addr,
-                                error: e.to_string(),
-                            });
                         }
                     });
                 }
@@ -418,6 +1008,7 @@ impl AudioServer {
     }
 
     /// Handle incoming packet
+    #[allow(clippy::too_many_arguments)]
     async fn handle_packet(
         data: &[u8],
         addr: SocketAddr,
@@ -425,31 +1016,46 @@ impl AudioServer {
         state_manager: &Arc<AudioStateManager>,
         channel_state: &Arc<ChannelAppState>,
         socket: &Arc<UdpSocket>,
-        event_tx: &mpsc::UnboundedSender<AudioServerEvent>,
         pending_handshakes: &Arc<Mutex<HashMap<SocketAddr, PendingHandshake>>>,
+        voice_connections: &Arc<Mutex<HashMap<SocketAddr, VoiceConnectionState>>>,
         jitter_buffers: &Arc<Mutex<HashMap<String, JitterBuffer>>>,
+        congestion: &Arc<CongestionController>,
+        layer_selector: &Arc<LayerSelector>,
+        taps: &Arc<TapRegistry>,
+        relay: &Option<Arc<Relay>>,
+        metrics: &Arc<Metrics>,
+        live: &Arc<RwLock<LiveSettings>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // Parse packet
-        let packet = AudioPacket::from_bytes(data)?;
+        let packet = match AudioPacket::from_bytes(data) {
+            Ok(packet) => packet,
+            Err(e) => {
+                metrics.packet_dropped("malformed");
+                return Err(e.into());
+            }
+        };
         
         match packet.header.packet_type {
             PacketType::Handshake => {
-                Self::handle_handshake(packet, addr, auth, state_manager, event_tx, pending_handshakes, jitter_buffers).await?;
+                Self::handle_handshake(packet, addr, auth, socket, channel_state, pending_handshakes, voice_connections, jitter_buffers, taps, relay, metrics, live).await?;
             }
             PacketType::Audio => {
-                Self::handle_audio_packet(packet, addr, auth, state_manager, socket, event_tx).await?;
+                Self::handle_audio_packet(packet, addr, auth, state_manager, socket).await?;
             }
             PacketType::JoinChannel => {
-                Self::handle_join_channel(packet, addr, auth, state_manager, channel_state, event_tx).await?;
+                Self::handle_join_channel(packet, addr, auth, state_manager, channel_state).await?;
             }
             PacketType::LeaveChannel => {
-                Self::handle_leave_channel(packet, addr, auth, state_manager, event_tx).await?;
+                Self::handle_leave_channel(packet, addr, auth, state_manager, channel_state, voice_connections, layer_selector, taps).await?;
             }
             PacketType::SetMute => {
-                Self::handle_set_mute(packet, addr, auth, state_manager, event_tx).await?;
+                Self::handle_set_mute(packet, auth, state_manager).await?;
             }
             PacketType::Heartbeat => {
-                Self::handle_heartbeat(packet, addr, auth, state_manager).await?;
+                Self::handle_heartbeat(packet, addr, auth, state_manager, socket).await?;
+            }
+            PacketType::ReceiverReport => {
+                Self::handle_receiver_report(packet, addr, voice_connections, congestion).await?;
             }
             _ => {
                 warn!("Unhandled packet type: {:?}", packet.header.packet_type);
@@ -460,27 +1066,39 @@ impl AudioServer {
     }
 
     /// Handle handshake packet
+    #[allow(clippy::too_many_arguments)]
     async fn handle_handshake(
         packet: AudioPacket,
         addr: SocketAddr,
         auth: &Arc<AudioAuth>,
-        state_manager: &Arc<AudioStateManager>,
-        event_tx: &mpsc::UnboundedSender<AudioServerEvent>,
+        socket: &Arc<UdpSocket>,
+        channel_state: &Arc<ChannelAppState>,
         pending_handshakes: &Arc<Mutex<HashMap<SocketAddr, PendingHandshake>>>,
+        voice_connections: &Arc<Mutex<HashMap<SocketAddr, VoiceConnectionState>>>,
         jitter_buffers: &Arc<Mutex<HashMap<String, JitterBuffer>>>,
+        taps: &Arc<TapRegistry>,
+        relay: &Option<Arc<Relay>>,
+        metrics: &Arc<Metrics>,
+        live: &Arc<RwLock<LiveSettings>>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // Check if this is a new handshake or a retry
-        let mut handshakes = pending_handshakes.lock().unwrap();
-        
-        if let Some(existing_handshake) = handshakes.get(&addr) {
-            // Check if handshake has timed out
-            if Instant::now().duration_since(existing_handshake.started_at) > Duration::from_secs(5) {
-                warn!("Handshake timeout for {}, removing", addr);
-                handshakes.remove(&addr);
-            } else {
-                // Still within timeout, ignore duplicate handshake
-                return Ok(());
+        let resend = {
+            let mut handshakes = pending_handshakes.lock().unwrap();
+            match handshakes.get(&addr) {
+                // Check if handshake has timed out
+                Some(existing_handshake) if Instant::now().duration_since(existing_handshake.started_at) > Duration::from_secs(5) => {
+                    warn!("Handshake timeout for {}, removing", addr);
+                    handshakes.remove(&addr);
+                    None
+                }
+                // Still within timeout: the client lost our ack, send it again
+                Some(existing_handshake) => Some(AudioPacket::ack(&existing_handshake.user_id, &existing_handshake.channel_id, 0)),
+                None => None,
             }
+        };
+        if let Some(ack_packet) = resend {
+            socket.send_to(&ack_packet.to_bytes()?, addr).await?;
+            return Ok(());
         }
 
         // Parse handshake data
@@ -491,11 +1109,23 @@ impl AudioServer {
             // Legacy format - extract channel_id from packet header
             (token, &packet.header.channel_id_str())
         } else {
+            metrics.handshake("malformed");
             return Err("Missing handshake data".into());
         };
 
-        // Authenticate user and verify channel membership
+        // Authenticate user and verify channel membership; channels living on
+        // another instance are checked by that instance over the relay
         let session = match auth.authenticate_with_channel(token, channel_id) {
+            Err(AuthError::ChannelNotFound) if relay.is_some() => {
+                let session = auth.authenticate(token)?;
+                relay.as_ref().unwrap().authorize(channel_id, &session.user_id).await.map(|_| session)
+            }
+            result => result,
+        };
+        if let Err(e) = &session {
+            metrics.handshake(e.as_str());
+        }
+        let session = match session {
             Ok(session) => session,
             Err(AuthError::InvalidToken) => {
                 error!("Invalid JWT token from {}", addr);
@@ -519,34 +1149,59 @@ impl AudioServer {
             }
         };
 
+        // Owners and moderators bypass the limit, everyone else may be waitlisted
+        let occupied = Self::other_participants(&voice_connections.lock().unwrap(), channel_id, &session.user_id).len();
+        if let Err(StateError::ChannelFull { limit, position }) = channel_state.admit_voice(channel_id, &session.user_id, occupied) {
+            metrics.handshake("channel_full");
+            warn!("Channel {} is full ({} participants), turned away {} at position {:?}",
+                  channel_id, limit, session.user_id, position);
+            let error_packet = AudioPacket::channel_full(&session.user_id, channel_id, position);
+            socket.send_to(&error_packet.to_bytes()?, addr).await?;
+            return Err("Channel is full".into());
+        }
+        metrics.handshake("ok");
+
         // Add to pending handshakes
-        handshakes.insert(addr, PendingHandshake {
+        pending_handshakes.lock().unwrap().insert(addr, PendingHandshake {
             user_id: session.user_id.clone(),
             channel_id: channel_id.to_string(),
             started_at: Instant::now(),
         });
-        
-        // Add to voice_connections
-        let mut vc_map = self.voice_connections.lock().unwrap();
-        vc_map.insert(addr, VoiceConnectionState {
+
+        // Add to voice_connections; reconnecting does not shed moderator restrictions
+        let moderation = channel_state.voice_moderation(channel_id, &session.user_id);
+        voice_connections.lock().unwrap().insert(addr, VoiceConnectionState {
             last_sequence: 0,
             last_active: Instant::now(),
             channel_id: channel_id.to_string(),
             user_id: session.user_id.clone(),
+            simulcast: false,
+            connected_at: chrono::Utc::now().timestamp() as u64,
+            server_muted: moderation.server_muted,
+            server_deafened: moderation.server_deafened,
+            last_voice: None,
+            token: session.token.clone(),
         });
-        
+
         // Create jitter buffer for the user
-        let mut buffers = jitter_buffers.lock().unwrap();
-        buffers.insert(session.user_id.clone(), JitterBuffer::new(20, 400));
+        let jitter_buffer = live.read().unwrap().jitter_buffer();
+        jitter_buffers.lock().unwrap().insert(session.user_id.clone(), jitter_buffer);
+
+        // Subscribe to the channel on peers right away instead of at the next announcement
+        if let Some(relay) = relay {
+            relay.announce(&Self::local_channels(voice_connections)).await;
+        }
+
+        taps.publish_event(TapEvent::SpeakerJoined {
+            channel_id: channel_id.to_string(),
+            user_id: session.user_id.clone(),
+        });
 
         info!("User {} authenticated for channel {} from {}", session.user_id, channel_id, addr);
 
-        // Send acknowledgment
+        // Send acknowledgment; clients learn their header user ID from it
         let ack_packet = AudioPacket::ack(&session.user_id, channel_id, 0);
-        let ack_data = ack_packet.to_bytes()?;
-        
-        // Note: We don't have socket here, so we'll need to handle this differently
-        // For now, we'll just log the authentication success
+        socket.send_to(&ack_packet.to_bytes()?, addr).await?;
         
         Ok(())
     }
@@ -558,18 +1213,14 @@ impl AudioServer {
         auth: &Arc<AudioAuth>,
         state_manager: &Arc<AudioStateManager>,
         socket: &Arc<UdpSocket>,
-        event_tx: &mpsc::UnboundedSender<AudioServerEvent>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let user_id = packet.header.user_id_str();
-        let channel_id = packet.header.channel_id_str();
 
-        // Get user session (must be authenticated)
-        let session = auth.get_session(&user_id)?;
+        // Sender must be authenticated
+        auth.get_session(&user_id)?;
         
         // Update user activity
-        if let Some(mut user) = state_manager.get_user_by_socket(&addr) {
-            user.update_activity();
-        }
+        state_manager.touch_user(&addr);
 
         // Get audio data
         let audio_data = packet.audio_data
@@ -579,20 +1230,12 @@ impl AudioServer {
         let targets = state_manager.get_broadcast_targets(&user_id, false);
 
         // Broadcast to all targets
-        for (target_user_id, target_addr) in targets {
+        for (_, target_addr) in targets {
             if let Err(e) = socket.send_to(&audio_data, target_addr).await {
                 warn!("Failed to send audio to {}: {}", target_addr, e);
             }
         }
 
-        // Send event
-        let _ = event_tx.send(AudioServerEvent::AudioPacket {
-            from_user_id: user_id,
-            channel_id,
-            sequence: packet.header.sequence,
-            data: audio_data,
-        });
-
         Ok(())
     }
 
@@ -603,7 +1246,6 @@ impl AudioServer {
         auth: &Arc<AudioAuth>,
         state_manager: &Arc<AudioStateManager>,
         channel_state: &Arc<ChannelAppState>,
-        event_tx: &mpsc::UnboundedSender<AudioServerEvent>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let user_id = packet.header.user_id_str();
         let channel_id = packet.header.channel_id_str();
@@ -638,23 +1280,20 @@ impl AudioServer {
 
         info!("User {} joined audio channel {}", user_id, channel_id);
 
-        // Send event
-        let _ = event_tx.send(AudioServerEvent::UserJoined {
-            user_id,
-            channel_id,
-            socket_addr: addr,
-        });
-
         Ok(())
     }
 
     /// Handle leave channel packet
+    #[allow(clippy::too_many_arguments)]
     async fn handle_leave_channel(
         packet: AudioPacket,
         addr: SocketAddr,
         auth: &Arc<AudioAuth>,
         state_manager: &Arc<AudioStateManager>,
-        event_tx: &mpsc::UnboundedSender<AudioServerEvent>,
+        channel_state: &Arc<ChannelAppState>,
+        voice_connections: &Arc<Mutex<HashMap<SocketAddr, VoiceConnectionState>>>,
+        layer_selector: &Arc<LayerSelector>,
+        taps: &Arc<TapRegistry>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let user_id = packet.header.user_id_str();
         let channel_id = packet.header.channel_id_str();
@@ -665,14 +1304,24 @@ impl AudioServer {
         // Remove user from audio channel
         state_manager.remove_user_from_channel(&user_id)?;
 
-        info!("User {} left audio channel {}", user_id, channel_id);
+        // Stop forwarding voice from and to this socket
+        let (removed, still_connected) = {
+            let mut connections = voice_connections.lock().unwrap();
+            let removed = connections.remove(&addr).is_some();
+            (removed, connections.values().any(|conn| conn.user_id == user_id))
+        };
+        if removed {
+            taps.publish_event(TapEvent::SpeakerLeft {
+                channel_id: channel_id.clone(),
+                user_id: user_id.clone(),
+            });
+        }
+        if !still_connected {
+            layer_selector.remove_user(&user_id);
+        }
 
-        // Send event
-        let _ = event_tx.send(AudioServerEvent::UserLeft {
-            user_id,
-            channel_id,
-            socket_addr: addr,
-        });
+        info!("User {} left audio channel {}", user_id, channel_id);
+        channel_state.clear_voice_moderation(&channel_id, &user_id).await;
 
         Ok(())
     }
@@ -680,10 +1329,8 @@ impl AudioServer {
     /// Handle set mute packet
     async fn handle_set_mute(
         packet: AudioPacket,
-        addr: SocketAddr,
         auth: &Arc<AudioAuth>,
         state_manager: &Arc<AudioStateManager>,
-        event_tx: &mpsc::UnboundedSender<AudioServerEvent>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let user_id = packet.header.user_id_str();
         let channel_id = packet.header.channel_id_str();
@@ -701,15 +1348,7 @@ impl AudioServer {
                 user_id, 
                 if muted { "muted" } else { "unmuted" }, 
                 channel_id
-            );
-
-            // Send event
-            let _ = event_tx.send(AudioServerEvent::UserMuted {
-                user_id,
-                channel_id,
-                muted,
-            });
-        }
+            );        }
 
         Ok(())
     }
@@ -720,84 +1359,71 @@ impl AudioServer {
         addr: SocketAddr,
         auth: &Arc<AudioAuth>,
         state_manager: &Arc<AudioStateManager>,
+        socket: &Arc<UdpSocket>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let user_id = packet.header.user_id_str();
+        let channel_id = packet.header.channel_id_str();
 
         // Get user session
         let _session = auth.get_session(&user_id)?;
 
         // Update user activity
-        if let Some(mut user) = state_manager.get_user_by_socket(&addr) {
-            user.update_activity();
-        }
+        state_manager.touch_user(&addr);
+
+        // Echo the sequence back so the client can measure RTT for its receiver reports
+        let ack_data = AudioPacket::ack(&user_id, &channel_id, packet.header.sequence).to_bytes()?;
+        socket.send_to(&ack_data, addr).await?;
 
         Ok(())
     }
 
-    /// Get event receiver
-    pub fn take_event_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<AudioServerEvent>> {
-        self.event_rx.take()
-    }
+    /// Handle receiver report packet
+    async fn handle_receiver_report(
+        packet: AudioPacket,
+        addr: SocketAddr,
+        voice_connections: &Arc<Mutex<HashMap<SocketAddr, VoiceConnectionState>>>,
+        congestion: &Arc<CongestionController>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let report = packet.receiver_report
+            .ok_or("Missing receiver report")?;
 
-    /// Get server statistics
-    pub fn get_stats(&self) -> AudioServerStats {
-        AudioServerStats {
-            auth_sessions: self.auth.session_count(),
-            state_stats: self.state_manager.get_stats(),
-        }
-    }
+        // Reports are attributed by source address, like voice packets
+        let user_id = voice_connections.lock().unwrap()
+            .get(&addr)
+            .map(|conn| conn.user_id.clone())
+            .ok_or("Receiver report from unknown socket")?;
+
+        congestion.on_receiver_report(&user_id, &report);
+        debug!("Receiver report from {}: loss {:.1}%, rtt {}ms", user_id, report.loss() * 100.0, report.rtt_ms);
 
-    /// Send packet to specific address
-    pub async fn send_packet(&self, packet: AudioPacket, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(socket) = &self.socket {
-            let data = packet.to_bytes()?;
-            socket.send_to(&data, addr).await?;
-        }
         Ok(())
     }
 
-    /// Broadcast packet to channel (excluding sender)
-    pub async fn broadcast_to_channel(
-        &self,
-        packet: AudioPacket,
-        sender_user_id: &str,
-        include_muted: bool,
+    /// Send a bitrate hint to a sender
+    async fn send_bitrate_hint(
+        socket: &Arc<UdpSocket>,
+        addr: SocketAddr,
+        user_id: &str,
+        channel_id: &str,
+        hint: BitrateHint,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(socket) = &self.socket {
-            let targets = self.state_manager.get_broadcast_targets(sender_user_id, include_muted);
-            let data = packet.to_bytes()?;
-
-            for (_, addr) in targets {
-                if let Err(e) = socket.send_to(&data, addr).await {
-                    warn!("Failed to broadcast to {}: {}", addr, e);
-                }
-            }
-        }
+        let data = AudioPacket::bitrate_hint(user_id, channel_id, hint).to_bytes()?;
+        socket.send_to(&data, addr).await?;
         Ok(())
     }
 }
 
-/// Audio server statistics
-#[derive(Debug, Clone)]
-pub struct AudioServerStats {
-    pub auth_sessions: usize,
-    pub state_stats: crate::audio::state::AudioStats,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::net::SocketAddr;
-    use std::str::FromStr;
 
     #[tokio::test]
     async fn test_audio_server_creation() {
         let config = AudioServerConfig::default();
         let channel_state = Arc::new(ChannelAppState::new());
         
-        let mut server = AudioServer::new(config, channel_state);
-        assert!(server.event_tx.is_some());
-        assert!(server.event_rx.is_some());
+        let server = AudioServer::new(config, channel_state);
+        assert!(server.socket.is_none());
     }
 
     #[test]
@@ -833,4 +1459,31 @@ mod tests {
             assert_eq!(handshake_data.channel_id, "test-channel");
         }
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_idle_connections_expire() {
+        let now = Instant::now();
+        let connection = |user_id: &str, idle: Duration| VoiceConnectionState {
+            last_sequence: 0,
+            last_active: now - idle,
+            channel_id: "channel1".to_string(),
+            user_id: user_id.to_string(),
+            simulcast: false,
+            connected_at: 0,
+            server_muted: false,
+            server_deafened: false,
+            last_voice: None,
+            token: TokenRef { jti: String::new(), sid: String::new(), iat: 0.0 },
+        };
+        let voice_connections = Mutex::new(HashMap::from([
+            ("127.0.0.1:5000".parse().unwrap(), connection("alice", Duration::from_secs(10))),
+            ("127.0.0.1:5001".parse().unwrap(), connection("bob", Duration::from_secs(400))),
+        ]));
+        let taps = TapRegistry::new(16);
+
+        let timed_out = AudioServer::expire_idle_connections(&voice_connections, &taps, Duration::from_secs(300), now);
+        assert_eq!(timed_out, vec!["bob"]);
+        let remaining: Vec<String> = voice_connections.lock().unwrap().values().map(|conn| conn.user_id.clone()).collect();
+        assert_eq!(remaining, vec!["alice"]);
+    }
+}
@@ -0,0 +1,415 @@
+use crate::audio::{
+    auth::AudioAuth,
+    congestion::CongestionController,
+    relay::{Relay, RelayStats},
+    server::{JitterBuffer, LiveSettings, VoiceConnectionState},
+    simulcast::{LayerSelector, SimulcastStats},
+    state::{AudioStateManager, AudioStats},
+    tap::{TapEvent, TapRegistry, TapStats},
+    AudioPacket,
+};
+use crate::audio::packet::BitrateHint;
+use crate::routes::channels::AppState as ChannelAppState;
+use crate::tokens::Revocation;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+/// A live UDP voice session
+#[derive(Debug, Clone, Serialize)]
+pub struct VoiceSessionInfo {
+    pub user_id: String,
+    pub channel_id: String,
+    pub addr: SocketAddr,
+    /// Unix time of the handshake
+    pub connected_at: u64,
+    /// From the latest receiver report, if any
+    pub rtt_ms: Option<f32>,
+    pub loss: Option<f32>,
+    /// Mute state declared by the client
+    pub self_muted: bool,
+    /// Mute imposed by the server; voice is dropped on arrival
+    pub server_muted: bool,
+    /// Deafen imposed by a channel moderator; nothing is forwarded
+    pub server_deafened: bool,
+    pub simulcast: bool,
+}
+
+/// Snapshot of the audio server's counters
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioServerStats {
+    pub auth_sessions: usize,
+    pub state: AudioStats,
+    /// Last hint sent to each channel
+    pub bitrate_hints: HashMap<String, BitrateHint>,
+    pub simulcast: SimulcastStats,
+    pub taps: Vec<TapStats>,
+    /// Present once the relay link is up
+    pub relay: Option<RelayStats>,
+}
+
+/// Session control errors
+#[derive(Debug, thiserror::Error)]
+pub enum SessionError {
+    #[error("No live voice session for this user")]
+    NotFound,
+    #[error("Channel not found")]
+    ChannelNotFound,
+}
+
+/// Inspects and controls the live voice sessions of an `AudioServer`.
+///
+/// Shares the server's connection table, so changes apply to the next packet
+/// the forwarder handles.
+#[derive(Clone)]
+pub struct VoiceSessions {
+    pub(super) voice_connections: Arc<Mutex<HashMap<SocketAddr, VoiceConnectionState>>>,
+    pub(super) jitter_buffers: Arc<Mutex<HashMap<String, JitterBuffer>>>,
+    pub(super) state_manager: Arc<AudioStateManager>,
+    pub(super) auth: Arc<AudioAuth>,
+    pub(super) congestion: Arc<CongestionController>,
+    pub(super) taps: Arc<TapRegistry>,
+    pub(super) layer_selector: Arc<LayerSelector>,
+    pub(super) channel_state: Arc<ChannelAppState>,
+    /// Set once the server binds its socket
+    pub(super) socket: Arc<OnceLock<Arc<UdpSocket>>>,
+    /// Set once the server binds its relay link
+    pub(super) relay: Arc<OnceLock<Arc<Relay>>>,
+    pub(super) live: Arc<RwLock<LiveSettings>>,
+}
+
+impl VoiceSessions {
+    /// Every live session
+    pub fn list(&self) -> Vec<VoiceSessionInfo> {
+        let connections = self.voice_connections.lock().unwrap();
+        let mut sessions: Vec<VoiceSessionInfo> = connections
+            .iter()
+            .map(|(addr, conn)| self.describe(*addr, conn))
+            .collect();
+        sessions.sort_by(|a, b| (&a.channel_id, &a.user_id).cmp(&(&b.channel_id, &b.user_id)));
+        sessions
+    }
+
+    /// Counters of the auth, congestion, simulcast, tap and relay layers
+    pub fn stats(&self) -> AudioServerStats {
+        AudioServerStats {
+            auth_sessions: self.auth.session_count(),
+            state: self.state_manager.get_stats(),
+            bitrate_hints: self.congestion.current_hints(),
+            simulcast: self.layer_selector.get_stats(),
+            taps: self.taps.get_stats(),
+            relay: self.relay.get().map(|relay| relay.get_stats()),
+        }
+    }
+
+    /// Live sessions of one user
+    pub fn get(&self, user_id: &str) -> Vec<VoiceSessionInfo> {
+        self.list().into_iter().filter(|session| session.user_id == user_id).collect()
+    }
+
+    /// End every session of a user; they must handshake again to return
+    pub async fn disconnect(&self, user_id: &str, reason: &str) -> Result<Vec<VoiceSessionInfo>, SessionError> {
+        let sessions = self.end_sessions(user_id, |_| true, reason).await;
+        if sessions.is_empty() {
+            return Err(SessionError::NotFound);
+        }
+        Ok(sessions)
+    }
+
+    /// End a user's sessions in one channel, e.g. after they lost membership
+    pub async fn remove_from_channel(&self, user_id: &str, channel_id: &str, reason: &str) -> Vec<VoiceSessionInfo> {
+        self.end_sessions(user_id, |conn| conn.channel_id == channel_id, reason).await
+    }
+
+    /// End the sessions opened with a revoked token
+    pub async fn revoke(&self, revocation: &Revocation) -> Vec<VoiceSessionInfo> {
+        self.end_sessions(revocation.user_id(), |conn| conn.token.is_revoked_by(revocation), "Session revoked").await
+    }
+
+    async fn end_sessions(
+        &self,
+        user_id: &str,
+        matches: impl Fn(&VoiceConnectionState) -> bool,
+        reason: &str,
+    ) -> Vec<VoiceSessionInfo> {
+        let removed: Vec<(SocketAddr, VoiceConnectionState)> = {
+            let mut connections = self.voice_connections.lock().unwrap();
+            let addrs: Vec<SocketAddr> = connections
+                .iter()
+                .filter(|(_, conn)| conn.user_id == user_id && matches(conn))
+                .map(|(addr, _)| *addr)
+                .collect();
+            addrs
+                .into_iter()
+                .filter_map(|addr| connections.remove(&addr).map(|conn| (addr, conn)))
+                .collect()
+        };
+        if removed.is_empty() {
+            return Vec::new();
+        }
+
+        self.jitter_buffers.lock().unwrap().remove(user_id);
+        if !self.voice_connections.lock().unwrap().values().any(|conn| conn.user_id == user_id) {
+            self.layer_selector.remove_user(user_id);
+        }
+        let _ = self.state_manager.remove_user_from_channel(user_id);
+        self.auth.remove_session(user_id);
+
+        let mut sessions = Vec::new();
+        for (addr, conn) in removed {
+            sessions.push(self.describe(addr, &conn));
+            self.taps.publish_event(TapEvent::SpeakerLeft {
+                channel_id: conn.channel_id.clone(),
+                user_id: user_id.to_string(),
+            });
+            self.send(AudioPacket::error(user_id, &conn.channel_id, reason.to_string()), addr).await;
+            info!("Voice session of {} from {} disconnected: {}", user_id, addr, reason);
+        }
+        sessions
+    }
+
+    /// Move every session of a user to another local channel
+    pub async fn move_to(&self, user_id: &str, channel_id: &str) -> Result<Vec<VoiceSessionInfo>, SessionError> {
+        if !self.channel_state.channels.lock().unwrap().contains_key(channel_id) {
+            return Err(SessionError::ChannelNotFound);
+        }
+
+        // Restrictions are per channel, so take on those of the new one
+        let moderation = self.channel_state.voice_moderation(channel_id, user_id);
+        let moved: Vec<(SocketAddr, String, VoiceSessionInfo)> = {
+            let mut connections = self.voice_connections.lock().unwrap();
+            connections
+                .iter_mut()
+                .filter(|(_, conn)| conn.user_id == user_id && conn.channel_id != channel_id)
+                .map(|(addr, conn)| {
+                    let previous = std::mem::replace(&mut conn.channel_id, channel_id.to_string());
+                    conn.server_muted = moderation.server_muted;
+                    conn.server_deafened = moderation.server_deafened;
+                    (*addr, previous, self.describe(*addr, conn))
+                })
+                .collect()
+        };
+        if moved.is_empty() {
+            return if self.get(user_id).is_empty() {
+                Err(SessionError::NotFound)
+            } else {
+                Ok(self.get(user_id))
+            };
+        }
+
+        // Frames queued for the old channel must not leak into the new one
+        self.jitter_buffers.lock().unwrap().insert(user_id.to_string(), self.live.read().unwrap().jitter_buffer());
+
+        let mut sessions = Vec::new();
+        for (addr, previous, session) in moved {
+            if let Some((_, user)) = self.state_manager.get_user_by_socket(&addr) {
+                let _ = self.state_manager.add_user_to_channel(
+                    user.user_id,
+                    user.username,
+                    channel_id.to_string(),
+                    addr,
+                    user.role,
+                );
+            }
+            self.taps.publish_event(TapEvent::SpeakerLeft {
+                channel_id: previous.clone(),
+                user_id: user_id.to_string(),
+            });
+            self.taps.publish_event(TapEvent::SpeakerJoined {
+                channel_id: channel_id.to_string(),
+                user_id: user_id.to_string(),
+            });
+            // Clients take their channel from the ack, as after a handshake
+            self.send(AudioPacket::ack(user_id, channel_id, 0), addr).await;
+            info!("Voice session of {} from {} moved from {} to {}", user_id, addr, previous, channel_id);
+            sessions.push(session);
+        }
+        Ok(sessions)
+    }
+
+    /// Drop, or stop dropping, voice from every session of a user
+    pub fn set_server_mute(&self, user_id: &str, muted: bool) -> Result<Vec<VoiceSessionInfo>, SessionError> {
+        let sessions: Vec<VoiceSessionInfo> = {
+            let mut connections = self.voice_connections.lock().unwrap();
+            connections
+                .iter_mut()
+                .filter(|(_, conn)| conn.user_id == user_id)
+                .map(|(addr, conn)| {
+                    conn.server_muted = muted;
+                    self.describe(*addr, conn)
+                })
+                .collect()
+        };
+        if sessions.is_empty() {
+            return Err(SessionError::NotFound);
+        }
+
+        if muted {
+            // Frames already buffered would otherwise play out
+            if let Some(buffer) = self.jitter_buffers.lock().unwrap().get_mut(user_id) {
+                *buffer = self.live.read().unwrap().jitter_buffer();
+            }
+        }
+        info!("Voice sessions of {} {}", user_id, if muted { "server muted" } else { "server unmuted" });
+        Ok(sessions)
+    }
+
+    fn describe(&self, addr: SocketAddr, conn: &VoiceConnectionState) -> VoiceSessionInfo {
+        let estimate = self.congestion.estimate(&conn.user_id);
+        let self_muted = self
+            .state_manager
+            .get_user_by_socket(&addr)
+            .map(|(_, user)| user.is_muted)
+            .unwrap_or(false);
+        VoiceSessionInfo {
+            user_id: conn.user_id.clone(),
+            channel_id: conn.channel_id.clone(),
+            addr,
+            connected_at: conn.connected_at,
+            rtt_ms: estimate.as_ref().map(|estimate| estimate.rtt_ms),
+            loss: estimate.as_ref().map(|estimate| estimate.loss),
+            self_muted,
+            server_muted: conn.server_muted,
+            server_deafened: conn.server_deafened,
+            simulcast: conn.simulcast,
+        }
+    }
+
+    async fn send(&self, packet: AudioPacket, addr: SocketAddr) {
+        let Some(socket) = self.socket.get() else {
+            return;
+        };
+        match packet.to_bytes() {
+            Ok(data) => {
+                if let Err(e) = socket.send_to(&data, addr).await {
+                    warn!("Failed to notify voice session at {}: {}", addr, e);
+                }
+            }
+            Err(e) => warn!("Failed to encode notification for {}: {}", addr, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioServer, PacketType};
+    use crate::audio::server::AudioServerConfig;
+    use crate::routes::channels::{Channel, ChannelPrivacy};
+    use crate::tokens::TokenRef;
+    use std::time::{Duration, Instant};
+
+    fn channel(id: &str) -> Channel {
+        Channel {
+            id: id.to_string(),
+            name: id.to_string(),
+            privacy: ChannelPrivacy::Public,
+            owner: "owner".to_string(),
+            moderators: Vec::new(),
+            members: vec!["alice".to_string()],
+            banned_users: Vec::new(),
+            invite_tokens: HashMap::new(),
+            min_bitrate_bps: None,
+            max_bitrate_bps: None,
+            max_participants: None,
+            max_speakers: None,
+            waitlist_enabled: false,
+        }
+    }
+
+    async fn sessions_with_alice() -> (VoiceSessions, UdpSocket) {
+        let channel_state = Arc::new(ChannelAppState::new());
+        {
+            let mut channels = channel_state.channels.lock().unwrap();
+            channels.insert("lobby".to_string(), channel("lobby"));
+            channels.insert("ops".to_string(), channel("ops"));
+        }
+        let server = AudioServer::new(AudioServerConfig::default(), channel_state);
+        let sessions = server.sessions();
+        let _ = sessions.socket.set(Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sessions.voice_connections.lock().unwrap().insert(client.local_addr().unwrap(), VoiceConnectionState {
+            last_sequence: 0,
+            last_active: Instant::now(),
+            channel_id: "lobby".to_string(),
+            user_id: "alice".to_string(),
+            simulcast: false,
+            connected_at: 1_700_000_000,
+            server_muted: false,
+            server_deafened: false,
+            last_voice: None,
+            token: TokenRef {
+                jti: "token1".to_string(),
+                sid: "login1".to_string(),
+                iat: 1_700_000_000.0,
+            },
+        });
+        (sessions, client)
+    }
+
+    async fn recv_packet(client: &UdpSocket) -> AudioPacket {
+        let mut buf = [0u8; 1024];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(1), client.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        AudioPacket::from_bytes(&buf[..len]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mute_and_move_apply_to_live_session() {
+        let (sessions, client) = sessions_with_alice().await;
+        assert_eq!(sessions.list().len(), 1);
+
+        sessions.set_server_mute("alice", true).unwrap();
+        assert!(sessions.get("alice")[0].server_muted);
+
+        let moved = sessions.move_to("alice", "ops").await.unwrap();
+        assert_eq!(moved[0].channel_id, "ops");
+        let ack = recv_packet(&client).await;
+        assert_eq!(ack.header.packet_type, PacketType::Ack);
+        assert_eq!(ack.header.channel_id_str(), "ops");
+
+        assert!(matches!(sessions.move_to("alice", "missing").await, Err(SessionError::ChannelNotFound)));
+        assert!(matches!(sessions.set_server_mute("bob", true), Err(SessionError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_notifies_and_removes_session() {
+        let (sessions, client) = sessions_with_alice().await;
+
+        // Losing membership elsewhere leaves this session alone
+        assert!(sessions.remove_from_channel("alice", "ops", "You were kicked from the channel").await.is_empty());
+
+        let removed = sessions.disconnect("alice", "Disconnected by an administrator").await.unwrap();
+        assert_eq!(removed[0].channel_id, "lobby");
+        assert!(sessions.list().is_empty());
+
+        let notice = recv_packet(&client).await;
+        assert_eq!(notice.header.packet_type, PacketType::Error);
+        assert_eq!(notice.error_message.as_deref(), Some("Disconnected by an administrator"));
+        assert!(matches!(sessions.disconnect("alice", "again").await, Err(SessionError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_revocation_ends_sessions_of_the_token() {
+        let (sessions, client) = sessions_with_alice().await;
+        let revoke = |sid: &str| Revocation::Session {
+            sid: sid.to_string(),
+            user_id: "alice".to_string(),
+            expires_at: i64::MAX,
+        };
+
+        // Alice's other logins keep their sessions
+        assert!(sessions.revoke(&revoke("login2")).await.is_empty());
+        assert_eq!(sessions.revoke(&revoke("login1")).await.len(), 1);
+        assert!(sessions.list().is_empty());
+
+        let notice = recv_packet(&client).await;
+        assert_eq!(notice.error_message.as_deref(), Some("Session revoked"));
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::audio::congestion::PathEstimate;
+use serde::Serialize;
+
+/// Simulcast configuration
+#[derive(Debug, Clone)]
+pub struct SimulcastConfig {
+    /// Nominal bitrate of each layer in bps, indexed by layer ID (0 = highest quality)
+    pub layer_bitrates_bps: Vec<u32>,
+    /// Fraction of a listener's estimated bandwidth a layer may use
+    pub bandwidth_headroom: f32,
+    /// Loss above which a listener is moved one layer further down
+    pub high_loss: f32,
+    /// How long a better layer must stay affordable before switching up
+    pub upgrade_hold: Duration,
+}
+
+impl Default for SimulcastConfig {
+    fn default() -> Self {
+        Self {
+            layer_bitrates_bps: vec![48_000, 24_000, 12_000],
+            bandwidth_headroom: 0.9,
+            high_loss: 0.05,
+            upgrade_hold: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Layer currently forwarded from one sender to one listener
+#[derive(Debug, Clone)]
+struct LayerSelection {
+    /// Layer the selector wants to forward
+    target: u8,
+    /// Layer actually forwarded with the last frame
+    forwarded: Option<u8>,
+    /// Since when a better layer has been affordable
+    upgrade_since: Option<Instant>,
+    switches: u64,
+}
+
+/// Per-pair selection snapshot for stats
+#[derive(Debug, Clone, Serialize)]
+pub struct LayerSelectionStats {
+    pub listener_id: String,
+    pub sender_id: String,
+    pub layer: Option<u8>,
+    pub switches: u64,
+}
+
+/// Simulcast statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulcastStats {
+    pub total_switches: u64,
+    pub selections: Vec<LayerSelectionStats>,
+}
+
+/// Picks which simulcast layer of each sender is forwarded to each listener
+pub struct LayerSelector {
+    config: SimulcastConfig,
+    selections: Mutex<HashMap<(String, String), LayerSelection>>, // (listener, sender) -> selection
+}
+
+impl LayerSelector {
+    pub fn new(config: SimulcastConfig) -> Self {
+        Self {
+            config,
+            selections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Best layer a listener's path can carry, ignoring hysteresis
+    pub fn desired_layer(&self, estimate: Option<&PathEstimate>) -> u8 {
+        let lowest = self.config.layer_bitrates_bps.len().saturating_sub(1) as u8;
+        let estimate = match estimate {
+            Some(estimate) => estimate,
+            None => return 0, // No reports yet, assume a good path
+        };
+
+        let budget = estimate.available_bps as f32 * self.config.bandwidth_headroom;
+        let mut layer = self.config.layer_bitrates_bps
+            .iter()
+            .position(|&bitrate| bitrate as f32 <= budget)
+            .map(|layer| layer as u8)
+            .unwrap_or(lowest);
+
+        if estimate.loss > self.config.high_loss {
+            layer = (layer + 1).min(lowest);
+        }
+        layer
+    }
+
+    /// Choose the layer to forward for one frame.
+    ///
+    /// Called once per frame and listener, so any switch happens on a frame
+    /// boundary. Downgrades apply immediately; upgrades only once the better
+    /// layer has stayed affordable for `upgrade_hold`. If the selected layer
+    /// is missing from this frame, the nearest lower-quality layer is used,
+    /// then the nearest higher-quality one.
+    pub fn select(
+        &self,
+        listener_id: &str,
+        sender_id: &str,
+        available: &[u8],
+        estimate: Option<&PathEstimate>,
+    ) -> Option<u8> {
+        let desired = self.desired_layer(estimate);
+        let now = Instant::now();
+
+        let mut selections = self.selections.lock().unwrap();
+        let selection = selections
+            .entry((listener_id.to_string(), sender_id.to_string()))
+            .or_insert(LayerSelection {
+                target: desired,
+                forwarded: None,
+                upgrade_since: None,
+                switches: 0,
+            });
+
+        if desired > selection.target {
+            selection.target = desired;
+            selection.upgrade_since = None;
+        } else if desired < selection.target {
+            let since = *selection.upgrade_since.get_or_insert(now);
+            if now.duration_since(since) >= self.config.upgrade_hold {
+                selection.target = desired;
+                selection.upgrade_since = None;
+            }
+        } else {
+            selection.upgrade_since = None;
+        }
+
+        let target = selection.target;
+        let layer = available.iter().copied().filter(|&l| l >= target).min()
+            .or_else(|| available.iter().copied().filter(|&l| l < target).max())?;
+
+        if selection.forwarded.is_some() && selection.forwarded != Some(layer) {
+            selection.switches += 1;
+        }
+        selection.forwarded = Some(layer);
+        Some(layer)
+    }
+
+    /// Drop selections involving a user
+    pub fn remove_user(&self, user_id: &str) {
+        self.selections
+            .lock()
+            .unwrap()
+            .retain(|(listener, sender), _| listener != user_id && sender != user_id);
+    }
+
+    /// Get layer selection statistics
+    pub fn get_stats(&self) -> SimulcastStats {
+        let selections = self.selections.lock().unwrap();
+        let selections: Vec<LayerSelectionStats> = selections
+            .iter()
+            .map(|((listener_id, sender_id), selection)| LayerSelectionStats {
+                listener_id: listener_id.clone(),
+                sender_id: sender_id.clone(),
+                layer: selection.forwarded,
+                switches: selection.switches,
+            })
+            .collect();
+
+        SimulcastStats {
+            total_switches: selections.iter().map(|s| s.switches).sum(),
+            selections,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimate(available_bps: u32, loss: f32) -> PathEstimate {
+        PathEstimate {
+            loss,
+            rtt_ms: 50.0,
+            jitter_ms: 5.0,
+            available_bps,
+            last_report: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_desired_layer_follows_bandwidth_and_loss() {
+        let selector = LayerSelector::new(SimulcastConfig::default());
+
+        assert_eq!(selector.desired_layer(None), 0);
+        assert_eq!(selector.desired_layer(Some(&estimate(64_000, 0.0))), 0);
+        assert_eq!(selector.desired_layer(Some(&estimate(32_000, 0.0))), 1);
+        assert_eq!(selector.desired_layer(Some(&estimate(8_000, 0.0))), 2);
+        assert_eq!(selector.desired_layer(Some(&estimate(64_000, 0.2))), 1);
+    }
+
+    #[test]
+    fn test_downgrade_immediate_upgrade_held() {
+        let selector = LayerSelector::new(SimulcastConfig::default());
+        let layers = [0, 1, 2];
+
+        let good = estimate(64_000, 0.0);
+        let weak = estimate(16_000, 0.0);
+
+        assert_eq!(selector.select("listener", "sender", &layers, Some(&good)), Some(0));
+        assert_eq!(selector.select("listener", "sender", &layers, Some(&weak)), Some(2));
+        // Path recovered, but the upgrade is held back
+        assert_eq!(selector.select("listener", "sender", &layers, Some(&good)), Some(2));
+
+        let stats = selector.get_stats();
+        assert_eq!(stats.total_switches, 1);
+        assert_eq!(stats.selections[0].layer, Some(2));
+    }
+
+    #[test]
+    fn test_falls_back_to_available_layer() {
+        let selector = LayerSelector::new(SimulcastConfig::default());
+        let weak = estimate(16_000, 0.0);
+
+        // Sender only uploads the top layer
+        assert_eq!(selector.select("listener", "sender", &[0], Some(&weak)), Some(0));
+        // Sender uploads top and middle layers
+        assert_eq!(selector.select("listener", "sender", &[0, 1], Some(&weak)), Some(1));
+        assert_eq!(selector.select("listener", "sender", &[], Some(&weak)), None);
+    }
+
+    #[test]
+    fn test_listeners_are_independent() {
+        let selector = LayerSelector::new(SimulcastConfig::default());
+        let layers = [0, 1, 2];
+
+        assert_eq!(selector.select("strong", "sender", &layers, Some(&estimate(64_000, 0.0))), Some(0));
+        assert_eq!(selector.select("weak", "sender", &layers, Some(&estimate(16_000, 0.1))), Some(2));
+
+        selector.remove_user("weak");
+        assert_eq!(selector.get_stats().selections.len(), 1);
+    }
+}
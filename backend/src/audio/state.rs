@@ -1,20 +1,18 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use crate::routes::channels::Role;
+use serde::Serialize;
 
 /// Audio user state
 #[derive(Debug, Clone)]
 pub struct AudioUserState {
     pub user_id: String,
     pub username: String,
-    pub channel_id: String,
     pub socket_addr: SocketAddr,
     pub is_muted: bool,
-    pub is_speaking: bool,
     pub last_activity: Instant,
-    pub sequence_number: u32,
     pub role: Role,
 }
 
@@ -22,19 +20,15 @@ impl AudioUserState {
     pub fn new(
         user_id: String,
         username: String,
-        channel_id: String,
         socket_addr: SocketAddr,
         role: Role,
     ) -> Self {
         Self {
             user_id,
             username,
-            channel_id,
             socket_addr,
             is_muted: false,
-            is_speaking: false,
             last_activity: Instant::now(),
-            sequence_number: 0,
             role,
         }
     }
@@ -46,15 +40,10 @@ impl AudioUserState {
     pub fn is_expired(&self, timeout: Duration) -> bool {
         self.last_activity.elapsed() > timeout
     }
-
-    pub fn next_sequence(&mut self) -> u32 {
-        self.sequence_number = self.sequence_number.wrapping_add(1);
-        self.sequence_number
-    }
 }
 
 /// Channel state for audio streaming
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChannelState {
     pub channel_id: String,
     pub users: HashMap<String, AudioUserState>,
@@ -93,11 +82,6 @@ impl ChannelState {
         }
     }
 
-    /// Get user by ID
-    pub fn get_user(&self, user_id: &str) -> Option<&AudioUserState> {
-        self.users.get(user_id)
-    }
-
     /// Get user by socket address
     pub fn get_user_by_socket(&self, socket_addr: &SocketAddr) -> Option<&AudioUserState> {
         if let Some(user_id) = self.user_socket_map.get(socket_addr) {
@@ -143,17 +127,6 @@ impl ChannelState {
         }
     }
 
-    /// Update user speaking state
-    pub fn set_user_speaking(&mut self, user_id: &str, speaking: bool) -> bool {
-        if let Some(user) = self.users.get_mut(user_id) {
-            user.is_speaking = speaking;
-            user.update_activity();
-            true
-        } else {
-            false
-        }
-    }
-
     /// Clean up expired users
     pub fn cleanup_expired_users(&mut self, timeout: Duration) -> Vec<String> {
         let mut expired_users = Vec::new();
@@ -186,18 +159,12 @@ impl ChannelState {
     pub fn is_empty(&self) -> bool {
         self.users.is_empty()
     }
-
-    /// Get all user IDs
-    pub fn get_user_ids(&self) -> Vec<String> {
-        self.users.keys().cloned().collect()
-    }
 }
 
 /// Global audio state manager
 pub struct AudioStateManager {
     channels: Arc<Mutex<HashMap<String, ChannelState>>>,
     user_channels: Arc<Mutex<HashMap<String, String>>>, // user_id -> channel_id
-    cleanup_interval: Duration,
     user_timeout: Duration,
 }
 
@@ -206,7 +173,6 @@ impl AudioStateManager {
         Self {
             channels: Arc::new(Mutex::new(HashMap::new())),
             user_channels: Arc::new(Mutex::new(HashMap::new())),
-            cleanup_interval: Duration::from_secs(60), // 1 minute
             user_timeout: Duration::from_secs(300), // 5 minutes
         }
     }
@@ -236,7 +202,7 @@ impl AudioStateManager {
             .or_insert_with(|| ChannelState::new(channel_id.clone()));
 
         // Add user to channel
-        let user = AudioUserState::new(user_id.clone(), username, channel_id.clone(), socket_addr, role);
+        let user = AudioUserState::new(user_id.clone(), username, socket_addr, role);
         channel.add_user(user);
 
         // Update user-channel mapping
@@ -274,9 +240,9 @@ impl AudioStateManager {
         self.channels.lock().unwrap().get(channel_id).cloned()
     }
 
-    /// Get channel state (mutable)
-    pub fn get_channel_mut(&self, channel_id: &str) -> Option<impl std::ops::DerefMut<Target = ChannelState>> {
-        self.channels.lock().unwrap().get_mut(channel_id).map(|c| c)
+    /// Run `f` on a channel's state while holding the lock
+    pub fn with_channel_mut<R>(&self, channel_id: &str, f: impl FnOnce(&mut ChannelState) -> R) -> Option<R> {
+        self.channels.lock().unwrap().get_mut(channel_id).map(f)
     }
 
     /// Get user by socket address
@@ -292,24 +258,32 @@ impl AudioStateManager {
         None
     }
 
+    /// Record activity of the user at `socket_addr`
+    pub fn touch_user(&self, socket_addr: &SocketAddr) {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(user) = channels.values_mut().find_map(|channel| channel.get_user_by_socket_mut(socket_addr)) {
+            user.update_activity();
+        }
+    }
+
     /// Set user mute state
     pub fn set_user_mute(&self, user_id: &str, muted: bool) -> bool {
         if let Some(channel_id) = self.get_user_channel(user_id) {
-            if let Some(mut channel) = self.get_channel_mut(&channel_id) {
-                return channel.set_user_mute(user_id, muted);
-            }
+            return self.with_channel_mut(&channel_id, |channel| channel.set_user_mute(user_id, muted)).unwrap_or(false);
         }
         false
     }
 
-    /// Set user speaking state
-    pub fn set_user_speaking(&self, user_id: &str, speaking: bool) -> bool {
-        if let Some(channel_id) = self.get_user_channel(user_id) {
-            if let Some(mut channel) = self.get_channel_mut(&channel_id) {
-                return channel.set_user_speaking(user_id, speaking);
+    /// Set user role in a channel
+    pub fn set_user_role(&self, user_id: &str, channel_id: &str, role: Role) -> bool {
+        let mut channels = self.channels.lock().unwrap();
+        match channels.get_mut(channel_id).and_then(|channel| channel.users.get_mut(user_id)) {
+            Some(user) => {
+                user.role = role;
+                true
             }
+            None => false,
         }
-        false
     }
 
     /// Get users to broadcast to (excluding sender)
@@ -373,7 +347,7 @@ impl AudioStateManager {
             channel_stats.push(ChannelStats {
                 channel_id: channel_id.clone(),
                 user_count: channel.user_count(),
-                last_activity: channel.last_activity,
+                idle_secs: channel.last_activity.elapsed().as_secs(),
             });
         }
 
@@ -383,20 +357,10 @@ impl AudioStateManager {
             channel_stats,
         }
     }
-
-    /// Set cleanup interval
-    pub fn set_cleanup_interval(&mut self, interval: Duration) {
-        self.cleanup_interval = interval;
-    }
-
-    /// Set user timeout
-    pub fn set_user_timeout(&mut self, timeout: Duration) {
-        self.user_timeout = timeout;
-    }
 }
 
 /// Audio statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AudioStats {
     pub total_channels: usize,
     pub total_users: usize,
@@ -404,26 +368,20 @@ pub struct AudioStats {
 }
 
 /// Channel statistics
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ChannelStats {
     pub channel_id: String,
     pub user_count: usize,
-    pub last_activity: Instant,
+    /// Seconds since anyone joined, left or spoke
+    pub idle_secs: u64,
 }
 
 /// State management errors
 #[derive(Debug, thiserror::Error)]
 pub enum StateError {
-    #[error("Channel not found")]
-    ChannelNotFound,
-    #[error("User not found")]
-    UserNotFound,
-    #[error("User already in channel")]
-    UserAlreadyInChannel,
+    /// `position` is the user's place in the channel's waitlist, if it has one
     #[error("Channel is full")]
-    ChannelFull,
-    #[error("Permission denied")]
-    PermissionDenied,
+    ChannelFull { limit: u32, position: Option<u32> },
 }
 
 #[cfg(test)]
@@ -444,7 +402,6 @@ mod tests {
         let user = AudioUserState::new(
             "user1".to_string(),
             "User1".to_string(),
-            "test_channel".to_string(),
             socket,
             Role::Member,
         );
@@ -0,0 +1,291 @@
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// One forwarded voice frame of a speaker
+#[derive(Debug, Clone)]
+pub struct VoiceFrame {
+    pub channel_id: String,
+    pub speaker_id: String,
+    #[allow(dead_code)] // for bots and analytics; the recorder places frames by timestamp
+    pub sequence_number: u32,
+    pub timestamp: u64,
+    /// Simulcast layer of the payload (0 for non-simulcast senders)
+    #[allow(dead_code)] // for bots and analytics; the recorder takes whatever layer arrives
+    pub layer: u8,
+    pub payload: Arc<[u8]>,
+    /// When the forwarder released the frame from the jitter buffer
+    pub forwarded_at: Instant,
+}
+
+/// Event queued for a tap
+#[derive(Debug, Clone)]
+pub enum TapEvent {
+    Frame(VoiceFrame),
+    SpeakerJoined { channel_id: String, user_id: String },
+    SpeakerLeft { channel_id: String, user_id: String },
+}
+
+impl TapEvent {
+    fn channel_id(&self) -> &str {
+        match self {
+            TapEvent::Frame(frame) => &frame.channel_id,
+            TapEvent::SpeakerJoined { channel_id, .. } => channel_id,
+            TapEvent::SpeakerLeft { channel_id, .. } => channel_id,
+        }
+    }
+}
+
+/// In-process consumer of channel audio.
+///
+/// Each registered tap runs on its own task behind a bounded queue, so a
+/// slow tap loses frames instead of delaying the forwarder.
+#[async_trait]
+pub trait MediaTap: Send + Sync + 'static {
+    /// Whether events of a channel should be queued for this tap.
+    /// Called on the forwarding path, so it must be cheap.
+    fn wants_channel(&self, _channel_id: &str) -> bool {
+        true
+    }
+
+    async fn on_frame(&self, frame: VoiceFrame);
+
+    async fn on_speaker_joined(&self, _channel_id: &str, _user_id: &str) {}
+
+    async fn on_speaker_left(&self, _channel_id: &str, _user_id: &str) {}
+}
+
+/// Handle returned when registering a tap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TapId(u64);
+
+/// Per-tap delivery statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct TapStats {
+    pub name: String,
+    pub delivered: u64,
+    pub dropped: u64,
+}
+
+struct RegisteredTap {
+    id: TapId,
+    name: String,
+    tap: Arc<dyn MediaTap>,
+    tx: mpsc::Sender<TapEvent>,
+    delivered: Arc<AtomicU64>,
+    dropped: AtomicU64,
+}
+
+/// Fans forwarded frames and speaker lifecycle out to registered taps
+pub struct TapRegistry {
+    queue_capacity: usize,
+    next_id: AtomicU64,
+    taps: RwLock<Vec<RegisteredTap>>,
+}
+
+impl TapRegistry {
+    pub fn new(queue_capacity: usize) -> Self {
+        Self {
+            queue_capacity,
+            next_id: AtomicU64::new(1),
+            taps: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Register a tap and spawn its delivery task
+    pub fn register(&self, name: &str, tap: Arc<dyn MediaTap>) -> TapId {
+        let id = TapId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (tx, mut rx) = mpsc::channel::<TapEvent>(self.queue_capacity);
+        let delivered = Arc::new(AtomicU64::new(0));
+
+        let worker_tap = tap.clone();
+        let worker_delivered = delivered.clone();
+        let worker_name = name.to_string();
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                match event {
+                    TapEvent::Frame(frame) => worker_tap.on_frame(frame).await,
+                    TapEvent::SpeakerJoined { channel_id, user_id } => {
+                        worker_tap.on_speaker_joined(&channel_id, &user_id).await
+                    }
+                    TapEvent::SpeakerLeft { channel_id, user_id } => {
+                        worker_tap.on_speaker_left(&channel_id, &user_id).await
+                    }
+                }
+                worker_delivered.fetch_add(1, Ordering::Relaxed);
+            }
+            debug!("Media tap {} stopped", worker_name);
+        });
+
+        self.taps.write().unwrap().push(RegisteredTap {
+            id,
+            name: name.to_string(),
+            tap,
+            tx,
+            delivered,
+            dropped: AtomicU64::new(0),
+        });
+        id
+    }
+
+    /// Unregister a tap; its task stops once the queue drains
+    pub fn unregister(&self, id: TapId) -> bool {
+        let mut taps = self.taps.write().unwrap();
+        let before = taps.len();
+        taps.retain(|registered| registered.id != id);
+        taps.len() != before
+    }
+
+    /// Whether any tap wants events of a channel
+    pub fn wants_channel(&self, channel_id: &str) -> bool {
+        self.taps
+            .read()
+            .unwrap()
+            .iter()
+            .any(|registered| registered.tap.wants_channel(channel_id))
+    }
+
+    /// Queue a frame for every interested tap, dropping it for taps that are behind
+    pub fn publish_frame(&self, frame: VoiceFrame) {
+        let taps = self.taps.read().unwrap();
+        for registered in taps.iter() {
+            if !registered.tap.wants_channel(&frame.channel_id) {
+                continue;
+            }
+            if registered.tx.try_send(TapEvent::Frame(frame.clone())).is_err() {
+                let dropped = registered.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped.is_power_of_two() {
+                    warn!("Media tap {} is falling behind ({} frames dropped)", registered.name, dropped);
+                }
+            }
+        }
+    }
+
+    /// Queue a lifecycle event for every interested tap.
+    ///
+    /// Lifecycle events are rare and must not be lost, so a full queue
+    /// defers delivery to a background send instead of dropping.
+    pub fn publish_event(&self, event: TapEvent) {
+        let taps = self.taps.read().unwrap();
+        for registered in taps.iter() {
+            if !registered.tap.wants_channel(event.channel_id()) {
+                continue;
+            }
+            if let Err(mpsc::error::TrySendError::Full(event)) = registered.tx.try_send(event.clone()) {
+                let tx = registered.tx.clone();
+                tokio::spawn(async move {
+                    let _ = tx.send(event).await;
+                });
+            }
+        }
+    }
+
+    /// Get per-tap delivery statistics
+    pub fn get_stats(&self) -> Vec<TapStats> {
+        self.taps
+            .read()
+            .unwrap()
+            .iter()
+            .map(|registered| TapStats {
+                name: registered.name.clone(),
+                delivered: registered.delivered.load(Ordering::Relaxed),
+                dropped: registered.dropped.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    struct CollectingTap {
+        channel: String,
+        frames: Mutex<Vec<u32>>,
+        left: Mutex<Vec<String>>,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl MediaTap for CollectingTap {
+        fn wants_channel(&self, channel_id: &str) -> bool {
+            channel_id == self.channel
+        }
+
+        async fn on_frame(&self, frame: VoiceFrame) {
+            tokio::time::sleep(self.delay).await;
+            self.frames.lock().unwrap().push(frame.sequence_number);
+        }
+
+        async fn on_speaker_left(&self, _channel_id: &str, user_id: &str) {
+            self.left.lock().unwrap().push(user_id.to_string());
+        }
+    }
+
+    fn collecting_tap(channel: &str, delay: Duration) -> Arc<CollectingTap> {
+        Arc::new(CollectingTap {
+            channel: channel.to_string(),
+            frames: Mutex::new(Vec::new()),
+            left: Mutex::new(Vec::new()),
+            delay,
+        })
+    }
+
+    fn frame(channel_id: &str, sequence_number: u32) -> VoiceFrame {
+        VoiceFrame {
+            channel_id: channel_id.to_string(),
+            speaker_id: "speaker".to_string(),
+            sequence_number,
+            timestamp: sequence_number as u64 * 20,
+            layer: 0,
+            payload: Arc::from(&[0xFCu8, 1, 2][..]),
+            forwarded_at: Instant::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frames_reach_interested_taps() {
+        let registry = TapRegistry::new(16);
+        let tap = collecting_tap("channel1", Duration::ZERO);
+        registry.register("collector", tap.clone());
+
+        assert!(registry.wants_channel("channel1"));
+        assert!(!registry.wants_channel("channel2"));
+
+        registry.publish_frame(frame("channel1", 1));
+        registry.publish_frame(frame("channel2", 2));
+        registry.publish_frame(frame("channel1", 3));
+        registry.publish_event(TapEvent::SpeakerLeft {
+            channel_id: "channel1".to_string(),
+            user_id: "speaker".to_string(),
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*tap.frames.lock().unwrap(), vec![1, 3]);
+        assert_eq!(*tap.left.lock().unwrap(), vec!["speaker"]);
+        assert_eq!(registry.get_stats()[0].delivered, 3);
+    }
+
+    #[tokio::test]
+    async fn test_slow_tap_drops_frames() {
+        let registry = TapRegistry::new(2);
+        let tap = collecting_tap("channel1", Duration::from_millis(100));
+        let id = registry.register("slow", tap.clone());
+
+        for sequence_number in 0..10 {
+            registry.publish_frame(frame("channel1", sequence_number));
+        }
+
+        let stats = registry.get_stats();
+        assert!(stats[0].dropped >= 7);
+
+        assert!(registry.unregister(id));
+        assert!(!registry.wants_channel("channel1"));
+    }
+}
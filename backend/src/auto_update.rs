@@ -1,13 +1,18 @@
 use std::fs;
 use std::io::Write;
-use std::process::Command;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use reqwest::Client;
-use ring::signature::{UnparsedPublicKey, ED25519_PUBLIC_KEY_LEN, ED25519};
+use ring::signature::{UnparsedPublicKey, ED25519};
 
 const UPDATE_URL: &str = "https://updates.whisperfleet.link/latest.json";
-const PUBLIC_KEY: &[u8; ED25519_PUBLIC_KEY_LEN] = b"<YOUR_ED25519_PUBLIC_KEY_BYTES_HERE>";
 
 pub async fn check_and_apply_update(current_version: &str) -> Result<(), String> {
+    // Base64 Ed25519 key that releases are signed with; without it nothing
+    // downloaded could be trusted
+    let public_key = match std::env::var("UPDATE_PUBLIC_KEY") {
+        Ok(key) => STANDARD.decode(key.trim()).map_err(|e| format!("Invalid UPDATE_PUBLIC_KEY: {}", e))?,
+        Err(_) => return Ok(()),
+    };
     let client = Client::new();
     let resp = client.get(UPDATE_URL).send().await.map_err(|e| e.to_string())?;
     let meta: serde_json::Value = resp.json().await.map_err(|e| e.to_string())?;
@@ -21,7 +26,7 @@ pub async fn check_and_apply_update(current_version: &str) -> Result<(), String>
     let exe_bytes = client.get(exe_url).send().await.map_err(|e| e.to_string())?.bytes().await.map_err(|e| e.to_string())?;
     let sig_bytes = client.get(sig_url).send().await.map_err(|e| e.to_string())?.bytes().await.map_err(|e| e.to_string())?;
     // Verify signature
-    let pubkey = UnparsedPublicKey::new(&ED25519, PUBLIC_KEY);
+    let pubkey = UnparsedPublicKey::new(&ED25519, &public_key);
     pubkey.verify(&exe_bytes, &sig_bytes).map_err(|_| "Signature verification failed".to_string())?;
     // Write to temp file
     let tmp_path = "backend/updated_backend.exe";
@@ -33,4 +38,4 @@ pub async fn check_and_apply_update(current_version: &str) -> Result<(), String>
     // For now, just log and exit
     println!("[update] Please manually replace backend.exe with updated_backend.exe and restart.");
     Ok(())
-} 
\ No newline at end of file
+}
@@ -0,0 +1,1177 @@
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::audio::{AbuseConfig, AudioServerConfig, RelayConfig};
+use crate::routes::auth::Registration;
+use crate::routes::email::{FileTransport, LogTransport, MailTransport, Mailer, QueueSettings, SmtpTransport};
+use crate::routes::ldap::{LdapDirectory, LdapSettings};
+use crate::routes::oauth::{OAuthProviders, Provider, DEV_COOKIE_SECRET};
+use crate::routes::passkey::Passkeys;
+use crate::routes::password::{PasswordPolicy, DEFAULT_MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH};
+use crate::routes::twofa::{TwoFactor, DEV_TWOFA_KEY};
+use crate::tokens::{SigningKey, TokenService, DEFAULT_AUDIENCE, DEFAULT_ISSUER, DEFAULT_REFRESH_TTL, DEFAULT_TTL, DEV_JWT_SECRET, SECRET_KID};
+use crate::voice::security::BucketConfig;
+
+/// Config file read when `CONFIG_FILE` is unset; it may be missing
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Discovery document of Google sign-in, used with `GOOGLE_CLIENT_ID`
+pub const GOOGLE_DISCOVERY_URL: &str = "https://accounts.google.com/.well-known/openid-configuration";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Invalid {field}: {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
+
+fn invalid(field: &'static str, reason: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid { field, reason: reason.into() }
+}
+
+/// Backend configuration, from a TOML file with environment overrides
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub http: HttpConfig,
+    pub ws: WsConfig,
+    pub udp: UdpConfig,
+    pub database: DatabaseConfig,
+    pub secrets: SecretsConfig,
+    pub tokens: TokensConfig,
+    pub accounts: AccountsConfig,
+    pub mail: MailConfig,
+    pub twofa: TwoFactorConfig,
+    pub webauthn: WebauthnConfig,
+    pub oauth: OAuthConfig,
+    pub ldap: LdapConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub bind_addr: String,
+    /// Origins allowed by CORS; empty allows any
+    pub cors_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WsConfig {
+    /// Serve `/ws` on its own listener as well; `None` serves it with HTTP only
+    pub bind_addr: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UdpConfig {
+    pub bind_addr: String,
+    pub max_packet_size: usize,
+    pub buffer_size: usize,
+    pub cleanup_interval_secs: u64,
+    pub heartbeat_interval_secs: u64,
+    pub handshake_timeout_secs: u64,
+    pub user_timeout_secs: u64,
+    pub jitter_buffer_size: usize,
+    pub jitter_buffer_window_ms: u64,
+    pub limits: UdpLimits,
+}
+
+/// Per-second budgets of the UDP rate limits; bursts of twice the rate are allowed
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UdpLimits {
+    pub packets_per_addr: f64,
+    pub bytes_per_addr: f64,
+    pub packets_per_session: f64,
+    pub bytes_per_session: f64,
+    pub handshakes_per_min: f64,
+    pub handshake_burst: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    #[default]
+    Memory,
+    Postgres,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub state_backend: StateBackend,
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsConfig {
+    pub jwt_secret: String,
+    /// Encrypts the TOTP secrets of users; changing it disables everyone's 2FA
+    pub twofa_key: String,
+    /// Signs the cookies of OAuth logins in progress
+    pub cookie_secret: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokensConfig {
+    pub issuer: String,
+    pub audience: String,
+    /// Lifetime of access tokens
+    pub ttl_secs: u64,
+    /// Lifetime of refresh tokens; each refresh starts it over
+    pub refresh_ttl_secs: u64,
+    /// Newest first: the first key signs and the rest only verify. Without
+    /// keys, tokens are signed with `secrets.jwt_secret` (HS256).
+    pub keys: Vec<TokenKeyConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenKeyConfig {
+    pub kid: String,
+    pub algorithm: TokenAlgorithm,
+    /// PKCS#8 PEM private key
+    pub private_key_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum TokenAlgorithm {
+    EdDSA,
+    RS256,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    /// Accept self-service registration at `/auth/register`
+    pub registration: bool,
+    /// Lifetime of email verification links
+    pub verification_ttl_secs: u64,
+    /// Lifetime of password reset links
+    pub reset_ttl_secs: u64,
+    pub min_password_length: usize,
+    /// Passwords to refuse besides the built-in list, one per line
+    pub breached_passwords_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransportKind {
+    #[default]
+    Log,
+    File,
+    Smtp,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    /// `log` only logs messages, and only with the development token secret;
+    /// `file` drops them into `dir`
+    pub transport: MailTransportKind,
+    pub from: String,
+    /// Base of the links in emails
+    pub frontend_url: String,
+    pub dir: PathBuf,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: String,
+    pub smtp_password: String,
+    /// Messages waiting to be sent before new ones are refused
+    pub queue_size: usize,
+    /// Sending attempts per message, including the first
+    pub max_attempts: u32,
+    /// Wait before the first retry; it doubles with each further attempt
+    pub retry_delay_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TwoFactorConfig {
+    /// Account name shown in authenticator apps
+    pub issuer: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebauthnConfig {
+    /// Domain passkeys are bound to; the frontend's or a parent of it
+    pub rp_id: String,
+    /// Origin of the frontend, where the browser runs the ceremonies
+    pub rp_origin: String,
+    /// Name shown when creating a passkey
+    pub rp_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OAuthConfig {
+    /// Sign-in providers; their callback is `<mail.frontend_url>/oauth/<name>/callback`
+    pub providers: Vec<OAuthProviderConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OAuthProviderKind {
+    #[default]
+    Oidc,
+    Github,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OAuthProviderConfig {
+    /// Used in routes and the callback URL, e.g. `keycloak`
+    pub name: String,
+    #[serde(default)]
+    pub kind: OAuthProviderKind,
+    /// OIDC discovery document, required for `oidc` providers
+    #[serde(default)]
+    pub discovery_url: String,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+    /// Replaces the default scopes when set
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+impl OAuthConfig {
+    /// The provider called `name`, added if there is none
+    fn provider_mut(&mut self, name: &str, kind: OAuthProviderKind, discovery_url: &str) -> &mut OAuthProviderConfig {
+        let index = match self.providers.iter().position(|provider| provider.name == name) {
+            Some(index) => index,
+            None => {
+                self.providers.push(OAuthProviderConfig {
+                    name: name.to_string(),
+                    kind,
+                    discovery_url: discovery_url.to_string(),
+                    client_id: String::new(),
+                    client_secret: String::new(),
+                    scopes: Vec::new(),
+                });
+                self.providers.len() - 1
+            }
+        };
+        &mut self.providers[index]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LdapConfig {
+    /// Sign directory users in with an LDAP bind; local accounts keep their passwords
+    pub enabled: bool,
+    /// `ldap://` or `ldaps://` URL of the server
+    pub url: String,
+    /// Upgrade `ldap://` connections with StartTLS
+    pub starttls: bool,
+    /// Service account that looks users up; anonymous when empty
+    pub bind_dn: String,
+    pub bind_password: String,
+    pub user_base_dn: String,
+    /// `{username}` is replaced with the escaped login name
+    pub user_filter: String,
+    pub username_attribute: String,
+    pub email_attribute: String,
+    /// Attribute of user entries that lists their groups
+    pub group_attribute: String,
+    /// When set, groups are searched below it with `group_filter` instead
+    pub group_base_dn: String,
+    /// `{dn}` is replaced with the DN of the user
+    pub group_filter: String,
+    pub group_roles: Vec<LdapGroupConfig>,
+    /// Roles of every directory user
+    pub default_roles: Vec<String>,
+    /// Refuse directory users who are in none of `group_roles`
+    pub require_group: bool,
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LdapGroupConfig {
+    /// DN of the group
+    pub group: String,
+    /// Roles of its members
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// `EnvFilter` directives, e.g. `info` or `info,whisper_fleet_backend::audio=debug`
+    pub level: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: "127.0.0.1:3000".to_string(),
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        let audio = AudioServerConfig::default();
+        Self {
+            bind_addr: audio.bind_addr,
+            max_packet_size: audio.max_packet_size,
+            buffer_size: audio.buffer_size,
+            cleanup_interval_secs: audio.cleanup_interval.as_secs(),
+            heartbeat_interval_secs: audio.heartbeat_interval.as_secs(),
+            handshake_timeout_secs: audio.handshake_timeout.as_secs(),
+            user_timeout_secs: audio.user_timeout.as_secs(),
+            jitter_buffer_size: audio.jitter_buffer_size,
+            jitter_buffer_window_ms: audio.jitter_buffer_window_ms,
+            limits: UdpLimits::default(),
+        }
+    }
+}
+
+impl Default for UdpLimits {
+    fn default() -> Self {
+        let abuse = AbuseConfig::default();
+        Self {
+            packets_per_addr: abuse.addr_packets.rate,
+            bytes_per_addr: abuse.addr_bytes.rate,
+            packets_per_session: abuse.session_packets.rate,
+            bytes_per_session: abuse.session_bytes.rate,
+            handshakes_per_min: abuse.handshakes.rate * 60.0,
+            handshake_burst: abuse.handshakes.burst,
+        }
+    }
+}
+
+impl Default for SecretsConfig {
+    fn default() -> Self {
+        Self {
+            jwt_secret: DEV_JWT_SECRET.to_string(),
+            twofa_key: DEV_TWOFA_KEY.to_string(),
+            cookie_secret: DEV_COOKIE_SECRET.to_string(),
+        }
+    }
+}
+
+impl Default for TokensConfig {
+    fn default() -> Self {
+        Self {
+            issuer: DEFAULT_ISSUER.to_string(),
+            audience: DEFAULT_AUDIENCE.to_string(),
+            ttl_secs: DEFAULT_TTL.as_secs(),
+            refresh_ttl_secs: DEFAULT_REFRESH_TTL.as_secs(),
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        Self {
+            registration: true,
+            verification_ttl_secs: 24 * 60 * 60,
+            reset_ttl_secs: 60 * 60,
+            min_password_length: DEFAULT_MIN_PASSWORD_LENGTH,
+            breached_passwords_file: None,
+        }
+    }
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            transport: MailTransportKind::Log,
+            from: "VoiceLink <noreply@localhost>".to_string(),
+            frontend_url: "http://localhost:5173".to_string(),
+            dir: PathBuf::from("mail"),
+            smtp_host: String::new(),
+            smtp_port: 587,
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            queue_size: 100,
+            max_attempts: 5,
+            retry_delay_secs: 5,
+        }
+    }
+}
+
+impl Default for TwoFactorConfig {
+    fn default() -> Self {
+        Self {
+            issuer: "VoiceLink".to_string(),
+        }
+    }
+}
+
+impl Default for WebauthnConfig {
+    fn default() -> Self {
+        Self {
+            rp_id: "localhost".to_string(),
+            rp_origin: "http://localhost:5173".to_string(),
+            rp_name: "VoiceLink".to_string(),
+        }
+    }
+}
+
+impl Default for LdapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: "ldap://localhost:389".to_string(),
+            starttls: false,
+            bind_dn: String::new(),
+            bind_password: String::new(),
+            user_base_dn: String::new(),
+            user_filter: "(uid={username})".to_string(),
+            username_attribute: "uid".to_string(),
+            email_attribute: "mail".to_string(),
+            group_attribute: "memberOf".to_string(),
+            group_base_dn: String::new(),
+            group_filter: "(member={dn})".to_string(),
+            group_roles: Vec::new(),
+            default_roles: vec!["user".to_string()],
+            require_group: false,
+            timeout_secs: 5,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Load `CONFIG_FILE` (or `config.toml` when present), apply environment
+    /// overrides and validate the result
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = std::env::var("CONFIG_FILE").ok().map(PathBuf::from);
+        Self::load_from(path.as_deref(), |name| std::env::var(name).ok())
+    }
+
+    /// Load from `path`, or the default file if it exists, reading overrides through `env`
+    pub fn load_from(path: Option<&Path>, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let default_path = Path::new(DEFAULT_CONFIG_FILE);
+        let path = match path {
+            Some(path) => Some(path),
+            None if default_path.exists() => Some(default_path),
+            None => None,
+        };
+        let mut config = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+                    path: path.to_path_buf(),
+                    source,
+                })?;
+                toml::from_str(&text).map_err(|source| ConfigError::Parse {
+                    path: path.to_path_buf(),
+                    source,
+                })?
+            }
+            None => Config::default(),
+        };
+        config.apply_env(env)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Override settings from environment variables
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        if let Some(value) = env("HTTP_BIND_ADDR") {
+            self.http.bind_addr = value;
+        }
+        if let Some(value) = env("WS_BIND_ADDR") {
+            self.ws.bind_addr = Some(value).filter(|value| !value.is_empty());
+        }
+        if let Some(value) = env("UDP_BIND_ADDR") {
+            self.udp.bind_addr = value;
+        }
+        if let Some(value) = env("CORS_ORIGINS") {
+            self.http.cors_origins = value
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(value) = env("STATE_BACKEND") {
+            self.database.state_backend = match value.as_str() {
+                "memory" | "" => StateBackend::Memory,
+                "postgres" => StateBackend::Postgres,
+                _ => return Err(invalid("STATE_BACKEND", format!("expected memory or postgres, got '{}'", value))),
+            };
+        }
+        if let Some(value) = env("DATABASE_URL") {
+            self.database.url = Some(value);
+        }
+        if let Some(value) = env("JWT_SECRET") {
+            self.secrets.jwt_secret = value;
+        }
+        if let Some(value) = env("TWOFA_KEY") {
+            self.secrets.twofa_key = value;
+        }
+        if let Some(value) = env("COOKIE_SECRET") {
+            self.secrets.cookie_secret = value;
+        }
+        if let Some(value) = env("MAIL_TRANSPORT") {
+            self.mail.transport = match value.as_str() {
+                "log" | "" => MailTransportKind::Log,
+                "file" => MailTransportKind::File,
+                "smtp" => MailTransportKind::Smtp,
+                _ => return Err(invalid("MAIL_TRANSPORT", format!("expected log, file or smtp, got '{}'", value))),
+            };
+        }
+        if let Some(value) = env("EMAIL_FROM") {
+            self.mail.from = value;
+        }
+        if let Some(value) = env("FRONTEND_URL") {
+            self.mail.frontend_url = value;
+        }
+        if let Some(value) = env("SMTP_HOST") {
+            self.mail.smtp_host = value;
+        }
+        if let Some(value) = env("SMTP_PORT") {
+            self.mail.smtp_port = value
+                .parse()
+                .map_err(|_| invalid("SMTP_PORT", format!("'{}' is not a port", value)))?;
+        }
+        if let Some(value) = env("SMTP_USER") {
+            self.mail.smtp_username = value;
+        }
+        if let Some(value) = env("SMTP_PASS") {
+            self.mail.smtp_password = value;
+        }
+        if let Some(value) = env("WEBAUTHN_RP_ID") {
+            self.webauthn.rp_id = value;
+        }
+        if let Some(value) = env("WEBAUTHN_RP_ORIGIN") {
+            self.webauthn.rp_origin = value;
+        }
+        // Google and GitHub need no more than client credentials
+        for (prefix, name, kind, discovery_url) in [
+            ("GOOGLE", "google", OAuthProviderKind::Oidc, GOOGLE_DISCOVERY_URL),
+            ("GITHUB", "github", OAuthProviderKind::Github, ""),
+        ] {
+            if let Some(value) = env(&format!("{}_CLIENT_ID", prefix)) {
+                self.oauth.provider_mut(name, kind, discovery_url).client_id = value;
+            }
+            if let Some(value) = env(&format!("{}_CLIENT_SECRET", prefix)) {
+                self.oauth.provider_mut(name, kind, discovery_url).client_secret = value;
+            }
+        }
+        // Secrets of other providers, e.g. OAUTH_KEYCLOAK_CLIENT_SECRET
+        for provider in &mut self.oauth.providers {
+            let name = format!("OAUTH_{}_CLIENT_SECRET", provider.name.to_uppercase().replace('-', "_"));
+            if let Some(value) = env(&name) {
+                provider.client_secret = value;
+            }
+        }
+        if let Some(value) = env("LDAP_URL") {
+            self.ldap.url = value;
+        }
+        if let Some(value) = env("LDAP_BIND_DN") {
+            self.ldap.bind_dn = value;
+        }
+        if let Some(value) = env("LDAP_BIND_PASSWORD") {
+            self.ldap.bind_password = value;
+        }
+        if let Some(value) = env("RUST_LOG") {
+            self.log.level = value;
+        }
+        Ok(())
+    }
+
+    /// Check every setting, naming the first one that is wrong
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let http = parse_addr("http.bind_addr", &self.http.bind_addr)?;
+        if let Some(ws) = &self.ws.bind_addr {
+            if parse_addr("ws.bind_addr", ws)? == http {
+                return Err(invalid("ws.bind_addr", "must differ from http.bind_addr"));
+            }
+        }
+        parse_addr("udp.bind_addr", &self.udp.bind_addr)?;
+
+        for origin in &self.http.cors_origins {
+            if !(origin.starts_with("http://") || origin.starts_with("https://"))
+                || origin.parse::<axum::http::HeaderValue>().is_err()
+            {
+                return Err(invalid("http.cors_origins", format!("'{}' is not an http(s) origin", origin)));
+            }
+        }
+
+        let udp = &self.udp;
+        for (field, value) in [
+            ("udp.max_packet_size", udp.max_packet_size as u64),
+            ("udp.buffer_size", udp.buffer_size as u64),
+            ("udp.cleanup_interval_secs", udp.cleanup_interval_secs),
+            ("udp.heartbeat_interval_secs", udp.heartbeat_interval_secs),
+            ("udp.handshake_timeout_secs", udp.handshake_timeout_secs),
+            ("udp.user_timeout_secs", udp.user_timeout_secs),
+            ("udp.jitter_buffer_size", udp.jitter_buffer_size as u64),
+            ("udp.jitter_buffer_window_ms", udp.jitter_buffer_window_ms),
+        ] {
+            if value == 0 {
+                return Err(invalid(field, "must be greater than 0"));
+            }
+        }
+        if udp.buffer_size < udp.max_packet_size {
+            return Err(invalid("udp.buffer_size", "must be at least udp.max_packet_size"));
+        }
+        if udp.user_timeout_secs <= udp.heartbeat_interval_secs {
+            return Err(invalid("udp.user_timeout_secs", "must exceed udp.heartbeat_interval_secs"));
+        }
+
+        let limits = &udp.limits;
+        for (field, value) in [
+            ("udp.limits.packets_per_addr", limits.packets_per_addr),
+            ("udp.limits.bytes_per_addr", limits.bytes_per_addr),
+            ("udp.limits.packets_per_session", limits.packets_per_session),
+            ("udp.limits.bytes_per_session", limits.bytes_per_session),
+            ("udp.limits.handshakes_per_min", limits.handshakes_per_min),
+            ("udp.limits.handshake_burst", limits.handshake_burst),
+        ] {
+            if !value.is_finite() || value <= 0.0 {
+                return Err(invalid(field, "must be a positive number"));
+            }
+        }
+
+        if self.database.state_backend == StateBackend::Postgres
+            && self.database.url.as_deref().unwrap_or("").is_empty()
+        {
+            return Err(invalid("database.url", "must be set when database.state_backend is postgres"));
+        }
+        if self.secrets.jwt_secret.is_empty() {
+            return Err(invalid("secrets.jwt_secret", "must not be empty"));
+        }
+        if self.secrets.twofa_key.is_empty() {
+            return Err(invalid("secrets.twofa_key", "must not be empty"));
+        }
+        if self.secrets.cookie_secret.is_empty() {
+            return Err(invalid("secrets.cookie_secret", "must not be empty"));
+        }
+        // With the well-known default anyone could forge a login in progress
+        if !self.oauth.providers.is_empty() && self.uses_dev_cookie_secret() && !self.uses_dev_secret() {
+            return Err(invalid("secrets.cookie_secret", "must be set for OAuth unless the development token secret is in use"));
+        }
+        if self.twofa.issuer.is_empty() {
+            return Err(invalid("twofa.issuer", "must not be empty"));
+        }
+        let tokens = &self.tokens;
+        if tokens.issuer.is_empty() {
+            return Err(invalid("tokens.issuer", "must not be empty"));
+        }
+        if tokens.audience.is_empty() {
+            return Err(invalid("tokens.audience", "must not be empty"));
+        }
+        if tokens.ttl_secs == 0 {
+            return Err(invalid("tokens.ttl_secs", "must be greater than 0"));
+        }
+        if tokens.refresh_ttl_secs <= tokens.ttl_secs {
+            return Err(invalid("tokens.refresh_ttl_secs", "must be greater than tokens.ttl_secs"));
+        }
+        for (i, key) in tokens.keys.iter().enumerate() {
+            if key.kid.is_empty() {
+                return Err(invalid("tokens.keys", "every key needs a kid"));
+            }
+            if tokens.keys[..i].iter().any(|other| other.kid == key.kid) {
+                return Err(invalid("tokens.keys", format!("kid '{}' is used twice", key.kid)));
+            }
+        }
+        let accounts = &self.accounts;
+        if accounts.verification_ttl_secs == 0 {
+            return Err(invalid("accounts.verification_ttl_secs", "must be greater than 0"));
+        }
+        if accounts.reset_ttl_secs == 0 {
+            return Err(invalid("accounts.reset_ttl_secs", "must be greater than 0"));
+        }
+        if !(1..=MAX_PASSWORD_LENGTH).contains(&accounts.min_password_length) {
+            return Err(invalid("accounts.min_password_length", format!("must be between 1 and {}", MAX_PASSWORD_LENGTH)));
+        }
+        let mail = &self.mail;
+        if mail.from.parse::<lettre::message::Mailbox>().is_err() {
+            return Err(invalid("mail.from", format!("'{}' is not an email address", mail.from)));
+        }
+        if !(mail.frontend_url.starts_with("http://") || mail.frontend_url.starts_with("https://")) {
+            return Err(invalid("mail.frontend_url", format!("'{}' is not an http(s) URL", mail.frontend_url)));
+        }
+        if mail.transport == MailTransportKind::Smtp && mail.smtp_host.is_empty() {
+            return Err(invalid("mail.smtp_host", "must be set when mail.transport is smtp"));
+        }
+        // Logged mail would put reset links in the logs of a real deployment
+        if mail.transport == MailTransportKind::Log && !self.uses_dev_secret() {
+            return Err(invalid("mail.transport", "must be file or smtp unless the development token secret is in use"));
+        }
+        if mail.queue_size == 0 {
+            return Err(invalid("mail.queue_size", "must be greater than 0"));
+        }
+        if mail.max_attempts == 0 {
+            return Err(invalid("mail.max_attempts", "must be greater than 0"));
+        }
+        if let Err(e) = self.passkeys() {
+            return Err(invalid("webauthn.rp_origin", format!("must be within webauthn.rp_id: {}", e)));
+        }
+        for (i, provider) in self.oauth.providers.iter().enumerate() {
+            if provider.name.is_empty()
+                || !provider.name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            {
+                return Err(invalid("oauth.providers", format!("'{}' is not a name of lowercase letters, digits and '-'", provider.name)));
+            }
+            if self.oauth.providers[..i].iter().any(|other| other.name == provider.name) {
+                return Err(invalid("oauth.providers", format!("'{}' is configured twice", provider.name)));
+            }
+            if provider.client_id.is_empty() {
+                return Err(invalid("oauth.providers", format!("{} needs a client_id", provider.name)));
+            }
+            if provider.kind == OAuthProviderKind::Oidc
+                && !(provider.discovery_url.starts_with("http://") || provider.discovery_url.starts_with("https://"))
+            {
+                return Err(invalid("oauth.providers", format!("{} needs an http(s) discovery_url", provider.name)));
+            }
+        }
+        let ldap = &self.ldap;
+        if ldap.enabled {
+            if !(ldap.url.starts_with("ldap://") || ldap.url.starts_with("ldaps://")) {
+                return Err(invalid("ldap.url", format!("'{}' is not an ldap(s) URL", ldap.url)));
+            }
+            if ldap.starttls && ldap.url.starts_with("ldaps://") {
+                return Err(invalid("ldap.starttls", "an ldaps URL is encrypted already"));
+            }
+            if ldap.bind_dn.is_empty() && !ldap.bind_password.is_empty() {
+                return Err(invalid("ldap.bind_dn", "must be set with ldap.bind_password"));
+            }
+            if ldap.user_base_dn.is_empty() {
+                return Err(invalid("ldap.user_base_dn", "must be set when ldap is enabled"));
+            }
+            if !ldap.user_filter.contains("{username}") {
+                return Err(invalid("ldap.user_filter", "must contain {username}"));
+            }
+            if ldap.username_attribute.is_empty() {
+                return Err(invalid("ldap.username_attribute", "must not be empty"));
+            }
+            if ldap.email_attribute.is_empty() {
+                return Err(invalid("ldap.email_attribute", "must not be empty"));
+            }
+            if ldap.group_base_dn.is_empty() && ldap.group_attribute.is_empty() {
+                return Err(invalid("ldap.group_attribute", "must be set unless ldap.group_base_dn is"));
+            }
+            if !ldap.group_base_dn.is_empty() && !ldap.group_filter.contains("{dn}") {
+                return Err(invalid("ldap.group_filter", "must contain {dn}"));
+            }
+            if let Some(group) = ldap.group_roles.iter().find(|group| group.group.is_empty() || group.roles.is_empty()) {
+                return Err(invalid("ldap.group_roles", format!("'{}' needs a group DN and roles", group.group)));
+            }
+            if ldap.require_group && ldap.group_roles.is_empty() {
+                return Err(invalid("ldap.group_roles", "must not be empty when ldap.require_group is set"));
+            }
+            if ldap.timeout_secs == 0 {
+                return Err(invalid("ldap.timeout_secs", "must be greater than 0"));
+            }
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log.level) {
+            return Err(invalid("log.level", e.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Settings that differ in `new` but only take effect after a restart
+    pub fn restart_required(&self, new: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.http != new.http {
+            changed.push("http");
+        }
+        if self.ws != new.ws {
+            changed.push("ws");
+        }
+        let (old_udp, new_udp) = (&self.udp, &new.udp);
+        if old_udp.bind_addr != new_udp.bind_addr {
+            changed.push("udp.bind_addr");
+        }
+        if old_udp.max_packet_size != new_udp.max_packet_size || old_udp.buffer_size != new_udp.buffer_size {
+            changed.push("udp packet sizes");
+        }
+        if old_udp.cleanup_interval_secs != new_udp.cleanup_interval_secs
+            || old_udp.heartbeat_interval_secs != new_udp.heartbeat_interval_secs
+        {
+            changed.push("udp intervals");
+        }
+        if self.database != new.database {
+            changed.push("database");
+        }
+        let (old_tokens, new_tokens) = (&self.tokens, &new.tokens);
+        if old_tokens.issuer != new_tokens.issuer
+            || old_tokens.audience != new_tokens.audience
+            || old_tokens.ttl_secs != new_tokens.ttl_secs
+            || old_tokens.refresh_ttl_secs != new_tokens.refresh_ttl_secs
+        {
+            changed.push("tokens");
+        }
+        if self.accounts != new.accounts {
+            changed.push("accounts");
+        }
+        if self.mail != new.mail {
+            changed.push("mail");
+        }
+        if self.secrets.twofa_key != new.secrets.twofa_key || self.twofa != new.twofa {
+            changed.push("twofa");
+        }
+        if self.webauthn != new.webauthn {
+            changed.push("webauthn");
+        }
+        if self.oauth != new.oauth {
+            changed.push("oauth");
+        }
+        if self.ldap != new.ldap {
+            changed.push("ldap");
+        }
+        changed
+    }
+
+    /// Read the token keys, signing key first
+    pub fn token_keys(&self) -> Result<Vec<SigningKey>, ConfigError> {
+        if self.tokens.keys.is_empty() {
+            return Ok(vec![SigningKey::hmac(SECRET_KID, self.secrets.jwt_secret.as_bytes())]);
+        }
+        self.tokens
+            .keys
+            .iter()
+            .map(|key| {
+                let pem = std::fs::read_to_string(&key.private_key_file).map_err(|e| {
+                    invalid("tokens.keys", format!("can't read {}: {}", key.private_key_file.display(), e))
+                })?;
+                let algorithm = match key.algorithm {
+                    TokenAlgorithm::EdDSA => jsonwebtoken::Algorithm::EdDSA,
+                    TokenAlgorithm::RS256 => jsonwebtoken::Algorithm::RS256,
+                };
+                SigningKey::from_pkcs8_pem(&key.kid, algorithm, &pem).map_err(|e| invalid("tokens.keys", e.to_string()))
+            })
+            .collect()
+    }
+
+    pub fn token_service(&self) -> Result<TokenService, ConfigError> {
+        let tokens = TokenService::new(
+            self.token_keys()?,
+            &self.tokens.issuer,
+            &self.tokens.audience,
+            Duration::from_secs(self.tokens.ttl_secs),
+        )
+        .map_err(|e| invalid("tokens.keys", e.to_string()))?;
+        Ok(tokens.with_refresh_ttl(Duration::from_secs(self.tokens.refresh_ttl_secs)))
+    }
+
+    /// Registration settings, reading `accounts.breached_passwords_file`
+    pub fn registration(&self) -> Result<Registration, ConfigError> {
+        let accounts = &self.accounts;
+        let mut passwords = PasswordPolicy::new(accounts.min_password_length);
+        if let Some(path) = &accounts.breached_passwords_file {
+            passwords = passwords.with_breached_file(path).map_err(|e| {
+                invalid("accounts.breached_passwords_file", format!("can't read {}: {}", path.display(), e))
+            })?;
+        }
+        Ok(Registration {
+            enabled: accounts.registration,
+            verification_ttl: Duration::from_secs(accounts.verification_ttl_secs),
+            reset_ttl: Duration::from_secs(accounts.reset_ttl_secs),
+            passwords,
+        })
+    }
+
+    /// The configured transport behind a send queue; call inside the runtime
+    pub fn mailer(&self) -> Result<Mailer, ConfigError> {
+        let mail = &self.mail;
+        let transport: Arc<dyn MailTransport> = match mail.transport {
+            MailTransportKind::Log => Arc::new(LogTransport),
+            MailTransportKind::File => Arc::new(FileTransport::new(&mail.dir)),
+            MailTransportKind::Smtp => Arc::new(
+                SmtpTransport::new(&mail.smtp_host, mail.smtp_port, &mail.smtp_username, &mail.smtp_password)
+                    .map_err(|e| invalid("mail.smtp_host", e.to_string()))?,
+            ),
+        };
+        let queue = QueueSettings {
+            capacity: mail.queue_size,
+            max_attempts: mail.max_attempts,
+            retry_delay: Duration::from_secs(mail.retry_delay_secs),
+        };
+        Ok(Mailer::new(transport, &mail.from, &mail.frontend_url).with_queue(queue))
+    }
+
+    pub fn two_factor(&self) -> TwoFactor {
+        TwoFactor::new(&self.secrets.twofa_key, &self.twofa.issuer)
+    }
+
+    pub fn passkeys(&self) -> Result<Passkeys, ConfigError> {
+        let webauthn = &self.webauthn;
+        Passkeys::new(&webauthn.rp_id, &webauthn.rp_origin, &webauthn.rp_name)
+            .map_err(|e| invalid("webauthn.rp_origin", e.to_string()))
+    }
+
+    /// Sign-in providers, sending users back to the frontend
+    pub fn oauth_providers(&self) -> OAuthProviders {
+        let frontend_url = self.mail.frontend_url.trim_end_matches('/');
+        let providers = self
+            .oauth
+            .providers
+            .iter()
+            .map(|provider| {
+                let redirect_url = format!("{}/oauth/{}/callback", frontend_url, provider.name);
+                let built = match provider.kind {
+                    OAuthProviderKind::Oidc => Provider::oidc(
+                        &provider.name,
+                        &provider.discovery_url,
+                        &provider.client_id,
+                        &provider.client_secret,
+                        &redirect_url,
+                    ),
+                    OAuthProviderKind::Github => {
+                        Provider::github(&provider.name, &provider.client_id, &provider.client_secret, &redirect_url)
+                    }
+                };
+                if provider.scopes.is_empty() {
+                    built
+                } else {
+                    built.with_scopes(provider.scopes.clone())
+                }
+            })
+            .collect();
+        // Any instance sharing the cookie secret can finish a login
+        OAuthProviders::new(providers).with_cookie_secret(self.secrets.cookie_secret.as_bytes())
+    }
+
+    /// The directory to check passwords with, when enabled
+    pub fn ldap(&self) -> Option<LdapDirectory> {
+        let ldap = &self.ldap;
+        if !ldap.enabled {
+            return None;
+        }
+        Some(LdapDirectory::new(LdapSettings {
+            url: ldap.url.clone(),
+            starttls: ldap.starttls,
+            bind_dn: ldap.bind_dn.clone(),
+            bind_password: ldap.bind_password.clone(),
+            user_base_dn: ldap.user_base_dn.clone(),
+            user_filter: ldap.user_filter.clone(),
+            username_attribute: ldap.username_attribute.clone(),
+            email_attribute: ldap.email_attribute.clone(),
+            group_attribute: ldap.group_attribute.clone(),
+            group_base_dn: ldap.group_base_dn.clone(),
+            group_filter: ldap.group_filter.clone(),
+            group_roles: ldap.group_roles.iter().map(|group| (group.group.clone(), group.roles.clone())).collect(),
+            default_roles: ldap.default_roles.clone(),
+            require_group: ldap.require_group,
+            timeout: Duration::from_secs(ldap.timeout_secs),
+        }))
+    }
+
+    /// Whether TOTP secrets are encrypted with the well-known development key
+    pub fn uses_dev_twofa_key(&self) -> bool {
+        self.secrets.twofa_key == DEV_TWOFA_KEY
+    }
+
+    /// Whether OAuth login cookies are signed with the well-known development secret
+    pub fn uses_dev_cookie_secret(&self) -> bool {
+        self.secrets.cookie_secret == DEV_COOKIE_SECRET
+    }
+
+    /// Whether tokens are signed with the well-known development secret
+    pub fn uses_dev_secret(&self) -> bool {
+        self.tokens.keys.is_empty() && self.secrets.jwt_secret == DEV_JWT_SECRET
+    }
+
+    /// Audio server settings; relay settings still come from `RELAY_*`
+    pub fn audio_server_config(&self, relay: Option<RelayConfig>) -> AudioServerConfig {
+        let udp = &self.udp;
+        AudioServerConfig {
+            bind_addr: udp.bind_addr.clone(),
+            max_packet_size: udp.max_packet_size,
+            buffer_size: udp.buffer_size,
+            cleanup_interval: Duration::from_secs(udp.cleanup_interval_secs),
+            user_timeout: Duration::from_secs(udp.user_timeout_secs),
+            heartbeat_interval: Duration::from_secs(udp.heartbeat_interval_secs),
+            handshake_timeout: Duration::from_secs(udp.handshake_timeout_secs),
+            jitter_buffer_size: udp.jitter_buffer_size,
+            jitter_buffer_window_ms: udp.jitter_buffer_window_ms,
+            relay,
+            abuse: self.abuse_config(),
+            ..AudioServerConfig::default()
+        }
+    }
+
+    fn abuse_config(&self) -> AbuseConfig {
+        let limits = &self.udp.limits;
+        let doubled = |rate: f64| BucketConfig::new(rate, rate * 2.0);
+        AbuseConfig {
+            addr_packets: doubled(limits.packets_per_addr),
+            addr_bytes: doubled(limits.bytes_per_addr),
+            session_packets: doubled(limits.packets_per_session),
+            session_bytes: doubled(limits.bytes_per_session),
+            handshakes: BucketConfig::new(limits.handshakes_per_min / 60.0, limits.handshake_burst),
+            ..AbuseConfig::default()
+        }
+    }
+}
+
+fn parse_addr(field: &'static str, value: &str) -> Result<SocketAddr, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| invalid(field, format!("'{}' is not a host:port socket address", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(toml: &str, env: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let path = std::env::temp_dir().join(format!("whisper-fleet-config-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, toml).unwrap();
+        let env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let result = Config::load_from(Some(&path), |name| env.get(name).cloned());
+        std::fs::remove_file(&path).ok();
+        result
+    }
+
+    #[test]
+    fn test_file_and_env_overrides() {
+        let config = load(
+            r#"
+            [http]
+            bind_addr = "0.0.0.0:3000"
+            cors_origins = ["https://fleet.example.com"]
+
+            [udp]
+            user_timeout_secs = 120
+
+            [udp.limits]
+            handshakes_per_min = 60
+
+            [database]
+            state_backend = "postgres"
+            url = "postgres://localhost/fleet"
+
+            [ldap]
+            enabled = true
+            user_base_dn = "ou=people,dc=example,dc=org"
+            require_group = true
+
+            [[ldap.group_roles]]
+            group = "cn=fleet-admins,ou=groups,dc=example,dc=org"
+            roles = ["admin", "user"]
+
+            [[oauth.providers]]
+            name = "keycloak"
+            discovery_url = "https://sso.example.com/realms/fleet/.well-known/openid-configuration"
+            client_id = "voicelink"
+            "#,
+            &[
+                ("UDP_BIND_ADDR", "0.0.0.0:9000"),
+                ("JWT_SECRET", "s3cret"),
+                ("COOKIE_SECRET", "c00kie"),
+                ("MAIL_TRANSPORT", "file"),
+                ("OAUTH_KEYCLOAK_CLIENT_SECRET", "kc-secret"),
+                ("GITHUB_CLIENT_ID", "gh-id"),
+                ("LDAP_BIND_DN", "cn=voicelink,dc=example,dc=org"),
+                ("LDAP_BIND_PASSWORD", "ldap-secret"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(config.http.cors_origins, vec!["https://fleet.example.com"]);
+        assert_eq!(config.database.state_backend, StateBackend::Postgres);
+
+        let audio = config.audio_server_config(None);
+        assert_eq!(audio.bind_addr, "0.0.0.0:9000");
+        assert_eq!(audio.user_timeout, Duration::from_secs(120));
+        assert!(!config.uses_dev_secret());
+        assert_eq!(audio.abuse.handshakes.rate, 1.0);
+        assert_eq!(audio.abuse.addr_packets, AbuseConfig::default().addr_packets);
+
+        assert_eq!(config.oauth.providers[0].client_secret, "kc-secret");
+        assert_eq!(config.oauth.providers[1].kind, OAuthProviderKind::Github);
+        assert_eq!(config.oauth_providers().names(), vec!["github", "keycloak"]);
+
+        assert_eq!(config.ldap.bind_password, "ldap-secret");
+        let directory = config.ldap().unwrap();
+        assert_eq!(directory.roles(&["cn=fleet-admins,ou=groups,dc=example,dc=org".to_string()]), Some(vec!["user".to_string(), "admin".to_string()]));
+        assert_eq!(directory.roles(&[]), None);
+        assert!(Config::default().ldap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_settings_are_named() {
+        let field = |result: Result<Config, ConfigError>| match result {
+            Err(ConfigError::Invalid { field, .. }) => field,
+            other => panic!("expected an invalid setting, got {:?}", other),
+        };
+
+        assert_eq!(field(load("", &[("HTTP_BIND_ADDR", "localhost")])), "http.bind_addr");
+        assert_eq!(field(load("[udp]\nuser_timeout_secs = 0", &[])), "udp.user_timeout_secs");
+        assert_eq!(field(load("[database]\nstate_backend = \"postgres\"", &[])), "database.url");
+        assert_eq!(field(load("[http]\ncors_origins = [\"fleet.example.com\"]", &[])), "http.cors_origins");
+        assert_eq!(field(load("", &[("WS_BIND_ADDR", "127.0.0.1:3000")])), "ws.bind_addr");
+        assert_eq!(field(load("", &[("JWT_SECRET", "")])), "secrets.jwt_secret");
+        assert_eq!(field(load("", &[("TWOFA_KEY", "")])), "secrets.twofa_key");
+        assert_eq!(field(load("", &[("COOKIE_SECRET", "")])), "secrets.cookie_secret");
+        let github = [("JWT_SECRET", "s3cret"), ("MAIL_TRANSPORT", "file"), ("GITHUB_CLIENT_ID", "id"), ("GITHUB_CLIENT_SECRET", "secret")];
+        assert_eq!(field(load("", &github)), "secrets.cookie_secret");
+        assert!(load("", &[github.as_slice(), &[("COOKIE_SECRET", "c00kie")]].concat()).is_ok());
+        assert_eq!(field(load("", &[("RUST_LOG", "info,[")])), "log.level");
+        assert_eq!(field(load("[tokens]\nttl_secs = 3600\nrefresh_ttl_secs = 600", &[])), "tokens.refresh_ttl_secs");
+        assert_eq!(field(load("", &[("MAIL_TRANSPORT", "smtp")])), "mail.smtp_host");
+        assert_eq!(field(load("", &[("JWT_SECRET", "s3cret")])), "mail.transport");
+        assert!(load("", &[("JWT_SECRET", "s3cret"), ("MAIL_TRANSPORT", "file")]).is_ok());
+        assert_eq!(field(load("[mail]\nfrom = \"nobody\"", &[])), "mail.from");
+        assert_eq!(field(load("[mail]\nqueue_size = 0", &[])), "mail.queue_size");
+        assert_eq!(field(load("[mail]\nmax_attempts = 0", &[])), "mail.max_attempts");
+        assert_eq!(field(load("[accounts]\nmin_password_length = 0", &[])), "accounts.min_password_length");
+        assert_eq!(field(load("[accounts]\nreset_ttl_secs = 0", &[])), "accounts.reset_ttl_secs");
+        assert_eq!(field(load("", &[("WEBAUTHN_RP_ORIGIN", "https://fleet.example.com")])), "webauthn.rp_origin");
+        assert_eq!(field(load("[[oauth.providers]]\nname = \"sso\"\nclient_id = \"id\"", &[])), "oauth.providers");
+        assert_eq!(field(load("", &[("GOOGLE_CLIENT_SECRET", "secret")])), "oauth.providers");
+        assert_eq!(field(load("[ldap]\nenabled = true", &[])), "ldap.user_base_dn");
+        assert_eq!(field(load("[ldap]\nenabled = true\nuser_base_dn = \"dc=example\"\nuser_filter = \"(uid=*)\"", &[])), "ldap.user_filter");
+        assert_eq!(field(load("[ldap]\nenabled = true", &[("LDAP_URL", "ldap.example.org")])), "ldap.url");
+        assert_eq!(
+            field(load("[[tokens.keys]]\nkid = \"a\"\nalgorithm = \"EdDSA\"\nprivate_key_file = \"a.pem\"\n[[tokens.keys]]\nkid = \"a\"\nalgorithm = \"RS256\"\nprivate_key_file = \"b.pem\"", &[])),
+            "tokens.keys"
+        );
+
+        // Unknown keys are typos, not silently ignored
+        assert!(matches!(load("[udp]\nuser_timeout = 10", &[]), Err(ConfigError::Parse { .. })));
+    }
+
+    #[test]
+    fn test_restart_required() {
+        let old = Config::default();
+        let mut new = old.clone();
+        new.udp.user_timeout_secs = 60;
+        new.udp.limits.packets_per_addr = 100.0;
+        new.log.level = "debug".to_string();
+        assert!(old.restart_required(&new).is_empty());
+
+        // Keys rotate on reload, token claims need a restart
+        new.secrets.jwt_secret = "rotated".to_string();
+        assert!(old.restart_required(&new).is_empty());
+        new.udp.bind_addr = "0.0.0.0:9000".to_string();
+        new.tokens.audience = "fleet".to_string();
+        assert_eq!(old.restart_required(&new), vec!["udp.bind_addr", "tokens"]);
+    }
+}
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use crate::routes::channels::Role;
+
+/// Why a moderator removed a user from a channel
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalReason {
+    Kicked,
+    Banned,
+}
+
+impl RemovalReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RemovalReason::Kicked => "kicked",
+            RemovalReason::Banned => "banned",
+        }
+    }
+
+    /// Text shown to the removed user, with the moderator's note if any
+    pub fn message(&self, note: Option<&str>) -> String {
+        let message = match self {
+            RemovalReason::Kicked => "You were kicked from the channel",
+            RemovalReason::Banned => "You were banned from the channel",
+        };
+        match note {
+            Some(note) => format!("{}: {}", message, note),
+            None => message.to_string(),
+        }
+    }
+}
+
+/// Channel-level events published by the HTTP routes for other subsystems
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ChannelEvent {
+    RecordingStarted {
+        channel_id: String,
+        recording_id: String,
+        started_by: String,
+    },
+    RecordingStopped {
+        channel_id: String,
+        recording_id: String,
+    },
+    /// Moderator-imposed voice state of a user changed; both flags false
+    /// once lifted or the user has left the channel
+    VoiceModerationChanged {
+        channel_id: String,
+        user_id: String,
+        server_muted: bool,
+        server_deafened: bool,
+        changed_by: Option<String>,
+    },
+    /// A user lost membership; their live sessions in the channel must end
+    MemberRemoved {
+        channel_id: String,
+        user_id: String,
+        reason: RemovalReason,
+        removed_by: String,
+        note: Option<String>,
+    },
+    /// A user's role changed; live sessions keep running with the new role
+    RoleChanged {
+        channel_id: String,
+        user_id: String,
+        role: Role,
+        changed_by: String,
+    },
+}
+
+impl ChannelEvent {
+    pub fn channel_id(&self) -> &str {
+        match self {
+            ChannelEvent::RecordingStarted { channel_id, .. } => channel_id,
+            ChannelEvent::RecordingStopped { channel_id, .. } => channel_id,
+            ChannelEvent::VoiceModerationChanged { channel_id, .. } => channel_id,
+            ChannelEvent::MemberRemoved { channel_id, .. } => channel_id,
+            ChannelEvent::RoleChanged { channel_id, .. } => channel_id,
+        }
+    }
+}
+
+/// Broadcast bus for channel events
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<ChannelEvent>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    /// Publish an event; events without subscribers are dropped
+    pub fn publish(&self, event: ChannelEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChannelEvent> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
@@ -1,27 +1,32 @@
 use std::io;
-use std::fs;
-use std::path::Path;
-use rand::RngCore;
-use windows_dpapi::{encrypt_data, decrypt_data, ProtectionScope};
 
+#[cfg(target_os = "windows")]
 const KEY_FILE: &str = "backend/whisperlink.key";
 
 #[cfg(target_os = "windows")]
 mod windows {
     use super::*;
+    use rand::RngCore;
+    use std::fs;
+    use std::path::Path;
+    use windows_dpapi::{decrypt_data, encrypt_data, Scope};
 
     pub fn get_or_create_key() -> io::Result<[u8; 32]> {
         if Path::new(KEY_FILE).exists() {
             let enc = fs::read(KEY_FILE)?;
-            let key = decrypt_data(&enc, None, ProtectionScope::CurrentUser)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            let key = decrypt_data(&enc, Scope::User, None)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            if key.len() != 32 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Stored key has the wrong length"));
+            }
             let mut arr = [0u8; 32];
             arr.copy_from_slice(&key);
             Ok(arr)
         } else {
             let mut key = [0u8; 32];
             rand::thread_rng().fill_bytes(&mut key);
-            let enc = encrypt_data(&key, None, ProtectionScope::CurrentUser)?;
+            let enc = encrypt_data(&key, Scope::User, None)
+                .map_err(|e| io::Error::other(e.to_string()))?;
             fs::write(KEY_FILE, enc)?;
             Ok(key)
         }
@@ -30,8 +35,8 @@ mod windows {
 
 #[cfg(not(target_os = "windows"))]
 pub fn get_or_create_key() -> io::Result<[u8; 32]> {
-    Err(io::Error::new(io::ErrorKind::Other, "Key storage only implemented for Windows"))
+    Err(io::Error::other("Key storage only implemented for Windows"))
 }
 
 #[cfg(target_os = "windows")]
-pub use windows::get_or_create_key; 
\ No newline at end of file
+pub use windows::get_or_create_key;
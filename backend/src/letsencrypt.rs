@@ -1,61 +1,70 @@
 use std::io;
-use acme_lib::DirectoryUrl;
+use acme_lib::{create_p384_key, Directory, DirectoryUrl};
 use acme_lib::persist::FilePersist;
-use acme_lib::create_p384_key;
-use acme_lib::Certificate;
 
 pub async fn obtain_certificate(domain: &str, email: &str) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    // acme-lib blocks on its HTTP calls and the challenge prompt
+    let (domain, email) = (domain.to_string(), email.to_string());
+    tokio::task::spawn_blocking(move || obtain_certificate_blocking(&domain, &email))
+        .await
+        .map_err(|e| io::Error::other(e.to_string()))?
+}
+
+fn obtain_certificate_blocking(domain: &str, email: &str) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let other = |e: acme_lib::Error| io::Error::other(e.to_string());
     let persist = FilePersist::new("backend/acme_store");
-    let dir = match acme_lib::Directory::from_url(persist, DirectoryUrl::LetsEncrypt).await {
+    let dir = match Directory::from_url(persist, DirectoryUrl::LetsEncrypt) {
         Ok(d) => d,
         Err(e) => {
             eprintln!("[letsencrypt] Failed to connect to Let's Encrypt: {}", e);
             return fallback_self_signed(domain);
         }
     };
-    let acc = match dir.account(email).await {
+    let acc = match dir.account(email) {
         Ok(a) => a,
         Err(e) => {
             eprintln!("[letsencrypt] Failed to create ACME account: {}", e);
             return fallback_self_signed(domain);
         }
     };
-    let mut ord = match acc.new_order(domain, &[]).await {
+    let mut ord = match acc.new_order(domain, &[]) {
         Ok(o) => o,
         Err(e) => {
             eprintln!("[letsencrypt] Failed to create order: {}", e);
             return fallback_self_signed(domain);
         }
     };
-    let auths = ord.authorizations().await.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-    for auth in auths {
-        let chall = auth.http_challenge().ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No HTTP challenge"))?;
-        // User must serve chall.token() -> chall.http_proof() at http://domain/.well-known/acme-challenge/
-        println!("[letsencrypt] To verify, serve {} at /.well-known/acme-challenge/{}", chall.http_proof(), chall.token());
-        // For automation, you must set up a temporary HTTP server to serve this file.
-        // For now, wait for user to confirm.
-        println!("[letsencrypt] Press Enter after challenge is set up...");
-        let mut s = String::new();
-        let _ = std::io::stdin().read_line(&mut s);
-        chall.validate().await.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-    }
-    let pkey = create_p384_key();
-    let cert = ord.finalize_pkey(pkey, 5000).await.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-    let cert_pem = cert.certificate().to_pem().map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-    let key_pem = cert.private_key().to_pem().map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-    Ok((cert_pem, key_pem))
+    let csr = loop {
+        if let Some(csr) = ord.confirm_validations() {
+            break csr;
+        }
+        for auth in ord.authorizations().map_err(other)? {
+            let chall = auth.http_challenge();
+            // User must serve chall.http_token() -> chall.http_proof() at http://domain/.well-known/acme-challenge/
+            println!("[letsencrypt] To verify, serve {} at /.well-known/acme-challenge/{}", chall.http_proof(), chall.http_token());
+            // For automation, you must set up a temporary HTTP server to serve this file.
+            // For now, wait for user to confirm.
+            println!("[letsencrypt] Press Enter after challenge is set up...");
+            let mut s = String::new();
+            let _ = std::io::stdin().read_line(&mut s);
+            chall.validate(5000).map_err(other)?;
+        }
+        ord.refresh().map_err(other)?;
+    };
+    let cert = csr.finalize_pkey(create_p384_key(), 5000).map_err(other)?.download_and_save_cert().map_err(other)?;
+    Ok((cert.certificate().as_bytes().to_vec(), cert.private_key().as_bytes().to_vec()))
 }
 
 fn fallback_self_signed(domain: &str) -> io::Result<(Vec<u8>, Vec<u8>)> {
     use openssl::rsa::Rsa;
-    use openssl::x509::{X509, X509NameBuilder};
+    use openssl::x509::X509NameBuilder;
     use openssl::pkey::PKey;
     use openssl::x509::X509Builder;
     use openssl::x509::extension::SubjectAlternativeName;
     use openssl::hash::MessageDigest;
     use openssl::asn1::Asn1Time;
-    let rsa = Rsa::generate(4096).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-    let pkey = PKey::from_rsa(rsa).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    let rsa = Rsa::generate(4096).map_err(|e| io::Error::other(e.to_string()))?;
+    let pkey = PKey::from_rsa(rsa).map_err(|e| io::Error::other(e.to_string()))?;
     let mut name = X509NameBuilder::new().unwrap();
     name.append_entry_by_text("CN", domain).unwrap();
     let name = name.build();
@@ -1,36 +1,69 @@
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, post},
     Router,
 };
-use tower_http::cors::{Any, CorsLayer};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry};
 use std::fs;
 use std::path::PathBuf;
 use tracing_appender::rolling;
 
+mod config;
 mod routes;
 mod ws;
 mod audio;
+mod events;
+mod state;
+mod metrics;
+mod tokens;
+mod voice;
 use routes::channels::AppState;
 use ws::WsAppState;
 use audio::AudioServer;
 mod setup;
+mod key_manager;
+mod letsencrypt;
+mod auto_update;
 mod notify_helper;
 
 #[tokio::main]
 async fn main() {
+    dotenvy::dotenv().ok();
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Set up file logging to %APPDATA%/WhisperFleetLink/log.txt
-    let log_dir = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    let log_dir = data_dir().unwrap_or_else(|| PathBuf::from("."));
     let log_dir = log_dir.join("WhisperFleetLink");
     fs::create_dir_all(&log_dir).ok();
     let file_appender = rolling::never(&log_dir, "log.txt");
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+    // The filter sits behind a reload layer so SIGHUP can change the log level
+    let (log_filter, log_reload) = reload::Layer::new(EnvFilter::new(&config.log.level));
     tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
-        ))
+        .with(log_filter)
         .with(tracing_subscriber::fmt::layer().with_writer(non_blocking))
         .init();
+    if config.uses_dev_secret() {
+        tracing::warn!("Signing tokens with the development secret; configure tokens.keys or JWT_SECRET in production");
+    }
+    if config.uses_dev_twofa_key() {
+        tracing::warn!("Encrypting 2FA secrets with the development key; set TWOFA_KEY in production");
+    }
+    let tokens = match config.token_service() {
+        Ok(tokens) => std::sync::Arc::new(tokens),
+        Err(e) => {
+            tracing::error!("Failed to load token keys: {}", e);
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Orchestrate all setup, cert, and update logic
     if let Err(e) = run_startup().await {
@@ -39,37 +72,156 @@ async fn main() {
         std::process::exit(1);
     }
 
-    // Configure CORS
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    // Configure CORS; origins were validated when the config was loaded
+    // Listed origins may send cookies too, which OAuth sign-ins need
+    let cors = if config.http.cors_origins.is_empty() {
+        CorsLayer::new().allow_origin(AllowOrigin::any()).allow_methods(Any).allow_headers(Any)
+    } else {
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(config.http.cors_origins.iter().filter_map(|origin| origin.parse().ok())))
+            .allow_methods(AllowMethods::mirror_request())
+            .allow_headers(AllowHeaders::mirror_request())
+            .allow_credentials(true)
+    };
+
+    // User accounts live in the database; without one, logins are refused
+    let pool = match &config.database.url {
+        Some(db_url) => {
+            let pool = routes::db::get_pool(db_url).await;
+            routes::db::run_migrations(&pool).await;
+            Some(pool)
+        }
+        None => {
+            tracing::warn!("No database configured; user logins are disabled");
+            None
+        }
+    };
+    let users = std::sync::Arc::new(match &pool {
+        Some(pool) => routes::user::UserDirectory::new(pool.clone()),
+        None => routes::user::UserDirectory::default(),
+    });
+
+    // Share channels, presence, refresh tokens and revocations with other
+    // instances with the postgres state backend
+    let shared = match (config.database.state_backend, &pool) {
+        (config::StateBackend::Postgres, Some(pool)) => {
+            match state::PostgresBackend::connect(pool.clone()).await {
+                Ok(backend) => state::SharedState::new(std::sync::Arc::new(backend)),
+                Err(e) => {
+                    tracing::error!("Failed to start Postgres state backend: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => state::SharedState::in_process(),
+    };
+
+    // Mail goes out from a background queue, so slow servers never hold up
+    // a request
+    let mailer = match config.mailer() {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            tracing::error!("Invalid mail settings: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Create shared state
-    let state = AppState::new();
-    let ws_state = WsAppState::new();
+    let state = AppState::with_shared_state(
+        audio::RecordingConfig {
+            dir: log_dir.join("recordings"),
+        },
+        shared.clone(),
+    )
+    .with_tokens(tokens.clone())
+    .with_users(users)
+    .with_mailer(mailer.clone());
+    if let Err(e) = state.start_replication().await {
+        tracing::error!("Failed to load shared channel state: {}", e);
+        std::process::exit(1);
+    }
+    let metrics = std::sync::Arc::new(metrics::Metrics::new());
+    let ws_state = WsAppState::with_shared_state(shared.clone())
+        .with_metrics(metrics.clone())
+        .with_channel_state(state.clone())
+        .with_tokens(tokens.clone());
+    ws_state.listen(&state.events);
+
+    // Relay to other instances when RELAY_PEERS is configured
+    let relay_config = match audio::RelayConfig::from_env() {
+        Ok(relay_config) => relay_config,
+        Err(e) => {
+            tracing::error!("Invalid relay configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Create audio server
-    let audio_config = audio::AudioServerConfig {
-        bind_addr: "0.0.0.0:8080".to_string(),
-        max_packet_size: 1024,
-        buffer_size: 8192,
-        cleanup_interval: std::time::Duration::from_secs(60),
-        user_timeout: std::time::Duration::from_secs(300),
-        heartbeat_interval: std::time::Duration::from_secs(30),
-        jwt_secret: "your-secret-key".to_string(),
+    let audio_config = config.audio_server_config(relay_config);
+    let mut audio_server = AudioServer::new(audio_config, std::sync::Arc::new(state.clone())).with_metrics(metrics.clone());
+    audio_server.register_tap("recording", state.recordings.clone());
+
+    // Registration mails verification links; the log transport only logs them
+    let registration = match config.registration() {
+        Ok(registration) => registration,
+        Err(e) => {
+            tracing::error!("Invalid account settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if config.mail.transport == config::MailTransportKind::Log {
+        tracing::warn!("Mail is logged instead of sent; set mail.transport to deliver it");
+    }
+    let passkeys = match config.passkeys() {
+        Ok(passkeys) => passkeys,
+        Err(e) => {
+            tracing::error!("Invalid WebAuthn settings: {}", e);
+            std::process::exit(1);
+        }
     };
-    
-    let mut audio_server = AudioServer::new(audio_config, state.clone());
 
     // Create auth router
     let auth_router = Router::new()
         .route("/login", post(routes::auth::login))
-        .route("/google", get(routes::auth::google_oauth))
-        .route("/github", get(routes::auth::github_oauth))
+        .route("/refresh", post(routes::auth::refresh))
+        .route("/logout", post(routes::auth::logout))
+        .route("/register", post(routes::auth::register))
+        .route("/verify-email", post(routes::auth::verify_email))
+        .route("/verify-email/resend", post(routes::auth::resend_verification))
+        .route("/oauth", get(routes::auth::list_oauth_providers))
+        .route("/oauth/:provider/start", post(routes::auth::start_oauth_login))
+        .route("/oauth/:provider/finish", post(routes::auth::finish_oauth_login))
         .route("/reset", post(routes::auth::reset_password))
         .route("/reset/confirm", post(routes::auth::confirm_reset))
-        .route("/2fa/verify", post(routes::auth::verify_2fa));
+        .route("/2fa/verify", post(routes::auth::verify_2fa))
+        .route("/2fa/enroll", post(routes::auth::enroll_2fa))
+        .route("/2fa/confirm", post(routes::auth::confirm_2fa))
+        .route("/2fa/disable", post(routes::auth::disable_2fa))
+        .route("/2fa/recovery-codes", post(routes::auth::regenerate_recovery_codes))
+        .route("/2fa/reset", post(routes::auth::reset_2fa))
+        .route("/2fa/passkey", post(routes::auth::start_2fa_passkey))
+        .route("/passkeys", get(routes::auth::list_passkeys))
+        .route("/passkeys/:id", delete(routes::auth::delete_passkey))
+        .route("/passkeys/register/start", post(routes::auth::start_passkey_registration))
+        .route("/passkeys/register/finish", post(routes::auth::finish_passkey_registration))
+        .route("/passkeys/login/start", post(routes::auth::start_passkey_login))
+        .route("/passkeys/login/finish", post(routes::auth::finish_passkey_login))
+        .with_state(routes::auth::AuthState {
+            tokens: tokens.clone(),
+            shared,
+            pool,
+            mailer,
+            registration: std::sync::Arc::new(registration),
+            twofa: std::sync::Arc::new(config.two_factor()),
+            passkeys: std::sync::Arc::new(passkeys),
+            oauth: std::sync::Arc::new(config.oauth_providers()),
+            ldap: config.ldap().map(std::sync::Arc::new),
+        });
+
+    // Publish the public token keys
+    let jwks_router = Router::new()
+        .route("/jwks.json", get(routes::auth::jwks))
+        .with_state(tokens.clone());
 
     // Create channels router with new role management endpoints
     let channels_router = Router::new()
@@ -83,27 +235,102 @@ async fn main() {
         .route("/:id/users/:user_id/kick", post(routes::channels::kick_user))
         .route("/:id/users/:user_id/ban", post(routes::channels::ban_user))
         .route("/:id/users/:user_id/unban", post(routes::channels::unban_user))
+        .route("/:id/users/:user_id/mute", post(routes::channels::server_mute_user))
+        .route("/:id/users/:user_id/deafen", post(routes::channels::server_deafen_user))
+        .route("/:id/limits", post(routes::channels::set_channel_limits))
+        .route("/:id/recordings", get(routes::channels::list_recordings))
+        .route("/:id/recordings/start", post(routes::channels::start_recording))
+        .route("/:id/recordings/stop", post(routes::channels::stop_recording))
+        .route("/:id/recordings/consent", post(routes::channels::set_recording_consent))
+        .route("/:id/recordings/:recording_id", get(routes::channels::download_recording))
         .with_state(state.clone());
 
     // Create WebSocket router
     let ws_router = Router::new()
-        .route("/", ws::ws_handler)
+        .route("/", get(ws::ws_handler))
         .with_state(ws_state);
 
+    // Serve WebSockets on their own listener as well when one is configured
+    if let Some(ws_addr) = &config.ws.bind_addr {
+        let ws_listener = match tokio::net::TcpListener::bind(ws_addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("Failed to bind WebSocket listener on {}: {}", ws_addr, e);
+                std::process::exit(1);
+            }
+        };
+        let ws_app = Router::new().nest("/ws", ws_router.clone()).layer(cors.clone());
+        tracing::info!("WebSocket server running on ws://{}/ws", ws_addr);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(ws_listener, ws_app).await {
+                tracing::error!("WebSocket server error: {}", e);
+            }
+        });
+    }
+
+    // Create metrics router
+    let metrics_router = Router::new()
+        .route("/", get(routes::metrics::metrics))
+        .with_state(metrics.clone());
+
+    // Create admin router
+    let admin_router = Router::new()
+        .route("/voice/sessions", get(routes::admin::list_voice_sessions))
+        .route("/voice/stats", get(routes::admin::voice_stats))
+        .route("/voice/sessions/:user_id/disconnect", post(routes::admin::disconnect_voice_session))
+        .route("/voice/sessions/:user_id/move", post(routes::admin::move_voice_session))
+        .route("/voice/sessions/:user_id/mute", post(routes::admin::mute_voice_session))
+        .route("/udp/blocks", get(routes::admin::list_udp_blocks))
+        .route("/udp/blocks/clear", post(routes::admin::clear_udp_blocks))
+        .route("/udp/blocks/:ip/unblock", post(routes::admin::unblock_udp_address))
+        .with_state(routes::admin::AdminState {
+            sessions: audio_server.sessions(),
+            abuse: audio_server.abuse_guard(),
+            tokens: tokens.clone(),
+        });
+
     // Create main router
     let app = Router::new()
+        .nest("/.well-known", jwks_router)
         .nest("/auth", auth_router)
         .nest("/channels", channels_router)
         .nest("/ws", ws_router)
+        .nest("/metrics", metrics_router)
+        .nest("/admin", admin_router)
+        .route_layer(middleware::from_fn_with_state(metrics, routes::metrics::track_requests))
         .layer(cors);
 
+    // Reload safe settings on SIGHUP
+    #[cfg(unix)]
+    {
+        let running = config.clone();
+        let audio_reload = audio_server.reload_handle();
+        let tokens = tokens.clone();
+        tokio::spawn(async move {
+            let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    tracing::error!("Failed to listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                reload_config(&running, &log_reload, &audio_reload, &tokens);
+            }
+        });
+    }
+
     // Start HTTP server
-    let http_listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
-    
-    tracing::info!("HTTP server running on http://127.0.0.1:3000");
-    tracing::info!("UDP audio server starting on 0.0.0.0:8080");
+    let http_listener = match tokio::net::TcpListener::bind(&config.http.bind_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to bind HTTP listener on {}: {}", config.http.bind_addr, e);
+            std::process::exit(1);
+        }
+    };
+
+    tracing::info!("HTTP server running on http://{}", config.http.bind_addr);
+    tracing::info!("UDP audio server starting on {}", config.udp.bind_addr);
 
     // Start both servers concurrently
     tokio::select! {
@@ -120,6 +347,51 @@ async fn main() {
     }
 }
 
+/// The per-user data directory: %APPDATA% on Windows, otherwise
+/// $XDG_DATA_HOME or ~/.local/share
+fn data_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        return std::env::var_os("APPDATA").map(PathBuf::from);
+    }
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))
+}
+
+/// Re-read the configuration and apply what can change without a restart:
+/// the log level, voice timeouts, jitter buffers, UDP rate limits and token
+/// keys. An invalid file leaves the running configuration untouched.
+#[cfg(unix)]
+fn reload_config(
+    running: &config::Config,
+    log_reload: &reload::Handle<EnvFilter, Registry>,
+    audio_reload: &audio::ReloadHandle,
+    tokens: &tokens::TokenService,
+) {
+    let (config, keys) = match config::Config::load().and_then(|config| {
+        let keys = config.token_keys()?;
+        Ok((config, keys))
+    }) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            tracing::error!("Keeping the current configuration: {}", e);
+            return;
+        }
+    };
+    let restart_required = running.restart_required(&config);
+    if !restart_required.is_empty() {
+        tracing::warn!("Changes to {} take effect after a restart", restart_required.join(", "));
+    }
+    if let Err(e) = log_reload.reload(EnvFilter::new(&config.log.level)) {
+        tracing::error!("Failed to change the log level: {}", e);
+    }
+    audio_reload.apply(&config.audio_server_config(None));
+    if let Err(e) = tokens.set_keys(keys) {
+        tracing::error!("Keeping the current token keys: {}", e);
+    }
+    tracing::info!("Reloaded configuration");
+}
+
 async fn run_startup() -> Result<(), String> {
     // 1. Setup (keys, config, certs)
     setup::run_first_time_setup().await;
@@ -0,0 +1,217 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+/// Prometheus metrics for the HTTP, WebSocket and UDP servers.
+///
+/// Each `Metrics` owns its registry, so tests and several servers in one
+/// process do not share counters.
+pub struct Metrics {
+    registry: Registry,
+    /// UDP datagrams received, by `kind` (voice, control)
+    pub udp_packets_received: IntCounterVec,
+    /// Voice frames sent, by `path` (local, relay_out, relay_in)
+    pub udp_packets_forwarded: IntCounterVec,
+    /// UDP datagrams or frames dropped, by `reason`
+    pub udp_packets_dropped: IntCounterVec,
+    /// Frames waiting in a speaker's jitter buffer after each insert
+    pub jitter_buffer_depth: Histogram,
+    /// Handshakes by `outcome`: `ok`, `malformed`, `channel_full` or an `AuthError` name
+    pub handshakes: IntCounterVec,
+    /// Authenticated UDP sessions
+    pub voice_sessions: IntGauge,
+    /// Voice connections per `channel`
+    pub voice_channel_users: IntGaugeVec,
+    /// Open WebSocket connections
+    pub ws_connections: IntGauge,
+    /// WebSocket users per `channel`
+    pub ws_channel_users: IntGaugeVec,
+    /// WebSocket messages not delivered, by `reason`
+    pub ws_messages_dropped: IntCounterVec,
+    /// Time a batched channel broadcast waited before being sent
+    pub ws_broadcast_lag_seconds: Histogram,
+    /// HTTP request latency by `method`, `route` and `status`
+    pub http_request_duration_seconds: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let udp_packets_received = IntCounterVec::new(
+            Opts::new("whisper_udp_packets_received_total", "UDP datagrams received"),
+            &["kind"],
+        )
+        .unwrap();
+        let udp_packets_forwarded = IntCounterVec::new(
+            Opts::new("whisper_udp_packets_forwarded_total", "Voice frames sent to listeners or relay peers"),
+            &["path"],
+        )
+        .unwrap();
+        let udp_packets_dropped = IntCounterVec::new(
+            Opts::new("whisper_udp_packets_dropped_total", "UDP datagrams or voice frames dropped"),
+            &["reason"],
+        )
+        .unwrap();
+        let jitter_buffer_depth = Histogram::with_opts(
+            HistogramOpts::new("whisper_jitter_buffer_depth", "Frames in a speaker's jitter buffer after an insert")
+                .buckets(vec![0.0, 1.0, 2.0, 3.0, 5.0, 8.0, 13.0, 20.0]),
+        )
+        .unwrap();
+        let handshakes = IntCounterVec::new(
+            Opts::new("whisper_udp_handshakes_total", "UDP handshakes by outcome"),
+            &["outcome"],
+        )
+        .unwrap();
+        let voice_sessions = IntGauge::new("whisper_voice_sessions", "Authenticated UDP sessions").unwrap();
+        let voice_channel_users = IntGaugeVec::new(
+            Opts::new("whisper_voice_channel_users", "Voice connections per channel"),
+            &["channel"],
+        )
+        .unwrap();
+        let ws_connections = IntGauge::new("whisper_ws_connections", "Open WebSocket connections").unwrap();
+        let ws_channel_users = IntGaugeVec::new(
+            Opts::new("whisper_ws_channel_users", "WebSocket users per channel"),
+            &["channel"],
+        )
+        .unwrap();
+        let ws_messages_dropped = IntCounterVec::new(
+            Opts::new("whisper_ws_messages_dropped_total", "WebSocket messages not delivered"),
+            &["reason"],
+        )
+        .unwrap();
+        let ws_broadcast_lag_seconds = Histogram::with_opts(
+            HistogramOpts::new("whisper_ws_broadcast_lag_seconds", "Time a batched channel broadcast waited before being sent")
+                .buckets(vec![0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 1.0]),
+        )
+        .unwrap();
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new("whisper_http_request_duration_seconds", "HTTP request latency"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(udp_packets_received.clone())).unwrap();
+        registry.register(Box::new(udp_packets_forwarded.clone())).unwrap();
+        registry.register(Box::new(udp_packets_dropped.clone())).unwrap();
+        registry.register(Box::new(jitter_buffer_depth.clone())).unwrap();
+        registry.register(Box::new(handshakes.clone())).unwrap();
+        registry.register(Box::new(voice_sessions.clone())).unwrap();
+        registry.register(Box::new(voice_channel_users.clone())).unwrap();
+        registry.register(Box::new(ws_connections.clone())).unwrap();
+        registry.register(Box::new(ws_channel_users.clone())).unwrap();
+        registry.register(Box::new(ws_messages_dropped.clone())).unwrap();
+        registry.register(Box::new(ws_broadcast_lag_seconds.clone())).unwrap();
+        registry.register(Box::new(http_request_duration_seconds.clone())).unwrap();
+
+        Self {
+            registry,
+            udp_packets_received,
+            udp_packets_forwarded,
+            udp_packets_dropped,
+            jitter_buffer_depth,
+            handshakes,
+            voice_sessions,
+            voice_channel_users,
+            ws_connections,
+            ws_channel_users,
+            ws_messages_dropped,
+            ws_broadcast_lag_seconds,
+            http_request_duration_seconds,
+        }
+    }
+
+    pub fn packet_received(&self, kind: &str) {
+        self.udp_packets_received.with_label_values(&[kind]).inc();
+    }
+
+    pub fn packet_forwarded(&self, path: &str) {
+        self.udp_packets_forwarded.with_label_values(&[path]).inc();
+    }
+
+    pub fn packet_dropped(&self, reason: &str) {
+        self.udp_packets_dropped.with_label_values(&[reason]).inc();
+    }
+
+    pub fn handshake(&self, outcome: &str) {
+        self.handshakes.with_label_values(&[outcome]).inc();
+    }
+
+    pub fn ws_message_dropped(&self, reason: &str) {
+        self.ws_messages_dropped.with_label_values(&[reason]).inc();
+    }
+
+    /// Set the WebSocket user count of a channel, forgetting empty channels
+    pub fn set_ws_channel_users(&self, channel_id: &str, users: usize) {
+        if users == 0 {
+            let _ = self.ws_channel_users.remove_label_values(&[channel_id]);
+        } else {
+            self.ws_channel_users.with_label_values(&[channel_id]).set(users as i64);
+        }
+    }
+
+    /// Replace the voice user counts of all channels
+    pub fn set_voice_channel_users<'a>(&self, channels: impl IntoIterator<Item = (&'a str, usize)>) {
+        self.voice_channel_users.reset();
+        for (channel_id, users) in channels {
+            self.voice_channel_users.with_label_values(&[channel_id]).set(users as i64);
+        }
+    }
+
+    /// Current values in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Prometheus text encoding cannot fail");
+        String::from_utf8(buffer).expect("Prometheus text encoding is UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_exposes_labelled_series() {
+        let metrics = Metrics::new();
+        metrics.packet_received("voice");
+        metrics.packet_dropped("throttled");
+        metrics.handshake("user_banned");
+        metrics.jitter_buffer_depth.observe(3.0);
+        metrics
+            .http_request_duration_seconds
+            .with_label_values(&["POST", "/channels/:id/join", "200"])
+            .observe(0.004);
+
+        let text = metrics.render();
+        assert!(text.contains("whisper_udp_packets_received_total{kind=\"voice\"} 1"));
+        assert!(text.contains("whisper_udp_packets_dropped_total{reason=\"throttled\"} 1"));
+        assert!(text.contains("whisper_udp_handshakes_total{outcome=\"user_banned\"} 1"));
+        assert!(text.contains("whisper_jitter_buffer_depth_count 1"));
+        assert!(text.contains("route=\"/channels/:id/join\""));
+    }
+
+    #[test]
+    fn test_channel_user_gauges_forget_empty_channels() {
+        let metrics = Metrics::new();
+        metrics.set_ws_channel_users("chan1", 2);
+        metrics.set_ws_channel_users("chan2", 1);
+        metrics.set_ws_channel_users("chan2", 0);
+        metrics.set_voice_channel_users([("chan1", 3)]);
+        metrics.set_voice_channel_users([("chan3", 1)]);
+
+        let text = metrics.render();
+        assert!(text.contains("whisper_ws_channel_users{channel=\"chan1\"} 2"));
+        assert!(!text.contains("channel=\"chan2\""));
+        assert!(!text.contains("whisper_voice_channel_users{channel=\"chan1\"}"));
+        assert!(text.contains("whisper_voice_channel_users{channel=\"chan3\"} 1"));
+    }
+}
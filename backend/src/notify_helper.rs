@@ -11,5 +11,6 @@ pub fn notify_user(title: &str, message: &str) {
     #[cfg(not(target_os = "windows"))]
     {
         // No-op on non-Windows
+        let _ = (title, message);
     }
 } 
\ No newline at end of file
@@ -0,0 +1,225 @@
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::Json as JsonResponse,
+};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+use crate::audio::abuse::AbuseGuard;
+use crate::audio::sessions::{AudioServerStats, SessionError, VoiceSessionInfo, VoiceSessions};
+use crate::routes::bearer::Bearer;
+use crate::routes::channels::ErrorResponse;
+use crate::tokens::TokenService;
+use crate::voice::security::BlockedAddress;
+use tracing::info;
+
+/// State of the admin routes
+#[derive(Clone)]
+pub struct AdminState {
+    pub sessions: VoiceSessions,
+    pub abuse: Arc<AbuseGuard>,
+    pub tokens: Arc<TokenService>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveSessionRequest {
+    pub channel_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MuteSessionRequest {
+    pub muted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClearBlocksResponse {
+    pub cleared: usize,
+}
+
+type AdminResult<T> = Result<JsonResponse<T>, (StatusCode, JsonResponse<ErrorResponse>)>;
+
+fn error_response(status: StatusCode, message: &str) -> (StatusCode, JsonResponse<ErrorResponse>) {
+    (
+        status,
+        JsonResponse(ErrorResponse {
+            error: message.to_string(),
+        }),
+    )
+}
+
+/// The caller's user ID, if the token carries the admin role
+fn require_admin(tokens: &TokenService, token: &str) -> Result<String, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let claims = tokens
+        .verify(token)
+        .map_err(|_| error_response(StatusCode::UNAUTHORIZED, "Invalid token"))?;
+
+    if !claims.has_role("admin") {
+        return Err(error_response(StatusCode::FORBIDDEN, "Administrator role required"));
+    }
+    Ok(claims.sub)
+}
+
+fn session_error(error: SessionError) -> (StatusCode, JsonResponse<ErrorResponse>) {
+    error_response(StatusCode::NOT_FOUND, &error.to_string())
+}
+
+pub async fn list_voice_sessions(
+    State(admin): State<AdminState>,
+    auth: Bearer,
+) -> AdminResult<Vec<VoiceSessionInfo>> {
+    require_admin(&admin.tokens, auth.token())?;
+    Ok(JsonResponse(admin.sessions.list()))
+}
+
+pub async fn voice_stats(
+    State(admin): State<AdminState>,
+    auth: Bearer,
+) -> AdminResult<AudioServerStats> {
+    require_admin(&admin.tokens, auth.token())?;
+    Ok(JsonResponse(admin.sessions.stats()))
+}
+
+pub async fn disconnect_voice_session(
+    State(admin): State<AdminState>,
+    auth: Bearer,
+    Path(user_id): Path<String>,
+) -> AdminResult<Vec<VoiceSessionInfo>> {
+    let admin_id = require_admin(&admin.tokens, auth.token())?;
+    let removed = admin.sessions
+        .disconnect(&user_id, "Disconnected by an administrator")
+        .await
+        .map_err(session_error)?;
+
+    info!("Admin {} disconnected the voice session of {}", admin_id, user_id);
+    Ok(JsonResponse(removed))
+}
+
+pub async fn move_voice_session(
+    State(admin): State<AdminState>,
+    auth: Bearer,
+    Path(user_id): Path<String>,
+    Json(payload): Json<MoveSessionRequest>,
+) -> AdminResult<Vec<VoiceSessionInfo>> {
+    let admin_id = require_admin(&admin.tokens, auth.token())?;
+    let moved = admin.sessions
+        .move_to(&user_id, &payload.channel_id)
+        .await
+        .map_err(session_error)?;
+
+    info!("Admin {} moved the voice session of {} to {}", admin_id, user_id, payload.channel_id);
+    Ok(JsonResponse(moved))
+}
+
+pub async fn mute_voice_session(
+    State(admin): State<AdminState>,
+    auth: Bearer,
+    Path(user_id): Path<String>,
+    Json(payload): Json<MuteSessionRequest>,
+) -> AdminResult<Vec<VoiceSessionInfo>> {
+    let admin_id = require_admin(&admin.tokens, auth.token())?;
+    let updated = admin.sessions
+        .set_server_mute(&user_id, payload.muted)
+        .map_err(session_error)?;
+
+    info!(
+        "Admin {} {} the voice session of {}",
+        admin_id,
+        if payload.muted { "muted" } else { "unmuted" },
+        user_id
+    );
+    Ok(JsonResponse(updated))
+}
+
+pub async fn list_udp_blocks(
+    State(admin): State<AdminState>,
+    auth: Bearer,
+) -> AdminResult<Vec<BlockedAddress>> {
+    require_admin(&admin.tokens, auth.token())?;
+    Ok(JsonResponse(admin.abuse.blocklist().list()))
+}
+
+pub async fn unblock_udp_address(
+    State(admin): State<AdminState>,
+    auth: Bearer,
+    Path(ip): Path<IpAddr>,
+) -> AdminResult<Vec<BlockedAddress>> {
+    let admin_id = require_admin(&admin.tokens, auth.token())?;
+    if !admin.abuse.blocklist().unblock(&ip) {
+        return Err(error_response(StatusCode::NOT_FOUND, "Address is not blocked"));
+    }
+
+    info!("Admin {} unblocked UDP traffic from {}", admin_id, ip);
+    Ok(JsonResponse(admin.abuse.blocklist().list()))
+}
+
+pub async fn clear_udp_blocks(
+    State(admin): State<AdminState>,
+    auth: Bearer,
+) -> AdminResult<ClearBlocksResponse> {
+    let admin_id = require_admin(&admin.tokens, auth.token())?;
+    let cleared = admin.abuse.blocklist().clear();
+
+    info!("Admin {} cleared {} UDP address blocks", admin_id, cleared);
+    Ok(JsonResponse(ClearBlocksResponse { cleared }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(roles: &[&str]) -> String {
+        TokenService::development()
+            .issue("user1", "user1", roles.iter().map(|role| role.to_string()).collect())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_require_admin_checks_role() {
+        let tokens = TokenService::development();
+        assert_eq!(require_admin(&tokens, &token(&["admin", "user"])).unwrap(), "user1");
+        assert_eq!(require_admin(&tokens, &token(&["user"])).unwrap_err().0, StatusCode::FORBIDDEN);
+        assert_eq!(require_admin(&tokens, "not-a-token").unwrap_err().0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_udp_blocks_can_be_lifted() {
+        use axum::{body::Body, http::Request, routing::{get, post}, Router};
+        use tower::ServiceExt;
+        use crate::audio::AudioServer;
+        use crate::routes::channels::AppState as ChannelAppState;
+
+        let server = AudioServer::new(Default::default(), Arc::new(ChannelAppState::new()));
+        let abuse = server.abuse_guard();
+        abuse.blocklist().block("203.0.113.7".parse().unwrap(), "handshake rate limit");
+        let app = Router::new()
+            .route("/udp/blocks", get(list_udp_blocks))
+            .route("/udp/blocks/:ip/unblock", post(unblock_udp_address))
+            .with_state(AdminState {
+                sessions: server.sessions(),
+                abuse: abuse.clone(),
+                tokens: Arc::new(TokenService::development()),
+            });
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token(&["admin"])))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request("GET", "/udp/blocks")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let blocks: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(blocks[0]["ip"], "203.0.113.7");
+        assert_eq!(blocks[0]["offences"], 1);
+
+        let response = app.clone().oneshot(request("POST", "/udp/blocks/203.0.113.7/unblock")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(abuse.blocklist().list().is_empty());
+
+        let response = app.oneshot(request("POST", "/udp/blocks/203.0.113.7/unblock")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
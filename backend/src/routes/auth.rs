@@ -1,12 +1,67 @@
 use axum::{
-    extract::{Json, Query},
-    http::StatusCode,
-    response::{Json as JsonResponse, IntoResponse},
+    extract::{Json, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Json as JsonResponse,
 };
-use jsonwebtoken::{encode, EncodingKey, Header};
+use axum_extra::extract::cookie::SignedCookieJar;
+use jsonwebtoken::jwk::JwkSet;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::env;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+use webauthn_rs::prelude::{CreationChallengeResponse, PublicKeyCredential, RegisterPublicKeyCredential, RequestChallengeResponse};
+use crate::routes::bearer::Bearer;
+use crate::routes::email::Mailer;
+use crate::routes::ldap::{DirectoryUser, LdapDirectory};
+use crate::routes::oauth::{link_identity, linked_user, username_candidates, ExternalIdentity, OAuthError, OAuthProviders};
+use crate::routes::passkey::{PasskeyCredential, PasskeyError, Passkeys};
+use crate::routes::password::{hash_password, verify_login, PasswordPolicy};
+use crate::routes::twofa::{generate_recovery_codes, hash_recovery_code, verify_totp, TwoFactor};
+use crate::routes::user::{is_unique_violation, validate_username, User};
+use crate::state::SharedState;
+use crate::tokens::{hash_token, random_token, TokenError, TokenPair, TokenService};
+use tracing::{info, warn};
+
+/// State of the auth routes
+#[derive(Clone)]
+pub struct AuthState {
+    pub tokens: Arc<TokenService>,
+    /// Holds refresh tokens and revocations
+    pub shared: SharedState,
+    /// Holds user accounts; logins are refused without it
+    pub pool: Option<PgPool>,
+    pub mailer: Mailer,
+    pub registration: Arc<Registration>,
+    pub twofa: Arc<TwoFactor>,
+    pub passkeys: Arc<Passkeys>,
+    pub oauth: Arc<OAuthProviders>,
+    /// Checks the passwords of directory accounts, beside the local ones
+    pub ldap: Option<Arc<LdapDirectory>>,
+}
+
+/// Self-service account settings: registration and password resets
+#[derive(Debug, Clone)]
+pub struct Registration {
+    /// Accept new accounts at `/auth/register`
+    pub enabled: bool,
+    /// Lifetime of email verification links
+    pub verification_ttl: Duration,
+    /// Lifetime of password reset links
+    pub reset_ttl: Duration,
+    pub passwords: PasswordPolicy,
+}
+
+impl Default for Registration {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            verification_ttl: Duration::from_secs(24 * 60 * 60),
+            reset_ttl: Duration::from_secs(60 * 60),
+            passwords: PasswordPolicy::default(),
+        }
+    }
+}
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
@@ -17,27 +72,77 @@ pub struct LoginRequest {
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     token: String,
+    /// Trade in at `/auth/refresh` for a new pair before `expires_in` seconds
+    refresh_token: String,
+    expires_in: u64,
     user_id: String,
     roles: Vec<String>,
 }
 
+/// Reply to a correct password: a session, or a challenge when the account
+/// has a second factor
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginReply {
+    Session(LoginResponse),
+    SecondFactor(SecondFactorChallenge),
+}
+
+#[derive(Debug, Serialize)]
+pub struct SecondFactorChallenge {
+    twofa_required: bool,
+    /// Present with a code at `/auth/2fa/verify` within `expires_in` seconds
+    twofa_token: String,
+    expires_in: u64,
+    /// The account has a passkey, which may stand in for the code
+    passkey: bool,
+}
+
+impl From<TokenPair> for LoginResponse {
+    fn from(pair: TokenPair) -> Self {
+        Self {
+            token: pair.access_token,
+            refresh_token: pair.refresh_token,
+            expires_in: pair.expires_in,
+            user_id: pair.claims.sub,
+            roles: pair.claims.roles,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterRequest {
+    username: String,
+    email: String,
+    password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegisterResponse {
+    user_id: String,
+    username: String,
+    email: String,
+    email_verified: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    token: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     error: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String, // user_id
-    roles: Vec<String>,
-    exp: usize, // expiration time
-    iat: usize, // issued at
+fn error_response(status: StatusCode, error: impl Into<String>) -> (StatusCode, JsonResponse<ErrorResponse>) {
+    (status, JsonResponse(ErrorResponse { error: error.into() }))
 }
 
-#[derive(Debug, Deserialize)]
-pub struct OAuthQuery {
-    code: Option<String>,
-    state: Option<String>,
+fn require_pool(auth: &AuthState) -> Result<&PgPool, (StatusCode, JsonResponse<ErrorResponse>)> {
+    auth.pool
+        .as_ref()
+        .ok_or_else(|| error_response(StatusCode::SERVICE_UNAVAILABLE, "User accounts are not available"))
 }
 
 #[derive(Debug, Deserialize)]
@@ -51,137 +156,1578 @@ pub struct ResetConfirmRequest {
     new_password: String,
 }
 
+/// Second step of a login: the challenge token and a TOTP code, a recovery
+/// code or a passkey assertion
 #[derive(Debug, Deserialize)]
 pub struct TwoFARequest {
+    twofa_token: String,
+    #[serde(flatten)]
+    factor: SecondFactorRequest,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SecondFactorRequest {
+    code: Option<String>,
+    recovery_code: Option<String>,
+    passkey: Option<PasskeyAssertion>,
+}
+
+/// A passkey's answer to the options of a sign-in ceremony
+#[derive(Debug, Deserialize)]
+pub struct PasskeyAssertion {
+    ceremony_id: Uuid,
+    credential: PublicKeyCredential,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginRequest {
     username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyChallengeRequest {
+    twofa_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyRegisterRequest {
+    ceremony_id: Uuid,
+    /// Shown in the list of passkeys, e.g. "Laptop"
+    name: Option<String>,
+    credential: RegisterPublicKeyCredential,
+}
+
+/// Options to pass to `navigator.credentials`, and the ceremony to finish
+/// with its result
+#[derive(Debug, Serialize)]
+pub struct PasskeyCeremony<T> {
+    ceremony_id: Uuid,
+    options: T,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PasskeyResponse {
+    id: String,
+    name: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<PasskeyCredential> for PasskeyResponse {
+    fn from(credential: PasskeyCredential) -> Self {
+        Self {
+            id: credential.id,
+            name: credential.name,
+            created_at: credential.created_at,
+            last_used_at: credential.last_used_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OAuthProvidersResponse {
+    providers: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OAuthStartResponse {
+    /// Send the browser here; the provider sends it back to
+    /// `<frontend>/oauth/<provider>/callback` with a code and state
+    authorization_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OAuthFinishRequest {
     code: String,
+    state: String,
 }
 
-pub async fn login(Json(payload): Json<LoginRequest>) -> Result<JsonResponse<LoginResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    // Dummy credential validation
-    if !validate_credentials(&payload.username, &payload.password) {
-        return Err((
-            StatusCode::UNAUTHORIZED,
+#[derive(Debug, Deserialize)]
+pub struct ConfirmTwoFARequest {
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetTwoFARequest {
+    user_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EnrollResponse {
+    /// Base32, for typing into an authenticator app instead of scanning
+    secret: String,
+    otpauth_url: String,
+    qr_svg: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Shown once; each works once in place of a code
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// Passkey failures are the client's, except for the database
+fn passkey_error(e: PasskeyError, status: StatusCode, error: &str) -> (StatusCode, JsonResponse<ErrorResponse>) {
+    match e {
+        PasskeyError::Database(_) | PasskeyError::State(_) => {
+            warn!("Passkey error: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check passkey")
+        }
+        _ => {
+            info!("Passkey ceremony failed: {}", e);
+            error_response(status, error)
+        }
+    }
+}
+
+/// OAuth failures by whose they are: the client's, the provider's or ours
+fn oauth_error(e: OAuthError) -> (StatusCode, JsonResponse<ErrorResponse>) {
+    match e {
+        OAuthError::UnknownProvider(_) => error_response(StatusCode::NOT_FOUND, "Unknown sign-in provider"),
+        OAuthError::UnknownLogin => error_response(StatusCode::BAD_REQUEST, "Invalid or expired sign-in"),
+        OAuthError::OtherBrowser => {
+            info!("OAuth sign-in refused: {}", e);
+            error_response(StatusCode::BAD_REQUEST, "Sign-in was started in another browser")
+        }
+        OAuthError::Rejected(_) | OAuthError::IdToken(_) => {
+            info!("OAuth sign-in refused: {}", e);
+            error_response(StatusCode::UNAUTHORIZED, "Sign-in was not accepted")
+        }
+        OAuthError::Provider(_) | OAuthError::Http(_) => {
+            warn!("OAuth provider error: {}", e);
+            error_response(StatusCode::BAD_GATEWAY, "Sign-in provider is unavailable")
+        }
+        OAuthError::Database(_) => {
+            warn!("OAuth sign-in failed: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to sign in")
+        }
+    }
+}
+
+fn token_error(e: TokenError) -> (StatusCode, JsonResponse<ErrorResponse>) {
+    let status = match e {
+        TokenError::InvalidRefreshToken | TokenError::RefreshTokenReused => StatusCode::UNAUTHORIZED,
+        _ => {
+            warn!("Token service error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    (status, JsonResponse(ErrorResponse { error: e.to_string() }))
+}
+
+/// The user an access token belongs to
+async fn signed_in_user(auth: &AuthState, bearer: &Bearer) -> Result<User, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let claims = auth.tokens.verify(bearer.token()).map_err(|_| error_response(StatusCode::UNAUTHORIZED, "Invalid token"))?;
+    let pool = require_pool(auth)?;
+    match find_user(pool, &claims.sub).await? {
+        Some(user) => Ok(user),
+        None => Err(error_response(StatusCode::NOT_FOUND, "User not found")),
+    }
+}
+
+async fn find_user(pool: &PgPool, user_id: &str) -> Result<Option<User>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let id = match Uuid::parse_str(user_id) {
+        Ok(id) => id,
+        Err(_) => return Ok(None),
+    };
+    User::get_by_id(pool, id).await.map_err(|e| {
+        warn!("Failed to look up user {}: {}", user_id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up user")
+    })
+}
+
+/// Start a refreshable session for a fully authenticated user
+async fn start_user_session(auth: &AuthState, user: &User) -> Result<LoginResponse, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user_id = user.id.to_string();
+    let pair = auth.tokens.start_session(&auth.shared, &user_id, &user.username, user.token_roles()).await.map_err(|e| {
+        warn!("Failed to start session for {}: {}", user_id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
             JsonResponse(ErrorResponse {
-                error: "Invalid credentials".to_string(),
+                error: "Failed to generate token".to_string(),
             }),
-        ));
-    }
-
-    // Get user roles (dummy data)
-    let roles = get_user_roles(&payload.username);
-    
-    // Create JWT claims
-    let now = chrono::Utc::now();
-    let exp = (now + chrono::Duration::hours(24)).timestamp() as usize;
-    let iat = now.timestamp() as usize;
-    
-    let claims = Claims {
-        sub: payload.username.clone(),
-        roles,
-        exp,
-        iat,
-    };
-
-    // Sign the JWT token
-    let secret = "your-secret-key"; // In production, use environment variable
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_ref()),
-    )
-    .map_err(|_| {
+        )
+    })?;
+    Ok(pair.into())
+}
+
+/// Check a TOTP code, recovery code or passkey of a user with 2FA; each
+/// works once
+async fn check_second_factor(
+    auth: &AuthState,
+    pool: &PgPool,
+    user: &User,
+    factor: &SecondFactorRequest,
+) -> Result<bool, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let failed = |e: String| {
+        warn!("Failed to check second factor of {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check 2FA code")
+    };
+    if let Some(assertion) = &factor.passkey {
+        return match auth.passkeys.finish_authentication(pool, assertion.ceremony_id, &assertion.credential).await {
+            Ok(user_id) => Ok(user_id == user.id),
+            Err(e @ (PasskeyError::Database(_) | PasskeyError::State(_))) => Err(failed(e.to_string())),
+            Err(e) => {
+                info!("Passkey of {} was not accepted: {}", user.id, e);
+                Ok(false)
+            }
+        };
+    }
+    let secret = match (&user.twofa_secret, user.twofa_enabled) {
+        (Some(secret), true) => auth.twofa.decrypt_secret(&user.id.to_string(), secret).map_err(|e| failed(e.to_string()))?,
+        _ => return Ok(false),
+    };
+    match (&factor.code, &factor.recovery_code) {
+        (Some(code), _) => {
+            let now = chrono::Utc::now().timestamp();
+            match verify_totp(&secret, code.trim(), now, user.twofa_last_step) {
+                Some(step) => user.use_totp_step(pool, step).await.map_err(|e| failed(e.to_string())),
+                None => Ok(false),
+            }
+        }
+        (None, Some(recovery_code)) => {
+            let used = user.use_recovery_code(pool, &hash_recovery_code(recovery_code)).await.map_err(|e| failed(e.to_string()))?;
+            if used {
+                info!("User {} signed in with a recovery code", user.id);
+            }
+            Ok(used)
+        }
+        (None, None) => Ok(false),
+    }
+}
+
+pub async fn login(
+    State(auth): State<AuthState>,
+    Json(payload): Json<LoginRequest>,
+) -> Result<JsonResponse<LoginReply>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let pool = require_pool(&auth)?;
+    let user = User::get_by_username(pool, &payload.username).await.map_err(|e| {
+        warn!("Failed to look up user {}: {}", payload.username, e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             JsonResponse(ErrorResponse {
-                error: "Failed to generate token".to_string(),
+                error: "Failed to look up user".to_string(),
             }),
         )
     })?;
 
-    Ok(JsonResponse(LoginResponse {
-        token,
-        user_id: payload.username,
-        roles: claims.roles,
+    // Directory accounts, and names no local account has, ask the directory
+    if let Some(directory) = &auth.ldap {
+        if user.as_ref().is_none_or(|user| user.directory_dn.is_some()) {
+            let user = directory_login(pool, directory, &payload).await?;
+            return Ok(JsonResponse(login_reply(&auth, pool, &user).await?));
+        }
+    }
+
+    // Unknown users and wrong passwords take as long and look the same
+    let valid = verify_login(user.as_ref().map(|user| user.password_hash.as_str()), &payload.password).await;
+    let user = match user {
+        Some(user) if valid => user,
+        _ => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                JsonResponse(ErrorResponse {
+                    error: "Invalid credentials".to_string(),
+                }),
+            ))
+        }
+    };
+
+    Ok(JsonResponse(login_reply(&auth, pool, &user).await?))
+}
+
+/// Bind to the directory as the user, then create or update their account
+/// with what the directory says about them
+async fn directory_login(
+    pool: &PgPool,
+    directory: &LdapDirectory,
+    payload: &LoginRequest,
+) -> Result<User, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let found = match directory.authenticate(&payload.username, &payload.password).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return Err(error_response(StatusCode::UNAUTHORIZED, "Invalid credentials")),
+        Err(e) => {
+            warn!("Directory sign-in of {} failed: {}", payload.username, e);
+            return Err(error_response(StatusCode::BAD_GATEWAY, "Failed to reach the directory"));
+        }
+    };
+    directory_account(pool, &found).await
+}
+
+/// The account of a directory user, created on their first sign-in
+async fn directory_account(pool: &PgPool, found: &DirectoryUser) -> Result<User, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let failed = |e: sqlx::Error| {
+        if is_unique_violation(&e) {
+            return error_response(StatusCode::CONFLICT, "The directory email address belongs to another account");
+        }
+        warn!("Failed to provision directory user {}: {}", found.dn, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to sign in")
+    };
+    if let Err(e) = validate_username(&found.username) {
+        warn!("Directory user {} has an unusable username: {}", found.dn, e);
+        return Err(error_response(StatusCode::FORBIDDEN, e));
+    }
+    let email = match found.email.as_deref().map(str::trim) {
+        Some(email) if email.parse::<lettre::Address>().is_ok() => email,
+        _ => return Err(error_response(StatusCode::FORBIDDEN, "The directory account has no email address")),
+    };
+    let user = match User::get_by_username(pool, &found.username).await.map_err(failed)? {
+        Some(user) if user.directory_dn.is_some() => user.update_directory(pool, email, &found.dn, &found.roles).await,
+        Some(_) => return Err(error_response(StatusCode::CONFLICT, "A local account has this username")),
+        None => {
+            let created = User::create_directory(pool, &found.username, email, &found.dn, &found.roles).await;
+            if let Ok(user) = &created {
+                info!("Provisioned user {} ({}) from {}", user.username, user.id, found.dn);
+            }
+            created
+        }
+    };
+    user.map_err(failed)
+}
+
+/// A session for a user who proved who they are, or with 2FA only a
+/// challenge for `/auth/2fa/verify`
+async fn login_reply(auth: &AuthState, pool: &PgPool, user: &User) -> Result<LoginReply, (StatusCode, JsonResponse<ErrorResponse>)> {
+    if user.twofa_enabled {
+        let passkeys = PasskeyCredential::list(pool, user.id).await.map_err(|e| {
+            warn!("Failed to look up passkeys of {}: {}", user.id, e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up user")
+        })?;
+        let (twofa_token, claims) = auth.tokens.issue_challenge(&user.id.to_string()).map_err(token_error)?;
+        return Ok(LoginReply::SecondFactor(SecondFactorChallenge {
+            twofa_required: true,
+            twofa_token,
+            expires_in: (claims.exp - claims.iat) as u64,
+            passkey: !passkeys.is_empty(),
+        }));
+    }
+
+    // Sign the JWT token and start a refreshable session
+    Ok(LoginReply::Session(start_user_session(auth, user).await?))
+}
+
+// POST /auth/register
+/// Create an account and email a link to verify its address. Until then the
+/// account may sign in but not join voice.
+pub async fn register(
+    State(auth): State<AuthState>,
+    Json(payload): Json<RegisterRequest>,
+) -> Result<(StatusCode, JsonResponse<RegisterResponse>), (StatusCode, JsonResponse<ErrorResponse>)> {
+    if !auth.registration.enabled {
+        return Err(error_response(StatusCode::FORBIDDEN, "Registration is closed"));
+    }
+    let pool = require_pool(&auth)?;
+
+    let username = payload.username.trim();
+    validate_username(username).map_err(|e| error_response(StatusCode::BAD_REQUEST, e))?;
+    let email = payload.email.trim().to_lowercase();
+    if email.parse::<lettre::Address>().is_err() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "Invalid email address"));
+    }
+    auth.registration
+        .passwords
+        .check(&payload.password, username)
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let lookup_failed = |e: sqlx::Error| {
+        warn!("Failed to look up user {}: {}", username, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up user")
+    };
+    if User::get_by_username(pool, username).await.map_err(lookup_failed)?.is_some() {
+        return Err(error_response(StatusCode::CONFLICT, "Username is taken"));
+    }
+    if User::get_by_email(pool, &email).await.map_err(lookup_failed)?.is_some() {
+        return Err(error_response(StatusCode::CONFLICT, "Email address is already registered"));
+    }
+
+    let hash = hash_password(&payload.password).await.map_err(|e| {
+        warn!("Failed to hash password: {}", e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create account")
+    })?;
+    // A concurrent registration may have taken the name since the lookup
+    let user = User::register(pool, username, &email, &hash).await.map_err(|e| {
+        if is_unique_violation(&e) {
+            return error_response(StatusCode::CONFLICT, "Username or email address is taken");
+        }
+        warn!("Failed to create user {}: {}", username, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create account")
+    })?;
+    info!("Registered user {} ({})", user.username, user.id);
+
+    // The account stands even if the mail fails; the link can be resent
+    if let Err(e) = send_verification(&auth, pool, &user).await {
+        warn!("Failed to send verification email to {}: {}", user.id, e);
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        JsonResponse(RegisterResponse {
+            user_id: user.id.to_string(),
+            username: user.username,
+            email: user.email,
+            email_verified: user.email_verified,
+        }),
+    ))
+}
+
+/// Replace the user's verification token and mail them the new one
+async fn send_verification(auth: &AuthState, pool: &PgPool, user: &User) -> Result<(), String> {
+    let token = random_token();
+    let ttl = chrono::Duration::from_std(auth.registration.verification_ttl).map_err(|e| e.to_string())?;
+    user.set_verification_token(pool, &hash_token(&token), chrono::Utc::now() + ttl)
+        .await
+        .map_err(|e| e.to_string())?;
+    auth.mailer.send_verification_email(&user.email, &token).await.map_err(|e| e.to_string())
+}
+
+// POST /auth/verify-email
+/// Verify an email address with the token from the verification link
+pub async fn verify_email(
+    State(auth): State<AuthState>,
+    Json(payload): Json<VerifyEmailRequest>,
+) -> Result<StatusCode, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let pool = require_pool(&auth)?;
+    let user = User::verify_email(pool, &hash_token(&payload.token)).await.map_err(|e| {
+        warn!("Failed to verify email: {}", e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to verify email address")
+    })?;
+    match user {
+        Some(user) => {
+            info!("User {} verified {}", user.id, user.email);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Err(error_response(StatusCode::BAD_REQUEST, "Invalid or expired verification token")),
+    }
+}
+
+// POST /auth/verify-email/resend
+/// Mail the signed-in user a new verification link
+pub async fn resend_verification(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+) -> Result<StatusCode, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user = signed_in_user(&auth, &bearer).await?;
+    let pool = require_pool(&auth)?;
+    if user.email_verified {
+        return Err(error_response(StatusCode::CONFLICT, "Email address is already verified"));
+    }
+    send_verification(&auth, pool, &user).await.map_err(|e| {
+        warn!("Failed to send verification email to {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to send verification email")
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /auth/refresh
+/// Trade a refresh token for a new access and refresh token, carrying the
+/// account's current roles
+pub async fn refresh(
+    State(auth): State<AuthState>,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<JsonResponse<LoginResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let pool = require_pool(&auth)?;
+    let pair = auth.tokens.refresh(&auth.shared, pool, &payload.refresh_token).await.map_err(token_error)?;
+    Ok(JsonResponse(pair.into()))
+}
+
+// POST /auth/logout
+/// Revoke the caller's session: its access and refresh tokens stop working
+/// and connections opened with them are closed
+pub async fn logout(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+) -> Result<StatusCode, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let claims = auth.tokens.verify(bearer.token()).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            JsonResponse(ErrorResponse {
+                error: "Invalid token".to_string(),
+            }),
+        )
+    })?;
+    auth.tokens.revoke_session(&auth.shared, &claims.sid, &claims.sub).await.map_err(token_error)?;
+    info!("{} logged out of session {}", claims.sub, claims.sid);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /auth/reset
+/// Mail a password reset link to the account with this email address. The
+/// answer is the same whether or not there is one, and the account is looked
+/// up in the background so neither the timing nor a failure tells either.
+pub async fn reset_password(
+    State(auth): State<AuthState>,
+    Json(payload): Json<ResetRequest>,
+) -> Result<StatusCode, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let pool = require_pool(&auth)?.clone();
+    let email = payload.email.trim().to_string();
+    if email.parse::<lettre::Address>().is_err() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "Invalid email address"));
+    }
+    tokio::spawn(async move { send_reset_link(&auth, &pool, &email).await });
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Store a reset token for the account with this email address, if it has a
+/// local password, and mail the link. Failures are only logged.
+async fn send_reset_link(auth: &AuthState, pool: &PgPool, email: &str) {
+    let user = match User::get_by_email(pool, email).await {
+        // Directory passwords are changed in the directory
+        Ok(Some(user)) if user.directory_dn.is_none() => user,
+        Ok(_) => return,
+        Err(e) => {
+            warn!("Failed to look up user by email: {}", e);
+            return;
+        }
+    };
+
+    let token = random_token();
+    let ttl = chrono::Duration::from_std(auth.registration.reset_ttl).unwrap_or_else(|_| chrono::Duration::hours(1));
+    if let Err(e) = user.set_reset_token(pool, &hash_token(&token), chrono::Utc::now() + ttl).await {
+        warn!("Failed to store reset token of {}: {}", user.id, e);
+        return;
+    }
+    match auth.mailer.send_reset_email(&user.email, &token).await {
+        Ok(()) => info!("Sent password reset link to user {}", user.id),
+        Err(e) => warn!("Failed to send password reset email to {}: {}", user.id, e),
+    }
+}
+
+// POST /auth/reset/confirm
+/// Set a new password with the token from a reset link. Every session of the
+/// account is signed out, so a stolen session ends with the old password.
+pub async fn confirm_reset(
+    State(auth): State<AuthState>,
+    Json(payload): Json<ResetConfirmRequest>,
+) -> Result<StatusCode, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let pool = require_pool(&auth)?;
+    let token_hash = hash_token(&payload.token);
+    let invalid = || error_response(StatusCode::BAD_REQUEST, "Invalid or expired reset token");
+    let user = match User::get_by_reset_token(pool, &token_hash).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(invalid()),
+        Err(e) => {
+            warn!("Failed to look up reset token: {}", e);
+            return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to reset password"));
+        }
+    };
+    auth.registration
+        .passwords
+        .check(&payload.new_password, &user.username)
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    let failed = |e: String| {
+        warn!("Failed to reset password of {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to reset password")
+    };
+    let hash = hash_password(&payload.new_password).await.map_err(|e| failed(e.to_string()))?;
+    if !user.reset_password(pool, &token_hash, &hash).await.map_err(|e| failed(e.to_string()))? {
+        return Err(invalid());
+    }
+    auth.tokens.revoke_user(&auth.shared, &user.id.to_string()).await.map_err(token_error)?;
+    info!("User {} reset their password; all sessions signed out", user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /auth/2fa/verify
+/// Second step of a login with 2FA: trade the challenge from `/auth/login`
+/// and a TOTP or recovery code for a session
+pub async fn verify_2fa(
+    State(auth): State<AuthState>,
+    Json(payload): Json<TwoFARequest>,
+) -> Result<JsonResponse<LoginResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let challenge = auth
+        .tokens
+        .verify_challenge(&payload.twofa_token)
+        .map_err(|_| error_response(StatusCode::UNAUTHORIZED, "Invalid or expired 2FA token"))?;
+    if auth.twofa.is_exhausted(&challenge.jti) {
+        return Err(error_response(StatusCode::UNAUTHORIZED, "Too many attempts, sign in again"));
+    }
+    let pool = require_pool(&auth)?;
+    let user = match find_user(pool, &challenge.sub).await? {
+        Some(user) => user,
+        None => return Err(error_response(StatusCode::UNAUTHORIZED, "Invalid or expired 2FA token")),
+    };
+
+    if !check_second_factor(&auth, pool, &user, &payload.factor).await? {
+        auth.twofa.record_failure(&challenge.jti, challenge.exp as i64);
+        return Err(error_response(StatusCode::UNAUTHORIZED, "Invalid 2FA code"));
+    }
+    auth.twofa.complete(&challenge.jti, challenge.exp as i64);
+    Ok(JsonResponse(start_user_session(&auth, &user).await?))
+}
+
+// POST /auth/2fa/passkey
+/// Passkey options for the second step of a login, to answer at
+/// `/auth/2fa/verify` in place of a code
+pub async fn start_2fa_passkey(
+    State(auth): State<AuthState>,
+    Json(payload): Json<PasskeyChallengeRequest>,
+) -> Result<JsonResponse<PasskeyCeremony<RequestChallengeResponse>>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let challenge = auth
+        .tokens
+        .verify_challenge(&payload.twofa_token)
+        .map_err(|_| error_response(StatusCode::UNAUTHORIZED, "Invalid or expired 2FA token"))?;
+    if auth.twofa.is_exhausted(&challenge.jti) {
+        return Err(error_response(StatusCode::UNAUTHORIZED, "Too many attempts, sign in again"));
+    }
+    let pool = require_pool(&auth)?;
+    let user_id = Uuid::parse_str(&challenge.sub)
+        .map_err(|_| error_response(StatusCode::UNAUTHORIZED, "Invalid or expired 2FA token"))?;
+    match auth.passkeys.start_authentication(pool, user_id).await {
+        Ok(Some((ceremony_id, options))) => Ok(JsonResponse(PasskeyCeremony { ceremony_id, options })),
+        Ok(None) => Err(error_response(StatusCode::NOT_FOUND, "No passkeys registered")),
+        Err(e) => Err(passkey_error(e, StatusCode::BAD_REQUEST, "Failed to start passkey sign-in")),
+    }
+}
+
+// POST /auth/2fa/enroll
+/// Start enrolling the signed-in user in 2FA with a new secret, replacing
+/// any pending one. It takes effect once confirmed with a code.
+pub async fn enroll_2fa(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+) -> Result<JsonResponse<EnrollResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user = signed_in_user(&auth, &bearer).await?;
+    let pool = require_pool(&auth)?;
+    if user.twofa_enabled {
+        return Err(error_response(StatusCode::CONFLICT, "2FA is already enabled"));
+    }
+    let enrollment = auth.twofa.enroll(&user.username);
+    let failed = |e: String| {
+        warn!("Failed to store 2FA secret of {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start 2FA enrollment")
+    };
+    let encrypted = auth.twofa.encrypt_secret(&user.id.to_string(), &enrollment.secret).map_err(|e| failed(e.to_string()))?;
+    user.set_2fa_secret(pool, &encrypted).await.map_err(|e| failed(e.to_string()))?;
+    Ok(JsonResponse(EnrollResponse {
+        secret: enrollment.secret,
+        otpauth_url: enrollment.otpauth_url,
+        qr_svg: enrollment.qr_svg,
     }))
 }
 
-fn validate_credentials(username: &str, password: &str) -> bool {
-    // Dummy validation - in production, check against database
-    username == "admin" && password == "password123"
-}
-
-fn get_user_roles(username: &str) -> Vec<String> {
-    // Dummy role assignment - in production, fetch from database
-    match username {
-        "admin" => vec!["admin".to_string(), "user".to_string()],
-        "user" => vec!["user".to_string()],
-        _ => vec!["user".to_string()],
-    }
-}
-
-// GET /auth/google (stub)
-pub async fn google_oauth(Query(_query): Query<OAuthQuery>) -> impl IntoResponse {
-    // In real implementation, redirect to Google, handle callback, exchange code for user info
-    // For now, simulate success
-    let username = "google_user";
-    let roles = get_user_roles(username);
-    let now = chrono::Utc::now();
-    let exp = (now + chrono::Duration::hours(24)).timestamp() as usize;
-    let iat = now.timestamp() as usize;
-    let claims = Claims { sub: username.to_string(), roles, exp, iat };
-    let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "your-secret-key".to_string());
-    let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref())).unwrap();
-    Json(LoginResponse { token, user_id: username.to_string(), roles: claims.roles })
-}
-
-// GET /auth/github (stub)
-pub async fn github_oauth(Query(_query): Query<OAuthQuery>) -> impl IntoResponse {
-    // In real implementation, redirect to GitHub, handle callback, exchange code for user info
-    let username = "github_user";
-    let roles = get_user_roles(username);
-    let now = chrono::Utc::now();
-    let exp = (now + chrono::Duration::hours(24)).timestamp() as usize;
-    let iat = now.timestamp() as usize;
-    let claims = Claims { sub: username.to_string(), roles, exp, iat };
-    let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "your-secret-key".to_string());
-    let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref())).unwrap();
-    Json(LoginResponse { token, user_id: username.to_string(), roles: claims.roles })
-}
-
-// POST /auth/reset (stub)
-pub async fn reset_password(Json(payload): Json<ResetRequest>) -> impl IntoResponse {
-    // In real implementation, send reset email
-    if payload.email.contains('@') {
-        Json(serde_json::json!({ "status": "ok", "message": "Reset link sent" }))
-    } else {
-        (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": "Invalid email" })))
-    }
-}
-
-// POST /auth/reset/confirm (stub)
-pub async fn confirm_reset(Json(payload): Json<ResetConfirmRequest>) -> impl IntoResponse {
-    // In real implementation, verify token, update password
-    if payload.token == "valid-token" && payload.new_password.len() >= 8 {
-        Json(serde_json::json!({ "status": "ok", "message": "Password updated" }))
-    } else {
-        (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": "Invalid token or password" })))
-    }
-}
-
-// POST /auth/2fa/verify (stub)
-pub async fn verify_2fa(Json(payload): Json<TwoFARequest>) -> impl IntoResponse {
-    // In real implementation, check code for user
-    if payload.code == "123456" {
-        let roles = get_user_roles(&payload.username);
-        let now = chrono::Utc::now();
-        let exp = (now + chrono::Duration::hours(24)).timestamp() as usize;
-        let iat = now.timestamp() as usize;
-        let claims = Claims { sub: payload.username.clone(), roles, exp, iat };
-        let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "your-secret-key".to_string());
-        let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref())).unwrap();
-        Json(LoginResponse { token, user_id: payload.username, roles: claims.roles })
-    } else {
-        (StatusCode::UNAUTHORIZED, Json(serde_json::json!({ "error": "Invalid 2FA code" })))
-    }
-} 
\ No newline at end of file
+// POST /auth/2fa/confirm
+/// Enable 2FA with a code from the enrolled authenticator
+pub async fn confirm_2fa(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+    Json(payload): Json<ConfirmTwoFARequest>,
+) -> Result<JsonResponse<RecoveryCodesResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user = signed_in_user(&auth, &bearer).await?;
+    let pool = require_pool(&auth)?;
+    let encrypted = match (&user.twofa_secret, user.twofa_enabled) {
+        (_, true) => return Err(error_response(StatusCode::CONFLICT, "2FA is already enabled")),
+        (Some(encrypted), false) => encrypted,
+        (None, false) => return Err(error_response(StatusCode::BAD_REQUEST, "Enroll in 2FA first")),
+    };
+    let secret = auth.twofa.decrypt_secret(&user.id.to_string(), encrypted).map_err(|e| {
+        warn!("Failed to decrypt 2FA secret of {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check 2FA code")
+    })?;
+    let step = match verify_totp(&secret, payload.code.trim(), chrono::Utc::now().timestamp(), None) {
+        Some(step) => step,
+        None => return Err(error_response(StatusCode::BAD_REQUEST, "Invalid 2FA code")),
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    let hashes: Vec<String> = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect();
+    user.enable_2fa(pool, step, &hashes).await.map_err(|e| {
+        warn!("Failed to enable 2FA for {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to enable 2FA")
+    })?;
+    info!("User {} enabled 2FA", user.id);
+    Ok(JsonResponse(RecoveryCodesResponse { recovery_codes }))
+}
+
+// POST /auth/2fa/disable
+/// Turn 2FA off, given a current code or a recovery code
+pub async fn disable_2fa(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+    Json(payload): Json<SecondFactorRequest>,
+) -> Result<StatusCode, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user = signed_in_user(&auth, &bearer).await?;
+    let pool = require_pool(&auth)?;
+    if !user.twofa_enabled {
+        return Err(error_response(StatusCode::CONFLICT, "2FA is not enabled"));
+    }
+    if !check_second_factor(&auth, pool, &user, &payload).await? {
+        return Err(error_response(StatusCode::UNAUTHORIZED, "Invalid 2FA code"));
+    }
+    user.disable_2fa(pool).await.map_err(|e| {
+        warn!("Failed to disable 2FA for {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to disable 2FA")
+    })?;
+    info!("User {} disabled 2FA", user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /auth/2fa/recovery-codes
+/// Replace the recovery codes, given a current code or a recovery code
+pub async fn regenerate_recovery_codes(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+    Json(payload): Json<SecondFactorRequest>,
+) -> Result<JsonResponse<RecoveryCodesResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user = signed_in_user(&auth, &bearer).await?;
+    let pool = require_pool(&auth)?;
+    if !user.twofa_enabled {
+        return Err(error_response(StatusCode::CONFLICT, "2FA is not enabled"));
+    }
+    if !check_second_factor(&auth, pool, &user, &payload).await? {
+        return Err(error_response(StatusCode::UNAUTHORIZED, "Invalid 2FA code"));
+    }
+    let recovery_codes = generate_recovery_codes();
+    let hashes: Vec<String> = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect();
+    user.set_recovery_codes(pool, &hashes).await.map_err(|e| {
+        warn!("Failed to store recovery codes of {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to replace recovery codes")
+    })?;
+    Ok(JsonResponse(RecoveryCodesResponse { recovery_codes }))
+}
+
+// POST /auth/2fa/reset
+/// Admins turn off 2FA for a user who lost both authenticator and recovery codes
+pub async fn reset_2fa(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+    Json(payload): Json<ResetTwoFARequest>,
+) -> Result<StatusCode, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let claims = auth.tokens.verify(bearer.token()).map_err(|_| error_response(StatusCode::UNAUTHORIZED, "Invalid token"))?;
+    if !claims.has_role("admin") {
+        return Err(error_response(StatusCode::FORBIDDEN, "Admin role required"));
+    }
+    let pool = require_pool(&auth)?;
+    let user = match find_user(pool, &payload.user_id).await? {
+        Some(user) => user,
+        None => return Err(error_response(StatusCode::NOT_FOUND, "User not found")),
+    };
+    user.disable_2fa(pool).await.map_err(|e| {
+        warn!("Failed to reset 2FA for {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to reset 2FA")
+    })?;
+    info!("{} reset 2FA of user {}", claims.sub, user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /auth/passkeys/register/start
+/// Options for the browser to create a passkey for the signed-in user
+pub async fn start_passkey_registration(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+) -> Result<JsonResponse<PasskeyCeremony<CreationChallengeResponse>>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user = signed_in_user(&auth, &bearer).await?;
+    let pool = require_pool(&auth)?;
+    let (ceremony_id, options) = auth
+        .passkeys
+        .start_registration(pool, &user)
+        .await
+        .map_err(|e| passkey_error(e, StatusCode::BAD_REQUEST, "Failed to start passkey registration"))?;
+    Ok(JsonResponse(PasskeyCeremony { ceremony_id, options }))
+}
+
+// POST /auth/passkeys/register/finish
+/// Store the passkey the browser created for a registration ceremony
+pub async fn finish_passkey_registration(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+    Json(payload): Json<PasskeyRegisterRequest>,
+) -> Result<(StatusCode, JsonResponse<PasskeyResponse>), (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user = signed_in_user(&auth, &bearer).await?;
+    let pool = require_pool(&auth)?;
+    let name = payload.name.as_deref().map(str::trim).filter(|name| !name.is_empty()).unwrap_or("Passkey");
+    if name.chars().count() > 64 {
+        return Err(error_response(StatusCode::BAD_REQUEST, "Passkey name must be at most 64 characters long"));
+    }
+    let credential = auth
+        .passkeys
+        .finish_registration(pool, &user, payload.ceremony_id, name, &payload.credential)
+        .await
+        .map_err(|e| match e {
+            PasskeyError::Database(e) if is_unique_violation(&e) => {
+                error_response(StatusCode::CONFLICT, "Passkey is already registered")
+            }
+            e => passkey_error(e, StatusCode::BAD_REQUEST, "Invalid or expired passkey registration"),
+        })?;
+    info!("User {} registered passkey {}", user.id, credential.id);
+    Ok((StatusCode::CREATED, JsonResponse(credential.into())))
+}
+
+// GET /auth/passkeys
+/// The signed-in user's passkeys
+pub async fn list_passkeys(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+) -> Result<JsonResponse<Vec<PasskeyResponse>>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user = signed_in_user(&auth, &bearer).await?;
+    let pool = require_pool(&auth)?;
+    let credentials = PasskeyCredential::list(pool, user.id).await.map_err(|e| {
+        warn!("Failed to list passkeys of {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to list passkeys")
+    })?;
+    Ok(JsonResponse(credentials.into_iter().map(PasskeyResponse::from).collect()))
+}
+
+// DELETE /auth/passkeys/:id
+/// Remove one of the signed-in user's passkeys
+pub async fn delete_passkey(
+    State(auth): State<AuthState>,
+    bearer: Bearer,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user = signed_in_user(&auth, &bearer).await?;
+    let pool = require_pool(&auth)?;
+    let deleted = PasskeyCredential::delete(pool, user.id, &id).await.map_err(|e| {
+        warn!("Failed to delete passkey of {}: {}", user.id, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete passkey")
+    })?;
+    if !deleted {
+        return Err(error_response(StatusCode::NOT_FOUND, "Passkey not found"));
+    }
+    info!("User {} deleted passkey {}", user.id, id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /auth/passkeys/login/start
+/// Options for the browser to sign in with one of the user's passkeys
+pub async fn start_passkey_login(
+    State(auth): State<AuthState>,
+    Json(payload): Json<PasskeyLoginRequest>,
+) -> Result<JsonResponse<PasskeyCeremony<RequestChallengeResponse>>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let pool = require_pool(&auth)?;
+    let user = User::get_by_username(pool, &payload.username).await.map_err(|e| {
+        warn!("Failed to look up user {}: {}", payload.username, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up user")
+    })?;
+    // Unknown users look like users without passkeys
+    let started = match user {
+        Some(user) if user.directory_dn.is_some() => return Err(directory_passkey_refused()),
+        Some(user) => auth
+            .passkeys
+            .start_authentication(pool, user.id)
+            .await
+            .map_err(|e| passkey_error(e, StatusCode::BAD_REQUEST, "Failed to start passkey sign-in"))?,
+        None => None,
+    };
+    match started {
+        Some((ceremony_id, options)) => Ok(JsonResponse(PasskeyCeremony { ceremony_id, options })),
+        None => Err(error_response(StatusCode::NOT_FOUND, "No passkeys registered")),
+    }
+}
+
+/// Directory accounts sign in through the directory; their passkeys only
+/// serve as a second factor
+fn directory_passkey_refused() -> (StatusCode, JsonResponse<ErrorResponse>) {
+    error_response(StatusCode::FORBIDDEN, "This account signs in with its directory password")
+}
+
+// POST /auth/passkeys/login/finish
+/// Sign in with a passkey instead of a password. Passkeys check the user
+/// themselves, so no second factor is asked for.
+pub async fn finish_passkey_login(
+    State(auth): State<AuthState>,
+    Json(payload): Json<PasskeyAssertion>,
+) -> Result<JsonResponse<LoginResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let pool = require_pool(&auth)?;
+    let user_id = auth
+        .passkeys
+        .finish_authentication(pool, payload.ceremony_id, &payload.credential)
+        .await
+        .map_err(|e| passkey_error(e, StatusCode::UNAUTHORIZED, "Invalid passkey"))?;
+    let user = match User::get_by_id(pool, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(error_response(StatusCode::UNAUTHORIZED, "Invalid passkey")),
+        Err(e) => {
+            warn!("Failed to look up user {}: {}", user_id, e);
+            return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up user"));
+        }
+    };
+    // The directory may have disabled the account since it last signed in
+    if user.directory_dn.is_some() {
+        return Err(directory_passkey_refused());
+    }
+    info!("User {} signed in with a passkey", user.id);
+    Ok(JsonResponse(start_user_session(&auth, &user).await?))
+}
+
+// GET /auth/oauth
+/// Names of the configured sign-in providers
+pub async fn list_oauth_providers(State(auth): State<AuthState>) -> JsonResponse<OAuthProvidersResponse> {
+    JsonResponse(OAuthProvidersResponse { providers: auth.oauth.names() })
+}
+
+// POST /auth/oauth/:provider/start
+/// Start signing in with a provider. The cookie set here must come back with
+/// the finish request.
+pub async fn start_oauth_login(
+    State(auth): State<AuthState>,
+    Path(provider): Path<String>,
+) -> Result<(SignedCookieJar, JsonResponse<OAuthStartResponse>), (StatusCode, JsonResponse<ErrorResponse>)> {
+    let pool = require_pool(&auth)?;
+    let (authorization_url, cookies) = auth.oauth.start(pool, &provider).await.map_err(oauth_error)?;
+    Ok((cookies, JsonResponse(OAuthStartResponse { authorization_url })))
+}
+
+// POST /auth/oauth/:provider/finish
+/// Sign in with the code and state the provider sent back. As with a
+/// password, accounts with 2FA get a challenge instead of a session.
+pub async fn finish_oauth_login(
+    State(auth): State<AuthState>,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<OAuthFinishRequest>,
+) -> Result<JsonResponse<LoginReply>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let pool = require_pool(&auth)?;
+    let cookies = auth.oauth.login_cookies(&headers);
+    let identity = auth.oauth.finish(pool, &provider, &payload.code, &payload.state, &cookies).await.map_err(oauth_error)?;
+    let user = oauth_user(&auth, pool, &identity).await?;
+    info!("User {} signed in with {}", user.id, provider);
+    Ok(JsonResponse(login_reply(&auth, pool, &user).await?))
+}
+
+/// The user an external account signs in as. On its first sign-in it is
+/// linked to the user with the same email address, if both sides verified
+/// it, or gets a new account while registration is open.
+async fn oauth_user(auth: &AuthState, pool: &PgPool, identity: &ExternalIdentity) -> Result<User, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let failed = |e: sqlx::Error| {
+        warn!("Failed to look up {} account {}: {}", identity.provider, identity.subject, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to sign in")
+    };
+    if let Some(user_id) = linked_user(pool, &identity.provider, &identity.subject).await.map_err(failed)? {
+        return match User::get_by_id(pool, user_id).await.map_err(failed)? {
+            Some(user) => Ok(user),
+            None => Err(error_response(StatusCode::NOT_FOUND, "User not found")),
+        };
+    }
+
+    let email = match identity.email.as_deref().map(|email| email.trim().to_lowercase()) {
+        Some(email) if email.parse::<lettre::Address>().is_ok() => email,
+        _ => return Err(error_response(StatusCode::BAD_REQUEST, "The provider did not share an email address")),
+    };
+    let user = match User::get_by_email(pool, &email).await.map_err(failed)? {
+        Some(user) if user.directory_dn.is_some() => {
+            return Err(error_response(StatusCode::CONFLICT, "An account with this email address signs in with the directory"))
+        }
+        Some(user) if identity.email_verified && user.email_verified => user,
+        Some(_) => {
+            return Err(error_response(
+                StatusCode::CONFLICT,
+                "An account with this email address exists; sign in with its password",
+            ))
+        }
+        None if !auth.registration.enabled => return Err(error_response(StatusCode::FORBIDDEN, "Registration is closed")),
+        None => create_oauth_user(auth, pool, identity, &email).await?,
+    };
+    link_identity(pool, identity, user.id).await.map_err(|e| {
+        if is_unique_violation(&e) {
+            return error_response(StatusCode::CONFLICT, "This account was linked meanwhile; sign in again");
+        }
+        failed(e)
+    })?;
+    info!("Linked {} account {} to user {}", identity.provider, identity.subject, user.id);
+    Ok(user)
+}
+
+/// Register someone new from their external account, with its username if
+/// free and its email address, verified if the provider says so
+async fn create_oauth_user(
+    auth: &AuthState,
+    pool: &PgPool,
+    identity: &ExternalIdentity,
+    email: &str,
+) -> Result<User, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let failed = |e: sqlx::Error| {
+        warn!("Failed to create user for {} account {}: {}", identity.provider, identity.subject, e);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create account")
+    };
+    let preferred = identity.username.as_deref().unwrap_or(email);
+    for username in username_candidates(preferred).take(20) {
+        if User::get_by_username(pool, &username).await.map_err(failed)?.is_some() {
+            continue;
+        }
+        let user = match User::create_external(pool, &username, email, identity.email_verified).await {
+            Ok(user) => user,
+            // Taken since the lookup
+            Err(e) if is_unique_violation(&e) => continue,
+            Err(e) => return Err(failed(e)),
+        };
+        info!("Registered user {} ({}) through {}", user.username, user.id, identity.provider);
+        if !user.email_verified {
+            if let Err(e) = send_verification(auth, pool, &user).await {
+                warn!("Failed to send verification email to {}: {}", user.id, e);
+            }
+        }
+        return Ok(user);
+    }
+    Err(error_response(StatusCode::CONFLICT, "No free username; register first"))
+}
+
+// GET /.well-known/jwks.json
+/// Public keys of the token service, for other services to verify our tokens
+pub async fn jwks(State(tokens): State<Arc<TokenService>>) -> JsonResponse<JwkSet> {
+    JsonResponse(tokens.jwks())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::db::test_pool;
+    use axum::http::header::{COOKIE, SET_COOKIE};
+    use axum::response::IntoResponse;
+    use crate::routes::email::MemoryTransport;
+    use crate::routes::ldap::tests::fleet_settings;
+    use crate::routes::oauth::tests::{mock_providers, MockProvider};
+    use crate::routes::password::NO_PASSWORD;
+    use crate::routes::user::UNVERIFIED_ROLE;
+
+    fn request(username: &str, password: &str) -> Json<LoginRequest> {
+        Json(LoginRequest {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_login_checks_users_table() {
+        let pool = test_pool().await;
+        let username = format!("login-{}", Uuid::new_v4());
+        let hash = hash_password("correct horse").await.unwrap();
+        let roles = vec!["admin".to_string(), "user".to_string()];
+        let user = User::create(&pool, &username, &format!("{}@example.com", username), &hash, &roles)
+            .await
+            .unwrap();
+        let auth = AuthState {
+            tokens: Arc::new(TokenService::development()),
+            shared: SharedState::in_process(),
+            pool: Some(pool),
+            mailer: Mailer::default(),
+            registration: Arc::new(Registration::default()),
+            twofa: Arc::new(TwoFactor::development()),
+            passkeys: Arc::new(Passkeys::development()),
+            oauth: Arc::new(OAuthProviders::default()),
+            ldap: None,
+        };
+
+        let response = session(login(State(auth.clone()), request(&username, "correct horse")).await.unwrap());
+        let claims = auth.tokens.verify(&response.token).unwrap();
+        assert_eq!(claims.sub, user.id.to_string());
+        assert_eq!(claims.name, username);
+        assert_eq!(claims.roles, roles);
+
+        // A wrong password and an unknown user get the same answer
+        let wrong = login(State(auth.clone()), request(&username, "battery staple")).await.unwrap_err();
+        let unknown = login(State(auth.clone()), request("nobody", "correct horse")).await.unwrap_err();
+        assert_eq!(wrong.0, StatusCode::UNAUTHORIZED);
+        assert_eq!((wrong.0, wrong.1.0.error), (unknown.0, unknown.1.0.error));
+
+        let no_database = AuthState { pool: None, ..auth };
+        let unavailable = login(State(no_database), request(&username, "correct horse")).await.unwrap_err();
+        assert_eq!(unavailable.0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    /// The session of a login without 2FA
+    fn session(reply: JsonResponse<LoginReply>) -> LoginResponse {
+        match reply.0 {
+            LoginReply::Session(response) => response,
+            other => panic!("expected a session, got {:?}", other),
+        }
+    }
+
+    fn register_request(username: &str, email: &str, password: &str) -> Json<RegisterRequest> {
+        Json(RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    /// The token in the only mail sent since the last call
+    /// Reset links are mailed from a background task
+    async fn wait_for_mail(memory: &MemoryTransport) {
+        for _ in 0..100 {
+            if !memory.sent().is_empty() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn mailed_token(memory: &MemoryTransport) -> String {
+        let mails = memory.take();
+        assert_eq!(mails.len(), 1);
+        let start = mails[0].text.find("token=").unwrap() + "token=".len();
+        mails[0].text[start..].chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_').collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_register_and_verify_email() {
+        let pool = test_pool().await;
+        let memory = Arc::new(MemoryTransport::new());
+        let auth = AuthState {
+            tokens: Arc::new(TokenService::development()),
+            shared: SharedState::in_process(),
+            pool: Some(pool),
+            mailer: Mailer::new(memory.clone(), "VoiceLink <noreply@example.com>", "https://fleet.example.com"),
+            registration: Arc::new(Registration::default()),
+            twofa: Arc::new(TwoFactor::development()),
+            passkeys: Arc::new(Passkeys::development()),
+            oauth: Arc::new(OAuthProviders::default()),
+            ldap: None,
+        };
+        let username = format!("Pilot-{}", &Uuid::new_v4().simple().to_string()[..12]);
+        let email = format!("{}@Example.com", username);
+        let password = "tin foil kestrel";
+
+        let (status, JsonResponse(registered)) =
+            register(State(auth.clone()), register_request(&username, &email, password)).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(registered.email, email.to_lowercase());
+        assert!(!registered.email_verified);
+
+        // Taken regardless of case, and weak passwords are refused
+        let taken = register(State(auth.clone()), register_request(&username.to_lowercase(), "other@example.com", password)).await.unwrap_err();
+        assert_eq!(taken.0, StatusCode::CONFLICT);
+        let taken = register(State(auth.clone()), register_request("someone-else", &email, password)).await.unwrap_err();
+        assert_eq!(taken.0, StatusCode::CONFLICT);
+        let weak = register(State(auth.clone()), register_request("someone-else", "other@example.com", "password123")).await.unwrap_err();
+        assert_eq!(weak.0, StatusCode::BAD_REQUEST);
+        let invalid = register(State(auth.clone()), register_request("no spaces", "other@example.com", password)).await.unwrap_err();
+        assert_eq!(invalid.0, StatusCode::BAD_REQUEST);
+
+        // Unverified accounts may sign in, with a role that keeps them out of voice
+        let response = session(login(State(auth.clone()), request(&username.to_uppercase(), password)).await.unwrap());
+        assert!(response.roles.iter().any(|role| role == UNVERIFIED_ROLE));
+
+        // Resending replaces the first link
+        let first = mailed_token(&memory);
+        let bearer = Bearer::new(&response.token);
+        assert_eq!(resend_verification(State(auth.clone()), bearer).await.unwrap(), StatusCode::NO_CONTENT);
+        let second = mailed_token(&memory);
+        let verify = |token: &str| verify_email(State(auth.clone()), Json(VerifyEmailRequest { token: token.to_string() }));
+        assert_eq!(verify(&first).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(verify(&second).await.unwrap(), StatusCode::NO_CONTENT);
+        assert_eq!(verify(&second).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        let response = session(login(State(auth.clone()), request(&username, password)).await.unwrap());
+        assert_eq!(response.roles, vec!["user"]);
+
+        let closed = AuthState {
+            registration: Arc::new(Registration { enabled: false, ..Registration::default() }),
+            ..auth
+        };
+        let refused = register(State(closed), register_request("latecomer", "late@example.com", password)).await.unwrap_err();
+        assert_eq!(refused.0, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_two_factor_login() {
+        let pool = test_pool().await;
+        let username = format!("totp-{}", Uuid::new_v4());
+        let hash = hash_password("correct horse").await.unwrap();
+        User::create(&pool, &username, &format!("{}@example.com", username), &hash, &["user".to_string()])
+            .await
+            .unwrap();
+        let auth = AuthState {
+            tokens: Arc::new(TokenService::development()),
+            shared: SharedState::in_process(),
+            pool: Some(pool),
+            mailer: Mailer::default(),
+            registration: Arc::new(Registration::default()),
+            twofa: Arc::new(TwoFactor::development()),
+            passkeys: Arc::new(Passkeys::development()),
+            oauth: Arc::new(OAuthProviders::default()),
+            ldap: None,
+        };
+        let bearer = |token: &str| Bearer::new(token);
+        let code_at = |secret: &str, time: i64| {
+            let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret).unwrap();
+            crate::routes::twofa::totp_code(&secret, time / crate::routes::twofa::TOTP_STEP)
+        };
+        let challenge = |reply: JsonResponse<LoginReply>| match reply.0 {
+            LoginReply::SecondFactor(challenge) => challenge.twofa_token,
+            other => panic!("expected a 2FA challenge, got {:?}", other),
+        };
+        let verify = |twofa_token: &str, code: Option<String>, recovery_code: Option<&str>| {
+            verify_2fa(
+                State(auth.clone()),
+                Json(TwoFARequest {
+                    twofa_token: twofa_token.to_string(),
+                    factor: SecondFactorRequest {
+                        code,
+                        recovery_code: recovery_code.map(str::to_string),
+                        passkey: None,
+                    },
+                }),
+            )
+        };
+
+        // Enrollment takes effect once confirmed with a code
+        let access = session(login(State(auth.clone()), request(&username, "correct horse")).await.unwrap()).token;
+        let JsonResponse(enrollment) = enroll_2fa(State(auth.clone()), bearer(&access)).await.unwrap();
+        assert!(enrollment.qr_svg.contains("<svg"));
+        let now = chrono::Utc::now().timestamp();
+        let wrong = confirm_2fa(State(auth.clone()), bearer(&access), Json(ConfirmTwoFARequest { code: "000000".to_string() })).await;
+        assert_eq!(wrong.unwrap_err().0, StatusCode::BAD_REQUEST);
+        let code = code_at(&enrollment.secret, now);
+        let JsonResponse(confirmed) = confirm_2fa(State(auth.clone()), bearer(&access), Json(ConfirmTwoFARequest { code: code.clone() })).await.unwrap();
+        assert_eq!(confirmed.recovery_codes.len(), crate::routes::twofa::RECOVERY_CODE_COUNT);
+
+        // The password now only earns a challenge, which is no access token
+        let twofa_token = challenge(login(State(auth.clone()), request(&username, "correct horse")).await.unwrap());
+        assert_eq!(enroll_2fa(State(auth.clone()), bearer(&twofa_token)).await.unwrap_err().0, StatusCode::UNAUTHORIZED);
+
+        // The confirming code can't be replayed; the next one, a step early, works
+        assert_eq!(verify(&twofa_token, Some(code), None).await.unwrap_err().0, StatusCode::UNAUTHORIZED);
+        let JsonResponse(response) = verify(&twofa_token, Some(code_at(&enrollment.secret, now + 30)), None).await.unwrap();
+        assert_eq!(auth.tokens.verify(&response.token).unwrap().name, username);
+        // A challenge earns one session
+        let reused = verify(&twofa_token, Some(code_at(&enrollment.secret, now + 60)), None).await;
+        assert_eq!(reused.unwrap_err().0, StatusCode::UNAUTHORIZED);
+
+        // Recovery codes work once
+        let twofa_token = challenge(login(State(auth.clone()), request(&username, "correct horse")).await.unwrap());
+        let JsonResponse(recovered) = verify(&twofa_token, None, Some(&confirmed.recovery_codes[0].to_uppercase())).await.unwrap();
+        assert_eq!(recovered.user_id, response.user_id);
+        let twofa_token = challenge(login(State(auth.clone()), request(&username, "correct horse")).await.unwrap());
+        assert!(verify(&twofa_token, None, Some(&confirmed.recovery_codes[0])).await.is_err());
+
+        // Guessing uses up the challenge
+        let twofa_token = challenge(login(State(auth.clone()), request(&username, "correct horse")).await.unwrap());
+        for _ in 1..crate::routes::twofa::MAX_ATTEMPTS {
+            assert!(verify(&twofa_token, Some("000000".to_string()), None).await.is_err());
+        }
+        let last_guess = verify(&twofa_token, Some("000000".to_string()), None).await.unwrap_err();
+        assert_eq!(last_guess.1.0.error, "Invalid 2FA code");
+        let exhausted = verify(&twofa_token, None, Some(&confirmed.recovery_codes[1])).await.unwrap_err();
+        assert_eq!(exhausted.1.0.error, "Too many attempts, sign in again");
+
+        // Only admins reset someone's 2FA; users disable their own with a code
+        let reset = reset_2fa(State(auth.clone()), bearer(&access), Json(ResetTwoFARequest { user_id: response.user_id.clone() })).await;
+        assert_eq!(reset.unwrap_err().0, StatusCode::FORBIDDEN);
+        let disable = |recovery_code: &str| {
+            disable_2fa(
+                State(auth.clone()),
+                bearer(&access),
+                Json(SecondFactorRequest { recovery_code: Some(recovery_code.to_string()), ..SecondFactorRequest::default() }),
+            )
+        };
+        assert_eq!(disable("aaaa-bbbb-cccc").await.unwrap_err().0, StatusCode::UNAUTHORIZED);
+        assert_eq!(disable(&confirmed.recovery_codes[2]).await.unwrap(), StatusCode::NO_CONTENT);
+        session(login(State(auth.clone()), request(&username, "correct horse")).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_passkey_login() {
+        use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+
+        let pool = test_pool().await;
+        let username = format!("passkey-{}", Uuid::new_v4());
+        let hash = hash_password("correct horse").await.unwrap();
+        let user = User::create(&pool, &username, &format!("{}@example.com", username), &hash, &["user".to_string()])
+            .await
+            .unwrap();
+        let auth = AuthState {
+            tokens: Arc::new(TokenService::development()),
+            shared: SharedState::in_process(),
+            pool: Some(pool.clone()),
+            mailer: Mailer::default(),
+            registration: Arc::new(Registration::default()),
+            twofa: Arc::new(TwoFactor::development()),
+            passkeys: Arc::new(Passkeys::development()),
+            oauth: Arc::new(OAuthProviders::default()),
+            ldap: None,
+        };
+        let origin = webauthn_rs::prelude::Url::parse("http://localhost:5173").unwrap();
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+        let bearer = |token: &str| Bearer::new(token);
+        let start_login = || start_passkey_login(State(auth.clone()), Json(PasskeyLoginRequest { username: username.clone() }));
+
+        // Nothing to sign in with before a passkey is registered
+        assert_eq!(start_login().await.unwrap_err().0, StatusCode::NOT_FOUND);
+        let unknown = start_passkey_login(State(auth.clone()), Json(PasskeyLoginRequest { username: "nobody".to_string() })).await;
+        assert_eq!(unknown.unwrap_err().0, StatusCode::NOT_FOUND);
+
+        let access = session(login(State(auth.clone()), request(&username, "correct horse")).await.unwrap()).token;
+        let JsonResponse(ceremony) = start_passkey_registration(State(auth.clone()), bearer(&access)).await.unwrap();
+        let credential = authenticator.do_registration(origin.clone(), ceremony.options).unwrap();
+        let register = PasskeyRegisterRequest { ceremony_id: ceremony.ceremony_id, name: Some(" Laptop ".to_string()), credential };
+        let (status, JsonResponse(registered)) =
+            finish_passkey_registration(State(auth.clone()), bearer(&access), Json(register)).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(registered.name, "Laptop");
+
+        // Sign in without a password; each ceremony works once
+        let JsonResponse(ceremony) = start_login().await.unwrap();
+        let credential = authenticator.do_authentication(origin.clone(), ceremony.options).unwrap();
+        let assertion = || PasskeyAssertion { ceremony_id: ceremony.ceremony_id, credential: credential.clone() };
+        let JsonResponse(response) = finish_passkey_login(State(auth.clone()), Json(assertion())).await.unwrap();
+        assert_eq!(response.user_id, user.id.to_string());
+        let replayed = finish_passkey_login(State(auth.clone()), Json(assertion())).await.unwrap_err();
+        assert_eq!(replayed.0, StatusCode::UNAUTHORIZED);
+        let JsonResponse(listed) = list_passkeys(State(auth.clone()), bearer(&access)).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].last_used_at.is_some());
+
+        // A passkey stands in for the TOTP code of an account with 2FA
+        let enrollment = auth.twofa.enroll(&username);
+        let encrypted = auth.twofa.encrypt_secret(&user.id.to_string(), &enrollment.secret).unwrap();
+        user.set_2fa_secret(&pool, &encrypted).await.unwrap();
+        user.enable_2fa(&pool, 0, &[]).await.unwrap();
+        let twofa_token = match login(State(auth.clone()), request(&username, "correct horse")).await.unwrap().0 {
+            LoginReply::SecondFactor(challenge) if challenge.passkey => challenge.twofa_token,
+            other => panic!("expected a 2FA challenge offering passkeys, got {:?}", other),
+        };
+        let challenge = PasskeyChallengeRequest { twofa_token: twofa_token.clone() };
+        let JsonResponse(ceremony) = start_2fa_passkey(State(auth.clone()), Json(challenge)).await.unwrap();
+        let credential = authenticator.do_authentication(origin.clone(), ceremony.options).unwrap();
+        let factor = SecondFactorRequest {
+            passkey: Some(PasskeyAssertion { ceremony_id: ceremony.ceremony_id, credential }),
+            ..SecondFactorRequest::default()
+        };
+        let JsonResponse(response) = verify_2fa(State(auth.clone()), Json(TwoFARequest { twofa_token, factor })).await.unwrap();
+        assert_eq!(auth.tokens.verify(&response.token).unwrap().name, username);
+
+        // Directory accounts need the directory to agree, so a passkey alone won't do
+        let JsonResponse(ceremony) = start_login().await.unwrap();
+        let credential = authenticator.do_authentication(origin.clone(), ceremony.options).unwrap();
+        user.update_directory(&pool, &user.email, "uid=pilot,ou=people,dc=fleet,dc=test", &user.roles).await.unwrap();
+        let refused = finish_passkey_login(State(auth.clone()), Json(PasskeyAssertion { ceremony_id: ceremony.ceremony_id, credential })).await;
+        assert_eq!(refused.unwrap_err().0, StatusCode::FORBIDDEN);
+        assert_eq!(start_login().await.unwrap_err().0, StatusCode::FORBIDDEN);
+        sqlx::query("UPDATE users SET directory_dn = NULL WHERE id = $1").bind(user.id).execute(&pool).await.unwrap();
+
+        // Removed passkeys can't sign in
+        let deleted = delete_passkey(State(auth.clone()), bearer(&access), Path(registered.id.clone())).await.unwrap();
+        assert_eq!(deleted, StatusCode::NO_CONTENT);
+        let again = delete_passkey(State(auth.clone()), bearer(&access), Path(registered.id)).await.unwrap_err();
+        assert_eq!(again.0, StatusCode::NOT_FOUND);
+        assert_eq!(start_login().await.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_reset_answers_the_same_when_the_database_fails() {
+        // Nothing listens here, so the lookup fails after the answer
+        let pool = PgPool::connect_lazy("postgres://fleet@127.0.0.1:1/fleet").unwrap();
+        let memory = Arc::new(MemoryTransport::new());
+        let auth = AuthState {
+            tokens: Arc::new(TokenService::development()),
+            shared: SharedState::in_process(),
+            pool: Some(pool),
+            mailer: Mailer::new(memory.clone(), "VoiceLink <noreply@example.com>", "https://fleet.example.com"),
+            registration: Arc::new(Registration::default()),
+            twofa: Arc::new(TwoFactor::development()),
+            passkeys: Arc::new(Passkeys::development()),
+            oauth: Arc::new(OAuthProviders::default()),
+            ldap: None,
+        };
+        let reset = reset_password(State(auth.clone()), Json(ResetRequest { email: "pilot@example.com".to_string() })).await;
+        assert_eq!(reset.unwrap(), StatusCode::ACCEPTED);
+        let invalid = reset_password(State(auth), Json(ResetRequest { email: "not an address".to_string() })).await;
+        assert_eq!(invalid.unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert!(memory.sent().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_password_reset() {
+        let pool = test_pool().await;
+        let username = format!("reset-{}", &Uuid::new_v4().simple().to_string()[..12]);
+        let email = format!("{}@example.com", username);
+        let hash = hash_password("correct horse").await.unwrap();
+        User::create(&pool, &username, &email, &hash, &["user".to_string()]).await.unwrap();
+        let memory = Arc::new(MemoryTransport::new());
+        let auth = AuthState {
+            tokens: Arc::new(TokenService::development()),
+            shared: SharedState::in_process(),
+            pool: Some(pool),
+            mailer: Mailer::new(memory.clone(), "VoiceLink <noreply@example.com>", "https://fleet.example.com"),
+            registration: Arc::new(Registration::default()),
+            twofa: Arc::new(TwoFactor::development()),
+            passkeys: Arc::new(Passkeys::development()),
+            oauth: Arc::new(OAuthProviders::default()),
+            ldap: None,
+        };
+        let request_reset = |email: &str| reset_password(State(auth.clone()), Json(ResetRequest { email: email.to_string() }));
+        let confirm = |token: &str, new_password: &str| {
+            confirm_reset(
+                State(auth.clone()),
+                Json(ResetConfirmRequest {
+                    token: token.to_string(),
+                    new_password: new_password.to_string(),
+                }),
+            )
+        };
+        let old_session = session(login(State(auth.clone()), request(&username, "correct horse")).await.unwrap());
+
+        // Unknown addresses get the same answer, and no mail
+        assert_eq!(request_reset("nobody@example.com").await.unwrap(), StatusCode::ACCEPTED);
+        assert_eq!(request_reset(&email.to_uppercase()).await.unwrap(), StatusCode::ACCEPTED);
+        wait_for_mail(&memory).await;
+        let token = mailed_token(&memory);
+
+        // The policy applies, and a refused password leaves the token usable
+        assert_eq!(confirm(&token, "password123").await.unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(confirm("made-up", "tin foil kestrel").await.unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(confirm(&token, "tin foil kestrel").await.unwrap(), StatusCode::NO_CONTENT);
+        assert_eq!(confirm(&token, "another tin kestrel").await.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        // Every earlier session is signed out
+        assert!(auth.tokens.verify(&old_session.token).is_err());
+        let refreshed = refresh(State(auth.clone()), Json(RefreshRequest { refresh_token: old_session.refresh_token })).await;
+        assert_eq!(refreshed.unwrap_err().0, StatusCode::UNAUTHORIZED);
+        assert!(login(State(auth.clone()), request(&username, "correct horse")).await.is_err());
+        let new_session = session(login(State(auth.clone()), request(&username, "tin foil kestrel")).await.unwrap());
+        assert!(auth.tokens.verify(&new_session.token).is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_oauth_login_links_and_creates_accounts() {
+        let pool = test_pool().await;
+        let mock = MockProvider::start().await;
+        let memory = Arc::new(MemoryTransport::new());
+        let auth = AuthState {
+            tokens: Arc::new(TokenService::development()),
+            shared: SharedState::in_process(),
+            pool: Some(pool.clone()),
+            mailer: Mailer::new(memory.clone(), "VoiceLink <noreply@example.com>", "https://fleet.example.com"),
+            registration: Arc::new(Registration::default()),
+            twofa: Arc::new(TwoFactor::development()),
+            passkeys: Arc::new(Passkeys::development()),
+            oauth: Arc::new(mock_providers(&mock)),
+            ldap: None,
+        };
+        // The browser sends back the cookies the start response set
+        let sign_in_from = |auth: AuthState, claims: serde_json::Value, same_browser: bool| {
+            let mock = mock.clone();
+            async move {
+                let (cookies, JsonResponse(started)) = start_oauth_login(State(auth.clone()), Path("mock".to_string())).await.unwrap();
+                let (code, state) = mock.authorize(&started.authorization_url, claims);
+                let mut headers = HeaderMap::new();
+                if same_browser {
+                    for set_cookie in (cookies, ()).into_response().headers().get_all(SET_COOKIE) {
+                        let pair = set_cookie.to_str().unwrap().split(';').next().unwrap().to_string();
+                        headers.append(COOKIE, pair.parse().unwrap());
+                    }
+                }
+                finish_oauth_login(State(auth), Path("mock".to_string()), headers, Json(OAuthFinishRequest { code, state })).await
+            }
+        };
+        let sign_in = |auth: AuthState, claims: serde_json::Value| sign_in_from(auth, claims, true);
+        let id = Uuid::new_v4().simple().to_string();
+        assert_eq!(list_oauth_providers(State(auth.clone())).await.0.providers, vec!["mock"]);
+
+        // A callback in a browser that didn't start the login is refused
+        let claims = serde_json::json!({ "sub": format!("z-{}", id), "email": format!("z-{}@example.com", &id[..12]) });
+        assert_eq!(sign_in_from(auth.clone(), claims, false).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+        // A verified address links to the account that verified it too
+        let name = format!("linked-{}", &id[..12]);
+        let email = format!("{}@example.com", name);
+        let hash = hash_password("correct horse").await.unwrap();
+        let existing = User::create(&pool, &name, &email, &hash, &["user".to_string()]).await.unwrap();
+        let claims = serde_json::json!({ "sub": format!("a-{}", id), "email": email.to_uppercase(), "email_verified": true });
+        let response = session(sign_in(auth.clone(), claims.clone()).await.unwrap());
+        assert_eq!(response.user_id, existing.id.to_string());
+        // Later sign-ins follow the link, whatever the address is by then
+        let moved = serde_json::json!({ "sub": format!("a-{}", id), "email": "elsewhere@example.com" });
+        assert_eq!(session(sign_in(auth.clone(), moved).await.unwrap()).user_id, existing.id.to_string());
+
+        // An unverified address must not take over an account
+        let unverified = serde_json::json!({ "sub": format!("b-{}", id), "email": email, "email_verified": false });
+        assert_eq!(sign_in(auth.clone(), unverified).await.unwrap_err().0, StatusCode::CONFLICT);
+
+        // Unknown people get an account named after their preferred username
+        let new_email = format!("new-{}@example.com", &id[..12]);
+        let claims = serde_json::json!({
+            "sub": format!("c-{}", id),
+            "email": new_email,
+            "email_verified": false,
+            "preferred_username": name,
+        });
+        let closed = AuthState {
+            registration: Arc::new(Registration { enabled: false, ..Registration::default() }),
+            ..auth.clone()
+        };
+        assert_eq!(sign_in(closed, claims.clone()).await.unwrap_err().0, StatusCode::FORBIDDEN);
+        let response = session(sign_in(auth.clone(), claims).await.unwrap());
+        assert!(response.roles.iter().any(|role| role == UNVERIFIED_ROLE));
+        let created = User::get_by_email(&pool, &new_email).await.unwrap().unwrap();
+        assert_eq!(created.username, format!("{}-2", name));
+        assert_eq!(memory.take().len(), 1);
+        // It has no password to sign in with
+        assert!(login(State(auth.clone()), request(&created.username, NO_PASSWORD)).await.is_err());
+
+        // No address, no account
+        let anonymous = serde_json::json!({ "sub": format!("d-{}", id) });
+        assert_eq!(sign_in(auth.clone(), anonymous).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+        let unknown = start_oauth_login(State(auth.clone()), Path("other".to_string())).await.unwrap_err();
+        assert_eq!(unknown.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL and TEST_LDAP_URL"]
+    async fn test_directory_login_provisions_users() {
+        // Needs the OpenLDAP container described in the README besides the database
+        let url = match std::env::var("TEST_LDAP_URL") {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        let pool = test_pool().await;
+        let memory = Arc::new(MemoryTransport::new());
+        let auth = AuthState {
+            tokens: Arc::new(TokenService::development()),
+            shared: SharedState::in_process(),
+            pool: Some(pool.clone()),
+            mailer: Mailer::new(memory.clone(), "VoiceLink <noreply@example.com>", "https://fleet.example.com"),
+            registration: Arc::new(Registration::default()),
+            twofa: Arc::new(TwoFactor::development()),
+            passkeys: Arc::new(Passkeys::development()),
+            oauth: Arc::new(OAuthProviders::default()),
+            ldap: Some(Arc::new(LdapDirectory::new(fleet_settings(&url)))),
+        };
+
+        // The first sign-in creates the account, with roles from the groups
+        let response = session(login(State(auth.clone()), request("maverick", "danger-zone")).await.unwrap());
+        assert_eq!(response.roles, vec!["admin".to_string(), "user".to_string()]);
+        let user = User::get_by_username(&pool, "maverick").await.unwrap().unwrap();
+        assert_eq!(response.user_id, user.id.to_string());
+        assert_eq!(user.directory_dn.as_deref(), Some("uid=maverick,ou=people,dc=example,dc=org"));
+        assert_eq!(user.email, "maverick@example.org");
+        assert!(user.email_verified);
+
+        // Later sign-ins take the roles from the directory again
+        sqlx::query("UPDATE users SET roles = ARRAY['user'] WHERE id = $1").bind(user.id).execute(&pool).await.unwrap();
+        let again = session(login(State(auth.clone()), request("MAVERICK", "danger-zone")).await.unwrap());
+        assert_eq!(again.user_id, user.id.to_string());
+        assert_eq!(again.roles, vec!["admin".to_string(), "user".to_string()]);
+
+        let wrong = login(State(auth.clone()), request("maverick", "wrong")).await.unwrap_err();
+        assert_eq!(wrong.0, StatusCode::UNAUTHORIZED);
+        let ungrouped = login(State(auth.clone()), request("iceman", "you-can-be-my-wingman")).await.unwrap_err();
+        assert_eq!(ungrouped.0, StatusCode::UNAUTHORIZED);
+        let no_password = AuthState { ldap: None, ..auth.clone() };
+        assert!(login(State(no_password), request("maverick", NO_PASSWORD)).await.is_err());
+
+        // Local accounts keep signing in with their own password
+        let username = format!("local-{}", &Uuid::new_v4().simple().to_string()[..12]);
+        let hash = hash_password("correct horse").await.unwrap();
+        User::create(&pool, &username, &format!("{}@example.com", username), &hash, &["user".to_string()]).await.unwrap();
+        assert!(login(State(auth.clone()), request(&username, "correct horse")).await.is_ok());
+
+        // Directory passwords aren't reset here
+        let reset = reset_password(State(auth.clone()), Json(ResetRequest { email: "maverick@example.org".to_string() })).await;
+        assert_eq!(reset.unwrap(), StatusCode::ACCEPTED);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(memory.take().is_empty());
+    }
+}
@@ -0,0 +1,44 @@
+//! The access token of API requests
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    response::Json as JsonResponse,
+};
+use crate::routes::channels::ErrorResponse;
+
+/// The token of a request's `Authorization: Bearer` header. Requests without
+/// one are refused with 401 before the handler runs.
+#[derive(Debug, Clone)]
+pub struct Bearer(String);
+
+impl Bearer {
+    #[cfg(test)]
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+
+    pub fn token(&self) -> &str {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Bearer {
+    type Rejection = (StatusCode, JsonResponse<ErrorResponse>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = parts.headers.get(AUTHORIZATION).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        // The scheme is case-insensitive
+        match header.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() => {
+                Ok(Self(token.trim().to_string()))
+            }
+            _ => Err((
+                StatusCode::UNAUTHORIZED,
+                JsonResponse(ErrorResponse { error: "Missing bearer token".to_string() }),
+            )),
+        }
+    }
+}
@@ -1,14 +1,22 @@
 use axum::{
-    extract::{Json, Path, State, TypedHeader},
-    headers::{Authorization, Bearer},
-    http::StatusCode,
-    response::Json as JsonResponse,
+    extract::{Json, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Json as JsonResponse},
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use uuid::Uuid;
+use crate::audio::capacity::Waitlists;
+use crate::audio::recording::{RecordingConfig, RecordingError, RecordingInfo, RecordingManager};
+use crate::audio::state::StateError as VoiceStateError;
+use crate::events::{ChannelEvent, EventBus, RemovalReason};
+use crate::state::{SharedState, StateChange, StateError};
+use crate::routes::bearer::Bearer;
+use crate::routes::email::Mailer;
+use crate::routes::user::UserDirectory;
+use crate::tokens::TokenService;
+use tracing::{error, warn};
 
 // Data structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +36,24 @@ pub struct Channel {
     pub members: Vec<String>,
     pub banned_users: Vec<BannedUser>,
     pub invite_tokens: HashMap<String, InviteToken>,
+    /// Lowest bitrate senders are asked to use (bps), server default if unset
+    pub min_bitrate_bps: Option<u32>,
+    /// Bitrate cap enforced on senders (bps), server default if unset
+    pub max_bitrate_bps: Option<u32>,
+    /// Most users in the channel's voice at once, unlimited if unset
+    pub max_participants: Option<u32>,
+    /// Most users speaking at once, unlimited if unset
+    pub max_speakers: Option<u32>,
+    /// Queue users turned away from a full channel instead of rejecting them outright
+    #[serde(default)]
+    pub waitlist_enabled: bool,
+}
+
+impl Channel {
+    /// Owners and moderators are not held to capacity limits
+    pub fn bypasses_limits(&self, user_id: &str) -> bool {
+        can_moderate_channel(self, user_id)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,6 +82,21 @@ pub struct UserRole {
     pub role: Role,
 }
 
+/// Voice restrictions a moderator has imposed on a user in a channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct VoiceModeration {
+    /// Voice from the user is dropped
+    pub server_muted: bool,
+    /// Nothing is forwarded to the user
+    pub server_deafened: bool,
+}
+
+impl VoiceModeration {
+    pub fn is_active(&self) -> bool {
+        self.server_muted || self.server_deafened
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Role {
     Owner,
@@ -82,12 +123,10 @@ impl Role {
     }
 
     pub fn can_manage(&self, target_role: &Role) -> bool {
-        match (self, target_role) {
-            (Role::Owner, _) => true,
-            (Role::Moderator, Role::Member) => true,
-            (Role::Moderator, Role::Moderator) => true,
-            _ => false,
-        }
+        matches!(
+            (self, target_role),
+            (Role::Owner, _) | (Role::Moderator, Role::Member) | (Role::Moderator, Role::Moderator)
+        )
     }
 }
 
@@ -96,9 +135,13 @@ impl Role {
 pub struct CreateChannelRequest {
     pub name: String,
     pub privacy: ChannelPrivacy,
+    pub min_bitrate_bps: Option<u32>,
+    pub max_bitrate_bps: Option<u32>,
+    #[serde(flatten)]
+    pub limits: ChannelLimits,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CreateChannelResponse {
     pub channel_id: String,
     pub name: String,
@@ -115,7 +158,7 @@ pub struct InviteUserRequest {
     pub username: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct InviteUserResponse {
     pub invite_token: String,
     pub expires_at: u64,
@@ -141,19 +184,27 @@ pub struct ChangeRoleRequest {
 }
 
 #[derive(Debug, Deserialize)]
-pub struct KickUserRequest {
-    pub username: String,
+pub struct BanUserRequest {
+    pub reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct BanUserRequest {
-    pub username: String,
-    pub reason: Option<String>,
+pub struct ServerMuteRequest {
+    pub muted: bool,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct UnbanUserRequest {
-    pub username: String,
+pub struct ServerDeafenRequest {
+    pub deafened: bool,
+}
+
+/// Capacity limits of a channel, as set through the API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelLimits {
+    pub max_participants: Option<u32>,
+    pub max_speakers: Option<u32>,
+    #[serde(default)]
+    pub waitlist_enabled: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -161,36 +212,208 @@ pub struct ListUsersResponse {
     pub users: Vec<UserRole>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct StartRecordingRequest {
+    #[serde(default)]
+    pub mixed_track: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecordingConsentRequest {
+    pub consent: bool,
+}
+
 #[derive(Debug, Serialize)]
-pub struct ErrorResponse {
-    pub error: String,
+pub struct ListRecordingsResponse {
+    pub active: Option<RecordingInfo>,
+    pub recordings: Vec<RecordingInfo>,
 }
 
-// JWT Claims structure (reused from auth)
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    roles: Vec<String>,
-    exp: usize,
-    iat: usize,
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
 }
 
 // App state
 #[derive(Clone)]
 pub struct AppState {
     pub channels: Arc<Mutex<HashMap<String, Channel>>>,
+    pub recordings: Arc<RecordingManager>,
+    pub events: EventBus,
+    pub shared: SharedState,
+    /// Moderator-imposed voice state by channel, then user
+    pub voice_moderation: Arc<Mutex<HashMap<String, HashMap<String, VoiceModeration>>>>,
+    /// Users queued for a place in full channels
+    pub waitlists: Arc<Waitlists>,
+    /// Verifies access tokens
+    pub tokens: Arc<TokenService>,
+    /// Resolves user IDs to usernames
+    pub users: Arc<UserDirectory>,
+    /// Sends invites to invited users' verified addresses
+    pub mailer: Mailer,
 }
 
 impl AppState {
+    #[cfg(test)]
     pub fn new() -> Self {
+        Self::with_shared_state(RecordingConfig::default(), SharedState::in_process())
+    }
+
+    pub fn with_shared_state(config: RecordingConfig, shared: SharedState) -> Self {
         Self {
             channels: Arc::new(Mutex::new(HashMap::new())),
+            recordings: Arc::new(RecordingManager::new(config)),
+            events: EventBus::default(),
+            shared,
+            voice_moderation: Arc::new(Mutex::new(HashMap::new())),
+            waitlists: Arc::new(Waitlists::new()),
+            tokens: Arc::new(TokenService::development()),
+            users: Arc::new(UserDirectory::default()),
+            mailer: Mailer::default(),
+        }
+    }
+
+    pub fn with_tokens(mut self, tokens: Arc<TokenService>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    pub fn with_users(mut self, users: Arc<UserDirectory>) -> Self {
+        self.users = users;
+        self
+    }
+
+    pub fn with_mailer(mut self, mailer: Mailer) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    /// Load stored channels and token revocations and keep applying changes
+    /// made by other instances
+    pub async fn start_replication(&self) -> Result<(), StateError> {
+        // Subscribe first so nothing saved during the load is missed
+        let mut changes = self.shared.subscribe_remote();
+        let stored = self.shared.load_channels().await?;
+        {
+            let mut channels = self.channels.lock().unwrap();
+            for channel in stored {
+                channels.insert(channel.id.clone(), channel);
+            }
+        }
+        for revocation in self.shared.load_revocations().await? {
+            self.tokens.apply_revocation(revocation);
+        }
+
+        let channels = self.channels.clone();
+        let events = self.events.clone();
+        let voice_moderation = self.voice_moderation.clone();
+        let tokens = self.tokens.clone();
+        tokio::spawn(async move {
+            while let Some(change) = changes.recv().await {
+                match change {
+                    StateChange::ChannelUpdated { channel } => {
+                        channels.lock().unwrap().insert(channel.id.clone(), channel);
+                    }
+                    StateChange::ChannelRemoved { channel_id } => {
+                        channels.lock().unwrap().remove(&channel_id);
+                    }
+                    StateChange::Event { event } => {
+                        if let ChannelEvent::VoiceModerationChanged { channel_id, user_id, server_muted, server_deafened, .. } = &event {
+                            apply_voice_moderation(&voice_moderation, channel_id, user_id, VoiceModeration {
+                                server_muted: *server_muted,
+                                server_deafened: *server_deafened,
+                            });
+                        }
+                        events.publish(event);
+                    }
+                    StateChange::TokenRevoked { revocation } => tokens.apply_revocation(revocation),
+                    // Presence is tracked by the WebSocket state
+                    StateChange::PresenceUpdated { .. } | StateChange::PresenceRemoved { .. } => {}
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Publish an event to this instance's subscribers and to other instances
+    pub async fn publish_event(&self, event: ChannelEvent) {
+        if let Err(e) = self.shared.publish_event(&event).await {
+            warn!("Failed to share channel event: {}", e);
+        }
+        self.events.publish(event);
+    }
+
+    /// Voice restrictions on a user in a channel
+    pub fn voice_moderation(&self, channel_id: &str, user_id: &str) -> VoiceModeration {
+        self.voice_moderation
+            .lock()
+            .unwrap()
+            .get(channel_id)
+            .and_then(|users| users.get(user_id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Replace the voice restrictions on a user and tell the voice and
+    /// WebSocket layers of every instance
+    pub async fn set_voice_moderation(
+        &self,
+        channel_id: &str,
+        user_id: &str,
+        moderation: VoiceModeration,
+        changed_by: Option<String>,
+    ) {
+        apply_voice_moderation(&self.voice_moderation, channel_id, user_id, moderation);
+        self.publish_event(ChannelEvent::VoiceModerationChanged {
+            channel_id: channel_id.to_string(),
+            user_id: user_id.to_string(),
+            server_muted: moderation.server_muted,
+            server_deafened: moderation.server_deafened,
+            changed_by,
+        })
+        .await;
+    }
+
+    /// Admit a user to the voice of a channel that `occupied` other users
+    /// on this instance are already in
+    pub fn admit_voice(&self, channel_id: &str, user_id: &str, occupied: usize) -> Result<(), VoiceStateError> {
+        match self.channels.lock().unwrap().get(channel_id) {
+            Some(channel) => self.waitlists.admit(channel, user_id, occupied),
+            // Channels this instance does not know have no limits to apply
+            None => Ok(()),
+        }
+    }
+
+    /// Lift any voice restrictions once a user has left a channel
+    pub async fn clear_voice_moderation(&self, channel_id: &str, user_id: &str) {
+        if self.voice_moderation(channel_id, user_id).is_active() {
+            self.set_voice_moderation(channel_id, user_id, VoiceModeration::default(), None).await;
+        }
+    }
+}
+
+fn apply_voice_moderation(
+    voice_moderation: &Mutex<HashMap<String, HashMap<String, VoiceModeration>>>,
+    channel_id: &str,
+    user_id: &str,
+    moderation: VoiceModeration,
+) {
+    let mut voice_moderation = voice_moderation.lock().unwrap();
+    if moderation.is_active() {
+        voice_moderation
+            .entry(channel_id.to_string())
+            .or_default()
+            .insert(user_id.to_string(), moderation);
+    } else if let Some(users) = voice_moderation.get_mut(channel_id) {
+        users.remove(user_id);
+        if users.is_empty() {
+            voice_moderation.remove(channel_id);
         }
     }
 }
 
 // Helper functions
-fn extract_user_from_token(auth_header: &str) -> Result<String, (StatusCode, JsonResponse<ErrorResponse>)> {
+fn extract_user_from_token(tokens: &TokenService, auth_header: &str) -> Result<String, (StatusCode, JsonResponse<ErrorResponse>)> {
     let token = auth_header
         .strip_prefix("Bearer ")
         .ok_or((
@@ -200,13 +423,7 @@ fn extract_user_from_token(auth_header: &str) -> Result<String, (StatusCode, Jso
             }),
         ))?;
 
-    let secret = "your-secret-key"; // Should match auth.rs
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_ref()),
-        &Validation::default(),
-    )
-    .map_err(|_| {
+    let claims = tokens.verify(token).map_err(|_| {
         (
             StatusCode::UNAUTHORIZED,
             JsonResponse(ErrorResponse {
@@ -215,7 +432,7 @@ fn extract_user_from_token(auth_header: &str) -> Result<String, (StatusCode, Jso
         )
     })?;
 
-    Ok(token_data.claims.sub)
+    Ok(claims.sub)
 }
 
 fn get_user_role_in_channel(channel: &Channel, user_id: &str) -> Option<Role> {
@@ -241,19 +458,66 @@ fn is_user_banned(channel: &Channel, user_id: &str) -> bool {
     channel.banned_users.iter().any(|banned| banned.user_id == user_id)
 }
 
-fn get_username_by_id(user_id: &str) -> String {
-    // In a real app, this would query a user database
-    // For now, we'll use the user_id as username
-    user_id.to_string()
+fn recording_error(error: RecordingError) -> (StatusCode, JsonResponse<ErrorResponse>) {
+    let status = match error {
+        RecordingError::AlreadyRecording | RecordingError::NotRecording => StatusCode::CONFLICT,
+        RecordingError::NotFound => StatusCode::NOT_FOUND,
+        RecordingError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (
+        status,
+        JsonResponse(ErrorResponse {
+            error: error.to_string(),
+        }),
+    )
+}
+
+/// Look up a channel and check the requester belongs to it
+fn require_channel_member(
+    state: &AppState,
+    channel_id: &str,
+    user_id: &str,
+) -> Result<Role, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let channels = state.channels.lock().unwrap();
+    let channel = channels
+        .get(channel_id)
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            JsonResponse(ErrorResponse {
+                error: "Channel not found".to_string(),
+            }),
+        ))?;
+
+    get_user_role_in_channel(channel, user_id)
+        .filter(|_| !is_user_banned(channel, user_id))
+        .ok_or((
+            StatusCode::FORBIDDEN,
+            JsonResponse(ErrorResponse {
+                error: "You are not a member of this channel".to_string(),
+            }),
+        ))
+}
+
+/// Store a changed channel so other instances pick it up
+async fn save_channel(state: &AppState, channel: &Channel) -> Result<(), (StatusCode, JsonResponse<ErrorResponse>)> {
+    state.shared.save_channel(channel).await.map_err(|e| {
+        error!("Failed to save channel {}: {}", channel.id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            JsonResponse(ErrorResponse {
+                error: "Failed to save channel".to_string(),
+            }),
+        )
+    })
 }
 
 // Endpoint handlers
 pub async fn create_channel(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    auth: Bearer,
     Json(payload): Json<CreateChannelRequest>,
 ) -> Result<JsonResponse<CreateChannelResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let user_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
     let channel_id = Uuid::new_v4().to_string();
 
     let channel = Channel {
@@ -265,10 +529,15 @@ pub async fn create_channel(
         members: vec![user_id],
         banned_users: Vec::new(),
         invite_tokens: HashMap::new(),
+        min_bitrate_bps: payload.min_bitrate_bps,
+        max_bitrate_bps: payload.max_bitrate_bps,
+        max_participants: payload.limits.max_participants,
+        max_speakers: payload.limits.max_speakers,
+        waitlist_enabled: payload.limits.waitlist_enabled,
     };
 
-    let mut channels = state.channels.lock().unwrap();
-    channels.insert(channel_id.clone(), channel);
+    state.channels.lock().unwrap().insert(channel_id.clone(), channel.clone());
+    save_channel(&state, &channel).await?;
 
     Ok(JsonResponse(CreateChannelResponse {
         channel_id,
@@ -279,145 +548,161 @@ pub async fn create_channel(
 
 pub async fn join_channel(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    auth: Bearer,
     Path(channel_id): Path<String>,
     Json(payload): Json<JoinChannelRequest>,
 ) -> Result<JsonResponse<()>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let user_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
-    let mut channels = state.channels.lock().unwrap();
-
-    let channel = channels
-        .get_mut(&channel_id)
-        .ok_or((
-            StatusCode::NOT_FOUND,
-            JsonResponse(ErrorResponse {
-                error: "Channel not found".to_string(),
-            }),
-        ))?;
-
-    // Check if user is already a member
-    if channel.members.contains(&user_id) || channel.moderators.contains(&user_id) || channel.owner == user_id {
-        return Ok(JsonResponse(()));
-    }
-
-    // Check if user is banned
-    if is_user_banned(channel, &user_id) {
-        return Err((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "You are banned from this channel".to_string(),
-            }),
-        ));
-    }
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    let updated = {
+        let mut channels = state.channels.lock().unwrap();
+
+        let channel = channels
+            .get_mut(&channel_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Channel not found".to_string(),
+                }),
+            ))?;
 
-    // Check privacy settings
-    match channel.privacy {
-        ChannelPrivacy::Public => {
-            // Anyone can join
+        // Check if user is already a member
+        if channel.members.contains(&user_id) || channel.moderators.contains(&user_id) || channel.owner == user_id {
+            return Ok(JsonResponse(()));
         }
-        ChannelPrivacy::Private => {
+
+        // Check if user is banned
+        if is_user_banned(channel, &user_id) {
             return Err((
                 StatusCode::FORBIDDEN,
                 JsonResponse(ErrorResponse {
-                    error: "This channel is private".to_string(),
+                    error: "You are banned from this channel".to_string(),
                 }),
             ));
         }
-        ChannelPrivacy::InviteOnly => {
-            // Check for valid invite token
-            if let Some(ref join_token) = payload.join_token {
-                let token = channel
-                    .invite_tokens
-                    .get(join_token)
-                    .ok_or((
-                        StatusCode::FORBIDDEN,
-                        JsonResponse(ErrorResponse {
-                            error: "Invalid invite token".to_string(),
-                        }),
-                    ))?;
 
-                if token.used {
-                    return Err((
-                        StatusCode::FORBIDDEN,
-                        JsonResponse(ErrorResponse {
-                            error: "Invite token already used".to_string(),
-                        }),
-                    ));
-                }
-
-                if token.expires_at < chrono::Utc::now().timestamp() as u64 {
+        // Check privacy settings
+        match channel.privacy {
+            ChannelPrivacy::Public => {
+                // Anyone can join
+            }
+            ChannelPrivacy::Private => {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    JsonResponse(ErrorResponse {
+                        error: "This channel is private".to_string(),
+                    }),
+                ));
+            }
+            ChannelPrivacy::InviteOnly => {
+                // Check for valid invite token
+                if let Some(ref join_token) = payload.join_token {
+                    let token = channel
+                        .invite_tokens
+                        .get(join_token)
+                        .ok_or((
+                            StatusCode::FORBIDDEN,
+                            JsonResponse(ErrorResponse {
+                                error: "Invalid invite token".to_string(),
+                            }),
+                        ))?;
+
+                    if token.used {
+                        return Err((
+                            StatusCode::FORBIDDEN,
+                            JsonResponse(ErrorResponse {
+                                error: "Invite token already used".to_string(),
+                            }),
+                        ));
+                    }
+
+                    if token.expires_at < chrono::Utc::now().timestamp() as u64 {
+                        return Err((
+                            StatusCode::FORBIDDEN,
+                            JsonResponse(ErrorResponse {
+                                error: "Invite token expired".to_string(),
+                            }),
+                        ));
+                    }
+
+                    // Mark token as used
+                    if let Some(token) = channel.invite_tokens.get_mut(join_token) {
+                        token.used = true;
+                        token.used_by = Some(user_id.clone());
+                    }
+                } else {
                     return Err((
                         StatusCode::FORBIDDEN,
                         JsonResponse(ErrorResponse {
-                            error: "Invite token expired".to_string(),
+                            error: "Invite token required for this channel".to_string(),
                         }),
                     ));
                 }
-
-                // Mark token as used
-                if let Some(token) = channel.invite_tokens.get_mut(join_token) {
-                    token.used = true;
-                    token.used_by = Some(user_id.clone());
-                }
-            } else {
-                return Err((
-                    StatusCode::FORBIDDEN,
-                    JsonResponse(ErrorResponse {
-                        error: "Invite token required for this channel".to_string(),
-                    }),
-                ));
             }
         }
-    }
 
-    // Add user to channel
-    channel.members.push(user_id);
+        // Add user to channel
+        channel.members.push(user_id);
+        channel.clone()
+    };
+    save_channel(&state, &updated).await?;
 
     Ok(JsonResponse(()))
 }
 
 pub async fn invite_user(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    auth: Bearer,
     Path(channel_id): Path<String>,
     Json(payload): Json<InviteUserRequest>,
 ) -> Result<JsonResponse<InviteUserResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let user_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
-    let mut channels = state.channels.lock().unwrap();
-
-    let channel = channels
-        .get_mut(&channel_id)
-        .ok_or((
-            StatusCode::NOT_FOUND,
-            JsonResponse(ErrorResponse {
-                error: "Channel not found".to_string(),
-            }),
-        ))?;
-
-    // Check if user has permission to invite
-    if !can_moderate_channel(channel, &user_id) {
-        return Err((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "You don't have permission to invite users".to_string(),
-            }),
-        ));
-    }
-
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
     // Generate invite token
     let token = Uuid::new_v4().to_string();
     let expires_at = (chrono::Utc::now() + chrono::Duration::hours(24)).timestamp() as u64;
 
-    let invite_token = InviteToken {
-        token: token.clone(),
-        created_by: user_id,
-        created_for: Some(payload.username),
-        expires_at,
-        used: false,
-        used_by: None,
+    let updated = {
+        let mut channels = state.channels.lock().unwrap();
+
+        let channel = channels
+            .get_mut(&channel_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Channel not found".to_string(),
+                }),
+            ))?;
+
+        // Check if user has permission to invite
+        if !can_moderate_channel(channel, &user_id) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You don't have permission to invite users".to_string(),
+                }),
+            ));
+        }
+
+        let invite_token = InviteToken {
+            token: token.clone(),
+            created_by: user_id.clone(),
+            created_for: Some(payload.username.clone()),
+            expires_at,
+            used: false,
+            used_by: None,
+        };
+
+        channel.invite_tokens.insert(token.clone(), invite_token);
+        channel.clone()
     };
+    save_channel(&state, &updated).await?;
 
-    channel.invite_tokens.insert(token.clone(), invite_token);
+    // The token is in the response too, so a failed mail only gets logged
+    if let Some(email) = state.users.verified_email(&payload.username).await {
+        let inviter = state.users.username(&user_id).await;
+        if let Err(e) = state.mailer.send_invite_email(&email, &inviter, &updated.name, &updated.id, &token).await {
+            warn!("Failed to mail invite for {} to {}: {}", updated.id, payload.username, e);
+        }
+    }
 
     Ok(JsonResponse(InviteUserResponse {
         invite_token: token,
@@ -427,10 +712,10 @@ pub async fn invite_user(
 
 pub async fn list_invites(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    auth: Bearer,
     Path(channel_id): Path<String>,
 ) -> Result<JsonResponse<ListInvitesResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let user_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
     let channels = state.channels.lock().unwrap();
 
     let channel = channels
@@ -469,143 +754,168 @@ pub async fn list_invites(
 
 pub async fn revoke_invite(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    auth: Bearer,
     Path((channel_id, token)): Path<(String, String)>,
 ) -> Result<JsonResponse<()>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let user_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
-    let mut channels = state.channels.lock().unwrap();
-
-    let channel = channels
-        .get_mut(&channel_id)
-        .ok_or((
-            StatusCode::NOT_FOUND,
-            JsonResponse(ErrorResponse {
-                error: "Channel not found".to_string(),
-            }),
-        ))?;
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    let updated = {
+        let mut channels = state.channels.lock().unwrap();
+
+        let channel = channels
+            .get_mut(&channel_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Channel not found".to_string(),
+                }),
+            ))?;
 
-    // Check if user has permission to revoke invites
-    if !can_moderate_channel(channel, &user_id) {
-        return Err((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "You don't have permission to revoke invites".to_string(),
-            }),
-        ));
-    }
+        // Check if user has permission to revoke invites
+        if !can_moderate_channel(channel, &user_id) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You don't have permission to revoke invites".to_string(),
+                }),
+            ));
+        }
 
-    // Check if token exists
-    if !channel.invite_tokens.contains_key(&token) {
-        return Err((
-            StatusCode::NOT_FOUND,
-            JsonResponse(ErrorResponse {
-                error: "Invite token not found".to_string(),
-            }),
-        ));
-    }
+        // Check if token exists
+        if !channel.invite_tokens.contains_key(&token) {
+            return Err((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Invite token not found".to_string(),
+                }),
+            ));
+        }
 
-    // Remove the token
-    channel.invite_tokens.remove(&token);
+        // Remove the token
+        channel.invite_tokens.remove(&token);
+        channel.clone()
+    };
+    save_channel(&state, &updated).await?;
 
     Ok(JsonResponse(()))
 }
 
 pub async fn change_user_role(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    auth: Bearer,
     Path((channel_id, target_user_id)): Path<(String, String)>,
     Json(payload): Json<ChangeRoleRequest>,
 ) -> Result<JsonResponse<()>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let requester_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
-    let mut channels = state.channels.lock().unwrap();
+    let requester_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    // Every user whose role changes, an ownership transfer demotes the old owner
+    let mut role_changes = Vec::new();
+    let updated = {
+        let mut channels = state.channels.lock().unwrap();
+
+        let channel = channels
+            .get_mut(&channel_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Channel not found".to_string(),
+                }),
+            ))?;
 
-    let channel = channels
-        .get_mut(&channel_id)
-        .ok_or((
-            StatusCode::NOT_FOUND,
+        // Parse the new role
+        let new_role = Role::from_str(&payload.role).ok_or((
+            StatusCode::BAD_REQUEST,
             JsonResponse(ErrorResponse {
-                error: "Channel not found".to_string(),
+                error: "Invalid role. Must be 'owner', 'moderator', or 'member'".to_string(),
             }),
         ))?;
 
-    // Parse the new role
-    let new_role = Role::from_str(&payload.role).ok_or((
-        StatusCode::BAD_REQUEST,
-        JsonResponse(ErrorResponse {
-            error: "Invalid role. Must be 'owner', 'moderator', or 'member'".to_string(),
-        }),
-    ))?;
+        // Get requester's role
+        let requester_role = get_user_role_in_channel(channel, &requester_id)
+            .ok_or((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You are not a member of this channel".to_string(),
+                }),
+            ))?;
 
-    // Get requester's role
-    let requester_role = get_user_role_in_channel(channel, &requester_id)
-        .ok_or((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "You are not a member of this channel".to_string(),
-            }),
-        ))?;
+        // Get target user's current role
+        let target_role = get_user_role_in_channel(channel, &target_user_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Target user is not a member of this channel".to_string(),
+                }),
+            ))?;
 
-    // Get target user's current role
-    let target_role = get_user_role_in_channel(channel, &target_user_id)
-        .ok_or((
-            StatusCode::NOT_FOUND,
-            JsonResponse(ErrorResponse {
-                error: "Target user is not a member of this channel".to_string(),
-            }),
-        ))?;
+        // Check permissions
+        if !requester_role.can_manage(&target_role) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You don't have permission to change this user's role".to_string(),
+                }),
+            ));
+        }
 
-    // Check permissions
-    if !requester_role.can_manage(&target_role) {
-        return Err((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "You don't have permission to change this user's role".to_string(),
-            }),
-        ));
-    }
+        // Prevent self-demotion of owners
+        if requester_id == target_user_id && requester_role == Role::Owner && new_role != Role::Owner {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "Owners cannot demote themselves".to_string(),
+                }),
+            ));
+        }
 
-    // Prevent self-demotion of owners
-    if requester_id == target_user_id && requester_role == Role::Owner && new_role != Role::Owner {
-        return Err((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "Owners cannot demote themselves".to_string(),
-            }),
-        ));
-    }
-
-    // Update the user's role
-    match new_role {
-        Role::Owner => {
-            // Transfer ownership
-            let old_owner = channel.owner.clone();
-            channel.owner = target_user_id.clone();
-            
-            // Move old owner to moderators if they're not the target
-            if old_owner != target_user_id {
-                if !channel.moderators.contains(&old_owner) {
-                    channel.moderators.push(old_owner);
+        // Update the user's role
+        if new_role != target_role {
+            role_changes.push((target_user_id.clone(), new_role.clone()));
+        }
+        match new_role {
+            Role::Owner => {
+                // Transfer ownership
+                let old_owner = channel.owner.clone();
+                channel.owner = target_user_id.clone();
+                
+                // Move old owner to moderators if they're not the target
+                if old_owner != target_user_id {
+                    role_changes.push((old_owner.clone(), Role::Moderator));
+                    if !channel.moderators.contains(&old_owner) {
+                        channel.moderators.push(old_owner);
+                    }
                 }
+                
+                // Remove target from other lists
+                channel.moderators.retain(|id| id != &target_user_id);
+                channel.members.retain(|id| id != &target_user_id);
             }
-            
-            // Remove target from other lists
-            channel.moderators.retain(|id| id != &target_user_id);
-            channel.members.retain(|id| id != &target_user_id);
-        }
-        Role::Moderator => {
-            // Remove from members, add to moderators
-            channel.members.retain(|id| id != &target_user_id);
-            if !channel.moderators.contains(&target_user_id) {
-                channel.moderators.push(target_user_id);
+            Role::Moderator => {
+                // Remove from members, add to moderators
+                channel.members.retain(|id| id != &target_user_id);
+                if !channel.moderators.contains(&target_user_id) {
+                    channel.moderators.push(target_user_id.clone());
+                }
             }
-        }
-        Role::Member => {
-            // Remove from moderators, add to members
-            channel.moderators.retain(|id| id != &target_user_id);
-            if !channel.members.contains(&target_user_id) {
-                channel.members.push(target_user_id);
+            Role::Member => {
+                // Remove from moderators, add to members
+                channel.moderators.retain(|id| id != &target_user_id);
+                if !channel.members.contains(&target_user_id) {
+                    channel.members.push(target_user_id.clone());
+                }
             }
         }
+        channel.clone()
+    };
+    save_channel(&state, &updated).await?;
+
+    for (user_id, role) in role_changes {
+        state
+            .publish_event(ChannelEvent::RoleChanged {
+                channel_id: channel_id.clone(),
+                user_id,
+                role,
+                changed_by: requester_id.clone(),
+            })
+            .await;
     }
 
     Ok(JsonResponse(()))
@@ -613,134 +923,288 @@ pub async fn change_user_role(
 
 pub async fn list_users(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    auth: Bearer,
     Path(channel_id): Path<String>,
 ) -> Result<JsonResponse<ListUsersResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let user_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
-    let channels = state.channels.lock().unwrap();
-
-    let channel = channels
-        .get(&channel_id)
-        .ok_or((
-            StatusCode::NOT_FOUND,
-            JsonResponse(ErrorResponse {
-                error: "Channel not found".to_string(),
-            }),
-        ))?;
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    let roles = {
+        let channels = state.channels.lock().unwrap();
+
+        let channel = channels
+            .get(&channel_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Channel not found".to_string(),
+                }),
+            ))?;
 
-    // Check if user is a member of the channel
-    if get_user_role_in_channel(channel, &user_id).is_none() {
-        return Err((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "You are not a member of this channel".to_string(),
-            }),
-        ));
-    }
+        // Check if user is a member of the channel
+        if get_user_role_in_channel(channel, &user_id).is_none() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You are not a member of this channel".to_string(),
+                }),
+            ));
+        }
 
-    let mut users = Vec::new();
-
-    // Add owner
-    users.push(UserRole {
-        user_id: channel.owner.clone(),
-        username: get_username_by_id(&channel.owner),
-        role: Role::Owner,
-    });
-
-    // Add moderators
-    for moderator_id in &channel.moderators {
-        users.push(UserRole {
-            user_id: moderator_id.clone(),
-            username: get_username_by_id(moderator_id),
-            role: Role::Moderator,
-        });
-    }
+        // Owner, then moderators, then members
+        let mut roles = vec![(channel.owner.clone(), Role::Owner)];
+        roles.extend(channel.moderators.iter().map(|id| (id.clone(), Role::Moderator)));
+        roles.extend(channel.members.iter().map(|id| (id.clone(), Role::Member)));
+        roles
+    };
 
-    // Add members
-    for member_id in &channel.members {
-        users.push(UserRole {
-            user_id: member_id.clone(),
-            username: get_username_by_id(member_id),
-            role: Role::Member,
-        });
-    }
+    let ids: Vec<String> = roles.iter().map(|(id, _)| id.clone()).collect();
+    let mut usernames = state.users.usernames(&ids).await;
+    let users = roles
+        .into_iter()
+        .map(|(user_id, role)| UserRole {
+            username: usernames.remove(&user_id).unwrap_or_else(|| user_id.clone()),
+            user_id,
+            role,
+        })
+        .collect();
 
     Ok(JsonResponse(ListUsersResponse { users }))
 }
 
 pub async fn kick_user(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    auth: Bearer,
     Path((channel_id, target_user_id)): Path<(String, String)>,
 ) -> Result<JsonResponse<()>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let requester_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
-    let mut channels = state.channels.lock().unwrap();
-
-    let channel = channels
-        .get_mut(&channel_id)
-        .ok_or((
-            StatusCode::NOT_FOUND,
-            JsonResponse(ErrorResponse {
-                error: "Channel not found".to_string(),
-            }),
-        ))?;
+    let requester_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    let updated = {
+        let mut channels = state.channels.lock().unwrap();
+
+        let channel = channels
+            .get_mut(&channel_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Channel not found".to_string(),
+                }),
+            ))?;
 
-    // Get requester's role
-    let requester_role = get_user_role_in_channel(channel, &requester_id)
-        .ok_or((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "You are not a member of this channel".to_string(),
-            }),
-        ))?;
+        // Get requester's role
+        let requester_role = get_user_role_in_channel(channel, &requester_id)
+            .ok_or((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You are not a member of this channel".to_string(),
+                }),
+            ))?;
 
-    // Get target user's role
-    let target_role = get_user_role_in_channel(channel, &target_user_id)
-        .ok_or((
-            StatusCode::NOT_FOUND,
-            JsonResponse(ErrorResponse {
-                error: "Target user is not a member of this channel".to_string(),
-            }),
-        ))?;
+        // Get target user's role
+        let target_role = get_user_role_in_channel(channel, &target_user_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Target user is not a member of this channel".to_string(),
+                }),
+            ))?;
 
-    // Check permissions
-    if !requester_role.can_manage(&target_role) {
-        return Err((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "You don't have permission to kick this user".to_string(),
-            }),
-        ));
-    }
+        // Check permissions
+        if !requester_role.can_manage(&target_role) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You don't have permission to kick this user".to_string(),
+                }),
+            ));
+        }
 
-    // Prevent self-kicking
-    if requester_id == target_user_id {
-        return Err((
-            StatusCode::FORBIDDEN,
-            JsonResponse(ErrorResponse {
-                error: "You cannot kick yourself".to_string(),
-            }),
-        ));
-    }
+        // Prevent self-kicking
+        if requester_id == target_user_id {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You cannot kick yourself".to_string(),
+                }),
+            ));
+        }
 
-    // Remove user from channel
-    channel.members.retain(|id| id != &target_user_id);
-    channel.moderators.retain(|id| id != &target_user_id);
+        // Remove user from channel
+        channel.members.retain(|id| id != &target_user_id);
+        channel.moderators.retain(|id| id != &target_user_id);
+        channel.clone()
+    };
+    save_channel(&state, &updated).await?;
+    state.clear_voice_moderation(&channel_id, &target_user_id).await;
+    state
+        .publish_event(ChannelEvent::MemberRemoved {
+            channel_id,
+            user_id: target_user_id,
+            reason: RemovalReason::Kicked,
+            removed_by: requester_id,
+            note: None,
+        })
+        .await;
 
     Ok(JsonResponse(()))
 }
 
 pub async fn ban_user(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
+    auth: Bearer,
     Path((channel_id, target_user_id)): Path<(String, String)>,
     Json(payload): Json<BanUserRequest>,
 ) -> Result<JsonResponse<()>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let requester_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
-    let mut channels = state.channels.lock().unwrap();
+    let requester_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    let target_username = state.users.username(&target_user_id).await;
+    let updated = {
+        let mut channels = state.channels.lock().unwrap();
+
+        let channel = channels
+            .get_mut(&channel_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Channel not found".to_string(),
+                }),
+            ))?;
+
+        // Get requester's role
+        let requester_role = get_user_role_in_channel(channel, &requester_id)
+            .ok_or((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You are not a member of this channel".to_string(),
+                }),
+            ))?;
+
+        // Get target user's role
+        let target_role = get_user_role_in_channel(channel, &target_user_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Target user is not a member of this channel".to_string(),
+                }),
+            ))?;
+
+        // Check permissions
+        if !requester_role.can_manage(&target_role) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You don't have permission to ban this user".to_string(),
+                }),
+            ));
+        }
+
+        // Prevent self-banning
+        if requester_id == target_user_id {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You cannot ban yourself".to_string(),
+                }),
+            ));
+        }
+
+        // Check if user is already banned
+        if is_user_banned(channel, &target_user_id) {
+            return Err((
+                StatusCode::CONFLICT,
+                JsonResponse(ErrorResponse {
+                    error: "User is already banned".to_string(),
+                }),
+            ));
+        }
+
+        // Add user to banned list and remove from members/moderators
+        let banned_user = BannedUser {
+            user_id: target_user_id.clone(),
+            username: target_username,
+            banned_by: requester_id.clone(),
+            banned_at: chrono::Utc::now().timestamp() as u64,
+            reason: payload.reason.clone(),
+        };
+
+        channel.banned_users.push(banned_user);
+        channel.members.retain(|id| id != &target_user_id);
+        channel.moderators.retain(|id| id != &target_user_id);
+        channel.clone()
+    };
+    save_channel(&state, &updated).await?;
+    state.clear_voice_moderation(&channel_id, &target_user_id).await;
+    state
+        .publish_event(ChannelEvent::MemberRemoved {
+            channel_id,
+            user_id: target_user_id,
+            reason: RemovalReason::Banned,
+            removed_by: requester_id,
+            note: payload.reason,
+        })
+        .await;
+
+    Ok(JsonResponse(()))
+}
+
+pub async fn unban_user(
+    State(state): State<AppState>,
+    auth: Bearer,
+    Path((channel_id, target_user_id)): Path<(String, String)>,
+) -> Result<JsonResponse<()>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let requester_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    let updated = {
+        let mut channels = state.channels.lock().unwrap();
+
+        let channel = channels
+            .get_mut(&channel_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Channel not found".to_string(),
+                }),
+            ))?;
+
+        // Check if user has permission to unban
+        if !can_moderate_channel(channel, &requester_id) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "You don't have permission to unban users".to_string(),
+                }),
+            ));
+        }
+
+        // Check if user is actually banned
+        let banned_index = channel
+            .banned_users
+            .iter()
+            .position(|banned| banned.user_id == target_user_id);
 
+        if banned_index.is_none() {
+            return Err((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "User is not banned from this channel".to_string(),
+                }),
+            ));
+        }
+
+        // Remove user from banned list
+        channel.banned_users.remove(banned_index.unwrap());
+        channel.clone()
+    };
+    save_channel(&state, &updated).await?;
+
+    Ok(JsonResponse(()))
+}
+
+/// Check that the requester may impose voice restrictions on the target
+fn require_voice_moderator(
+    state: &AppState,
+    channel_id: &str,
+    requester_id: &str,
+    target_user_id: &str,
+) -> Result<(), (StatusCode, JsonResponse<ErrorResponse>)> {
+    let channels = state.channels.lock().unwrap();
     let channel = channels
-        .get_mut(&channel_id)
+        .get(channel_id)
         .ok_or((
             StatusCode::NOT_FOUND,
             JsonResponse(ErrorResponse {
@@ -748,8 +1212,7 @@ pub async fn ban_user(
             }),
         ))?;
 
-    // Get requester's role
-    let requester_role = get_user_role_in_channel(channel, &requester_id)
+    let requester_role = get_user_role_in_channel(channel, requester_id)
         .ok_or((
             StatusCode::FORBIDDEN,
             JsonResponse(ErrorResponse {
@@ -757,8 +1220,7 @@ pub async fn ban_user(
             }),
         ))?;
 
-    // Get target user's role
-    let target_role = get_user_role_in_channel(channel, &target_user_id)
+    let target_role = get_user_role_in_channel(channel, target_user_id)
         .ok_or((
             StatusCode::NOT_FOUND,
             JsonResponse(ErrorResponse {
@@ -766,149 +1228,307 @@ pub async fn ban_user(
             }),
         ))?;
 
-    // Check permissions
-    if !requester_role.can_manage(&target_role) {
+    // Same rules as kick and ban
+    if requester_role == Role::Member || !requester_role.can_manage(&target_role) {
         return Err((
             StatusCode::FORBIDDEN,
             JsonResponse(ErrorResponse {
-                error: "You don't have permission to ban this user".to_string(),
+                error: "You don't have permission to moderate this user".to_string(),
             }),
         ));
     }
 
-    // Prevent self-banning
     if requester_id == target_user_id {
         return Err((
             StatusCode::FORBIDDEN,
             JsonResponse(ErrorResponse {
-                error: "You cannot ban yourself".to_string(),
+                error: "You cannot moderate yourself".to_string(),
             }),
         ));
     }
 
-    // Check if user is already banned
-    if is_user_banned(channel, &target_user_id) {
+    Ok(())
+}
+
+pub async fn server_mute_user(
+    State(state): State<AppState>,
+    auth: Bearer,
+    Path((channel_id, target_user_id)): Path<(String, String)>,
+    Json(payload): Json<ServerMuteRequest>,
+) -> Result<JsonResponse<VoiceModeration>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let requester_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    require_voice_moderator(&state, &channel_id, &requester_id, &target_user_id)?;
+
+    let moderation = VoiceModeration {
+        server_muted: payload.muted,
+        ..state.voice_moderation(&channel_id, &target_user_id)
+    };
+    state
+        .set_voice_moderation(&channel_id, &target_user_id, moderation, Some(requester_id))
+        .await;
+
+    Ok(JsonResponse(moderation))
+}
+
+pub async fn server_deafen_user(
+    State(state): State<AppState>,
+    auth: Bearer,
+    Path((channel_id, target_user_id)): Path<(String, String)>,
+    Json(payload): Json<ServerDeafenRequest>,
+) -> Result<JsonResponse<VoiceModeration>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let requester_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    require_voice_moderator(&state, &channel_id, &requester_id, &target_user_id)?;
+
+    let moderation = VoiceModeration {
+        server_deafened: payload.deafened,
+        ..state.voice_moderation(&channel_id, &target_user_id)
+    };
+    state
+        .set_voice_moderation(&channel_id, &target_user_id, moderation, Some(requester_id))
+        .await;
+
+    Ok(JsonResponse(moderation))
+}
+
+pub async fn set_channel_limits(
+    State(state): State<AppState>,
+    auth: Bearer,
+    Path(channel_id): Path<String>,
+    Json(payload): Json<ChannelLimits>,
+) -> Result<JsonResponse<ChannelLimits>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+
+    if payload.max_participants == Some(0) || payload.max_speakers == Some(0) {
         return Err((
-            StatusCode::CONFLICT,
+            StatusCode::BAD_REQUEST,
             JsonResponse(ErrorResponse {
-                error: "User is already banned".to_string(),
+                error: "Limits must be at least 1, or unset for no limit".to_string(),
             }),
         ));
     }
 
-    // Add user to banned list and remove from members/moderators
-    let banned_user = BannedUser {
-        user_id: target_user_id.clone(),
-        username: get_username_by_id(&target_user_id),
-        banned_by: requester_id,
-        banned_at: chrono::Utc::now().timestamp() as u64,
-        reason: payload.reason,
+    let updated = {
+        let mut channels = state.channels.lock().unwrap();
+        let channel = channels
+            .get_mut(&channel_id)
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                JsonResponse(ErrorResponse {
+                    error: "Channel not found".to_string(),
+                }),
+            ))?;
+
+        if !can_moderate_channel(channel, &user_id) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                JsonResponse(ErrorResponse {
+                    error: "Only owners and moderators can change channel limits".to_string(),
+                }),
+            ));
+        }
+
+        channel.max_participants = payload.max_participants;
+        channel.max_speakers = payload.max_speakers;
+        channel.waitlist_enabled = payload.waitlist_enabled;
+        channel.clone()
     };
+    save_channel(&state, &updated).await?;
 
-    channel.banned_users.push(banned_user);
-    channel.members.retain(|id| id != &target_user_id);
-    channel.moderators.retain(|id| id != &target_user_id);
+    if !payload.waitlist_enabled {
+        state.waitlists.clear(&channel_id);
+    }
 
-    Ok(JsonResponse(()))
+    Ok(JsonResponse(payload))
 }
 
-pub async fn unban_user(
+pub async fn start_recording(
     State(state): State<AppState>,
-    TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
-    Path((channel_id, target_user_id)): Path<(String, String)>,
-) -> Result<JsonResponse<()>, (StatusCode, JsonResponse<ErrorResponse>)> {
-    let requester_id = extract_user_from_token(&format!("Bearer {}", auth.token()))?;
-    let mut channels = state.channels.lock().unwrap();
-
-    let channel = channels
-        .get_mut(&channel_id)
-        .ok_or((
-            StatusCode::NOT_FOUND,
-            JsonResponse(ErrorResponse {
-                error: "Channel not found".to_string(),
-            }),
-        ))?;
+    auth: Bearer,
+    Path(channel_id): Path<String>,
+    Json(payload): Json<StartRecordingRequest>,
+) -> Result<JsonResponse<RecordingInfo>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    let role = require_channel_member(&state, &channel_id, &user_id)?;
 
-    // Check if user has permission to unban
-    if !can_moderate_channel(channel, &requester_id) {
+    // Check if user has permission to record
+    if role == Role::Member {
         return Err((
             StatusCode::FORBIDDEN,
             JsonResponse(ErrorResponse {
-                error: "You don't have permission to unban users".to_string(),
+                error: "You don't have permission to record this channel".to_string(),
             }),
         ));
     }
 
-    // Check if user is actually banned
-    let banned_index = channel
-        .banned_users
-        .iter()
-        .position(|banned| banned.user_id == target_user_id);
+    let info = state
+        .recordings
+        .start(&channel_id, &user_id, payload.mixed_track)
+        .map_err(recording_error)?;
 
-    if banned_index.is_none() {
+    state
+        .publish_event(ChannelEvent::RecordingStarted {
+            channel_id,
+            recording_id: info.id.clone(),
+            started_by: user_id,
+        })
+        .await;
+
+    Ok(JsonResponse(info))
+}
+
+pub async fn stop_recording(
+    State(state): State<AppState>,
+    auth: Bearer,
+    Path(channel_id): Path<String>,
+) -> Result<JsonResponse<RecordingInfo>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    let role = require_channel_member(&state, &channel_id, &user_id)?;
+
+    // Check if user has permission to stop the recording
+    if role == Role::Member {
         return Err((
-            StatusCode::NOT_FOUND,
+            StatusCode::FORBIDDEN,
             JsonResponse(ErrorResponse {
-                error: "User is not banned from this channel".to_string(),
+                error: "You don't have permission to stop the recording".to_string(),
             }),
         ));
     }
 
-    // Remove user from banned list
-    channel.banned_users.remove(banned_index.unwrap());
+    // Finalizing merges the spooled tracks, keep it off the async workers
+    let recordings = state.recordings.clone();
+    let stop_channel_id = channel_id.clone();
+    let info = tokio::task::spawn_blocking(move || recordings.stop(&stop_channel_id))
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                JsonResponse(ErrorResponse {
+                    error: "Failed to finalize recording".to_string(),
+                }),
+            )
+        })?
+        .map_err(recording_error)?;
+
+    state
+        .publish_event(ChannelEvent::RecordingStopped {
+            channel_id,
+            recording_id: info.id.clone(),
+        })
+        .await;
+
+    Ok(JsonResponse(info))
+}
+
+pub async fn set_recording_consent(
+    State(state): State<AppState>,
+    auth: Bearer,
+    Path(channel_id): Path<String>,
+    Json(payload): Json<RecordingConsentRequest>,
+) -> Result<JsonResponse<()>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    require_channel_member(&state, &channel_id, &user_id)?;
+
+    state
+        .recordings
+        .set_consent(&channel_id, &user_id, payload.consent)
+        .map_err(recording_error)?;
 
     Ok(JsonResponse(()))
 }
 
+pub async fn list_recordings(
+    State(state): State<AppState>,
+    auth: Bearer,
+    Path(channel_id): Path<String>,
+) -> Result<JsonResponse<ListRecordingsResponse>, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    require_channel_member(&state, &channel_id, &user_id)?;
+
+    Ok(JsonResponse(ListRecordingsResponse {
+        active: state.recordings.active(&channel_id),
+        recordings: state.recordings.list(&channel_id),
+    }))
+}
+
+pub async fn download_recording(
+    State(state): State<AppState>,
+    auth: Bearer,
+    Path((channel_id, recording_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, JsonResponse<ErrorResponse>)> {
+    let user_id = extract_user_from_token(&state.tokens, &format!("Bearer {}", auth.token()))?;
+    require_channel_member(&state, &channel_id, &user_id)?;
+
+    let path = state
+        .recordings
+        .file_path(&channel_id, &recording_id)
+        .map_err(recording_error)?;
+    let data = tokio::fs::read(&path)
+        .await
+        .map_err(|e| recording_error(RecordingError::Io(e)))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "audio/ogg".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.ogg\"", recording_id),
+            ),
+        ],
+        data,
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use axum::{
         body::Body,
         http::{Request, StatusCode},
-        response::Response,
+        routing::{get, post},
+        Router,
     };
     use serde_json::json;
     use tower::ServiceExt;
 
     // Helper function to create a test JWT token
     fn create_test_token(user_id: &str) -> String {
-        use jsonwebtoken::{encode, EncodingKey, Header};
-        use chrono::Utc;
-
-        let now = Utc::now();
-        let exp = (now + chrono::Duration::hours(24)).timestamp() as usize;
-        let iat = now.timestamp() as usize;
-
-        let claims = Claims {
-            sub: user_id.to_string(),
-            roles: vec!["user".to_string()],
-            exp,
-            iat,
-        };
-
-        encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret("your-secret-key".as_ref()),
-        )
-        .unwrap()
+        TokenService::development().issue(user_id, user_id, vec!["user".to_string()]).unwrap()
     }
 
     // Helper function to create a test app
     fn create_test_app() -> Router {
-        let state = AppState::new();
+        create_test_app_with_state(test_state(SharedState::in_process()))
+    }
+
+    fn test_state(shared: SharedState) -> AppState {
+        AppState::with_shared_state(
+            RecordingConfig {
+                dir: std::env::temp_dir().join(format!("wfl-recordings-{}", Uuid::new_v4())),
+            },
+            shared,
+        )
+    }
+
+    fn create_test_app_with_state(state: AppState) -> Router {
         Router::new()
-            .route("/channels", post(routes::channels::create_channel))
-            .route("/channels/:id/join", post(routes::channels::join_channel))
-            .route("/channels/:id/users", get(routes::channels::list_users))
-            .route("/channels/:id/invite", post(routes::channels::invite_user))
-            .route("/channels/:id/invites", get(routes::channels::list_invites))
-            .route("/channels/:id/invites/:token", post(routes::channels::revoke_invite))
-            .route("/channels/:id/users/:user_id/role", post(routes::channels::change_user_role))
-            .route("/channels/:id/users/:user_id/kick", post(routes::channels::kick_user))
-            .route("/channels/:id/users/:user_id/ban", post(routes::channels::ban_user))
-            .route("/channels/:id/users/:user_id/unban", post(routes::channels::unban_user))
+            .route("/channels", post(crate::routes::channels::create_channel))
+            .route("/channels/:id/join", post(crate::routes::channels::join_channel))
+            .route("/channels/:id/users", get(crate::routes::channels::list_users))
+            .route("/channels/:id/invite", post(crate::routes::channels::invite_user))
+            .route("/channels/:id/invites", get(crate::routes::channels::list_invites))
+            .route("/channels/:id/invites/:token", post(crate::routes::channels::revoke_invite))
+            .route("/channels/:id/users/:user_id/role", post(crate::routes::channels::change_user_role))
+            .route("/channels/:id/users/:user_id/kick", post(crate::routes::channels::kick_user))
+            .route("/channels/:id/users/:user_id/ban", post(crate::routes::channels::ban_user))
+            .route("/channels/:id/users/:user_id/unban", post(crate::routes::channels::unban_user))
+            .route("/channels/:id/users/:user_id/mute", post(crate::routes::channels::server_mute_user))
+            .route("/channels/:id/users/:user_id/deafen", post(crate::routes::channels::server_deafen_user))
+            .route("/channels/:id/limits", post(crate::routes::channels::set_channel_limits))
+            .route("/channels/:id/recordings", get(crate::routes::channels::list_recordings))
+            .route("/channels/:id/recordings/start", post(crate::routes::channels::start_recording))
+            .route("/channels/:id/recordings/stop", post(crate::routes::channels::stop_recording))
+            .route("/channels/:id/recordings/consent", post(crate::routes::channels::set_recording_consent))
+            .route("/channels/:id/recordings/:recording_id", get(crate::routes::channels::download_recording))
             .with_state(state)
     }
 
@@ -936,22 +1556,192 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_join_channel() {
+        let app = create_test_app();
+        let token = create_test_token("test_user");
+
+        // First create a channel
+        let create_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/channels")
+                    .header("Authorization", format!("Bearer {}", token.clone()))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        json!({
+                            "name": "Test Channel",
+                            "privacy": "Public"
+                        })
+                        .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
+        let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
+
+        // Then join the channel
+        let join_response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/join", create_data.channel_id))
+                    .header("Authorization", format!("Bearer {}", token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(join_response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_change_user_role() {
+        let app = create_test_app();
+        let owner_token = create_test_token("owner");
+        let member_token = create_test_token("member");
+
+        // Create a channel
+        let create_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/channels")
+                    .header("Authorization", format!("Bearer {}", owner_token.clone()))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        json!({
+                            "name": "Test Channel",
+                            "privacy": "Public"
+                        })
+                        .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
+        let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
+
+        // Join as member
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/join", create_data.channel_id))
+                    .header("Authorization", format!("Bearer {}", member_token.clone()))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Change member to moderator
+        let role_response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/users/{}/role", create_data.channel_id, "member"))
+                    .header("Authorization", format!("Bearer {}", owner_token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({ "role": "moderator" }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(role_response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_kick_user() {
+        let app = create_test_app();
+        let owner_token = create_test_token("owner");
+        let member_token = create_test_token("member");
+
+        // Create a channel
+        let create_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/channels")
+                    .header("Authorization", format!("Bearer {}", owner_token.clone()))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        json!({
+                            "name": "Test Channel",
+                            "privacy": "Public"
+                        })
+                        .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
+        let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
+
+        // Join as member
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/join", create_data.channel_id))
+                    .header("Authorization", format!("Bearer {}", member_token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Kick the member
+        let kick_response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/users/{}/kick", create_data.channel_id, "member"))
+                    .header("Authorization", format!("Bearer {}", owner_token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(kick_response.status(), StatusCode::OK);
     }
 
     #[tokio::test]
-    async fn test_join_channel() {
-        let app = create_test_app();
-        let token = create_test_token("test_user");
+    async fn test_server_mute_and_deafen() {
+        let state = test_state(SharedState::in_process());
+        let mut events = state.events.subscribe();
+        let app = create_test_app_with_state(state.clone());
+        let owner_token = create_test_token("owner");
+        let member_token = create_test_token("member");
 
-        // First create a channel
         let create_response = app
             .clone()
             .oneshot(
                 Request::builder()
                     .method("POST")
                     .uri("/channels")
-                    .header("Authorization", format!("Bearer {}", token.clone()))
+                    .header("Authorization", format!("Bearer {}", owner_token.clone()))
                     .header("Content-Type", "application/json")
                     .body(Body::from(
                         json!({
@@ -965,16 +1755,16 @@ mod tests {
             .await
             .unwrap();
 
-        let create_body = hyper::body::to_bytes(create_response.into_body()).await.unwrap();
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
         let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
+        let channel_id = create_data.channel_id;
 
-        // Then join the channel
-        let join_response = app
+        app.clone()
             .oneshot(
                 Request::builder()
                     .method("POST")
-                    .uri(format!("/channels/{}/join", create_data.channel_id))
-                    .header("Authorization", format!("Bearer {}", token))
+                    .uri(format!("/channels/{}/join", channel_id))
+                    .header("Authorization", format!("Bearer {}", member_token))
                     .header("Content-Type", "application/json")
                     .body(Body::from(json!({}).to_string()))
                     .unwrap(),
@@ -982,16 +1772,80 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(join_response.status(), StatusCode::OK);
+        // Members cannot moderate the owner
+        let forbidden_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/users/{}/mute", channel_id, "owner"))
+                    .header("Authorization", format!("Bearer {}", member_token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({ "muted": true }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(forbidden_response.status(), StatusCode::FORBIDDEN);
+
+        for (action, body) in [("mute", json!({ "muted": true })), ("deafen", json!({ "deafened": true }))] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/channels/{}/users/{}/{}", channel_id, "member", action))
+                        .header("Authorization", format!("Bearer {}", owner_token))
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        assert_eq!(
+            state.voice_moderation(&channel_id, "member"),
+            VoiceModeration { server_muted: true, server_deafened: true }
+        );
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ChannelEvent::VoiceModerationChanged { server_muted: true, server_deafened: false, .. }
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ChannelEvent::VoiceModerationChanged { server_muted: true, server_deafened: true, .. }
+        ));
+
+        // Leaving the channel lifts the restrictions
+        let kick_response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/users/{}/kick", channel_id, "member"))
+                    .header("Authorization", format!("Bearer {}", owner_token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(kick_response.status(), StatusCode::OK);
+        assert!(!state.voice_moderation(&channel_id, "member").is_active());
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ChannelEvent::VoiceModerationChanged { server_muted: false, server_deafened: false, changed_by: None, .. }
+        ));
     }
 
     #[tokio::test]
-    async fn test_change_user_role() {
-        let app = create_test_app();
+    async fn test_channel_limits() {
+        let state = test_state(SharedState::in_process());
+        let app = create_test_app_with_state(state.clone());
         let owner_token = create_test_token("owner");
         let member_token = create_test_token("member");
 
-        // Create a channel
         let create_response = app
             .clone()
             .oneshot(
@@ -1003,7 +1857,8 @@ mod tests {
                     .body(Body::from(
                         json!({
                             "name": "Test Channel",
-                            "privacy": "Public"
+                            "privacy": "Public",
+                            "max_participants": 2
                         })
                         .to_string(),
                     ))
@@ -1012,16 +1867,17 @@ mod tests {
             .await
             .unwrap();
 
-        let create_body = hyper::body::to_bytes(create_response.into_body()).await.unwrap();
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
         let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
+        let channel_id = create_data.channel_id;
+        assert_eq!(state.channels.lock().unwrap()[&channel_id].max_participants, Some(2));
 
-        // Join as member
         app.clone()
             .oneshot(
                 Request::builder()
                     .method("POST")
-                    .uri(format!("/channels/{}/join", create_data.channel_id))
-                    .header("Authorization", format!("Bearer {}", member_token.clone()))
+                    .uri(format!("/channels/{}/join", channel_id))
+                    .header("Authorization", format!("Bearer {}", member_token))
                     .header("Content-Type", "application/json")
                     .body(Body::from(json!({}).to_string()))
                     .unwrap(),
@@ -1029,30 +1885,40 @@ mod tests {
             .await
             .unwrap();
 
-        // Change member to moderator
-        let role_response = app
-            .oneshot(
-                Request::builder()
-                    .method("POST")
-                    .uri(format!("/channels/{}/users/{}/role", create_data.channel_id, "member"))
-                    .header("Authorization", format!("Bearer {}", owner_token))
-                    .header("Content-Type", "application/json")
-                    .body(Body::from(json!({ "role": "moderator" }).to_string()))
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+        let limits = json!({ "max_participants": 1, "max_speakers": 1, "waitlist_enabled": true });
+        for (token, expected) in [(&member_token, StatusCode::FORBIDDEN), (&owner_token, StatusCode::OK)] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/channels/{}/limits", channel_id))
+                        .header("Authorization", format!("Bearer {}", token))
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(limits.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected);
+        }
 
-        assert_eq!(role_response.status(), StatusCode::OK);
+        // Members wait for the one place, the owner does not
+        assert!(matches!(
+            state.admit_voice(&channel_id, "member", 1),
+            Err(VoiceStateError::ChannelFull { limit: 1, position: Some(1) })
+        ));
+        assert!(state.admit_voice(&channel_id, "owner", 1).is_ok());
+        assert!(state.admit_voice(&channel_id, "member", 0).is_ok());
     }
 
     #[tokio::test]
-    async fn test_kick_user() {
-        let app = create_test_app();
+    async fn test_membership_changes_reach_live_sessions() {
+        let state = test_state(SharedState::in_process());
+        let app = create_test_app_with_state(state.clone());
         let owner_token = create_test_token("owner");
         let member_token = create_test_token("member");
 
-        // Create a channel
         let create_response = app
             .clone()
             .oneshot(
@@ -1073,15 +1939,15 @@ mod tests {
             .await
             .unwrap();
 
-        let create_body = hyper::body::to_bytes(create_response.into_body()).await.unwrap();
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
         let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
+        let channel_id = create_data.channel_id;
 
-        // Join as member
         app.clone()
             .oneshot(
                 Request::builder()
                     .method("POST")
-                    .uri(format!("/channels/{}/join", create_data.channel_id))
+                    .uri(format!("/channels/{}/join", channel_id))
                     .header("Authorization", format!("Bearer {}", member_token))
                     .header("Content-Type", "application/json")
                     .body(Body::from(json!({}).to_string()))
@@ -1090,21 +1956,49 @@ mod tests {
             .await
             .unwrap();
 
-        // Kick the member
-        let kick_response = app
+        let mut events = state.events.subscribe();
+
+        let role_response = app
+            .clone()
             .oneshot(
                 Request::builder()
                     .method("POST")
-                    .uri(format!("/channels/{}/users/{}/kick", create_data.channel_id, "member"))
+                    .uri(format!("/channels/{}/users/{}/role", channel_id, "member"))
                     .header("Authorization", format!("Bearer {}", owner_token))
                     .header("Content-Type", "application/json")
-                    .body(Body::from(json!({}).to_string()))
+                    .body(Body::from(json!({ "role": "moderator" }).to_string()))
                     .unwrap(),
             )
             .await
             .unwrap();
+        assert_eq!(role_response.status(), StatusCode::OK);
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ChannelEvent::RoleChanged { user_id, role: Role::Moderator, .. } if user_id == "member"
+        ));
 
-        assert_eq!(kick_response.status(), StatusCode::OK);
+        let ban_response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/users/{}/ban", channel_id, "member"))
+                    .header("Authorization", format!("Bearer {}", owner_token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({ "username": "member", "reason": "Spam" }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(ban_response.status(), StatusCode::OK);
+        match events.recv().await.unwrap() {
+            ChannelEvent::MemberRemoved { user_id, reason, removed_by, note, .. } => {
+                assert_eq!(user_id, "member");
+                assert_eq!(reason, RemovalReason::Banned);
+                assert_eq!(removed_by, "owner");
+                assert_eq!(reason.message(note.as_deref()), "You were banned from the channel: Spam");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
     }
 
     #[tokio::test]
@@ -1134,7 +2028,7 @@ mod tests {
             .await
             .unwrap();
 
-        let create_body = hyper::body::to_bytes(create_response.into_body()).await.unwrap();
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
         let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
 
         // Join as member
@@ -1153,13 +2047,14 @@ mod tests {
 
         // Ban the member
         let ban_response = app
+            .clone()
             .oneshot(
                 Request::builder()
                     .method("POST")
                     .uri(format!("/channels/{}/users/{}/ban", create_data.channel_id, "member"))
                     .header("Authorization", format!("Bearer {}", owner_token.clone()))
                     .header("Content-Type", "application/json")
-                    .body(Body::from(json!({ "reason": "Test ban" }).to_string()))
+                    .body(Body::from(json!({ "username": "member", "reason": "Test ban" }).to_string()))
                     .unwrap(),
             )
             .await
@@ -1210,7 +2105,7 @@ mod tests {
             .await
             .unwrap();
 
-        let create_body = hyper::body::to_bytes(create_response.into_body()).await.unwrap();
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
         let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
 
         // Create an invite
@@ -1230,7 +2125,7 @@ mod tests {
 
         assert_eq!(invite_response.status(), StatusCode::OK);
 
-        let invite_body = hyper::body::to_bytes(invite_response.into_body()).await.unwrap();
+        let invite_body = axum::body::to_bytes(invite_response.into_body(), usize::MAX).await.unwrap();
         let invite_data: InviteUserResponse = serde_json::from_slice(&invite_body).unwrap();
 
         // List invites
@@ -1293,7 +2188,7 @@ mod tests {
             .await
             .unwrap();
 
-        let create_body = hyper::body::to_bytes(create_response.into_body()).await.unwrap();
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
         let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
 
         // Try to change role as member (should fail)
@@ -1339,7 +2234,7 @@ mod tests {
             .await
             .unwrap();
 
-        let create_body = hyper::body::to_bytes(create_response.into_body()).await.unwrap();
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
         let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
 
         // Try to kick self (should fail)
@@ -1367,7 +2262,7 @@ mod tests {
                     .uri(format!("/channels/{}/users/{}/ban", create_data.channel_id, "owner"))
                     .header("Authorization", format!("Bearer {}", owner_token))
                     .header("Content-Type", "application/json")
-                    .body(Body::from(json!({ "reason": "Test" }).to_string()))
+                    .body(Body::from(json!({ "username": "owner", "reason": "Test" }).to_string()))
                     .unwrap(),
             )
             .await
@@ -1375,4 +2270,222 @@ mod tests {
 
         assert_eq!(ban_response.status(), StatusCode::FORBIDDEN);
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_recording_lifecycle() {
+        let app = create_test_app();
+        let owner_token = create_test_token("owner");
+        let member_token = create_test_token("member");
+
+        // Create a channel
+        let create_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/channels")
+                    .header("Authorization", format!("Bearer {}", owner_token.clone()))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        json!({
+                            "name": "Test Channel",
+                            "privacy": "Public"
+                        })
+                        .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
+        let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
+
+        // Join as member
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/join", create_data.channel_id))
+                    .header("Authorization", format!("Bearer {}", member_token.clone()))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Members cannot start a recording
+        let member_start = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/recordings/start", create_data.channel_id))
+                    .header("Authorization", format!("Bearer {}", member_token.clone()))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(member_start.status(), StatusCode::FORBIDDEN);
+
+        // Owner starts and stops a recording
+        let start_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/recordings/start", create_data.channel_id))
+                    .header("Authorization", format!("Bearer {}", owner_token.clone()))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({ "mixed_track": true }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(start_response.status(), StatusCode::OK);
+
+        let stop_response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/recordings/stop", create_data.channel_id))
+                    .header("Authorization", format!("Bearer {}", owner_token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(stop_response.status(), StatusCode::OK);
+
+        let stop_body = axum::body::to_bytes(stop_response.into_body(), usize::MAX).await.unwrap();
+        let stop_data: serde_json::Value = serde_json::from_slice(&stop_body).unwrap();
+        let recording_id = stop_data["id"].as_str().unwrap().to_string();
+
+        // Members can list and download finished recordings
+        let download_response = app
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(format!("/channels/{}/recordings/{}", create_data.channel_id, recording_id))
+                    .header("Authorization", format!("Bearer {}", member_token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(download_response.status(), StatusCode::OK);
+        assert_eq!(download_response.headers()["content-type"], "audio/ogg");
+    }
+
+    /// Wait for replication to make `check` true for a channel
+    async fn wait_for_channel(state: &AppState, channel_id: &str, check: impl Fn(&Channel) -> bool) {
+        for _ in 0..100 {
+            if state.channels.lock().unwrap().get(channel_id).is_some_and(&check) {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("Channel {} was not replicated", channel_id);
+    }
+
+    #[tokio::test]
+    async fn test_channel_changes_replicate_between_instances() {
+        let backend: Arc<dyn crate::state::StateBackend> = Arc::new(crate::state::InProcessBackend::new());
+        let eu_state = test_state(SharedState::new(backend.clone()));
+        let na_state = test_state(SharedState::new(backend));
+        eu_state.start_replication().await.unwrap();
+        na_state.start_replication().await.unwrap();
+        let eu = create_test_app_with_state(eu_state.clone());
+        let na = create_test_app_with_state(na_state.clone());
+        let owner_token = create_test_token("owner");
+        let member_token = create_test_token("member");
+
+        // Create on one instance
+        let create_response = eu
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/channels")
+                    .header("Authorization", format!("Bearer {}", owner_token.clone()))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        json!({
+                            "name": "Test Channel",
+                            "privacy": "Public"
+                        })
+                        .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let create_body = axum::body::to_bytes(create_response.into_body(), usize::MAX).await.unwrap();
+        let create_data: CreateChannelResponse = serde_json::from_slice(&create_body).unwrap();
+        wait_for_channel(&na_state, &create_data.channel_id, |_| true).await;
+
+        // Join through the other
+        let join_response = na
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/join", create_data.channel_id))
+                    .header("Authorization", format!("Bearer {}", member_token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({}).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(join_response.status(), StatusCode::OK);
+        wait_for_channel(&eu_state, &create_data.channel_id, |channel| {
+            channel.members.contains(&"member".to_string())
+        })
+        .await;
+
+        // Moderation on the first instance reaches the second
+        let ban_response = eu
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/channels/{}/users/{}/ban", create_data.channel_id, "member"))
+                    .header("Authorization", format!("Bearer {}", owner_token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({ "username": "member", "reason": "Test" }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(ban_response.status(), StatusCode::OK);
+        wait_for_channel(&na_state, &create_data.channel_id, |channel| {
+            is_user_banned(channel, "member") && !channel.members.contains(&"member".to_string())
+        })
+        .await;
+
+        // Events published on one instance reach the other's bus
+        let mut na_events = na_state.events.subscribe();
+        eu_state
+            .publish_event(ChannelEvent::RecordingStopped {
+                channel_id: create_data.channel_id.clone(),
+                recording_id: "rec1".to_string(),
+            })
+            .await;
+        let event = tokio::time::timeout(std::time::Duration::from_secs(1), na_events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.channel_id(), create_data.channel_id);
+    }
+}
@@ -1,19 +1,24 @@
 use sqlx::{postgres::PgPoolOptions, PgPool};
-use dotenvy::dotenv;
-use std::env;
 
-pub async fn get_pool() -> PgPool {
-    dotenv().ok();
-    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+pub async fn get_pool(db_url: &str) -> PgPool {
     PgPoolOptions::new()
         .max_connections(10)
-        .connect(&db_url)
+        .connect(db_url)
         .await
         .expect("Failed to connect to Postgres")
 }
 
 pub async fn run_migrations(pool: &PgPool) {
-    sqlx::migrate!("migrations").run(pool).await.expect("Migrations failed");
+    sqlx::migrate!().run(pool).await.expect("Migrations failed");
 }
 
-pub type DbPool = PgPool; 
\ No newline at end of file
+/// Connect to `TEST_DATABASE_URL` and migrate it. Tests that need it are
+/// `#[ignore]`d and run with
+/// `TEST_DATABASE_URL=postgres://... cargo test -- --include-ignored`.
+#[cfg(test)]
+pub async fn test_pool() -> PgPool {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must point at a scratch database");
+    let pool = PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+    run_migrations(&pool).await;
+    pool
+}
@@ -0,0 +1,282 @@
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+mod templates;
+mod transport;
+
+use templates::Template;
+pub use transport::{FileTransport, LogTransport, MailTransport, SmtpTransport};
+#[cfg(test)]
+pub use transport::MemoryTransport;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailError {
+    #[error("Invalid address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("Failed to build message: {0}")]
+    Build(#[from] lettre::error::Error),
+    #[error("Failed to write message: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("SMTP error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Mail queue is full")]
+    QueueFull,
+}
+
+impl MailError {
+    /// Whether sending again later may succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            MailError::Smtp(e) => !e.is_permanent(),
+            MailError::Io(_) | MailError::QueueFull => true,
+            MailError::Address(_) | MailError::Build(_) => false,
+        }
+    }
+}
+
+/// A rendered email, ready for a transport
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub from: Mailbox,
+    pub to: Mailbox,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+impl Email {
+    /// The MIME message, with text and HTML alternatives
+    pub fn message(&self) -> Result<Message, MailError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(self.to.clone())
+            .subject(&self.subject)
+            .multipart(MultiPart::alternative_plain_html(self.text.clone(), self.html.clone()))?;
+        Ok(message)
+    }
+}
+
+/// Background sending; attempt `n` is retried after `retry_delay * 2^(n-1)`
+#[derive(Debug, Clone, Copy)]
+pub struct QueueSettings {
+    /// Messages waiting to be sent before `send` fails with `QueueFull`
+    pub capacity: usize,
+    pub max_attempts: u32,
+    pub retry_delay: Duration,
+}
+
+impl Default for QueueSettings {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            max_attempts: 5,
+            retry_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+struct Queued {
+    email: Email,
+    attempts: u32,
+}
+
+/// Sends account emails with links back to the frontend. Without a queue
+/// each send waits for the transport; with one, sends return once the
+/// message is queued.
+#[derive(Clone)]
+pub struct Mailer {
+    transport: Arc<dyn MailTransport>,
+    from: String,
+    frontend_url: String,
+    queue: Option<mpsc::Sender<Queued>>,
+}
+
+impl Default for Mailer {
+    fn default() -> Self {
+        Self::new(Arc::new(LogTransport), "VoiceLink <noreply@localhost>", "http://localhost:5173")
+    }
+}
+
+impl Mailer {
+    pub fn new(transport: Arc<dyn MailTransport>, from: &str, frontend_url: &str) -> Self {
+        Self {
+            transport,
+            from: from.to_string(),
+            frontend_url: frontend_url.trim_end_matches('/').to_string(),
+            queue: None,
+        }
+    }
+
+    /// Send through a background task, retrying transient failures. Must be
+    /// called inside a Tokio runtime; the task ends with the last clone.
+    pub fn with_queue(mut self, settings: QueueSettings) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<Queued>(settings.capacity.max(1));
+        let retry = sender.downgrade();
+        let transport = self.transport.clone();
+        tokio::spawn(async move {
+            while let Some(mut queued) = receiver.recv().await {
+                queued.attempts += 1;
+                let e = match transport.deliver(&queued.email).await {
+                    Ok(()) => continue,
+                    Err(e) => e,
+                };
+                if !e.is_transient() || queued.attempts >= settings.max_attempts {
+                    error!("Giving up on mail to {} after {} attempts: {}", queued.email.to, queued.attempts, e);
+                    continue;
+                }
+                let delay = settings.retry_delay.saturating_mul(1 << (queued.attempts - 1).min(16));
+                warn!("Mail to {} failed, retrying in {:?}: {}", queued.email.to, delay, e);
+                let retry = retry.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    if let Some(sender) = retry.upgrade() {
+                        let _ = sender.send(queued).await;
+                    }
+                });
+            }
+        });
+        self.queue = Some(sender);
+        self
+    }
+
+    async fn send(&self, to: &str, template: Template<'_>) -> Result<(), MailError> {
+        let rendered = template.render();
+        let email = Email {
+            from: self.from.parse()?,
+            to: to.parse()?,
+            subject: rendered.subject,
+            text: rendered.text,
+            html: rendered.html,
+        };
+        match &self.queue {
+            Some(queue) => queue.try_send(Queued { email, attempts: 0 }).map_err(|_| MailError::QueueFull),
+            None => self.transport.deliver(&email).await,
+        }
+    }
+
+    pub async fn send_verification_email(&self, to: &str, token: &str) -> Result<(), MailError> {
+        let link = format!("{}/verify-email?token={}", self.frontend_url, token);
+        self.send(to, Template::Verification { link: &link }).await
+    }
+
+    pub async fn send_reset_email(&self, to: &str, token: &str) -> Result<(), MailError> {
+        let link = format!("{}/reset/confirm?token={}", self.frontend_url, token);
+        self.send(to, Template::PasswordReset { link: &link }).await
+    }
+
+    pub async fn send_invite_email(
+        &self,
+        to: &str,
+        inviter: &str,
+        channel_name: &str,
+        channel_id: &str,
+        token: &str,
+    ) -> Result<(), MailError> {
+        let link = format!("{}/invite?channel={}&token={}", self.frontend_url, channel_id, token);
+        self.send(to, Template::ChannelInvite { inviter, channel: channel_name, link: &link }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails with an I/O error until `failures` attempts have been made
+    struct FlakyTransport {
+        failures: u32,
+        attempts: AtomicU32,
+        inner: MemoryTransport,
+    }
+
+    #[async_trait]
+    impl MailTransport for FlakyTransport {
+        async fn deliver(&self, email: &Email) -> Result<(), MailError> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "down").into());
+            }
+            self.inner.deliver(email).await
+        }
+    }
+
+    fn flaky(failures: u32) -> Arc<FlakyTransport> {
+        Arc::new(FlakyTransport { failures, attempts: AtomicU32::new(0), inner: MemoryTransport::new() })
+    }
+
+    fn quick_queue(max_attempts: u32) -> QueueSettings {
+        QueueSettings { capacity: 10, max_attempts, retry_delay: Duration::from_millis(10) }
+    }
+
+    #[tokio::test]
+    async fn test_file_transport_writes_messages() {
+        let dir = std::env::temp_dir().join(format!("whisper-fleet-mail-{}", uuid::Uuid::new_v4()));
+        let mailer = Mailer::new(Arc::new(FileTransport::new(&dir)), "VoiceLink <noreply@example.com>", "https://fleet.example.com/");
+        mailer.send_verification_email("pilot@example.com", "abc123").await.unwrap();
+
+        let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        let path = entries[0].as_ref().unwrap().path();
+        assert_eq!(path.extension().unwrap(), "eml");
+        let message = std::fs::read_to_string(path).unwrap();
+        assert!(message.contains("To: pilot@example.com"));
+        assert!(message.contains("multipart/alternative"));
+        assert!(message.contains("https://fleet.example.com/verify-email?token=abc123"));
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(mailer.send_verification_email("not an address", "abc123").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_transport_keeps_rendered_mail() {
+        let memory = Arc::new(MemoryTransport::new());
+        let mailer = Mailer::new(memory.clone(), "VoiceLink <noreply@example.com>", "https://fleet.example.com");
+        mailer.send_invite_email("wing@example.com", "ace", "Red <Leader>", "c1", "t1").await.unwrap();
+        mailer.send_reset_email("ace@example.com", "123456").await.unwrap();
+
+        let sent = memory.take();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].to.email.to_string(), "wing@example.com");
+        assert_eq!(sent[0].subject, "ace invited you to Red <Leader> on VoiceLink");
+        assert!(sent[0].text.contains("https://fleet.example.com/invite?channel=c1&token=t1"));
+        assert!(sent[0].html.contains("Red &lt;Leader&gt;"));
+        assert!(sent[1].text.contains("123456"));
+        assert!(memory.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_queue_retries_transient_failures() {
+        let transport = flaky(2);
+        let mailer = Mailer::new(transport.clone(), "VoiceLink <noreply@example.com>", "https://fleet.example.com")
+            .with_queue(quick_queue(3));
+        mailer.send_reset_email("pilot@example.com", "abc123").await.unwrap();
+
+        for _ in 0..100 {
+            if !transport.inner.sent().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(transport.inner.sent().len(), 1);
+        assert_eq!(transport.attempts.load(Ordering::SeqCst), 3);
+
+        // Invalid addresses fail before queueing
+        assert!(matches!(mailer.send_reset_email("nope", "abc123").await, Err(MailError::Address(_))));
+    }
+
+    #[tokio::test]
+    async fn test_queue_gives_up_after_max_attempts() {
+        let transport = flaky(u32::MAX);
+        let mailer = Mailer::new(transport.clone(), "VoiceLink <noreply@example.com>", "https://fleet.example.com")
+            .with_queue(quick_queue(3));
+        mailer.send_reset_email("pilot@example.com", "123456").await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(transport.attempts.load(Ordering::SeqCst), 3);
+        assert!(transport.inner.sent().is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+/// Account emails. Each renders to a subject, a plain text part and an HTML
+/// part wrapped in the shared layout.
+#[derive(Debug, Clone, Copy)]
+pub enum Template<'a> {
+    Verification { link: &'a str },
+    PasswordReset { link: &'a str },
+    ChannelInvite { inviter: &'a str, channel: &'a str, link: &'a str },
+}
+
+#[derive(Debug, Clone)]
+pub struct Rendered {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+const LAYOUT: &str = include_str!("templates/layout.html");
+
+impl Template<'_> {
+    pub fn render(&self) -> Rendered {
+        let (subject, text, html, vars) = match *self {
+            Template::Verification { link } => (
+                "Verify your VoiceLink email address".to_string(),
+                include_str!("templates/verification.txt"),
+                include_str!("templates/verification.html"),
+                vec![("link", link)],
+            ),
+            Template::PasswordReset { link } => (
+                "Reset your VoiceLink password".to_string(),
+                include_str!("templates/reset.txt"),
+                include_str!("templates/reset.html"),
+                vec![("link", link)],
+            ),
+            Template::ChannelInvite { inviter, channel, link } => (
+                format!("{} invited you to {} on VoiceLink", inviter, channel),
+                include_str!("templates/invite.txt"),
+                include_str!("templates/invite.html"),
+                vec![("inviter", inviter), ("channel", channel), ("link", link)],
+            ),
+        };
+        let content = fill(html, &vars, escape_html);
+        let title = escape_html(&subject);
+        Rendered {
+            text: fill(text, &vars, str::to_string),
+            html: fill(LAYOUT, &[("title", &title), ("content", &content)], str::to_string),
+            subject,
+        }
+    }
+}
+
+/// Replace each `{{name}}` in one pass, so values are never expanded again.
+/// Unknown names are left as they are.
+fn fill(template: &str, vars: &[(&str, &str)], escape: fn(&str) -> String) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None => break,
+        };
+        match vars.iter().find(|(name, _)| *name == after[..end].trim()) {
+            Some((_, value)) => out.push_str(&escape(value)),
+            None => out.push_str(&rest[start..start + end + 4]),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn escape_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_escapes_html_only() {
+        let rendered = Template::ChannelInvite {
+            inviter: "ace",
+            channel: "<Wing> & {{link}}",
+            link: "https://fleet.example.com/invite?channel=1&token=abc",
+        }
+        .render();
+        assert_eq!(rendered.subject, "ace invited you to <Wing> & {{link}} on VoiceLink");
+        assert!(rendered.text.contains("the channel <Wing> & {{link}}."));
+        assert!(rendered.text.contains("https://fleet.example.com/invite?channel=1&token=abc"));
+        assert!(rendered.html.contains("<strong>&lt;Wing&gt; &amp; {{link}}</strong>"));
+        assert!(rendered.html.contains("href=\"https://fleet.example.com/invite?channel=1&amp;token=abc\""));
+        assert!(rendered.html.contains("<title>ace invited you to &lt;Wing&gt; &amp; {{link}} on VoiceLink</title>"));
+    }
+
+    #[test]
+    fn test_templates_fill_every_placeholder() {
+        let templates = [
+            Template::Verification { link: "L" },
+            Template::PasswordReset { link: "L" },
+            Template::ChannelInvite { inviter: "I", channel: "C", link: "L" },
+        ];
+        for template in templates {
+            let rendered = template.render();
+            assert!(!rendered.text.contains("{{"), "{:?}", template);
+            assert!(!rendered.html.contains("{{"), "{:?}", template);
+        }
+    }
+}
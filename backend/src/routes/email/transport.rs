@@ -0,0 +1,113 @@
+use async_trait::async_trait;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use std::path::PathBuf;
+use tracing::{debug, info};
+use super::{Email, MailError};
+
+/// Delivers composed emails. The `Mailer` queues and retries around it, so
+/// a transport makes one attempt per call.
+#[async_trait]
+pub trait MailTransport: Send + Sync + 'static {
+    async fn deliver(&self, email: &Email) -> Result<(), MailError>;
+}
+
+/// Logs each message instead of sending it, for development. Bodies carry
+/// live reset and verification links, so they are only logged at debug level.
+pub struct LogTransport;
+
+#[async_trait]
+impl MailTransport for LogTransport {
+    async fn deliver(&self, email: &Email) -> Result<(), MailError> {
+        info!("Mail to {} not sent, log transport: {}", email.to, email.subject);
+        debug!("Body of the mail to {}:\n{}", email.to, email.text);
+        Ok(())
+    }
+}
+
+/// Drops each message into a directory as `<id>.eml`, like a sendmail pickup
+/// directory. Files appear complete, so other tools can collect them.
+pub struct FileTransport {
+    dir: PathBuf,
+}
+
+impl FileTransport {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl MailTransport for FileTransport {
+    async fn deliver(&self, email: &Email) -> Result<(), MailError> {
+        let message = email.message()?;
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let id = uuid::Uuid::new_v4();
+        let partial = self.dir.join(format!(".{}.tmp", id));
+        tokio::fs::write(&partial, message.formatted()).await?;
+        tokio::fs::rename(&partial, self.dir.join(format!("{}.eml", id))).await?;
+        Ok(())
+    }
+}
+
+/// Relays through an SMTP server, with implicit TLS on port 465 and STARTTLS
+/// otherwise. Connections are pooled between messages.
+pub struct SmtpTransport {
+    smtp: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    /// Credentials are skipped when `username` is empty
+    pub fn new(host: &str, port: u16, username: &str, password: &str) -> Result<Self, MailError> {
+        let builder = match port {
+            465 => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+        };
+        let mut builder = builder.port(port);
+        if !username.is_empty() {
+            builder = builder.credentials(Credentials::new(username.to_string(), password.to_string()));
+        }
+        Ok(Self { smtp: builder.build() })
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpTransport {
+    async fn deliver(&self, email: &Email) -> Result<(), MailError> {
+        self.smtp.send(email.message()?).await?;
+        Ok(())
+    }
+}
+
+/// Keeps messages in memory, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryTransport {
+    sent: std::sync::Mutex<Vec<Email>>,
+}
+
+#[cfg(test)]
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Messages delivered so far, oldest first
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Messages delivered so far, forgetting them
+    pub fn take(&self) -> Vec<Email> {
+        std::mem::take(&mut *self.sent.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MailTransport for MemoryTransport {
+    async fn deliver(&self, email: &Email) -> Result<(), MailError> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Result code of a bind with a wrong password or an unknown DN
+const INVALID_CREDENTIALS: u32 = 49;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DirectoryError {
+    #[error("LDAP error: {0}")]
+    Ldap(#[from] ldap3::LdapError),
+}
+
+/// Where directory users and their groups are, and what their groups may do
+#[derive(Debug, Clone)]
+pub struct LdapSettings {
+    /// `ldap://` or `ldaps://` URL of the server
+    pub url: String,
+    /// Upgrade `ldap://` connections with StartTLS
+    pub starttls: bool,
+    /// Service account that looks users up; anonymous when empty
+    pub bind_dn: String,
+    pub bind_password: String,
+    pub user_base_dn: String,
+    /// `{username}` is replaced with the escaped login name
+    pub user_filter: String,
+    /// Attribute with the username to sign in as
+    pub username_attribute: String,
+    pub email_attribute: String,
+    /// Attribute of user entries that lists their groups, e.g. `memberOf`
+    pub group_attribute: String,
+    /// When set, groups are searched below it with `group_filter` instead
+    pub group_base_dn: String,
+    /// `{dn}` is replaced with the escaped DN of the user
+    pub group_filter: String,
+    /// Roles of the members of each group, by group DN
+    pub group_roles: Vec<(String, Vec<String>)>,
+    /// Roles of every directory user
+    pub default_roles: Vec<String>,
+    /// Refuse directory users who are in none of the mapped groups
+    pub require_group: bool,
+    pub timeout: Duration,
+}
+
+/// A directory user who proved their password
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectoryUser {
+    pub dn: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub roles: Vec<String>,
+}
+
+/// Checks passwords of directory accounts by binding as them. Every sign-in
+/// opens its own connection, so a bind never leaks into another request.
+pub struct LdapDirectory {
+    settings: LdapSettings,
+}
+
+impl LdapDirectory {
+    pub fn new(settings: LdapSettings) -> Self {
+        Self { settings }
+    }
+
+    /// The directory user `username` if `password` is theirs. Unknown users,
+    /// wrong passwords and users the group mapping refuses are all `None`.
+    pub async fn authenticate(&self, username: &str, password: &str) -> Result<Option<DirectoryUser>, DirectoryError> {
+        // A bind with an empty password is an unauthenticated bind, which succeeds
+        if username.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+        let mut ldap = self.connect().await?;
+        let result = self.lookup(&mut ldap, username, password).await;
+        let _ = ldap.unbind().await;
+        result
+    }
+
+    async fn connect(&self) -> Result<Ldap, DirectoryError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(self.settings.timeout)
+            .set_starttls(self.settings.starttls);
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.settings.url).await?;
+        ldap3::drive!(conn);
+        Ok(ldap)
+    }
+
+    async fn service_bind(&self, ldap: &mut Ldap) -> Result<(), DirectoryError> {
+        let s = &self.settings;
+        if !s.bind_dn.is_empty() {
+            ldap.with_timeout(s.timeout).simple_bind(&s.bind_dn, &s.bind_password).await?.success()?;
+        }
+        Ok(())
+    }
+
+    async fn lookup(&self, ldap: &mut Ldap, username: &str, password: &str) -> Result<Option<DirectoryUser>, DirectoryError> {
+        let s = &self.settings;
+        self.service_bind(ldap).await?;
+        let filter = s.user_filter.replace("{username}", &ldap_escape(username));
+        let attrs = vec![s.username_attribute.as_str(), s.email_attribute.as_str(), s.group_attribute.as_str()];
+        let (entries, _) = ldap
+            .with_timeout(s.timeout)
+            .search(&s.user_base_dn, Scope::Subtree, &filter, attrs)
+            .await?
+            .success()?;
+        let mut entries = entries.into_iter().filter(|entry| !entry.is_ref());
+        let entry = match (entries.next(), entries.next()) {
+            (Some(entry), None) => SearchEntry::construct(entry),
+            (None, _) => return Ok(None),
+            (Some(_), Some(_)) => {
+                warn!("More than one directory entry matches {}; refusing the sign-in", filter);
+                return Ok(None);
+            }
+        };
+
+        let bound = ldap.with_timeout(s.timeout).simple_bind(&entry.dn, password).await?;
+        if bound.rc == INVALID_CREDENTIALS {
+            return Ok(None);
+        }
+        bound.success()?;
+
+        let groups = if s.group_base_dn.is_empty() {
+            values(&entry, &s.group_attribute).to_vec()
+        } else {
+            // Users may not be allowed to read groups themselves
+            self.service_bind(ldap).await?;
+            let filter = s.group_filter.replace("{dn}", &ldap_escape(&entry.dn));
+            let (groups, _) = ldap
+                .with_timeout(s.timeout)
+                .search(&s.group_base_dn, Scope::Subtree, &filter, vec!["1.1"])
+                .await?
+                .success()?;
+            groups
+                .into_iter()
+                .filter(|group| !group.is_ref())
+                .map(|group| SearchEntry::construct(group).dn)
+                .collect()
+        };
+        let roles = match self.roles(&groups) {
+            Some(roles) => roles,
+            None => {
+                info!("Directory user {} is in none of the mapped groups", entry.dn);
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(DirectoryUser {
+            username: values(&entry, &s.username_attribute).first().cloned().unwrap_or_else(|| username.to_string()),
+            email: values(&entry, &s.email_attribute).first().cloned(),
+            dn: entry.dn,
+            roles,
+        }))
+    }
+
+    /// Roles of a member of `groups`, or `None` when a mapped group is
+    /// required and they are in none
+    pub fn roles(&self, groups: &[String]) -> Option<Vec<String>> {
+        let groups: Vec<String> = groups.iter().map(|group| normalize_dn(group)).collect();
+        let mut roles = self.settings.default_roles.clone();
+        let mut mapped = false;
+        for (group, group_roles) in &self.settings.group_roles {
+            if !groups.contains(&normalize_dn(group)) {
+                continue;
+            }
+            mapped = true;
+            for role in group_roles {
+                if !roles.contains(role) {
+                    roles.push(role.clone());
+                }
+            }
+        }
+        if self.settings.require_group && !mapped {
+            return None;
+        }
+        Some(roles)
+    }
+}
+
+/// Values of an attribute; servers may spell its name in another case
+fn values<'a>(entry: &'a SearchEntry, attribute: &str) -> &'a [String] {
+    entry
+        .attrs
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+        .map(|(_, values)| values.as_slice())
+        .unwrap_or(&[])
+}
+
+/// Compare DNs without case or spaces around the separators
+fn normalize_dn(dn: &str) -> String {
+    dn.split(',').map(|rdn| rdn.trim().to_lowercase()).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Settings for the OpenLDAP container seeded with `testdata/ldap/fleet.ldif`
+    pub(crate) fn fleet_settings(url: &str) -> LdapSettings {
+        LdapSettings {
+            url: url.to_string(),
+            starttls: false,
+            bind_dn: "cn=admin,dc=example,dc=org".to_string(),
+            bind_password: "admin".to_string(),
+            user_base_dn: "ou=people,dc=example,dc=org".to_string(),
+            user_filter: "(&(objectClass=inetOrgPerson)(uid={username}))".to_string(),
+            username_attribute: "uid".to_string(),
+            email_attribute: "mail".to_string(),
+            group_attribute: "memberOf".to_string(),
+            group_base_dn: "ou=groups,dc=example,dc=org".to_string(),
+            group_filter: "(member={dn})".to_string(),
+            group_roles: vec![
+                ("cn=fleet-admins,ou=groups,dc=example,dc=org".to_string(), vec!["admin".to_string()]),
+                ("cn=fleet-pilots,ou=groups,dc=example,dc=org".to_string(), vec!["user".to_string()]),
+            ],
+            default_roles: Vec::new(),
+            require_group: true,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_group_roles() {
+        let directory = LdapDirectory::new(fleet_settings("ldap://127.0.0.1:1"));
+        let admin = "CN=Fleet-Admins, OU=Groups, DC=example, DC=org".to_string();
+        let pilot = "cn=fleet-pilots,ou=groups,dc=example,dc=org".to_string();
+        assert_eq!(directory.roles(&[pilot.clone(), admin]), Some(vec!["admin".to_string(), "user".to_string()]));
+        assert_eq!(directory.roles(&[pilot]), Some(vec!["user".to_string()]));
+        assert_eq!(directory.roles(&["cn=cooks,ou=groups,dc=example,dc=org".to_string()]), None);
+
+        let open = LdapDirectory::new(LdapSettings {
+            default_roles: vec!["user".to_string()],
+            require_group: false,
+            ..fleet_settings("ldap://127.0.0.1:1")
+        });
+        assert_eq!(open.roles(&[]), Some(vec!["user".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_empty_password_never_binds() {
+        // Nothing listens here; an attempt to bind would be an error
+        let directory = LdapDirectory::new(fleet_settings("ldap://127.0.0.1:1"));
+        assert_eq!(directory.authenticate("maverick", "").await.unwrap(), None);
+        assert_eq!(directory.authenticate("", "danger-zone").await.unwrap(), None);
+        assert!(directory.authenticate("maverick", "danger-zone").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_openldap_bind_and_groups() {
+        // Run against the OpenLDAP container described in the README
+        let url = match std::env::var("TEST_LDAP_URL") {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        let directory = LdapDirectory::new(fleet_settings(&url));
+
+        let maverick = directory.authenticate("maverick", "danger-zone").await.unwrap().unwrap();
+        assert_eq!(maverick.dn, "uid=maverick,ou=people,dc=example,dc=org");
+        assert_eq!(maverick.email.as_deref(), Some("maverick@example.org"));
+        assert_eq!(maverick.roles, vec!["admin".to_string(), "user".to_string()]);
+        let goose = directory.authenticate("goose", "great-balls-of-fire").await.unwrap().unwrap();
+        assert_eq!(goose.roles, vec!["user".to_string()]);
+
+        assert_eq!(directory.authenticate("maverick", "wrong").await.unwrap(), None);
+        assert_eq!(directory.authenticate("nobody", "danger-zone").await.unwrap(), None);
+        // Filter syntax in the username is only ever a literal
+        assert_eq!(directory.authenticate("*", "danger-zone").await.unwrap(), None);
+        assert_eq!(directory.authenticate("maverick)(uid=*", "danger-zone").await.unwrap(), None);
+        // In the directory but in no mapped group
+        assert_eq!(directory.authenticate("iceman", "you-can-be-my-wingman").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,75 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use std::time::Instant;
+use crate::metrics::Metrics;
+
+/// Prometheus scrape endpoint
+pub async fn metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+/// Record the latency of each routed request under its route pattern
+pub async fn track_requests(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Patterns rather than paths, so channel IDs do not become label values
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    metrics
+        .http_request_duration_seconds
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_requests_are_recorded_by_route() {
+        let metrics = Arc::new(Metrics::new());
+        let channels = Router::new().route("/:id/users", get(|| async { "[]" }));
+        let app = Router::new()
+            .nest("/channels", channels)
+            .nest("/metrics", Router::new().route("/", get(super::metrics)).with_state(metrics.clone()))
+            .route_layer(middleware::from_fn_with_state(metrics.clone(), track_requests));
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/channels/abc/users").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains(
+            "whisper_http_request_duration_seconds_count{method=\"GET\",route=\"/channels/:id/users\",status=\"200\"} 1"
+        ));
+    }
+}
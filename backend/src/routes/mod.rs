@@ -1,7 +1,13 @@
 pub mod auth;
+pub mod bearer;
 pub mod channels;
 pub mod user;
+pub mod password;
 pub mod db;
 pub mod email;
 pub mod oauth;
-pub mod twofa; 
\ No newline at end of file
+pub mod ldap;
+pub mod twofa;
+pub mod passkey;
+pub mod metrics;
+pub mod admin; 
\ No newline at end of file
@@ -1,112 +1,755 @@
-use axum::{extract::{Query, State}, response::Redirect, http::StatusCode, Json as JsonResponse};
-use oauth2::{AuthorizationCode, CsrfToken, Scope, TokenResponse, basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
+use axum::http::HeaderMap;
+use axum_extra::extract::cookie::{Cookie, Key, SameSite, SignedCookieJar};
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use oauth2::basic::{BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse, BasicTokenType};
 use oauth2::reqwest::async_http_client;
+use oauth2::{
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, ExtraTokenFields, PkceCodeChallenge, PkceCodeVerifier,
+    RedirectUrl, RequestTokenError, Scope, StandardRevocableToken, StandardTokenResponse, TokenResponse, TokenUrl,
+};
+use ring::digest::{digest, SHA512};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use uuid::Uuid;
-use std::env;
-use crate::routes::user::User;
-use jsonwebtoken::{encode, EncodingKey, Header};
-use chrono::{Utc, Duration};
+use crate::tokens::{hash_token, random_token};
 
+/// How long a user has to sign in at the provider and come back
+pub const LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Cookie with the hash of the state of the login a browser started, so a
+/// callback carrying someone else's state is refused
+pub const LOGIN_COOKIE: &str = "oauth_login";
+
+/// Well-known login cookie secret, only accepted for development
+pub const DEV_COOKIE_SECRET: &str = "your-cookie-secret";
+
+const WELL_KNOWN: &str = "/.well-known/openid-configuration";
+const GITHUB_AUTH_URL: &str = "https://github.com/login/oauth/authorize";
+const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+#[derive(Debug, thiserror::Error)]
+pub enum OAuthError {
+    #[error("Unknown OAuth provider {0}")]
+    UnknownProvider(String),
+    #[error("Unknown or expired OAuth login")]
+    UnknownLogin,
+    #[error("OAuth login was not started by this browser")]
+    OtherBrowser,
+    #[error("Provider refused the authorization code: {0}")]
+    Rejected(String),
+    #[error("Invalid ID token: {0}")]
+    IdToken(String),
+    #[error("Provider error: {0}")]
+    Provider(String),
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Who the provider says signed in
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    /// The provider's stable ID for the account, `sub` for OIDC
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    /// Suggested username, e.g. `preferred_username` or the GitHub login
+    pub username: Option<String>,
+}
+
+/// An OAuth 2.0 identity provider. OIDC providers such as Google or Keycloak
+/// are configured by their discovery document; GitHub, which has no OIDC
+/// login, has its own.
+pub struct Provider {
+    name: String,
+    client_id: String,
+    client_secret: String,
+    redirect_url: String,
+    scopes: Vec<String>,
+    kind: ProviderKind,
+}
+
+enum ProviderKind {
+    Oidc {
+        discovery_url: String,
+        /// Fetched on first use; keys again when a token names an unknown one
+        discovered: Mutex<Option<Arc<Discovered>>>,
+    },
+    Github,
+}
+
+struct Discovered {
+    metadata: ProviderMetadata,
+    jwks: JwkSet,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+    jwks_uri: String,
+}
+
+impl Provider {
+    /// `redirect_url` is where the provider sends the browser back to, with
+    /// the code and state for `/auth/oauth/<name>/finish`
+    pub fn oidc(name: &str, discovery_url: &str, client_id: &str, client_secret: &str, redirect_url: &str) -> Self {
+        Self::new(name, client_id, client_secret, redirect_url, ProviderKind::Oidc {
+            discovery_url: discovery_url.to_string(),
+            discovered: Mutex::new(None),
+        })
+    }
+
+    pub fn github(name: &str, client_id: &str, client_secret: &str, redirect_url: &str) -> Self {
+        Self::new(name, client_id, client_secret, redirect_url, ProviderKind::Github)
+    }
+
+    fn new(name: &str, client_id: &str, client_secret: &str, redirect_url: &str, kind: ProviderKind) -> Self {
+        let scopes = match kind {
+            ProviderKind::Oidc { .. } => vec!["openid", "email", "profile"],
+            ProviderKind::Github => vec!["read:user", "user:email"],
+        };
+        Self {
+            name: name.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            redirect_url: redirect_url.to_string(),
+            scopes: scopes.into_iter().map(str::to_string).collect(),
+            kind,
+        }
+    }
+
+    /// Request these scopes instead of the defaults; OIDC always gets `openid`
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes = scopes;
+        if matches!(self.kind, ProviderKind::Oidc { .. }) && !self.scopes.iter().any(|scope| scope == "openid") {
+            self.scopes.insert(0, "openid".to_string());
+        }
+        self
+    }
+
+    /// Authorization and token endpoints
+    async fn endpoints(&self, http: &reqwest::Client) -> Result<(String, String), OAuthError> {
+        match &self.kind {
+            ProviderKind::Oidc { .. } => {
+                let discovered = self.discover(http, false).await?;
+                Ok((discovered.metadata.authorization_endpoint.clone(), discovered.metadata.token_endpoint.clone()))
+            }
+            ProviderKind::Github => Ok((GITHUB_AUTH_URL.to_string(), GITHUB_TOKEN_URL.to_string())),
+        }
+    }
+
+    /// The discovery document and keys, cached unless `refresh`
+    async fn discover(&self, http: &reqwest::Client, refresh: bool) -> Result<Arc<Discovered>, OAuthError> {
+        let (discovery_url, cache) = match &self.kind {
+            ProviderKind::Oidc { discovery_url, discovered } => (discovery_url, discovered),
+            ProviderKind::Github => return Err(OAuthError::Provider(format!("{} is not an OIDC provider", self.name))),
+        };
+        if !refresh {
+            if let Some(discovered) = cache.lock().unwrap().clone() {
+                return Ok(discovered);
+            }
+        }
+        let metadata: ProviderMetadata = http.get(discovery_url).send().await?.error_for_status()?.json().await?;
+        // The document must be the issuer's own, or it could vouch for anyone
+        if let Some(base) = discovery_url.strip_suffix(WELL_KNOWN) {
+            if base.trim_end_matches('/') != metadata.issuer.trim_end_matches('/') {
+                return Err(OAuthError::Provider(format!(
+                    "discovery document of {} names issuer {}",
+                    discovery_url, metadata.issuer
+                )));
+            }
+        }
+        let jwks: JwkSet = http.get(&metadata.jwks_uri).send().await?.error_for_status()?.json().await?;
+        let discovered = Arc::new(Discovered { metadata, jwks });
+        *cache.lock().unwrap() = Some(discovered.clone());
+        Ok(discovered)
+    }
+
+    fn client(&self, auth_url: String, token_url: String) -> Result<Client, OAuthError> {
+        let invalid = |e: oauth2::url::ParseError| OAuthError::Provider(format!("invalid URL for {}: {}", self.name, e));
+        let secret = Some(self.client_secret.clone()).filter(|secret| !secret.is_empty()).map(ClientSecret::new);
+        Ok(Client::new(
+            ClientId::new(self.client_id.clone()),
+            secret,
+            AuthUrl::new(auth_url).map_err(invalid)?,
+            Some(TokenUrl::new(token_url).map_err(invalid)?),
+        )
+        .set_redirect_uri(RedirectUrl::new(self.redirect_url.clone()).map_err(invalid)?))
+    }
+
+    /// Check the ID token of an OIDC login against the provider's keys, our
+    /// client ID and the login's nonce, and read the account from it
+    async fn oidc_identity(&self, http: &reqwest::Client, tokens: &TokenReply, nonce: &str) -> Result<ExternalIdentity, OAuthError> {
+        let id_token = match &tokens.extra_fields().id_token {
+            Some(id_token) => id_token,
+            None => return Err(OAuthError::IdToken("the provider sent none".to_string())),
+        };
+        let header = jsonwebtoken::decode_header(id_token).map_err(|e| OAuthError::IdToken(e.to_string()))?;
+        // Only public key signatures; HS256 would be keyed with our client secret
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(OAuthError::IdToken(format!("{:?} signatures are not accepted", header.alg)));
+        }
+        let mut discovered = self.discover(http, false).await?;
+        let jwk = match find_key(&discovered.jwks, header.kid.as_deref()) {
+            Some(jwk) => jwk.clone(),
+            None => {
+                // The provider may have rotated its keys since we fetched them
+                discovered = self.discover(http, true).await?;
+                match find_key(&discovered.jwks, header.kid.as_deref()) {
+                    Some(jwk) => jwk.clone(),
+                    None => return Err(OAuthError::IdToken(format!("unknown key {:?}", header.kid))),
+                }
+            }
+        };
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| OAuthError::IdToken(e.to_string()))?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[&discovered.metadata.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<IdClaims>(id_token, &key, &validation)
+            .map_err(|e| OAuthError::IdToken(e.to_string()))?
+            .claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OAuthError::IdToken("nonce does not match the login".to_string()));
+        }
+
+        // Some providers leave the email address to the userinfo endpoint
+        let mut identity = claims.identity(&self.name);
+        if let (None, Some(userinfo_url)) = (&identity.email, &discovered.metadata.userinfo_endpoint) {
+            let userinfo: IdClaims = http
+                .get(userinfo_url)
+                .bearer_auth(tokens.access_token().secret())
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            if userinfo.sub == identity.subject {
+                identity.email = userinfo.email;
+                identity.email_verified = userinfo.email_verified.is_some_and(|verified| verified.is_true());
+            }
+        }
+        Ok(identity)
+    }
+
+    async fn github_identity(&self, http: &reqwest::Client, tokens: &TokenReply) -> Result<ExternalIdentity, OAuthError> {
+        let access_token = tokens.access_token().secret();
+        let get = |path: &str| {
+            http.get(format!("{}{}", GITHUB_API_URL, path))
+                .bearer_auth(access_token)
+                .header("Accept", "application/vnd.github+json")
+                .header("User-Agent", "VoiceLink")
+        };
+        let user: GithubUser = get("/user").send().await?.error_for_status()?.json().await?;
+        let emails: Vec<GithubEmail> = get("/user/emails").send().await?.error_for_status()?.json().await?;
+        let email = emails.into_iter().find(|email| email.primary);
+        Ok(ExternalIdentity {
+            provider: self.name.clone(),
+            subject: user.id.to_string(),
+            email_verified: email.as_ref().is_some_and(|email| email.verified),
+            email: email.map(|email| email.email),
+            username: Some(user.login),
+        })
+    }
+}
+
+/// The key `kid` names, or the only key when the token names none
+fn find_key<'a>(jwks: &'a JwkSet, kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IdTokenFields {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id_token: Option<String>,
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+type TokenReply = StandardTokenResponse<IdTokenFields, BasicTokenType>;
+type Client = oauth2::Client<
+    BasicErrorResponse,
+    TokenReply,
+    BasicTokenType,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+>;
+
+/// Claims of ID tokens and userinfo responses
 #[derive(Debug, Deserialize)]
-pub struct OAuthCallback {
-    code: String,
-    state: Option<String>,
+struct IdClaims {
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<Flag>,
+    preferred_username: Option<String>,
+    nonce: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct AuthResponse {
-    pub token: String,
-    pub user_id: Uuid,
-    pub roles: Vec<String>,
+/// A boolean claim, which some providers send as a string
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Flag {
+    Bool(bool),
+    String(String),
 }
 
-fn google_client() -> BasicClient {
-    BasicClient::new(
-        ClientId::new(env::var("GOOGLE_CLIENT_ID").unwrap()),
-        Some(ClientSecret::new(env::var("GOOGLE_CLIENT_SECRET").unwrap())),
-        AuthUrl::new("https://accounts.google.com/o/oauth2/v2/auth".to_string()).unwrap(),
-        Some(TokenUrl::new("https://oauth2.googleapis.com/token".to_string()).unwrap()),
-    )
-    .set_redirect_uri(RedirectUrl::new(env::var("FRONTEND_URL").unwrap() + "/oauth/google/callback").unwrap())
+impl Flag {
+    fn is_true(&self) -> bool {
+        match self {
+            Flag::Bool(value) => *value,
+            Flag::String(value) => value == "true",
+        }
+    }
+}
+
+impl IdClaims {
+    fn identity(self, provider: &str) -> ExternalIdentity {
+        ExternalIdentity {
+            provider: provider.to_string(),
+            subject: self.sub,
+            email_verified: self.email_verified.is_some_and(|verified| verified.is_true()),
+            email: self.email,
+            username: self.preferred_username,
+        }
+    }
 }
 
-fn github_client() -> BasicClient {
-    BasicClient::new(
-        ClientId::new(env::var("GITHUB_CLIENT_ID").unwrap()),
-        Some(ClientSecret::new(env::var("GITHUB_CLIENT_SECRET").unwrap())),
-        AuthUrl::new("https://github.com/login/oauth/authorize".to_string()).unwrap(),
-        Some(TokenUrl::new("https://github.com/login/oauth/access_token".to_string()).unwrap()),
+#[derive(Debug, Deserialize)]
+struct GithubUser {
+    id: u64,
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+/// The configured providers and logins through them. Logins in progress live
+/// in the database, so any instance can finish them.
+pub struct OAuthProviders {
+    providers: BTreeMap<String, Provider>,
+    http: reqwest::Client,
+    /// Signs login cookies; instances finishing each other's logins share it
+    cookie_key: Key,
+}
+
+impl Default for OAuthProviders {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl OAuthProviders {
+    pub fn new(providers: Vec<Provider>) -> Self {
+        // Userinfo and key requests must not be redirected elsewhere either
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_default();
+        Self {
+            providers: providers.into_iter().map(|provider| (provider.name.clone(), provider)).collect(),
+            http,
+            cookie_key: Key::generate(),
+        }
+    }
+
+    /// Sign login cookies with a key derived from `secret` instead of a
+    /// random one, so every instance with the same secret accepts them
+    pub fn with_cookie_secret(mut self, secret: &[u8]) -> Self {
+        let mut material = b"whisper-fleet oauth login cookie\0".to_vec();
+        material.extend_from_slice(secret);
+        self.cookie_key = Key::from(digest(&SHA512, &material).as_ref());
+        self
+    }
+
+    /// The login cookies among the request's, with their signatures checked
+    pub fn login_cookies(&self, headers: &HeaderMap) -> SignedCookieJar {
+        SignedCookieJar::from_headers(headers, self.cookie_key.clone())
+    }
+
+    /// Provider names, sorted
+    pub fn names(&self) -> Vec<String> {
+        self.providers.keys().cloned().collect()
+    }
+
+    fn provider(&self, name: &str) -> Result<&Provider, OAuthError> {
+        self.providers.get(name).ok_or_else(|| OAuthError::UnknownProvider(name.to_string()))
+    }
+
+    /// Where to send the browser to sign in at `provider`, and the cookie to
+    /// set in it. The state, PKCE verifier and nonce of the login are kept
+    /// until it comes back.
+    pub async fn start(&self, pool: &PgPool, provider: &str) -> Result<(String, SignedCookieJar), OAuthError> {
+        let provider = self.provider(provider)?;
+        let (auth_url, token_url) = provider.endpoints(&self.http).await?;
+        let client = provider.client(auth_url, token_url)?;
+        let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+        let state = random_token();
+        let nonce = random_token();
+        let mut request = client
+            .authorize_url(|| CsrfToken::new(state.clone()))
+            .add_scopes(provider.scopes.iter().cloned().map(Scope::new))
+            .set_pkce_challenge(challenge);
+        if matches!(provider.kind, ProviderKind::Oidc { .. }) {
+            request = request.add_extra_param("nonce", &nonce);
+        }
+        let (url, _) = request.url();
+
+        sqlx::query("DELETE FROM oauth_logins WHERE expires_at <= now()")
+            .execute(pool)
+            .await?;
+        let expires_at = chrono::Utc::now() + chrono::Duration::from_std(LOGIN_TTL).expect("login TTL fits");
+        sqlx::query("INSERT INTO oauth_logins (state, provider, pkce_verifier, nonce, expires_at) VALUES ($1, $2, $3, $4, $5)")
+            .bind(hash_token(&state))
+            .bind(&provider.name)
+            .bind(verifier.secret())
+            .bind(&nonce)
+            .bind(expires_at)
+            .execute(pool)
+            .await?;
+
+        // Scoped by default to the provider's path, which finishing shares
+        let cookie = Cookie::build((LOGIN_COOKIE, hash_token(&state)))
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .secure(provider.redirect_url.starts_with("https://"));
+        Ok((url.to_string(), SignedCookieJar::new(self.cookie_key.clone()).add(cookie)))
+    }
+
+    /// Finish a login with the code and state the provider sent back, and
+    /// return who signed in. Each state works once, and only in the browser
+    /// whose `cookies` carry it from `start`.
+    pub async fn finish(
+        &self,
+        pool: &PgPool,
+        provider: &str,
+        code: &str,
+        state: &str,
+        cookies: &SignedCookieJar,
+    ) -> Result<ExternalIdentity, OAuthError> {
+        let provider = self.provider(provider)?;
+        // Otherwise someone could have a victim finish the attacker's login
+        if cookies.get(LOGIN_COOKIE).map(|cookie| cookie.value().to_string()) != Some(hash_token(state)) {
+            return Err(OAuthError::OtherBrowser);
+        }
+        let row: Option<(String, String)> = sqlx::query_as(
+            "DELETE FROM oauth_logins WHERE state = $1 AND provider = $2 AND expires_at > now() RETURNING pkce_verifier, nonce"
+        )
+        .bind(hash_token(state))
+        .bind(&provider.name)
+        .fetch_optional(pool)
+        .await?;
+        let (verifier, nonce) = match row {
+            Some(row) => row,
+            None => return Err(OAuthError::UnknownLogin),
+        };
+
+        let (auth_url, token_url) = provider.endpoints(&self.http).await?;
+        let tokens = provider
+            .client(auth_url, token_url)?
+            .exchange_code(AuthorizationCode::new(code.to_string()))
+            .set_pkce_verifier(PkceCodeVerifier::new(verifier))
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| match e {
+                RequestTokenError::ServerResponse(e) => OAuthError::Rejected(e.to_string()),
+                e => OAuthError::Provider(e.to_string()),
+            })?;
+        match provider.kind {
+            ProviderKind::Oidc { .. } => provider.oidc_identity(&self.http, &tokens, &nonce).await,
+            ProviderKind::Github => provider.github_identity(&self.http, &tokens).await,
+        }
+    }
+}
+
+/// The user an external account is linked to, noting the sign-in
+pub async fn linked_user(pool: &PgPool, provider: &str, subject: &str) -> sqlx::Result<Option<Uuid>> {
+    sqlx::query_scalar("UPDATE oauth_identities SET last_used_at = now() WHERE provider = $1 AND subject = $2 RETURNING user_id")
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Let `identity` sign in as `user_id` from now on
+pub async fn link_identity(pool: &PgPool, identity: &ExternalIdentity, user_id: Uuid) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO oauth_identities (provider, subject, user_id, email, last_used_at) VALUES ($1, $2, $3, $4, now())"
     )
-    .set_redirect_uri(RedirectUrl::new(env::var("FRONTEND_URL").unwrap() + "/oauth/github/callback").unwrap())
-}
-
-pub async fn google_oauth_start() -> Redirect {
-    let (auth_url, _csrf) = google_client()
-        .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("openid email profile".to_string()))
-        .url();
-    Redirect::temporary(auth_url.to_string())
-}
-
-pub async fn google_oauth_callback(State(pool): State<PgPool>, Query(cb): Query<OAuthCallback>) -> Result<JsonResponse<AuthResponse>, (StatusCode, JsonResponse<String>)> {
-    let client = google_client();
-    let token = client.exchange_code(AuthorizationCode::new(cb.code)).request_async(async_http_client).await.map_err(|_| (StatusCode::UNAUTHORIZED, JsonResponse("OAuth token exchange failed".to_string())))?;
-    let access_token = token.access_token().secret();
-    let userinfo: serde_json::Value = reqwest::Client::new()
-        .get("https://openidconnect.googleapis.com/v1/userinfo")
-        .bearer_auth(access_token)
-        .send().await.map_err(|_| (StatusCode::UNAUTHORIZED, JsonResponse("Failed to fetch user info".to_string())))?
-        .json().await.map_err(|_| (StatusCode::UNAUTHORIZED, JsonResponse("Invalid user info".to_string())))?;
-    let email = userinfo["email"].as_str().unwrap();
-    let username = userinfo["name"].as_str().unwrap_or(email);
-    // Upsert user
-    let user = match User::get_by_email(&pool, email).await.unwrap() {
-        Some(u) => u,
-        None => User::create(&pool, username, email, "oauth", &vec!["user".to_string()]).await.unwrap(),
-    };
-    // Issue JWT
-    let now = Utc::now();
-    let exp = (now + Duration::hours(24)).timestamp() as usize;
-    let iat = now.timestamp() as usize;
-    let claims = crate::routes::auth::Claims { sub: user.id.to_string(), roles: user.roles.clone(), exp, iat };
-    let secret = env::var("JWT_SECRET").unwrap();
-    let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref())).unwrap();
-    Ok(JsonResponse(AuthResponse { token, user_id: user.id, roles: user.roles }))
-}
-
-pub async fn github_oauth_start() -> Redirect {
-    let (auth_url, _csrf) = github_client()
-        .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("read:user user:email".to_string()))
-        .url();
-    Redirect::temporary(auth_url.to_string())
-}
-
-pub async fn github_oauth_callback(State(pool): State<PgPool>, Query(cb): Query<OAuthCallback>) -> Result<JsonResponse<AuthResponse>, (StatusCode, JsonResponse<String>)> {
-    let client = github_client();
-    let token = client.exchange_code(AuthorizationCode::new(cb.code)).request_async(async_http_client).await.map_err(|_| (StatusCode::UNAUTHORIZED, JsonResponse("OAuth token exchange failed".to_string())))?;
-    let access_token = token.access_token().secret();
-    let userinfo: serde_json::Value = reqwest::Client::new()
-        .get("https://api.github.com/user")
-        .bearer_auth(access_token)
-        .header("User-Agent", "VoiceLink")
-        .send().await.map_err(|_| (StatusCode::UNAUTHORIZED, JsonResponse("Failed to fetch user info".to_string())))?
-        .json().await.map_err(|_| (StatusCode::UNAUTHORIZED, JsonResponse("Invalid user info".to_string())))?;
-    let email = userinfo["email"].as_str().unwrap_or("");
-    let username = userinfo["login"].as_str().unwrap_or(email);
-    // Upsert user
-    let user = match User::get_by_email(&pool, email).await.unwrap() {
-        Some(u) => u,
-        None => User::create(&pool, username, email, "oauth", &vec!["user".to_string()]).await.unwrap(),
+    .bind(&identity.provider)
+    .bind(&identity.subject)
+    .bind(user_id)
+    .bind(&identity.email)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Usernames to try for `preferred`, in order: made valid by keeping what
+/// precedes an `@`, dropping invalid characters and padding short names, then
+/// with `-2`, `-3`, ... added for when it's taken
+pub fn username_candidates(preferred: &str) -> impl Iterator<Item = String> {
+    let mut base: String = preferred
+        .split('@')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        .skip_while(|c| !c.is_ascii_alphanumeric())
+        .take(28)
+        .collect();
+    if base.len() < 3 {
+        base.insert_str(0, "user");
+    }
+    (1..).map(move |n| match n {
+        1 => base.clone(),
+        n => format!("{}-{}", base, n),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::routes::db::test_pool;
+    use axum::http::header::{COOKIE, SET_COOKIE};
+    use axum::response::IntoResponse;
+    use axum::{
+        extract::{Form, State},
+        routing::{get, post},
+        Json, Router,
     };
-    // Issue JWT
-    let now = Utc::now();
-    let exp = (now + Duration::hours(24)).timestamp() as usize;
-    let iat = now.timestamp() as usize;
-    let claims = crate::routes::auth::Claims { sub: user.id.to_string(), roles: user.roles.clone(), exp, iat };
-    let secret = env::var("JWT_SECRET").unwrap();
-    let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref())).unwrap();
-    Ok(JsonResponse(AuthResponse { token, user_id: user.id, roles: user.roles }))
-} 
\ No newline at end of file
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jsonwebtoken::jwk::{AlgorithmParameters, CommonParameters, EllipticCurve, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType};
+    use jsonwebtoken::{EncodingKey, Header};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::collections::HashMap;
+
+    /// An authorization the mock provider granted, waiting for its code
+    #[derive(Clone)]
+    struct Grant {
+        challenge: String,
+        nonce: String,
+        claims: serde_json::Value,
+    }
+
+    /// A local OIDC provider: discovery, keys and a token endpoint that
+    /// checks PKCE. Tests grant codes directly instead of via a login page.
+    #[derive(Clone)]
+    pub(crate) struct MockProvider {
+        pub issuer: String,
+        signing: Arc<EncodingKey>,
+        jwk: Jwk,
+        grants: Arc<Mutex<HashMap<String, Grant>>>,
+    }
+
+    impl MockProvider {
+        pub async fn start() -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            let jwk = Jwk {
+                common: CommonParameters {
+                    key_id: Some("mock".to_string()),
+                    key_algorithm: Some(KeyAlgorithm::EdDSA),
+                    ..CommonParameters::default()
+                },
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+                }),
+            };
+            let mock = Self {
+                issuer,
+                signing: Arc::new(EncodingKey::from_ed_der(pkcs8.as_ref())),
+                jwk,
+                grants: Arc::new(Mutex::new(HashMap::new())),
+            };
+            let app = Router::new()
+                .route(WELL_KNOWN, get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(mock.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            mock
+        }
+
+        pub fn discovery_url(&self) -> String {
+            format!("{}{}", self.issuer, WELL_KNOWN)
+        }
+
+        /// Sign in as `claims` at the authorization URL, returning the code
+        /// and state to finish with
+        pub fn authorize(&self, authorization_url: &str, claims: serde_json::Value) -> (String, String) {
+            let url = oauth2::url::Url::parse(authorization_url).unwrap();
+            let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+            assert_eq!(params["code_challenge_method"], "S256");
+            let code = random_token();
+            self.grants.lock().unwrap().insert(code.clone(), Grant {
+                challenge: params["code_challenge"].clone(),
+                nonce: params["nonce"].clone(),
+                claims,
+            });
+            (code, params["state"].clone())
+        }
+
+        pub fn id_token(&self, claims: &serde_json::Value) -> String {
+            let mut header = Header::new(Algorithm::EdDSA);
+            header.kid = Some("mock".to_string());
+            jsonwebtoken::encode(&header, claims, &self.signing).unwrap()
+        }
+    }
+
+    async fn discovery(State(mock): State<MockProvider>) -> Json<serde_json::Value> {
+        Json(serde_json::json!({
+            "issuer": mock.issuer,
+            "authorization_endpoint": format!("{}/authorize", mock.issuer),
+            "token_endpoint": format!("{}/token", mock.issuer),
+            "jwks_uri": format!("{}/jwks", mock.issuer),
+        }))
+    }
+
+    async fn jwks(State(mock): State<MockProvider>) -> Json<JwkSet> {
+        Json(JwkSet { keys: vec![mock.jwk.clone()] })
+    }
+
+    async fn token(
+        State(mock): State<MockProvider>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<serde_json::Value>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+        let refused = || (axum::http::StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": "invalid_grant" })));
+        let grant = mock.grants.lock().unwrap().remove(&form["code"]).ok_or_else(refused)?;
+        let verifier = form.get("code_verifier").ok_or_else(refused)?;
+        let challenge = URL_SAFE_NO_PAD.encode(ring::digest::digest(&ring::digest::SHA256, verifier.as_bytes()));
+        if challenge != grant.challenge {
+            return Err(refused());
+        }
+        let mut claims = serde_json::json!({
+            "iss": mock.issuer,
+            "aud": "voicelink",
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "iat": chrono::Utc::now().timestamp(),
+            "nonce": grant.nonce,
+        });
+        if let (Some(claims), Some(extra)) = (claims.as_object_mut(), grant.claims.as_object()) {
+            claims.extend(extra.clone());
+        }
+        Ok(Json(serde_json::json!({
+            "access_token": random_token(),
+            "token_type": "Bearer",
+            "expires_in": 300,
+            "id_token": mock.id_token(&claims),
+        })))
+    }
+
+    pub(crate) fn mock_providers(mock: &MockProvider) -> OAuthProviders {
+        OAuthProviders::new(vec![Provider::oidc(
+            "mock",
+            &mock.discovery_url(),
+            "voicelink",
+            "secret",
+            "http://localhost:5173/oauth/mock/callback",
+        )])
+    }
+
+    #[test]
+    fn test_username_candidates() {
+        let names: Vec<String> = username_candidates("_Ace Pilot!").take(2).collect();
+        assert_eq!(names, vec!["AcePilot", "AcePilot-2"]);
+        assert_eq!(username_candidates("é").next().unwrap(), "user");
+        assert_eq!(username_candidates("ace@example.com").next().unwrap(), "ace");
+        assert_eq!(username_candidates(&"a".repeat(40)).nth(9).unwrap(), format!("{}-10", "a".repeat(28)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_oidc_login_checks_state_pkce_and_nonce() {
+        let pool = test_pool().await;
+        let mock = MockProvider::start().await;
+        let providers = mock_providers(&mock);
+        assert_eq!(providers.names(), vec!["mock"]);
+        assert!(matches!(providers.start(&pool, "other").await, Err(OAuthError::UnknownProvider(_))));
+
+        let (url, cookies) = providers.start(&pool, "mock").await.unwrap();
+        assert!(url.starts_with(&format!("{}/authorize?", mock.issuer)));
+        assert!(url.contains("scope=openid+email+profile"));
+        let (code, state) = mock.authorize(&url, serde_json::json!({ "sub": "u1", "email": "ace@example.com", "email_verified": "true" }));
+
+        // The state belongs to this provider and works once
+        assert!(matches!(providers.finish(&pool, "mock", &code, "forged", &cookies).await, Err(OAuthError::OtherBrowser)));
+        let identity = providers.finish(&pool, "mock", &code, &state, &cookies).await.unwrap();
+        assert_eq!(identity.subject, "u1");
+        assert_eq!(identity.email.as_deref(), Some("ace@example.com"));
+        assert!(identity.email_verified);
+        assert!(matches!(providers.finish(&pool, "mock", &code, &state, &cookies).await, Err(OAuthError::UnknownLogin)));
+
+        // A code granted for another login's PKCE challenge is refused
+        let (first, _) = providers.start(&pool, "mock").await.unwrap();
+        let (second, cookies) = providers.start(&pool, "mock").await.unwrap();
+        let (code, _) = mock.authorize(&first, serde_json::json!({ "sub": "u1" }));
+        let (_, state) = mock.authorize(&second, serde_json::json!({ "sub": "u1" }));
+        assert!(matches!(providers.finish(&pool, "mock", &code, &state, &cookies).await, Err(OAuthError::Rejected(_))));
+
+        // So is an ID token meant for another login, or another client
+        let (url, cookies) = providers.start(&pool, "mock").await.unwrap();
+        let (code, state) = mock.authorize(&url, serde_json::json!({ "sub": "u1", "nonce": "replayed" }));
+        assert!(matches!(providers.finish(&pool, "mock", &code, &state, &cookies).await, Err(OAuthError::IdToken(_))));
+        let (url, cookies) = providers.start(&pool, "mock").await.unwrap();
+        let (code, state) = mock.authorize(&url, serde_json::json!({ "sub": "u1", "aud": "someone-else" }));
+        assert!(matches!(providers.finish(&pool, "mock", &code, &state, &cookies).await, Err(OAuthError::IdToken(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_login_finishes_only_in_the_browser_that_started_it() {
+        let pool = test_pool().await;
+        let mock = MockProvider::start().await;
+        let providers = mock_providers(&mock);
+
+        // The attacker starts a login and keeps its code and state ...
+        let (url, attacker_cookies) = providers.start(&pool, "mock").await.unwrap();
+        let (code, state) = mock.authorize(&url, serde_json::json!({ "sub": "attacker" }));
+
+        // ... but a victim's browser without the cookie, or with the cookie of
+        // its own login, can't finish it
+        let no_cookies = providers.login_cookies(&HeaderMap::new());
+        assert!(matches!(providers.finish(&pool, "mock", &code, &state, &no_cookies).await, Err(OAuthError::OtherBrowser)));
+        let (_, victim_cookies) = providers.start(&pool, "mock").await.unwrap();
+        assert!(matches!(providers.finish(&pool, "mock", &code, &state, &victim_cookies).await, Err(OAuthError::OtherBrowser)));
+
+        // Cookies signed with another key don't count either
+        let mut headers = HeaderMap::new();
+        for set_cookie in (attacker_cookies.clone(), ()).into_response().headers().get_all(SET_COOKIE) {
+            let pair = set_cookie.to_str().unwrap().split(';').next().unwrap().to_string();
+            headers.append(COOKIE, pair.parse().unwrap());
+        }
+        let other = mock_providers(&mock).with_cookie_secret(b"another instance");
+        let resent = other.login_cookies(&headers);
+        assert!(matches!(other.finish(&pool, "mock", &code, &state, &resent).await, Err(OAuthError::OtherBrowser)));
+        assert!(providers.login_cookies(&headers).get(LOGIN_COOKIE).is_some());
+
+        // The refused attempts left the login in place for its own browser
+        let identity = providers.finish(&pool, "mock", &code, &state, &attacker_cookies).await.unwrap();
+        assert_eq!(identity.subject, "attacker");
+    }
+}
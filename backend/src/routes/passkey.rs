@@ -0,0 +1,204 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, Passkey, PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+    RegisterPublicKeyCredential, RequestChallengeResponse, Url, Webauthn, WebauthnBuilder, WebauthnError,
+};
+use crate::routes::user::User;
+
+/// How long the authenticator has to answer a registration or sign-in
+pub const CEREMONY_TTL: Duration = Duration::from_secs(5 * 60);
+
+const REGISTRATION: &str = "registration";
+const AUTHENTICATION: &str = "authentication";
+
+#[derive(Debug, thiserror::Error)]
+pub enum PasskeyError {
+    #[error("WebAuthn error: {0}")]
+    Webauthn(#[from] WebauthnError),
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Invalid ceremony state: {0}")]
+    State(#[from] serde_json::Error),
+    #[error("Unknown or expired passkey ceremony")]
+    UnknownCeremony,
+    #[error("Unknown passkey")]
+    UnknownPasskey,
+}
+
+/// A registered passkey or security key
+#[derive(Debug, Clone, FromRow)]
+pub struct PasskeyCredential {
+    /// Credential ID, base64url
+    pub id: String,
+    pub name: String,
+    pub passkey: Json<Passkey>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl PasskeyCredential {
+    pub async fn list(pool: &PgPool, user_id: Uuid) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as::<_, PasskeyCredential>("SELECT * FROM webauthn_credentials WHERE user_id = $1 ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+    }
+    /// Remove one of the user's passkeys; false if they have none by that ID
+    pub async fn delete(pool: &PgPool, user_id: Uuid, id: &str) -> sqlx::Result<bool> {
+        let result = sqlx::query("DELETE FROM webauthn_credentials WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+/// WebAuthn registration and sign-in ceremonies for passkeys, which are kept
+/// in `webauthn_credentials`. Ceremonies in progress live in the database so
+/// any instance can finish them.
+pub struct Passkeys {
+    webauthn: Webauthn,
+}
+
+impl Passkeys {
+    /// `rp_id` is the domain passkeys are bound to, and must be that of
+    /// `rp_origin`, the frontend's origin
+    pub fn new(rp_id: &str, rp_origin: &str, rp_name: &str) -> Result<Self, PasskeyError> {
+        let origin = Url::parse(rp_origin).map_err(|_| WebauthnError::Configuration)?;
+        let webauthn = WebauthnBuilder::new(rp_id, &origin)?.rp_name(rp_name).build()?;
+        Ok(Self { webauthn })
+    }
+
+    /// Relying party `localhost` for the frontend's dev server
+    #[cfg(test)]
+    pub fn development() -> Self {
+        Self::new("localhost", "http://localhost:5173", "VoiceLink").expect("localhost is a valid relying party")
+    }
+
+    /// Options for the browser to create a passkey for `user`, and the ID of
+    /// the ceremony to finish with the result
+    pub async fn start_registration(&self, pool: &PgPool, user: &User) -> Result<(Uuid, CreationChallengeResponse), PasskeyError> {
+        let existing = PasskeyCredential::list(pool, user.id).await?;
+        let exclude = existing.iter().map(|credential| credential.passkey.cred_id().clone()).collect();
+        let (options, state) = self.webauthn.start_passkey_registration(user.id, &user.username, &user.username, Some(exclude))?;
+        let ceremony_id = save_ceremony(pool, user.id, REGISTRATION, &state).await?;
+        Ok((ceremony_id, options))
+    }
+
+    /// Check the new credential and store it as `name`
+    pub async fn finish_registration(
+        &self,
+        pool: &PgPool,
+        user: &User,
+        ceremony_id: Uuid,
+        name: &str,
+        credential: &RegisterPublicKeyCredential,
+    ) -> Result<PasskeyCredential, PasskeyError> {
+        let (user_id, state) = take_ceremony::<PasskeyRegistration>(pool, ceremony_id, REGISTRATION).await?;
+        if user_id != user.id {
+            return Err(PasskeyError::UnknownCeremony);
+        }
+        let passkey = self.webauthn.finish_passkey_registration(credential, &state)?;
+        let credential = sqlx::query_as::<_, PasskeyCredential>(
+            "INSERT INTO webauthn_credentials (id, user_id, name, passkey) VALUES ($1, $2, $3, $4) RETURNING *"
+        )
+        .bind(URL_SAFE_NO_PAD.encode(passkey.cred_id()))
+        .bind(user.id)
+        .bind(name)
+        .bind(Json(&passkey))
+        .fetch_one(pool)
+        .await?;
+        Ok(credential)
+    }
+
+    /// Options for the browser to sign in with one of the user's passkeys;
+    /// `None` if they have none
+    pub async fn start_authentication(
+        &self,
+        pool: &PgPool,
+        user_id: Uuid,
+    ) -> Result<Option<(Uuid, RequestChallengeResponse)>, PasskeyError> {
+        let passkeys: Vec<Passkey> = PasskeyCredential::list(pool, user_id)
+            .await?
+            .into_iter()
+            .map(|credential| credential.passkey.0)
+            .collect();
+        if passkeys.is_empty() {
+            return Ok(None);
+        }
+        let (options, state) = self.webauthn.start_passkey_authentication(&passkeys)?;
+        let ceremony_id = save_ceremony(pool, user_id, AUTHENTICATION, &state).await?;
+        Ok(Some((ceremony_id, options)))
+    }
+
+    /// Check a signed challenge and return the ID of the user it signs in.
+    /// The stored signature counter follows the authenticator's, so cloned
+    /// keys are noticed.
+    pub async fn finish_authentication(
+        &self,
+        pool: &PgPool,
+        ceremony_id: Uuid,
+        credential: &PublicKeyCredential,
+    ) -> Result<Uuid, PasskeyError> {
+        let (user_id, state) = take_ceremony::<PasskeyAuthentication>(pool, ceremony_id, AUTHENTICATION).await?;
+        let result = self.webauthn.finish_passkey_authentication(credential, &state)?;
+        let id = URL_SAFE_NO_PAD.encode(result.cred_id());
+        let stored = sqlx::query_as::<_, PasskeyCredential>("SELECT * FROM webauthn_credentials WHERE id = $1 AND user_id = $2")
+            .bind(&id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+        let mut passkey = match stored {
+            Some(stored) => stored.passkey.0,
+            None => return Err(PasskeyError::UnknownPasskey),
+        };
+        passkey.update_credential(&result);
+        sqlx::query("UPDATE webauthn_credentials SET passkey = $1, last_used_at = now() WHERE id = $2")
+            .bind(Json(&passkey))
+            .bind(&id)
+            .execute(pool)
+            .await?;
+        Ok(user_id)
+    }
+}
+
+async fn save_ceremony<T: Serialize>(pool: &PgPool, user_id: Uuid, kind: &str, state: &T) -> Result<Uuid, PasskeyError> {
+    sqlx::query("DELETE FROM webauthn_ceremonies WHERE expires_at <= now()")
+        .execute(pool)
+        .await?;
+    let state = serde_json::to_value(state)?;
+    let id = Uuid::new_v4();
+    let expires_at = Utc::now() + chrono::Duration::from_std(CEREMONY_TTL).expect("ceremony TTL fits");
+    sqlx::query("INSERT INTO webauthn_ceremonies (id, user_id, kind, state, expires_at) VALUES ($1, $2, $3, $4, $5)")
+        .bind(id)
+        .bind(user_id)
+        .bind(kind)
+        .bind(state)
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+    Ok(id)
+}
+
+/// Remove an unexpired ceremony and return its user and state; atomic, so
+/// each ceremony is finished once
+async fn take_ceremony<T: DeserializeOwned>(pool: &PgPool, id: Uuid, kind: &str) -> Result<(Uuid, T), PasskeyError> {
+    let row: Option<(Uuid, serde_json::Value)> = sqlx::query_as(
+        "DELETE FROM webauthn_ceremonies WHERE id = $1 AND kind = $2 AND expires_at > now() RETURNING user_id, state"
+    )
+    .bind(id)
+    .bind(kind)
+    .fetch_optional(pool)
+    .await?;
+    match row {
+        Some((user_id, state)) => Ok((user_id, serde_json::from_value(state)?)),
+        None => Err(PasskeyError::UnknownCeremony),
+    }
+}
@@ -1,15 +1,162 @@
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use argon2::password_hash::{SaltString, rand_core::OsRng};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Minimum password length unless configured otherwise
+pub const DEFAULT_MIN_PASSWORD_LENGTH: usize = 10;
+/// Argon2 copes with more, but nobody types it
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Password hash of accounts without a password, e.g. those created by an
+/// identity provider sign-in; no password matches it
+pub const NO_PASSWORD: &str = "!";
+
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum PasswordError {
+    #[error("Password must be at least {0} characters long")]
+    TooShort(usize),
+    #[error("Password must be at most {MAX_PASSWORD_LENGTH} characters long")]
+    TooLong,
+    #[error("Password must not contain the username")]
+    ContainsUsername,
+    #[error("Password appears in known data breaches, choose another")]
+    Breached,
+}
+
+/// Rules for new passwords, including an offline check against passwords
+/// known from breaches
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    breached: HashSet<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MIN_PASSWORD_LENGTH)
+    }
+}
+
+impl PasswordPolicy {
+    /// Policy with the built-in list of common passwords
+    pub fn new(min_length: usize) -> Self {
+        let mut policy = Self {
+            min_length,
+            breached: HashSet::new(),
+        };
+        policy.add_breached(COMMON_PASSWORDS);
+        policy
+    }
+
+    /// Also refuse the passwords in `path`, one per line
+    pub fn with_breached_file(mut self, path: &Path) -> std::io::Result<Self> {
+        self.add_breached(&std::fs::read_to_string(path)?);
+        Ok(self)
+    }
+
+    fn add_breached(&mut self, list: &str) {
+        let passwords = list.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'));
+        self.breached.extend(passwords.map(str::to_lowercase));
+    }
+
+    pub fn check(&self, password: &str, username: &str) -> Result<(), PasswordError> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(PasswordError::TooShort(self.min_length));
+        }
+        if length > MAX_PASSWORD_LENGTH {
+            return Err(PasswordError::TooLong);
+        }
+        let lowercase = password.to_lowercase();
+        if lowercase.contains(&username.to_lowercase()) {
+            return Err(PasswordError::ContainsUsername);
+        }
+        if self.breached.contains(&lowercase) {
+            return Err(PasswordError::Breached);
+        }
+        Ok(())
+    }
+}
+
+// Argon2 takes tens of milliseconds of CPU, so it runs on the blocking pool
 
 pub async fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash(&password))
+        .await
+        .expect("Password hashing panicked")
+}
+
+pub async fn verify_password(hash: &str, password: &str) -> Result<bool, argon2::password_hash::Error> {
+    let (hash, password) = (hash.to_string(), password.to_string());
+    tokio::task::spawn_blocking(move || verify(&hash, &password))
+        .await
+        .expect("Password verification panicked")
+}
+
+/// Check a login attempt. Unknown users (`None`) and accounts without a
+/// password are checked against a dummy hash, so they take as long to refuse
+/// as a wrong password.
+pub async fn verify_login(hash: Option<&str>, password: &str) -> bool {
+    match hash {
+        Some(hash) if hash != NO_PASSWORD => verify_password(hash, password).await.unwrap_or(false),
+        _ => {
+            let _ = verify_password(dummy_hash(), password).await;
+            false
+        }
+    }
+}
+
+fn hash(password: &str) -> Result<String, argon2::password_hash::Error> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
     let hash = argon2.hash_password(password.as_bytes(), &salt)?.to_string();
     Ok(hash)
 }
 
-pub async fn verify_password(hash: &str, password: &str) -> Result<bool, argon2::password_hash::Error> {
+fn verify(hash: &str, password: &str) -> Result<bool, argon2::password_hash::Error> {
     let parsed_hash = PasswordHash::new(hash)?;
     let argon2 = Argon2::default();
     Ok(argon2.verify_password(password.as_bytes(), &parsed_hash).is_ok())
-} 
\ No newline at end of file
+}
+
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash("not a password").expect("Failed to hash dummy password"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_verify_login() {
+        let hash = hash_password("correct horse").await.unwrap();
+        assert!(verify_login(Some(&hash), "correct horse").await);
+        assert!(!verify_login(Some(&hash), "battery staple").await);
+        assert!(!verify_login(None, "not a password").await);
+        // Malformed stored hashes fail closed
+        assert!(!verify_login(Some("oauth"), "oauth").await);
+        assert!(!verify_login(Some(NO_PASSWORD), NO_PASSWORD).await);
+    }
+
+    #[test]
+    fn test_password_policy() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(policy.check("short", "ace"), Err(PasswordError::TooShort(DEFAULT_MIN_PASSWORD_LENGTH)));
+        assert_eq!(policy.check(&"x".repeat(129), "ace"), Err(PasswordError::TooLong));
+        assert_eq!(policy.check("Maverick-Flies-7", "maverick"), Err(PasswordError::ContainsUsername));
+        assert_eq!(policy.check("QWERTYUIOP123", "ace"), Err(PasswordError::Breached));
+        assert_eq!(policy.check("tin foil kestrel", "ace"), Ok(()));
+
+        let path = std::env::temp_dir().join(format!("whisper-fleet-breached-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "tin foil kestrel\n").unwrap();
+        let policy = policy.with_breached_file(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(policy.check("Tin Foil Kestrel", "ace"), Err(PasswordError::Breached));
+    }
+}
@@ -1,34 +1,249 @@
-use oath::totp_raw_now;
-use rand::RngCore;
 use base32::{Alphabet, encode as base32_encode};
-use std::env;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::hmac;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use crate::tokens::hash_token;
+
+/// Key for TOTP secrets when nothing else is configured; only fit for development
+pub const DEV_TWOFA_KEY: &str = "your-2fa-key";
+
+/// Seconds per TOTP code
+pub const TOTP_STEP: i64 = 30;
+/// Codes one step early or late are accepted, for clock skew
+pub const TOTP_SKEW: i64 = 1;
+/// Wrong codes allowed per login challenge before the password is needed again
+pub const MAX_ATTEMPTS: u32 = 5;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+const BASE32: Alphabet = Alphabet::RFC4648 { padding: false };
+
+#[derive(Debug, thiserror::Error)]
+pub enum TwoFactorError {
+    #[error("Failed to encrypt TOTP secret")]
+    Encrypt,
+    #[error("Failed to decrypt TOTP secret")]
+    Decrypt,
+}
+
+/// A new, not yet confirmed TOTP secret
+#[derive(Debug, Clone)]
+pub struct Enrollment {
+    /// Base32, for typing into an authenticator app
+    pub secret: String,
+    pub otpauth_url: String,
+    pub qr_svg: String,
+}
+
+/// TOTP second factor: enrollment, code checks and encryption of the stored
+/// secrets. Also counts wrong codes per login challenge.
+pub struct TwoFactor {
+    key: LessSafeKey,
+    issuer: String,
+    /// Wrong codes and expiry (unix time) by challenge token ID
+    attempts: Mutex<HashMap<String, (u32, i64)>>,
+}
 
-pub fn generate_totp_secret() -> (String, String) {
+impl TwoFactor {
+    /// Secrets are encrypted with AES-256-GCM under a key derived from `secret`
+    pub fn new(secret: &str, issuer: &str) -> Self {
+        let key_bytes = digest(&SHA256, secret.as_bytes());
+        let key = UnboundKey::new(&AES_256_GCM, key_bytes.as_ref()).expect("SHA-256 output is an AES-256 key");
+        Self {
+            key: LessSafeKey::new(key),
+            issuer: issuer.to_string(),
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `DEV_TWOFA_KEY` and the default issuer
+    #[cfg(test)]
+    pub fn development() -> Self {
+        Self::new(DEV_TWOFA_KEY, "VoiceLink")
+    }
+
+    pub fn enroll(&self, username: &str) -> Enrollment {
+        let secret = generate_totp_secret();
+        let otpauth_url = format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits=6&period={}",
+            url_encode(&self.issuer),
+            url_encode(username),
+            secret,
+            url_encode(&self.issuer),
+            TOTP_STEP
+        );
+        let qr_svg = get_qr_svg(&otpauth_url);
+        Enrollment { secret, otpauth_url, qr_svg }
+    }
+
+    /// Encrypt a secret for `users.twofa_secret`, bound to the user so it
+    /// can't be moved to another account
+    pub fn encrypt_secret(&self, user_id: &str, secret: &str) -> Result<String, TwoFactorError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut sealed = secret.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(user_id.as_bytes()), &mut sealed)
+            .map_err(|_| TwoFactorError::Encrypt)?;
+        Ok(URL_SAFE_NO_PAD.encode([&nonce[..], &sealed].concat()))
+    }
+
+    pub fn decrypt_secret(&self, user_id: &str, encrypted: &str) -> Result<String, TwoFactorError> {
+        let data = URL_SAFE_NO_PAD.decode(encrypted).map_err(|_| TwoFactorError::Decrypt)?;
+        if data.len() < NONCE_LEN {
+            return Err(TwoFactorError::Decrypt);
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| TwoFactorError::Decrypt)?;
+        let mut sealed = sealed.to_vec();
+        let secret = self
+            .key
+            .open_in_place(nonce, Aad::from(user_id.as_bytes()), &mut sealed)
+            .map_err(|_| TwoFactorError::Decrypt)?;
+        String::from_utf8(secret.to_vec()).map_err(|_| TwoFactorError::Decrypt)
+    }
+
+    /// Count a wrong code against a login challenge; false once it has used
+    /// up its attempts
+    pub fn record_failure(&self, challenge_id: &str, expires_at: i64) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.retain(|_, (_, expires_at)| *expires_at >= now);
+        let (count, _) = attempts.entry(challenge_id.to_string()).or_insert((0, expires_at));
+        *count += 1;
+        *count < MAX_ATTEMPTS
+    }
+
+    /// Use up a login challenge once it has earned a session
+    pub fn complete(&self, challenge_id: &str, expires_at: i64) {
+        self.attempts.lock().unwrap().insert(challenge_id.to_string(), (MAX_ATTEMPTS, expires_at));
+    }
+
+    /// Whether a login challenge has used up its attempts
+    pub fn is_exhausted(&self, challenge_id: &str) -> bool {
+        matches!(self.attempts.lock().unwrap().get(challenge_id), Some((count, _)) if *count >= MAX_ATTEMPTS)
+    }
+}
+
+/// 160 random bits, base32 encoded as authenticator apps expect
+pub fn generate_totp_secret() -> String {
     let mut secret_bytes = [0u8; 20];
     rand::thread_rng().fill_bytes(&mut secret_bytes);
-    let secret = base32_encode(Alphabet::RFC4648 { padding: false }, &secret_bytes);
-    let username = "user"; // Replace with real username in handler
-    let qr_svg = get_qr_svg(&secret, username);
-    (secret, qr_svg)
-}
-
-pub fn verify_totp(secret: &str, code: &str) -> bool {
-    if code.len() != 6 { return false; }
-    let secret_bytes = match base32::decode(Alphabet::RFC4648 { padding: false }, secret) {
-        Some(bytes) => bytes,
-        None => return false,
-    };
-    let now = totp_raw_now(&secret_bytes, 6, 0, 30, &oath::HashType::SHA1);
-    let code_int = code.parse::<u32>().unwrap_or(0);
-    now == code_int
-}
-
-pub fn get_qr_svg(secret: &str, username: &str) -> String {
-    let issuer = env::var("2FA_ISSUER").unwrap_or_else(|_| "VoiceLink".to_string());
-    let url = format!(
-        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits=6&period=30",
-        issuer, username, secret, issuer
-    );
-    let code = qrcode::QrCode::new(url.as_bytes()).unwrap();
+    base32_encode(BASE32, &secret_bytes)
+}
+
+/// Check a 6-digit code against the steps around `now` (unix time). Returns
+/// the matching step when it is later than `last_step`, the step of the
+/// previous accepted code, so every code works once.
+pub fn verify_totp(secret: &str, code: &str, now: i64, last_step: Option<i64>) -> Option<i64> {
+    if code.len() != 6 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secret_bytes = base32::decode(BASE32, secret)?;
+    let current = now / TOTP_STEP;
+    (current - TOTP_SKEW..=current + TOTP_SKEW)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| totp_code(&secret_bytes, *step) == code)
+}
+
+/// RFC 6238 code with HMAC-SHA1 and 6 digits
+pub(crate) fn totp_code(secret: &[u8], step: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let mac = hmac::sign(&key, &(step as u64).to_be_bytes());
+    let mac = mac.as_ref();
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([mac[offset], mac[offset + 1], mac[offset + 2], mac[offset + 3]]) & 0x7fff_ffff;
+    format!("{:06}", value % 1_000_000)
+}
+
+pub fn get_qr_svg(otpauth_url: &str) -> String {
+    let code = qrcode::QrCode::new(otpauth_url.as_bytes()).expect("otpauth URLs fit in a QR code");
     code.render::<qrcode::render::svg::Color>().min_dimensions(200, 200).build()
-} 
\ No newline at end of file
+}
+
+/// One-time codes for when the authenticator is lost, e.g. `k3xq-7mfa-p2rd`
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 8];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let code = base32_encode(BASE32, &bytes).to_lowercase();
+            format!("{}-{}-{}", &code[0..4], &code[4..8], &code[8..12])
+        })
+        .collect()
+}
+
+/// Stored form of a recovery code; case, spaces and dashes don't matter
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totp_matches_rfc_6238() {
+        // RFC 6238 appendix B, SHA-1 secret "12345678901234567890", last 6 digits
+        let secret = base32_encode(BASE32, b"12345678901234567890");
+        assert_eq!(totp_code(b"12345678901234567890", 59 / TOTP_STEP), "287082");
+        assert_eq!(verify_totp(&secret, "287082", 59, None), Some(1));
+        assert_eq!(verify_totp(&secret, "081804", 1111111109, None), Some(1111111109 / TOTP_STEP));
+
+        // One step of skew either way, each code once
+        let step = 1111111109 / TOTP_STEP;
+        assert_eq!(verify_totp(&secret, "081804", 1111111109 + TOTP_STEP, None), Some(step));
+        assert_eq!(verify_totp(&secret, "081804", 1111111109 + 2 * TOTP_STEP, None), None);
+        assert_eq!(verify_totp(&secret, "081804", 1111111109, Some(step)), None);
+        assert_eq!(verify_totp(&secret, "08180", 1111111109, None), None);
+    }
+
+    #[test]
+    fn test_secrets_are_encrypted_per_user() {
+        let twofa = TwoFactor::development();
+        let enrollment = twofa.enroll("maverick");
+        assert!(enrollment.otpauth_url.starts_with("otpauth://totp/VoiceLink:maverick?secret="));
+        assert!(enrollment.qr_svg.contains("<svg"));
+
+        let encrypted = twofa.encrypt_secret("user-1", &enrollment.secret).unwrap();
+        assert!(!encrypted.contains(&enrollment.secret));
+        assert_eq!(twofa.decrypt_secret("user-1", &encrypted).unwrap(), enrollment.secret);
+        assert!(twofa.decrypt_secret("user-2", &encrypted).is_err());
+        assert!(TwoFactor::new("other key", "VoiceLink").decrypt_secret("user-1", &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_recovery_codes_and_attempts() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[0].to_uppercase().replace('-', " ")));
+        assert_ne!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[1]));
+
+        let twofa = TwoFactor::development();
+        let expires_at = chrono::Utc::now().timestamp() + 60;
+        for _ in 1..MAX_ATTEMPTS {
+            assert!(twofa.record_failure("challenge", expires_at));
+        }
+        assert!(!twofa.is_exhausted("challenge"));
+        assert!(!twofa.record_failure("challenge", expires_at));
+        assert!(twofa.is_exhausted("challenge"));
+    }
+}
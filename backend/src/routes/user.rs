@@ -1,7 +1,17 @@
+use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
 use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+use std::sync::Mutex;
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
+use tracing::warn;
+use crate::routes::password::NO_PASSWORD;
+use crate::tokens::SessionUsers;
+
+/// Added to the token roles of accounts whose email address is not verified
+/// yet; they may sign in but not join voice
+pub const UNVERIFIED_ROLE: &str = "unverified";
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct User {
@@ -10,9 +20,21 @@ pub struct User {
     pub email: String,
     pub password_hash: String,
     pub roles: Vec<String>,
+    /// Encrypted TOTP secret; pending until `twofa_enabled`
     pub twofa_secret: Option<String>,
+    pub twofa_enabled: bool,
+    pub twofa_last_step: Option<i64>,
+    /// Hashes of the unused recovery codes
+    pub twofa_recovery_codes: Vec<String>,
     pub reset_token: Option<String>,
     pub reset_token_expiry: Option<DateTime<Utc>>,
+    pub email_verified: bool,
+    /// Hash of the pending email verification token
+    pub verification_token: Option<String>,
+    pub verification_token_expiry: Option<DateTime<Utc>>,
+    /// Directory entry of an account that signs in with LDAP; its password,
+    /// email address and roles come from the directory
+    pub directory_dn: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -25,17 +47,18 @@ impl User {
             .await
     }
     pub async fn get_by_username(pool: &PgPool, username: &str) -> sqlx::Result<Option<Self>> {
-        sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE lower(username) = lower($1)")
             .bind(username)
             .fetch_optional(pool)
             .await
     }
     pub async fn get_by_email(pool: &PgPool, email: &str) -> sqlx::Result<Option<Self>> {
-        sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE lower(email) = lower($1)")
             .bind(email)
             .fetch_optional(pool)
             .await
     }
+    #[cfg(test)]
     pub async fn create(pool: &PgPool, username: &str, email: &str, password_hash: &str, roles: &[String]) -> sqlx::Result<Self> {
         let rec = sqlx::query_as::<_, User>(
             "INSERT INTO users (id, username, email, password_hash, roles, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, now(), now()) RETURNING *"
@@ -49,36 +72,387 @@ impl User {
         .await?;
         Ok(rec)
     }
-    pub async fn update_password(&self, pool: &PgPool, new_hash: &str) -> sqlx::Result<()> {
-        sqlx::query("UPDATE users SET password_hash = $1, updated_at = now() WHERE id = $2")
-            .bind(new_hash)
+    /// Create a self-registered `user` account, which starts out unverified
+    pub async fn register(pool: &PgPool, username: &str, email: &str, password_hash: &str) -> sqlx::Result<Self> {
+        sqlx::query_as::<_, User>(
+            "INSERT INTO users (id, username, email, password_hash, roles, email_verified, created_at, updated_at) VALUES ($1, $2, $3, $4, ARRAY['user'], false, now(), now()) RETURNING *"
+        )
+        .bind(Uuid::new_v4())
+        .bind(username)
+        .bind(email)
+        .bind(password_hash)
+        .fetch_one(pool)
+        .await
+    }
+    /// Create a `user` account for someone signing in with an identity
+    /// provider. It has no password until one is set with a reset link.
+    pub async fn create_external(pool: &PgPool, username: &str, email: &str, email_verified: bool) -> sqlx::Result<Self> {
+        sqlx::query_as::<_, User>(
+            "INSERT INTO users (id, username, email, password_hash, roles, email_verified, created_at, updated_at) VALUES ($1, $2, $3, $4, ARRAY['user'], $5, now(), now()) RETURNING *"
+        )
+        .bind(Uuid::new_v4())
+        .bind(username)
+        .bind(email)
+        .bind(NO_PASSWORD)
+        .bind(email_verified)
+        .fetch_one(pool)
+        .await
+    }
+    /// Create the account of a directory user on their first sign-in
+    pub async fn create_directory(pool: &PgPool, username: &str, email: &str, dn: &str, roles: &[String]) -> sqlx::Result<Self> {
+        sqlx::query_as::<_, User>(
+            "INSERT INTO users (id, username, email, password_hash, roles, email_verified, directory_dn, created_at, updated_at) \
+             VALUES ($1, $2, $3, $4, $5, true, $6, now(), now()) RETURNING *"
+        )
+        .bind(Uuid::new_v4())
+        .bind(username)
+        .bind(email)
+        .bind(NO_PASSWORD)
+        .bind(roles)
+        .bind(dn)
+        .fetch_one(pool)
+        .await
+    }
+    /// Take over what the directory says about this user at sign-in
+    pub async fn update_directory(&self, pool: &PgPool, email: &str, dn: &str, roles: &[String]) -> sqlx::Result<Self> {
+        sqlx::query_as::<_, User>(
+            "UPDATE users SET email = $1, email_verified = true, directory_dn = $2, roles = $3, updated_at = now() WHERE id = $4 RETURNING *"
+        )
+        .bind(email)
+        .bind(dn)
+        .bind(roles)
+        .bind(self.id)
+        .fetch_one(pool)
+        .await
+    }
+    /// Roles to put in this user's tokens
+    pub fn token_roles(&self) -> Vec<String> {
+        let mut roles = self.roles.clone();
+        if !self.email_verified {
+            roles.push(UNVERIFIED_ROLE.to_string());
+        }
+        roles
+    }
+    pub async fn set_verification_token(&self, pool: &PgPool, token_hash: &str, expiry: DateTime<Utc>) -> sqlx::Result<()> {
+        sqlx::query("UPDATE users SET verification_token = $1, verification_token_expiry = $2, updated_at = now() WHERE id = $3")
+            .bind(token_hash)
+            .bind(expiry)
             .bind(self.id)
             .execute(pool)
             .await?;
         Ok(())
     }
+    /// Mark the email address with a pending, unexpired verification token as
+    /// verified; the token works once
+    pub async fn verify_email(pool: &PgPool, token_hash: &str) -> sqlx::Result<Option<Self>> {
+        sqlx::query_as::<_, User>(
+            "UPDATE users SET email_verified = true, verification_token = NULL, verification_token_expiry = NULL, updated_at = now() \
+             WHERE verification_token = $1 AND verification_token_expiry > now() RETURNING *"
+        )
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await
+    }
+    /// Store a new encrypted TOTP secret, pending until `enable_2fa`
     pub async fn set_2fa_secret(&self, pool: &PgPool, secret: &str) -> sqlx::Result<()> {
-        sqlx::query("UPDATE users SET twofa_secret = $1, updated_at = now() WHERE id = $2")
+        sqlx::query("UPDATE users SET twofa_secret = $1, twofa_enabled = false, twofa_last_step = NULL, updated_at = now() WHERE id = $2")
             .bind(secret)
             .bind(self.id)
             .execute(pool)
             .await?;
         Ok(())
     }
-    pub async fn set_reset_token(&self, pool: &PgPool, token: &str, expiry: DateTime<Utc>) -> sqlx::Result<()> {
-        sqlx::query("UPDATE users SET reset_token = $1, reset_token_expiry = $2, updated_at = now() WHERE id = $3")
-            .bind(token)
-            .bind(expiry)
+    /// Require the pending secret at login, `step` being that of the confirming code
+    pub async fn enable_2fa(&self, pool: &PgPool, step: i64, recovery_codes: &[String]) -> sqlx::Result<()> {
+        sqlx::query("UPDATE users SET twofa_enabled = true, twofa_last_step = $1, twofa_recovery_codes = $2, updated_at = now() WHERE id = $3")
+            .bind(step)
+            .bind(recovery_codes)
             .bind(self.id)
             .execute(pool)
             .await?;
         Ok(())
     }
-    pub async fn clear_reset_token(&self, pool: &PgPool) -> sqlx::Result<()> {
-        sqlx::query("UPDATE users SET reset_token = NULL, reset_token_expiry = NULL, updated_at = now() WHERE id = $1")
+    pub async fn disable_2fa(&self, pool: &PgPool) -> sqlx::Result<()> {
+        sqlx::query(
+            "UPDATE users SET twofa_secret = NULL, twofa_enabled = false, twofa_last_step = NULL, twofa_recovery_codes = '{}', updated_at = now() WHERE id = $1"
+        )
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+    /// Accept the code of TOTP step `step` unless it or a later one was used
+    /// already; atomic, so concurrent logins can't replay a code
+    pub async fn use_totp_step(&self, pool: &PgPool, step: i64) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            "UPDATE users SET twofa_last_step = $1 WHERE id = $2 AND (twofa_last_step IS NULL OR twofa_last_step < $1)"
+        )
+        .bind(step)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+    /// Spend a recovery code by its hash; false if it isn't one of the unused codes
+    pub async fn use_recovery_code(&self, pool: &PgPool, code_hash: &str) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            "UPDATE users SET twofa_recovery_codes = array_remove(twofa_recovery_codes, $1), updated_at = now() \
+             WHERE id = $2 AND $1 = ANY(twofa_recovery_codes)"
+        )
+        .bind(code_hash)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+    pub async fn set_recovery_codes(&self, pool: &PgPool, recovery_codes: &[String]) -> sqlx::Result<()> {
+        sqlx::query("UPDATE users SET twofa_recovery_codes = $1, updated_at = now() WHERE id = $2")
+            .bind(recovery_codes)
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+    /// Store the hash of a password reset token, replacing any earlier one
+    pub async fn set_reset_token(&self, pool: &PgPool, token_hash: &str, expiry: DateTime<Utc>) -> sqlx::Result<()> {
+        sqlx::query("UPDATE users SET reset_token = $1, reset_token_expiry = $2, updated_at = now() WHERE id = $3")
+            .bind(token_hash)
+            .bind(expiry)
             .bind(self.id)
             .execute(pool)
             .await?;
         Ok(())
     }
-} 
\ No newline at end of file
+    /// The user with this unexpired password reset token
+    pub async fn get_by_reset_token(pool: &PgPool, token_hash: &str) -> sqlx::Result<Option<Self>> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE reset_token = $1 AND reset_token_expiry > now()")
+            .bind(token_hash)
+            .fetch_optional(pool)
+            .await
+    }
+    /// Set a new password with the reset token, which then stops working; false
+    /// if it was used or replaced meanwhile. The mailed link also proves the
+    /// email address.
+    pub async fn reset_password(&self, pool: &PgPool, token_hash: &str, new_hash: &str) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            "UPDATE users SET password_hash = $1, reset_token = NULL, reset_token_expiry = NULL, email_verified = true, \
+             verification_token = NULL, verification_token_expiry = NULL, updated_at = now() \
+             WHERE id = $2 AND reset_token = $3 AND reset_token_expiry > now()"
+        )
+        .bind(new_hash)
+        .bind(self.id)
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
+
+#[async_trait]
+impl SessionUsers for PgPool {
+    async fn current_identity(&self, user_id: &str) -> Result<Option<(String, Vec<String>)>, String> {
+        let id = match Uuid::parse_str(user_id) {
+            Ok(id) => id,
+            Err(_) => return Ok(None),
+        };
+        let user = User::get_by_id(self, id).await.map_err(|e| e.to_string())?;
+        Ok(user.map(|user| (user.username.clone(), user.token_roles())))
+    }
+}
+
+/// Check a requested username: 3 to 32 letters, digits, `_`, `-` or `.`,
+/// starting with a letter or digit
+pub fn validate_username(username: &str) -> Result<(), &'static str> {
+    if !(3..=32).contains(&username.len()) {
+        return Err("Username must be 3 to 32 characters long");
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        return Err("Username may only contain letters, digits, '_', '-' and '.'");
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err("Username must start with a letter or digit");
+    }
+    Ok(())
+}
+
+/// Whether `e` is a unique constraint violation, e.g. a taken username
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(db) if db.code().as_deref() == Some("23505"))
+}
+
+/// Usernames by user ID, read from the users table and cached.
+///
+/// IDs without an account, e.g. every ID when there is no database, stand
+/// for themselves.
+#[derive(Default)]
+pub struct UserDirectory {
+    pool: Option<PgPool>,
+    names: Mutex<HashMap<String, String>>,
+}
+
+impl UserDirectory {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool: Some(pool),
+            names: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn username(&self, user_id: &str) -> String {
+        self.usernames(&[user_id.to_string()]).await.remove(user_id).unwrap_or_else(|| user_id.to_string())
+    }
+
+    /// Usernames of `user_ids`, with one query for those not cached yet
+    pub async fn usernames(&self, user_ids: &[String]) -> HashMap<String, String> {
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let names = self.names.lock().unwrap();
+            for user_id in user_ids {
+                match names.get(user_id) {
+                    Some(name) => {
+                        found.insert(user_id.clone(), name.clone());
+                    }
+                    None => {
+                        if let Ok(id) = Uuid::parse_str(user_id) {
+                            missing.push(id);
+                        }
+                    }
+                }
+            }
+        }
+
+        if let (Some(pool), false) = (&self.pool, missing.is_empty()) {
+            let rows = sqlx::query_as::<_, (Uuid, String)>("SELECT id, username FROM users WHERE id = ANY($1)")
+                .bind(&missing)
+                .fetch_all(pool)
+                .await;
+            match rows {
+                Ok(rows) => {
+                    let mut names = self.names.lock().unwrap();
+                    for (id, username) in rows {
+                        names.insert(id.to_string(), username.clone());
+                        found.insert(id.to_string(), username);
+                    }
+                }
+                Err(e) => warn!("Failed to look up usernames: {}", e),
+            }
+        }
+
+        for user_id in user_ids {
+            found.entry(user_id.clone()).or_insert_with(|| user_id.clone());
+        }
+        found
+    }
+
+    /// Verified email address of the user called `username`, if any
+    pub async fn verified_email(&self, username: &str) -> Option<String> {
+        let pool = self.pool.as_ref()?;
+        let row = sqlx::query_scalar::<_, String>(
+            "SELECT email FROM users WHERE lower(username) = lower($1) AND email_verified"
+        )
+        .bind(username)
+        .fetch_optional(pool)
+        .await;
+        match row {
+            Ok(email) => email,
+            Err(e) => {
+                warn!("Failed to look up the email of {}: {}", username, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::db::test_pool;
+
+    #[test]
+    fn test_validate_username() {
+        for name in ["ace", "Red.Leader-5", "wing_2"] {
+            assert!(validate_username(name).is_ok(), "{}", name);
+        }
+        for name in ["ab", "_ace", "ace pilot", "pilöt", &"a".repeat(33)] {
+            assert!(validate_username(name).is_err(), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_directory_resolves_usernames() {
+        let pool = test_pool().await;
+
+        let name = format!("dir-{}", Uuid::new_v4());
+        let user = User::create(&pool, &name, &format!("{}@example.com", name), "hash", &["user".to_string()])
+            .await
+            .unwrap();
+        let directory = UserDirectory::new(pool);
+        let names = directory.usernames(&[user.id.to_string(), "legacy".to_string()]).await;
+        assert_eq!(names[&user.id.to_string()], name);
+        assert_eq!(names["legacy"], "legacy");
+
+        // Served from the cache afterwards
+        assert!(directory.names.lock().unwrap().contains_key(&user.id.to_string()));
+        assert_eq!(UserDirectory::default().username(&user.id.to_string()).await, user.id.to_string());
+
+        // Only verified addresses are handed out
+        let pool = directory.pool.clone().unwrap();
+        for verified in [false, true] {
+            sqlx::query("UPDATE users SET email_verified = $1 WHERE id = $2")
+                .bind(verified)
+                .bind(user.id)
+                .execute(&pool)
+                .await
+                .unwrap();
+            let expected = verified.then(|| format!("{}@example.com", name));
+            assert_eq!(directory.verified_email(&name.to_uppercase()).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_emails_are_unique_regardless_of_case() {
+        let pool = test_pool().await;
+
+        let name = format!("mail-{}", Uuid::new_v4());
+        let email = format!("{}@Example.com", name);
+        User::create(&pool, &name, &email, "hash", &["user".to_string()]).await.unwrap();
+        let duplicate = User::create(&pool, &format!("{}-2", name), &email.to_lowercase(), "hash", &["user".to_string()]).await;
+        assert!(is_unique_violation(&duplicate.unwrap_err()));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_case_variant_accounts_stop_the_migration() {
+        use sqlx::Executor;
+
+        // Replay the migrations in a scratch schema, with a case-variant pair
+        // inserted before the unique indexes are created
+        let pool = test_pool().await;
+        let schema = format!("migration_{}", Uuid::new_v4().simple());
+        let mut conn = pool.acquire().await.unwrap();
+        conn.execute(format!("CREATE SCHEMA {0}; SET search_path TO {0}", schema).as_str()).await.unwrap();
+
+        let mut error = None;
+        for migration in sqlx::migrate!().iter() {
+            if migration.version == 20250105 {
+                conn.execute(
+                    "INSERT INTO users (id, username, email, password_hash) VALUES
+                     (gen_random_uuid(), 'ace', 'Ace@example.com', 'hash'),
+                     (gen_random_uuid(), 'ace-2', 'ace@example.com', 'hash')",
+                )
+                .await
+                .unwrap();
+                error = conn.execute(&*migration.sql).await.err();
+                break;
+            }
+            conn.execute(&*migration.sql).await.unwrap();
+        }
+        conn.execute(format!("SET search_path TO public; DROP SCHEMA {} CASCADE", schema).as_str()).await.unwrap();
+
+        let error = error.expect("migration accepted case-variant emails").to_string();
+        assert!(error.contains("email Ace@example.com, ace@example.com"), "{}", error);
+    }
+}
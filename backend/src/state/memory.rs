@@ -0,0 +1,231 @@
+use super::{Presence, RefreshToken, StateBackend, StateChange, StateError, StateMessage};
+use crate::events::ChannelEvent;
+use crate::routes::channels::Channel;
+use crate::tokens::Revocation;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Shared state held in process memory.
+///
+/// Instances sharing one `InProcessBackend` (e.g. in tests) see each other's
+/// changes exactly like instances sharing a database.
+pub struct InProcessBackend {
+    channels: Mutex<HashMap<String, Channel>>,
+    presence: Mutex<HashMap<String, Presence>>, // user_id -> presence
+    refresh_tokens: Mutex<HashMap<String, RefreshToken>>, // token_hash -> token
+    revocations: Mutex<HashMap<String, Revocation>>, // token or session ID -> revocation
+    tx: broadcast::Sender<StateMessage>,
+}
+
+impl InProcessBackend {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(1024);
+        Self {
+            channels: Mutex::new(HashMap::new()),
+            presence: Mutex::new(HashMap::new()),
+            refresh_tokens: Mutex::new(HashMap::new()),
+            revocations: Mutex::new(HashMap::new()),
+            tx,
+        }
+    }
+
+    fn notify(&self, origin: &str, change: StateChange) {
+        let _ = self.tx.send(StateMessage {
+            origin: origin.to_string(),
+            change,
+        });
+    }
+}
+
+impl Default for InProcessBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl StateBackend for InProcessBackend {
+    async fn load_channels(&self) -> Result<Vec<Channel>, StateError> {
+        Ok(self.channels.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn save_channel(&self, origin: &str, channel: &Channel) -> Result<(), StateError> {
+        self.channels.lock().unwrap().insert(channel.id.clone(), channel.clone());
+        self.notify(origin, StateChange::ChannelUpdated { channel: channel.clone() });
+        Ok(())
+    }
+
+    async fn remove_channel(&self, origin: &str, channel_id: &str) -> Result<(), StateError> {
+        if self.channels.lock().unwrap().remove(channel_id).is_some() {
+            self.notify(origin, StateChange::ChannelRemoved { channel_id: channel_id.to_string() });
+        }
+        Ok(())
+    }
+
+    async fn set_presence(&self, presence: &Presence) -> Result<(), StateError> {
+        let previous = self.presence.lock().unwrap().insert(presence.user_id.clone(), presence.clone());
+        // Moving between channels leaves the old one
+        if let Some(previous) = previous.filter(|previous| previous.channel_id != presence.channel_id) {
+            self.notify(&presence.instance_id, StateChange::PresenceRemoved {
+                user_id: previous.user_id,
+                channel_id: previous.channel_id,
+            });
+        }
+        self.notify(&presence.instance_id, StateChange::PresenceUpdated { presence: presence.clone() });
+        Ok(())
+    }
+
+    async fn clear_presence(&self, instance_id: &str, user_id: &str) -> Result<(), StateError> {
+        let removed = {
+            let mut presence = self.presence.lock().unwrap();
+            match presence.get(user_id) {
+                Some(current) if current.instance_id == instance_id => presence.remove(user_id),
+                _ => None,
+            }
+        };
+        if let Some(removed) = removed {
+            self.notify(instance_id, StateChange::PresenceRemoved {
+                user_id: removed.user_id,
+                channel_id: removed.channel_id,
+            });
+        }
+        Ok(())
+    }
+
+    async fn channel_presence(&self, channel_id: &str) -> Result<Vec<Presence>, StateError> {
+        Ok(self
+            .presence
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|presence| presence.channel_id == channel_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn publish_event(&self, origin: &str, event: &ChannelEvent) -> Result<(), StateError> {
+        self.notify(origin, StateChange::Event { event: event.clone() });
+        Ok(())
+    }
+
+    async fn save_refresh_token(&self, token: &RefreshToken) -> Result<(), StateError> {
+        let now = chrono::Utc::now().timestamp();
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+        refresh_tokens.retain(|_, token| token.expires_at >= now);
+        refresh_tokens.insert(token.token_hash.clone(), token.clone());
+        Ok(())
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, StateError> {
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+        Ok(refresh_tokens.get_mut(token_hash).map(|token| {
+            let stored = token.clone();
+            token.used = true;
+            stored
+        }))
+    }
+
+    async fn revoke(&self, origin: &str, revocation: &Revocation) -> Result<(), StateError> {
+        {
+            let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+            match revocation {
+                Revocation::Session { sid, .. } => refresh_tokens.retain(|_, token| token.session_id != *sid),
+                Revocation::User { user_id, .. } => refresh_tokens.retain(|_, token| token.user_id != *user_id),
+                Revocation::Token { .. } => {}
+            }
+        }
+        let now = chrono::Utc::now().timestamp();
+        {
+            let mut revocations = self.revocations.lock().unwrap();
+            revocations.retain(|_, revocation| revocation.expires_at() >= now);
+            revocations.insert(revocation.id().to_string(), revocation.clone());
+        }
+        self.notify(origin, StateChange::TokenRevoked { revocation: revocation.clone() });
+        Ok(())
+    }
+
+    async fn load_revocations(&self) -> Result<Vec<Revocation>, StateError> {
+        let now = chrono::Utc::now().timestamp();
+        Ok(self
+            .revocations
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|revocation| revocation.expires_at() >= now)
+            .cloned()
+            .collect())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<StateMessage> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SharedState;
+    use std::sync::Arc;
+
+    fn channel(id: &str) -> Channel {
+        Channel {
+            id: id.to_string(),
+            name: "Ops".to_string(),
+            privacy: crate::routes::channels::ChannelPrivacy::Public,
+            owner: "alice".to_string(),
+            moderators: vec!["alice".to_string()],
+            members: vec!["alice".to_string()],
+            banned_users: Vec::new(),
+            invite_tokens: HashMap::new(),
+            min_bitrate_bps: None,
+            max_bitrate_bps: None,
+            max_participants: None,
+            max_speakers: None,
+            waitlist_enabled: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_changes_reach_other_instances_only() {
+        let backend: Arc<dyn StateBackend> = Arc::new(InProcessBackend::new());
+        let eu = SharedState::new(backend.clone());
+        let na = SharedState::new(backend);
+        let mut eu_changes = eu.subscribe_remote();
+        let mut na_changes = na.subscribe_remote();
+
+        eu.save_channel(&channel("chan1")).await.unwrap();
+        na.publish_event(&ChannelEvent::RecordingStopped {
+            channel_id: "chan1".to_string(),
+            recording_id: "rec1".to_string(),
+        })
+        .await
+        .unwrap();
+
+        // Each instance only sees the other's change
+        assert!(matches!(na_changes.recv().await, Some(StateChange::ChannelUpdated { channel }) if channel.id == "chan1"));
+        assert!(matches!(eu_changes.recv().await, Some(StateChange::Event { .. })));
+        assert_eq!(na.load_channels().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_presence_follows_user() {
+        let backend: Arc<dyn StateBackend> = Arc::new(InProcessBackend::new());
+        let eu = SharedState::new(backend.clone());
+        let na = SharedState::new(backend);
+
+        eu.set_presence("bob", "bob", "chan1", false).await.unwrap();
+        assert_eq!(na.remote_presence("chan1").await.unwrap().len(), 1);
+        assert!(eu.remote_presence("chan1").await.unwrap().is_empty());
+
+        // Bob reconnects through NA; EU's late disconnect must not remove him
+        na.set_presence("bob", "bob", "chan2", true).await.unwrap();
+        eu.clear_presence("bob").await.unwrap();
+        assert!(eu.remote_presence("chan1").await.unwrap().is_empty());
+        assert!(eu.remote_presence("chan2").await.unwrap()[0].is_muted);
+
+        na.clear_presence("bob").await.unwrap();
+        assert!(eu.remote_presence("chan2").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+//! Control-plane state shared between backend instances.
+//!
+//! Every instance keeps its own in-memory view of channels and presence and
+//! writes changes through a [`StateBackend`], which stores them and notifies
+//! the other instances. A single instance uses [`InProcessBackend`]; a fleet
+//! behind a load balancer shares a [`PostgresBackend`].
+//!
+//! Refresh tokens and token revocations live here too, so a session can be
+//! refreshed or revoked through any instance.
+
+mod memory;
+mod postgres;
+
+pub use memory::InProcessBackend;
+pub use postgres::PostgresBackend;
+
+use crate::events::ChannelEvent;
+use crate::routes::channels::Channel;
+use crate::tokens::Revocation;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::warn;
+use uuid::Uuid;
+
+/// State backend errors
+#[derive(Debug, thiserror::Error)]
+pub enum StateError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("Notification of {0} bytes exceeds the Postgres payload limit")]
+    NotificationTooLarge(usize),
+}
+
+/// A user connected to a channel over WebSocket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct Presence {
+    pub user_id: String,
+    pub username: String,
+    pub channel_id: String,
+    pub is_muted: bool,
+    /// Instance holding the user's WebSocket
+    pub instance_id: String,
+}
+
+/// A refresh token as stored; only its hash is kept
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct RefreshToken {
+    pub token_hash: String,
+    /// Login session the token belongs to
+    pub session_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub roles: Vec<String>,
+    /// Unix time
+    pub expires_at: i64,
+    /// Already traded in for a new token
+    pub used: bool,
+}
+
+/// A change to shared state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum StateChange {
+    ChannelUpdated { channel: Channel },
+    ChannelRemoved { channel_id: String },
+    PresenceUpdated { presence: Presence },
+    PresenceRemoved { user_id: String, channel_id: String },
+    Event { event: ChannelEvent },
+    TokenRevoked { revocation: Revocation },
+}
+
+/// A change and the instance that made it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateMessage {
+    pub origin: String,
+    pub change: StateChange,
+}
+
+/// Storage and pub/sub for shared state
+#[async_trait]
+pub trait StateBackend: Send + Sync + 'static {
+    /// Every stored channel
+    async fn load_channels(&self) -> Result<Vec<Channel>, StateError>;
+
+    /// Store a channel and notify other instances
+    async fn save_channel(&self, origin: &str, channel: &Channel) -> Result<(), StateError>;
+
+    async fn remove_channel(&self, origin: &str, channel_id: &str) -> Result<(), StateError>;
+
+    /// Record that a user is connected to a channel, replacing their previous presence
+    async fn set_presence(&self, presence: &Presence) -> Result<(), StateError>;
+
+    /// Forget a user's presence if it is still held by `instance_id`
+    async fn clear_presence(&self, instance_id: &str, user_id: &str) -> Result<(), StateError>;
+
+    async fn channel_presence(&self, channel_id: &str) -> Result<Vec<Presence>, StateError>;
+
+    /// Notify other instances of a channel event
+    async fn publish_event(&self, origin: &str, event: &ChannelEvent) -> Result<(), StateError>;
+
+    /// Store a refresh token, forgetting expired ones
+    async fn save_refresh_token(&self, token: &RefreshToken) -> Result<(), StateError>;
+
+    /// Mark a refresh token used and return it as it was, `None` if unknown
+    async fn use_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, StateError>;
+
+    /// Store a revocation, drop the refresh tokens of a revoked session or
+    /// user and notify other instances
+    async fn revoke(&self, origin: &str, revocation: &Revocation) -> Result<(), StateError>;
+
+    /// Revocations that have not expired
+    async fn load_revocations(&self) -> Result<Vec<Revocation>, StateError>;
+
+    /// Every change, including those made by this instance
+    fn subscribe(&self) -> broadcast::Receiver<StateMessage>;
+}
+
+/// Handle to the shared state backend, tagged with this instance's ID
+#[derive(Clone)]
+pub struct SharedState {
+    instance_id: String,
+    backend: Arc<dyn StateBackend>,
+}
+
+impl SharedState {
+    pub fn new(backend: Arc<dyn StateBackend>) -> Self {
+        Self {
+            instance_id: Uuid::new_v4().to_string(),
+            backend,
+        }
+    }
+
+    /// State private to this process
+    pub fn in_process() -> Self {
+        Self::new(Arc::new(InProcessBackend::new()))
+    }
+
+    pub async fn load_channels(&self) -> Result<Vec<Channel>, StateError> {
+        self.backend.load_channels().await
+    }
+
+    pub async fn save_channel(&self, channel: &Channel) -> Result<(), StateError> {
+        self.backend.save_channel(&self.instance_id, channel).await
+    }
+
+    #[allow(dead_code)] // no route deletes channels yet; backends still replicate removals
+    pub async fn remove_channel(&self, channel_id: &str) -> Result<(), StateError> {
+        self.backend.remove_channel(&self.instance_id, channel_id).await
+    }
+
+    pub async fn set_presence(&self, user_id: &str, username: &str, channel_id: &str, is_muted: bool) -> Result<(), StateError> {
+        self.backend
+            .set_presence(&Presence {
+                user_id: user_id.to_string(),
+                username: username.to_string(),
+                channel_id: channel_id.to_string(),
+                is_muted,
+                instance_id: self.instance_id.clone(),
+            })
+            .await
+    }
+
+    pub async fn clear_presence(&self, user_id: &str) -> Result<(), StateError> {
+        self.backend.clear_presence(&self.instance_id, user_id).await
+    }
+
+    /// Users of a channel connected to other instances
+    pub async fn remote_presence(&self, channel_id: &str) -> Result<Vec<Presence>, StateError> {
+        let presence = self.backend.channel_presence(channel_id).await?;
+        Ok(presence
+            .into_iter()
+            .filter(|presence| presence.instance_id != self.instance_id)
+            .collect())
+    }
+
+    pub async fn publish_event(&self, event: &ChannelEvent) -> Result<(), StateError> {
+        self.backend.publish_event(&self.instance_id, event).await
+    }
+
+    pub async fn save_refresh_token(&self, token: &RefreshToken) -> Result<(), StateError> {
+        self.backend.save_refresh_token(token).await
+    }
+
+    pub async fn use_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, StateError> {
+        self.backend.use_refresh_token(token_hash).await
+    }
+
+    pub async fn revoke(&self, revocation: &Revocation) -> Result<(), StateError> {
+        self.backend.revoke(&self.instance_id, revocation).await
+    }
+
+    pub async fn load_revocations(&self) -> Result<Vec<Revocation>, StateError> {
+        self.backend.load_revocations().await
+    }
+
+    /// Changes made by other instances
+    pub fn subscribe_remote(&self) -> RemoteChanges {
+        RemoteChanges {
+            instance_id: self.instance_id.clone(),
+            rx: self.backend.subscribe(),
+        }
+    }
+}
+
+/// Stream of changes made by other instances
+pub struct RemoteChanges {
+    instance_id: String,
+    rx: broadcast::Receiver<StateMessage>,
+}
+
+impl RemoteChanges {
+    /// Next remote change, `None` once the backend is gone
+    pub async fn recv(&mut self) -> Option<StateChange> {
+        loop {
+            match self.rx.recv().await {
+                Ok(message) if message.origin == self.instance_id => continue,
+                Ok(message) => return Some(message.change),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Skipped {} shared state changes", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,555 @@
+use super::{Presence, RefreshToken, StateBackend, StateChange, StateError, StateMessage};
+use crate::events::ChannelEvent;
+use crate::routes::channels::Channel;
+use crate::tokens::Revocation;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgListener, PgNotification};
+use sqlx::types::Json;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+/// Postgres channel carrying state notifications
+const NOTIFY_CHANNEL: &str = "whisper_fleet_state";
+
+/// Postgres rejects NOTIFY payloads of 8000 bytes or more
+const MAX_PAYLOAD_BYTES: usize = 7999;
+
+/// Payload of a state notification.
+///
+/// Channels can outgrow the payload limit, so channel changes only carry the
+/// ID and listeners re-read the row.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+enum Notification {
+    Channel { origin: String, channel_id: String },
+    Message { message: Box<StateMessage> },
+}
+
+/// Shared state stored in Postgres and propagated with LISTEN/NOTIFY
+pub struct PostgresBackend {
+    pool: PgPool,
+    tx: broadcast::Sender<StateMessage>,
+}
+
+impl PostgresBackend {
+    /// Start listening for notifications; the shared state tables must already be migrated
+    pub async fn connect(pool: PgPool) -> Result<Self, StateError> {
+        let mut listener = PgListener::connect_with(&pool).await?;
+        listener.listen(NOTIFY_CHANNEL).await?;
+
+        let (tx, _) = broadcast::channel(1024);
+        tokio::spawn(listen(listener, pool.clone(), tx.clone()));
+
+        Ok(Self { pool, tx })
+    }
+
+    async fn notify(
+        tx: &mut Transaction<'_, Postgres>,
+        notification: &Notification,
+    ) -> Result<(), StateError> {
+        let payload = serde_json::to_string(notification)?;
+        if payload.len() > MAX_PAYLOAD_BYTES {
+            return Err(StateError::NotificationTooLarge(payload.len()));
+        }
+
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(NOTIFY_CHANNEL)
+            .bind(payload)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    async fn notify_message(
+        tx: &mut Transaction<'_, Postgres>,
+        origin: &str,
+        change: StateChange,
+    ) -> Result<(), StateError> {
+        let message = Box::new(StateMessage {
+            origin: origin.to_string(),
+            change,
+        });
+        Self::notify(tx, &Notification::Message { message }).await
+    }
+}
+
+/// Forward notifications to subscribers until the process exits
+async fn listen(mut listener: PgListener, pool: PgPool, tx: broadcast::Sender<StateMessage>) {
+    loop {
+        match listener.try_recv().await {
+            Ok(Some(notification)) => {
+                if let Err(e) = handle_notification(&pool, &tx, &notification).await {
+                    warn!("Dropping state notification: {}", e);
+                }
+            }
+            Ok(None) => {
+                // The listener reconnected and may have missed notifications
+                info!("State listener reconnected, resyncing channels and revocations");
+                if let Err(e) = resync_channels(&pool, &tx).await {
+                    error!("Failed to resync channels: {}", e);
+                }
+                if let Err(e) = resync_revocations(&pool, &tx).await {
+                    error!("Failed to resync revocations: {}", e);
+                }
+            }
+            Err(e) => {
+                error!("State listener error: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+async fn handle_notification(
+    pool: &PgPool,
+    tx: &broadcast::Sender<StateMessage>,
+    notification: &PgNotification,
+) -> Result<(), StateError> {
+    let message = match serde_json::from_str(notification.payload())? {
+        Notification::Channel { origin, channel_id } => {
+            let change = match load_channel(pool, &channel_id).await? {
+                Some(channel) => StateChange::ChannelUpdated { channel },
+                None => StateChange::ChannelRemoved { channel_id },
+            };
+            StateMessage { origin, change }
+        }
+        Notification::Message { message } => *message,
+    };
+
+    let _ = tx.send(message);
+    Ok(())
+}
+
+async fn resync_channels(pool: &PgPool, tx: &broadcast::Sender<StateMessage>) -> Result<(), StateError> {
+    let channels = sqlx::query_scalar::<_, Json<Channel>>("SELECT data FROM fleet_channels")
+        .fetch_all(pool)
+        .await?;
+
+    for Json(channel) in channels {
+        // No origin, so every instance applies it
+        let _ = tx.send(StateMessage {
+            origin: String::new(),
+            change: StateChange::ChannelUpdated { channel },
+        });
+    }
+    Ok(())
+}
+
+/// Re-announce the revocations in force; applying one twice is harmless
+async fn resync_revocations(pool: &PgPool, tx: &broadcast::Sender<StateMessage>) -> Result<(), StateError> {
+    let revocations = sqlx::query_scalar::<_, Json<Revocation>>(
+        "SELECT data FROM fleet_revocations WHERE expires_at >= $1",
+    )
+    .bind(chrono::Utc::now().timestamp())
+    .fetch_all(pool)
+    .await?;
+
+    for Json(revocation) in revocations {
+        let _ = tx.send(StateMessage {
+            origin: String::new(),
+            change: StateChange::TokenRevoked { revocation },
+        });
+    }
+    Ok(())
+}
+
+async fn load_channel(pool: &PgPool, channel_id: &str) -> Result<Option<Channel>, StateError> {
+    let channel = sqlx::query_scalar::<_, Json<Channel>>("SELECT data FROM fleet_channels WHERE id = $1")
+        .bind(channel_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(channel.map(|Json(channel)| channel))
+}
+
+#[async_trait]
+impl StateBackend for PostgresBackend {
+    async fn load_channels(&self) -> Result<Vec<Channel>, StateError> {
+        let channels = sqlx::query_scalar::<_, Json<Channel>>("SELECT data FROM fleet_channels")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(channels.into_iter().map(|Json(channel)| channel).collect())
+    }
+
+    async fn save_channel(&self, origin: &str, channel: &Channel) -> Result<(), StateError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO fleet_channels (id, data) VALUES ($1, $2)
+             ON CONFLICT (id) DO UPDATE SET data = EXCLUDED.data, updated_at = now()",
+        )
+        .bind(&channel.id)
+        .bind(Json(channel))
+        .execute(&mut *tx)
+        .await?;
+        Self::notify(&mut tx, &Notification::Channel {
+            origin: origin.to_string(),
+            channel_id: channel.id.clone(),
+        })
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_channel(&self, origin: &str, channel_id: &str) -> Result<(), StateError> {
+        let mut tx = self.pool.begin().await?;
+        let removed = sqlx::query("DELETE FROM fleet_channels WHERE id = $1")
+            .bind(channel_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if removed > 0 {
+            sqlx::query("DELETE FROM fleet_presence WHERE channel_id = $1")
+                .bind(channel_id)
+                .execute(&mut *tx)
+                .await?;
+            Self::notify(&mut tx, &Notification::Channel {
+                origin: origin.to_string(),
+                channel_id: channel_id.to_string(),
+            })
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_presence(&self, presence: &Presence) -> Result<(), StateError> {
+        let mut tx = self.pool.begin().await?;
+        let previous_channel = sqlx::query_scalar::<_, String>(
+            "SELECT channel_id FROM fleet_presence WHERE user_id = $1 FOR UPDATE",
+        )
+        .bind(&presence.user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO fleet_presence (user_id, channel_id, username, is_muted, instance_id)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (user_id) DO UPDATE SET
+                channel_id = EXCLUDED.channel_id,
+                username = EXCLUDED.username,
+                is_muted = EXCLUDED.is_muted,
+                instance_id = EXCLUDED.instance_id,
+                updated_at = now()",
+        )
+        .bind(&presence.user_id)
+        .bind(&presence.channel_id)
+        .bind(&presence.username)
+        .bind(presence.is_muted)
+        .bind(&presence.instance_id)
+        .execute(&mut *tx)
+        .await?;
+
+        // Moving between channels leaves the old one
+        if let Some(channel_id) = previous_channel.filter(|channel_id| *channel_id != presence.channel_id) {
+            Self::notify_message(&mut tx, &presence.instance_id, StateChange::PresenceRemoved {
+                user_id: presence.user_id.clone(),
+                channel_id,
+            })
+            .await?;
+        }
+        Self::notify_message(&mut tx, &presence.instance_id, StateChange::PresenceUpdated {
+            presence: presence.clone(),
+        })
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn clear_presence(&self, instance_id: &str, user_id: &str) -> Result<(), StateError> {
+        let mut tx = self.pool.begin().await?;
+        let channel_id = sqlx::query_scalar::<_, String>(
+            "DELETE FROM fleet_presence WHERE user_id = $1 AND instance_id = $2 RETURNING channel_id",
+        )
+        .bind(user_id)
+        .bind(instance_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(channel_id) = channel_id {
+            Self::notify_message(&mut tx, instance_id, StateChange::PresenceRemoved {
+                user_id: user_id.to_string(),
+                channel_id,
+            })
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn channel_presence(&self, channel_id: &str) -> Result<Vec<Presence>, StateError> {
+        let presence = sqlx::query_as::<_, Presence>(
+            "SELECT user_id, username, channel_id, is_muted, instance_id FROM fleet_presence WHERE channel_id = $1",
+        )
+        .bind(channel_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(presence)
+    }
+
+    async fn publish_event(&self, origin: &str, event: &ChannelEvent) -> Result<(), StateError> {
+        let mut tx = self.pool.begin().await?;
+        Self::notify_message(&mut tx, origin, StateChange::Event { event: event.clone() }).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn save_refresh_token(&self, token: &RefreshToken) -> Result<(), StateError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM fleet_refresh_tokens WHERE expires_at < $1")
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO fleet_refresh_tokens (token_hash, session_id, user_id, username, roles, expires_at, used)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(&token.token_hash)
+        .bind(&token.session_id)
+        .bind(&token.user_id)
+        .bind(&token.username)
+        .bind(&token.roles)
+        .bind(token.expires_at)
+        .bind(token.used)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, StateError> {
+        let mut tx = self.pool.begin().await?;
+        let token = sqlx::query_as::<_, RefreshToken>(
+            "SELECT token_hash, session_id, user_id, username, roles, expires_at, used
+             FROM fleet_refresh_tokens WHERE token_hash = $1 FOR UPDATE",
+        )
+        .bind(token_hash)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if token.as_ref().is_some_and(|token| !token.used) {
+            sqlx::query("UPDATE fleet_refresh_tokens SET used = true WHERE token_hash = $1")
+                .bind(token_hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(token)
+    }
+
+    async fn revoke(&self, origin: &str, revocation: &Revocation) -> Result<(), StateError> {
+        let mut tx = self.pool.begin().await?;
+        match revocation {
+            Revocation::Session { sid, .. } => {
+                sqlx::query("DELETE FROM fleet_refresh_tokens WHERE session_id = $1")
+                    .bind(sid)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Revocation::User { user_id, .. } => {
+                sqlx::query("DELETE FROM fleet_refresh_tokens WHERE user_id = $1")
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Revocation::Token { .. } => {}
+        }
+        sqlx::query("DELETE FROM fleet_revocations WHERE expires_at < $1")
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO fleet_revocations (id, data, expires_at) VALUES ($1, $2, $3)
+             ON CONFLICT (id) DO UPDATE SET data = EXCLUDED.data, expires_at = EXCLUDED.expires_at",
+        )
+        .bind(revocation.id())
+        .bind(Json(revocation))
+        .bind(revocation.expires_at())
+        .execute(&mut *tx)
+        .await?;
+        Self::notify_message(&mut tx, origin, StateChange::TokenRevoked {
+            revocation: revocation.clone(),
+        })
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn load_revocations(&self) -> Result<Vec<Revocation>, StateError> {
+        let revocations = sqlx::query_scalar::<_, Json<Revocation>>(
+            "SELECT data FROM fleet_revocations WHERE expires_at >= $1",
+        )
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(revocations.into_iter().map(|Json(revocation)| revocation).collect())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<StateMessage> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::db::test_pool;
+    use crate::routes::channels::ChannelPrivacy;
+    use crate::state::SharedState;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn channel(id: &str) -> Channel {
+        Channel {
+            id: id.to_string(),
+            name: "Ops".to_string(),
+            privacy: ChannelPrivacy::Public,
+            owner: "alice".to_string(),
+            moderators: vec!["alice".to_string()],
+            members: vec!["alice".to_string()],
+            banned_users: Vec::new(),
+            invite_tokens: HashMap::new(),
+            min_bitrate_bps: None,
+            max_bitrate_bps: None,
+            max_participants: None,
+            max_speakers: None,
+            waitlist_enabled: false,
+        }
+    }
+
+    async fn next(changes: &mut crate::state::RemoteChanges) -> StateChange {
+        tokio::time::timeout(Duration::from_secs(5), changes.recv())
+            .await
+            .expect("Timed out waiting for notification")
+            .expect("Backend closed")
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_channel_changes_propagate() {
+        let pool = test_pool().await;
+        let eu = SharedState::new(Arc::new(PostgresBackend::connect(pool.clone()).await.unwrap()));
+        let na = SharedState::new(Arc::new(PostgresBackend::connect(pool).await.unwrap()));
+        let mut na_changes = na.subscribe_remote();
+
+        let id = Uuid::new_v4().to_string();
+        let mut created = channel(&id);
+        eu.save_channel(&created).await.unwrap();
+        match next(&mut na_changes).await {
+            StateChange::ChannelUpdated { channel } => assert_eq!(channel.members, vec!["alice"]),
+            other => panic!("Unexpected change: {:?}", other),
+        }
+
+        created.members.push("bob".to_string());
+        eu.save_channel(&created).await.unwrap();
+        match next(&mut na_changes).await {
+            StateChange::ChannelUpdated { channel } => assert_eq!(channel.members, vec!["alice", "bob"]),
+            other => panic!("Unexpected change: {:?}", other),
+        }
+        assert!(na.load_channels().await.unwrap().iter().any(|channel| channel.id == id));
+
+        eu.remove_channel(&id).await.unwrap();
+        assert!(matches!(next(&mut na_changes).await, StateChange::ChannelRemoved { channel_id } if channel_id == id));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_presence_and_events_propagate() {
+        let pool = test_pool().await;
+        let eu = SharedState::new(Arc::new(PostgresBackend::connect(pool.clone()).await.unwrap()));
+        let na = SharedState::new(Arc::new(PostgresBackend::connect(pool).await.unwrap()));
+        let mut na_changes = na.subscribe_remote();
+
+        let channel_id = Uuid::new_v4().to_string();
+        let user_id = Uuid::new_v4().to_string();
+        eu.set_presence(&user_id, "bob", &channel_id, false).await.unwrap();
+        assert!(matches!(next(&mut na_changes).await, StateChange::PresenceUpdated { presence } if presence.user_id == user_id));
+        assert_eq!(na.remote_presence(&channel_id).await.unwrap().len(), 1);
+
+        // Only the instance holding the presence can clear it
+        na.clear_presence(&user_id).await.unwrap();
+        assert_eq!(na.remote_presence(&channel_id).await.unwrap().len(), 1);
+        eu.clear_presence(&user_id).await.unwrap();
+        assert!(matches!(next(&mut na_changes).await, StateChange::PresenceRemoved { .. }));
+        assert!(na.remote_presence(&channel_id).await.unwrap().is_empty());
+
+        let event = ChannelEvent::RecordingStopped {
+            channel_id,
+            recording_id: "rec1".to_string(),
+        };
+        eu.publish_event(&event).await.unwrap();
+        assert!(matches!(next(&mut na_changes).await, StateChange::Event { event: received } if received == event));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_refresh_tokens_and_revocations() {
+        let pool = test_pool().await;
+        let eu = SharedState::new(Arc::new(PostgresBackend::connect(pool.clone()).await.unwrap()));
+        let na = SharedState::new(Arc::new(PostgresBackend::connect(pool).await.unwrap()));
+        let mut na_changes = na.subscribe_remote();
+
+        let session_id = Uuid::new_v4().to_string();
+        let token = RefreshToken {
+            token_hash: Uuid::new_v4().to_string(),
+            session_id: session_id.clone(),
+            user_id: "bob".to_string(),
+            username: "bob".to_string(),
+            roles: vec!["user".to_string()],
+            expires_at: chrono::Utc::now().timestamp() + 60,
+            used: false,
+        };
+        eu.save_refresh_token(&token).await.unwrap();
+
+        // Only the first use finds it unused, whichever instance it reaches
+        assert_eq!(na.use_refresh_token(&token.token_hash).await.unwrap(), Some(token.clone()));
+        assert!(eu.use_refresh_token(&token.token_hash).await.unwrap().unwrap().used);
+
+        let revocation = Revocation::Session {
+            sid: session_id,
+            user_id: "bob".to_string(),
+            expires_at: chrono::Utc::now().timestamp() + 60,
+        };
+        eu.revoke(&revocation).await.unwrap();
+        assert!(matches!(next(&mut na_changes).await, StateChange::TokenRevoked { revocation: received } if received == revocation));
+        assert!(na.load_revocations().await.unwrap().contains(&revocation));
+        assert_eq!(na.use_refresh_token(&token.token_hash).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn test_reconnect_resyncs_revocations() {
+        let pool = test_pool().await;
+        let backend = PostgresBackend::connect(pool.clone()).await.unwrap();
+        let mut changes = backend.subscribe();
+
+        // Written while the listener was away, so never announced
+        let revocation = Revocation::User {
+            user_id: Uuid::new_v4().to_string(),
+            issued_before: chrono::Utc::now().timestamp() as f64,
+            expires_at: chrono::Utc::now().timestamp() + 60,
+        };
+        sqlx::query("INSERT INTO fleet_revocations (id, data, expires_at) VALUES ($1, $2, $3)")
+            .bind(revocation.id())
+            .bind(Json(&revocation))
+            .bind(revocation.expires_at())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        resync_revocations(&pool, &backend.tx).await.unwrap();
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), changes.recv())
+                .await
+                .expect("Timed out waiting for resync")
+                .unwrap();
+            if let StateChange::TokenRevoked { revocation: received } = message.change {
+                if received == revocation {
+                    assert!(message.origin.is_empty());
+                    break;
+                }
+            }
+        }
+    }
+}
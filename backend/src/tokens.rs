@@ -0,0 +1,778 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters,
+    OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::signature::KeyPair;
+use rand::RngCore;
+use ring::digest::{digest, SHA256};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use crate::state::{RefreshToken, SharedState, StateError};
+
+/// HS256 secret used when nothing else is configured; only fit for development
+pub const DEV_JWT_SECRET: &str = "your-secret-key";
+
+/// Key ID of the HS256 key made from a shared secret
+pub const SECRET_KID: &str = "default";
+
+pub const DEFAULT_ISSUER: &str = "whisper-fleet";
+pub const DEFAULT_AUDIENCE: &str = "whisper-fleet";
+pub const DEFAULT_TTL: Duration = Duration::from_secs(15 * 60);
+pub const DEFAULT_REFRESH_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Time to present the second factor after the password
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Claims of every access token we issue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String, // user_id
+    pub name: String, // username
+    pub roles: Vec<String>,
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize, // expiration time
+    pub iat: f64, // issued at, to the microsecond
+    pub jti: String, // token ID
+    pub sid: String, // login session, shared by every token refreshed from it
+}
+
+impl Claims {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn token_ref(&self) -> TokenRef {
+        TokenRef {
+            jti: self.jti.clone(),
+            sid: self.sid.clone(),
+            iat: self.iat,
+        }
+    }
+}
+
+/// Claims of the token handed out between the password and the second factor.
+/// Its audience differs from access tokens', so it is no use as one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub sub: String, // user_id
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub jti: String,
+}
+
+/// The access token a live connection was opened with
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenRef {
+    pub jti: String,
+    pub sid: String,
+    pub iat: f64,
+}
+
+impl TokenRef {
+    pub fn is_revoked_by(&self, revocation: &Revocation) -> bool {
+        match revocation {
+            Revocation::Token { jti, .. } => *jti == self.jti,
+            Revocation::Session { sid, .. } => *sid == self.sid,
+            Revocation::User { issued_before, .. } => self.iat <= *issued_before,
+        }
+    }
+}
+
+/// Tokens revoked before they expire; kept until the last token they cover
+/// has expired anyway
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Revocation {
+    /// One access token
+    Token { jti: String, user_id: String, expires_at: i64 },
+    /// Every token of a login session, e.g. on logout
+    Session { sid: String, user_id: String, expires_at: i64 },
+    /// Every token of a user issued up to `issued_before` (unix time, to the
+    /// microsecond), e.g. after a password reset
+    User { user_id: String, issued_before: f64, expires_at: i64 },
+}
+
+impl Revocation {
+    /// The revoked token, session or user ID
+    pub fn id(&self) -> &str {
+        match self {
+            Revocation::Token { jti, .. } => jti,
+            Revocation::Session { sid, .. } => sid,
+            Revocation::User { user_id, .. } => user_id,
+        }
+    }
+
+    pub fn user_id(&self) -> &str {
+        match self {
+            Revocation::Token { user_id, .. } | Revocation::Session { user_id, .. } | Revocation::User { user_id, .. } => user_id,
+        }
+    }
+
+    /// Unix time after which the revocation can be forgotten
+    pub fn expires_at(&self) -> i64 {
+        match self {
+            Revocation::Token { expires_at, .. } | Revocation::Session { expires_at, .. } | Revocation::User { expires_at, .. } => {
+                *expires_at
+            }
+        }
+    }
+}
+
+/// Tokens handed out at login and on refresh
+#[derive(Debug, Clone)]
+pub struct TokenPair {
+    pub access_token: String,
+    /// Opaque; only its hash is stored
+    pub refresh_token: String,
+    /// Lifetime of the access token in seconds
+    pub expires_in: u64,
+    pub claims: Claims,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenError {
+    #[error("Invalid key {kid}: {reason}")]
+    InvalidKey { kid: String, reason: String },
+    #[error("No signing keys configured")]
+    NoKeys,
+    #[error("Duplicate key ID {0}")]
+    DuplicateKey(String),
+    #[error("Token has no key ID")]
+    MissingKeyId,
+    #[error("Token signed with unknown key {0}")]
+    UnknownKey(String),
+    #[error("Invalid token: {0}")]
+    Invalid(#[from] jsonwebtoken::errors::Error),
+    #[error("Token has been revoked")]
+    Revoked,
+    #[error("Invalid or expired refresh token")]
+    InvalidRefreshToken,
+    #[error("Refresh token was already used; the session has been revoked")]
+    RefreshTokenReused,
+    #[error("Failed to look up user: {0}")]
+    UserLookup(String),
+    #[error("State error: {0}")]
+    State(#[from] StateError),
+}
+
+/// Who the user of a session is now, looked up on every refresh so that
+/// changed roles and deleted accounts take effect
+#[async_trait]
+pub trait SessionUsers: Send + Sync {
+    /// Username and token roles of `user_id`, or `None` if the account is gone
+    async fn current_identity(&self, user_id: &str) -> Result<Option<(String, Vec<String>)>, String>;
+}
+
+/// One key, identified in token headers by its `kid`
+pub struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// Public half for the JWKS; `None` for shared secrets
+    jwk: Option<Jwk>,
+}
+
+impl SigningKey {
+    /// HS256 key; it can't be published, so only this service verifies its tokens
+    pub fn hmac(kid: &str, secret: &[u8]) -> Self {
+        Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    /// EdDSA (Ed25519) or RS256 key from a PKCS#8 PEM private key
+    pub fn from_pkcs8_pem(kid: &str, algorithm: Algorithm, pem: &str) -> Result<Self, TokenError> {
+        let invalid = |reason: String| TokenError::InvalidKey { kid: kid.to_string(), reason };
+        let der = pem::parse(pem).map_err(|e| invalid(e.to_string()))?;
+        if der.tag() != "PRIVATE KEY" {
+            return Err(invalid(format!("expected a PKCS#8 PRIVATE KEY, got {}", der.tag())));
+        }
+        let (encoding, parameters) = match algorithm {
+            Algorithm::EdDSA => {
+                let pair = ring::signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.contents())
+                    .map_err(|e| invalid(e.to_string()))?;
+                let public = pair.public_key();
+                let parameters = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(public.as_ref()),
+                });
+                (EncodingKey::from_ed_der(der.contents()), parameters)
+            }
+            Algorithm::RS256 => {
+                let pair = ring::signature::RsaKeyPair::from_pkcs8(der.contents())
+                    .map_err(|e| invalid(e.to_string()))?;
+                let public = pair.public_key();
+                let parameters = AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(public.modulus().big_endian_without_leading_zero()),
+                    e: URL_SAFE_NO_PAD.encode(public.exponent().big_endian_without_leading_zero()),
+                });
+                let encoding = EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(|e| invalid(e.to_string()))?;
+                (encoding, parameters)
+            }
+            other => return Err(invalid(format!("unsupported algorithm {:?}, use EdDSA or RS256", other))),
+        };
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(match algorithm {
+                    Algorithm::EdDSA => KeyAlgorithm::EdDSA,
+                    _ => KeyAlgorithm::RS256,
+                }),
+                key_id: Some(kid.to_string()),
+                ..CommonParameters::default()
+            },
+            algorithm: parameters,
+        };
+        let decoding = DecodingKey::from_jwk(&jwk).map_err(|e| invalid(e.to_string()))?;
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm,
+            encoding,
+            decoding,
+            jwk: Some(jwk),
+        })
+    }
+}
+
+/// Issues and verifies every access token.
+///
+/// The first key signs; the others still verify, so a new key can take over
+/// while tokens signed by the old one run out.
+///
+/// Access tokens are short-lived. Logins also get a refresh token, stored
+/// hashed in the shared state and replaced on every use; presenting a
+/// replaced one again revokes the whole login session.
+pub struct TokenService {
+    keys: RwLock<Vec<SigningKey>>,
+    issuer: String,
+    audience: String,
+    ttl: Duration,
+    refresh_ttl: Duration,
+    /// Revocations in force, by token or session ID
+    revoked: Mutex<HashMap<String, Revocation>>,
+    revocations: broadcast::Sender<Revocation>,
+}
+
+impl TokenService {
+    pub fn new(keys: Vec<SigningKey>, issuer: &str, audience: &str, ttl: Duration) -> Result<Self, TokenError> {
+        check_keys(&keys)?;
+        let (revocations, _) = broadcast::channel(256);
+        Ok(Self {
+            keys: RwLock::new(keys),
+            issuer: issuer.to_string(),
+            audience: audience.to_string(),
+            ttl,
+            refresh_ttl: DEFAULT_REFRESH_TTL,
+            revoked: Mutex::new(HashMap::new()),
+            revocations,
+        })
+    }
+
+    pub fn with_refresh_ttl(mut self, refresh_ttl: Duration) -> Self {
+        self.refresh_ttl = refresh_ttl;
+        self
+    }
+
+    /// HS256 with `DEV_JWT_SECRET` and the default issuer and audience
+    pub fn development() -> Self {
+        Self::new(
+            vec![SigningKey::hmac(SECRET_KID, DEV_JWT_SECRET.as_bytes())],
+            DEFAULT_ISSUER,
+            DEFAULT_AUDIENCE,
+            DEFAULT_TTL,
+        )
+        .unwrap()
+    }
+
+    /// Replace the keys, e.g. to start signing with a new key while keeping the old one for verification
+    pub fn set_keys(&self, keys: Vec<SigningKey>) -> Result<(), TokenError> {
+        check_keys(&keys)?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Access token in a session of its own, without a refresh token
+    #[cfg(test)]
+    pub fn issue(&self, user_id: &str, username: &str, roles: Vec<String>) -> Result<String, TokenError> {
+        let (token, _) = self.issue_in_session(user_id, username, roles, &Uuid::new_v4().to_string())?;
+        Ok(token)
+    }
+
+    fn issue_in_session(&self, user_id: &str, username: &str, roles: Vec<String>, sid: &str) -> Result<(String, Claims), TokenError> {
+        let now = precise_now();
+        let claims = Claims {
+            sub: user_id.to_string(),
+            name: username.to_string(),
+            roles,
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            exp: now as usize + self.ttl.as_secs() as usize,
+            iat: now,
+            jti: Uuid::new_v4().to_string(),
+            sid: sid.to_string(),
+        };
+        Ok((self.sign(&claims)?, claims))
+    }
+
+    fn sign<T: Serialize>(&self, claims: &T) -> Result<String, TokenError> {
+        let keys = self.keys.read().unwrap();
+        let key = &keys[0];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        Ok(encode(&header, claims, &key.encoding)?)
+    }
+
+    fn challenge_audience(&self) -> String {
+        format!("{}:2fa", self.audience)
+    }
+
+    /// Token proving the password of `user_id` was right, to trade in with a
+    /// second factor for a session within `CHALLENGE_TTL`
+    pub fn issue_challenge(&self, user_id: &str) -> Result<(String, ChallengeClaims), TokenError> {
+        let now = chrono::Utc::now().timestamp() as usize;
+        let claims = ChallengeClaims {
+            sub: user_id.to_string(),
+            iss: self.issuer.clone(),
+            aud: self.challenge_audience(),
+            exp: now + CHALLENGE_TTL.as_secs() as usize,
+            iat: now,
+            jti: Uuid::new_v4().to_string(),
+        };
+        Ok((self.sign(&claims)?, claims))
+    }
+
+    pub fn verify_challenge(&self, token: &str) -> Result<ChallengeClaims, TokenError> {
+        self.decode(token, &self.challenge_audience())
+    }
+
+    /// Start a login session: an access token and a refresh token for it
+    pub async fn start_session(
+        &self,
+        shared: &SharedState,
+        user_id: &str,
+        username: &str,
+        roles: Vec<String>,
+    ) -> Result<TokenPair, TokenError> {
+        self.issue_pair(shared, user_id, username, roles, &Uuid::new_v4().to_string()).await
+    }
+
+    /// Trade a refresh token for a new pair in the same session, with the
+    /// user's current username and roles from `users`.
+    ///
+    /// A refresh token that was already traded in means it leaked: the
+    /// session is revoked, cutting off both the thief and the user. So is the
+    /// session of a user who no longer exists.
+    pub async fn refresh(&self, shared: &SharedState, users: &dyn SessionUsers, refresh_token: &str) -> Result<TokenPair, TokenError> {
+        let stored = match shared.use_refresh_token(&hash_token(refresh_token)).await? {
+            Some(stored) => stored,
+            None => return Err(TokenError::InvalidRefreshToken),
+        };
+        if stored.used {
+            self.revoke_session(shared, &stored.session_id, &stored.user_id).await?;
+            return Err(TokenError::RefreshTokenReused);
+        }
+        if stored.expires_at < chrono::Utc::now().timestamp() {
+            return Err(TokenError::InvalidRefreshToken);
+        }
+        let (username, roles) = match users.current_identity(&stored.user_id).await.map_err(TokenError::UserLookup)? {
+            Some(identity) => identity,
+            None => {
+                self.revoke_session(shared, &stored.session_id, &stored.user_id).await?;
+                return Err(TokenError::InvalidRefreshToken);
+            }
+        };
+        self.issue_pair(shared, &stored.user_id, &username, roles, &stored.session_id).await
+    }
+
+    async fn issue_pair(
+        &self,
+        shared: &SharedState,
+        user_id: &str,
+        username: &str,
+        roles: Vec<String>,
+        sid: &str,
+    ) -> Result<TokenPair, TokenError> {
+        let (access_token, claims) = self.issue_in_session(user_id, username, roles.clone(), sid)?;
+        let refresh_token = random_token();
+        shared
+            .save_refresh_token(&RefreshToken {
+                token_hash: hash_token(&refresh_token),
+                session_id: sid.to_string(),
+                user_id: user_id.to_string(),
+                username: username.to_string(),
+                roles,
+                expires_at: chrono::Utc::now().timestamp() + self.refresh_ttl.as_secs() as i64,
+                used: false,
+            })
+            .await?;
+        Ok(TokenPair {
+            access_token,
+            refresh_token,
+            expires_in: self.ttl.as_secs(),
+            claims,
+        })
+    }
+
+    /// Revoke one access token
+    #[cfg(test)]
+    pub async fn revoke_token(&self, shared: &SharedState, claims: &Claims) -> Result<(), TokenError> {
+        self.revoke(shared, Revocation::Token {
+            jti: claims.jti.clone(),
+            user_id: claims.sub.clone(),
+            expires_at: claims.exp as i64,
+        })
+        .await
+    }
+
+    /// Revoke every token of a login session, refresh tokens included
+    pub async fn revoke_session(&self, shared: &SharedState, sid: &str, user_id: &str) -> Result<(), TokenError> {
+        // Access tokens of the session run out within one TTL
+        self.revoke(shared, Revocation::Session {
+            sid: sid.to_string(),
+            user_id: user_id.to_string(),
+            expires_at: chrono::Utc::now().timestamp() + self.ttl.as_secs() as i64,
+        })
+        .await
+    }
+
+    /// Revoke every token of a user issued so far, refresh tokens included
+    pub async fn revoke_user(&self, shared: &SharedState, user_id: &str) -> Result<(), TokenError> {
+        let now = precise_now();
+        self.revoke(shared, Revocation::User {
+            user_id: user_id.to_string(),
+            issued_before: now,
+            expires_at: now as i64 + self.ttl.as_secs() as i64,
+        })
+        .await
+    }
+
+    async fn revoke(&self, shared: &SharedState, revocation: Revocation) -> Result<(), TokenError> {
+        self.apply_revocation(revocation.clone());
+        shared.revoke(&revocation).await?;
+        Ok(())
+    }
+
+    /// Refuse the tokens a revocation covers from now on and tell
+    /// subscribers, e.g. to drop connections opened with them
+    pub fn apply_revocation(&self, revocation: Revocation) {
+        let now = chrono::Utc::now().timestamp();
+        {
+            let mut revoked = self.revoked.lock().unwrap();
+            revoked.retain(|_, revocation| revocation.expires_at() >= now);
+            revoked.insert(revocation.id().to_string(), revocation.clone());
+        }
+        let _ = self.revocations.send(revocation);
+    }
+
+    /// Revocations applied from now on
+    pub fn subscribe_revocations(&self) -> broadcast::Receiver<Revocation> {
+        self.revocations.subscribe()
+    }
+
+    fn is_revoked(&self, claims: &Claims) -> bool {
+        let revoked = self.revoked.lock().unwrap();
+        revoked.contains_key(&claims.jti)
+            || revoked.contains_key(&claims.sid)
+            || revoked.get(&claims.sub).is_some_and(|revocation| claims.token_ref().is_revoked_by(revocation))
+    }
+
+    /// Check a token's signature, key, expiry, issuer, audience and revocation
+    pub fn verify(&self, token: &str) -> Result<Claims, TokenError> {
+        let claims: Claims = self.decode(token, &self.audience)?;
+        if self.is_revoked(&claims) {
+            return Err(TokenError::Revoked);
+        }
+        Ok(claims)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, token: &str, audience: &str) -> Result<T, TokenError> {
+        let header = decode_header(token)?;
+        let kid = header.kid.ok_or(TokenError::MissingKeyId)?;
+        let keys = self.keys.read().unwrap();
+        let key = keys
+            .iter()
+            .find(|key| key.kid == kid)
+            .ok_or_else(|| TokenError::UnknownKey(kid.clone()))?;
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[audience]);
+        Ok(decode::<T>(token, &key.decoding, &validation)?.claims)
+    }
+
+    /// Public keys for other services to verify our tokens with
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.read().unwrap().iter().filter_map(|key| key.jwk.clone()).collect(),
+        }
+    }
+}
+
+/// Unix time to the microsecond
+fn precise_now() -> f64 {
+    chrono::Utc::now().timestamp_micros() as f64 / 1_000_000.0
+}
+
+/// 32 random bytes, base64url encoded, for refresh and email tokens
+pub fn random_token() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    URL_SAFE_NO_PAD.encode(secret)
+}
+
+/// Tokens from `random_token` are unguessable, so a plain hash is enough to store them safely
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, token.as_bytes()))
+}
+
+fn check_keys(keys: &[SigningKey]) -> Result<(), TokenError> {
+    if keys.is_empty() {
+        return Err(TokenError::NoKeys);
+    }
+    let mut seen = HashSet::new();
+    for key in keys {
+        if !seen.insert(key.kid.as_str()) {
+            return Err(TokenError::DuplicateKey(key.kid.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ed25519_pem() -> String {
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+        pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref().to_vec()))
+    }
+
+    fn service(keys: Vec<SigningKey>) -> TokenService {
+        TokenService::new(keys, DEFAULT_ISSUER, DEFAULT_AUDIENCE, DEFAULT_TTL).unwrap()
+    }
+
+    /// Usernames and roles by user ID
+    #[derive(Default)]
+    struct Accounts(Mutex<HashMap<String, (String, Vec<String>)>>);
+
+    impl Accounts {
+        fn set(&self, user_id: &str, username: &str, roles: &[&str]) {
+            let roles = roles.iter().map(|role| role.to_string()).collect();
+            self.0.lock().unwrap().insert(user_id.to_string(), (username.to_string(), roles));
+        }
+
+        fn remove(&self, user_id: &str) {
+            self.0.lock().unwrap().remove(user_id);
+        }
+    }
+
+    #[async_trait]
+    impl SessionUsers for Accounts {
+        async fn current_identity(&self, user_id: &str) -> Result<Option<(String, Vec<String>)>, String> {
+            Ok(self.0.lock().unwrap().get(user_id).cloned())
+        }
+    }
+
+    #[test]
+    fn test_issue_and_verify_eddsa() {
+        let tokens = service(vec![SigningKey::from_pkcs8_pem("k1", Algorithm::EdDSA, &ed25519_pem()).unwrap()]);
+        let token = tokens.issue("u-alice", "alice", vec!["user".to_string()]).unwrap();
+
+        let claims = tokens.verify(&token).unwrap();
+        assert_eq!(claims.sub, "u-alice");
+        assert_eq!(claims.name, "alice");
+        assert_eq!(claims.iss, DEFAULT_ISSUER);
+        assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some("k1"));
+
+        let jwks = tokens.jwks();
+        assert_eq!(jwks.keys.len(), 1);
+        assert!(jwks.find("k1").is_some());
+    }
+
+    #[test]
+    fn test_rotation_keeps_old_tokens_valid() {
+        let old_pem = ed25519_pem();
+        let tokens = service(vec![SigningKey::from_pkcs8_pem("old", Algorithm::EdDSA, &old_pem).unwrap()]);
+        let old_token = tokens.issue("alice", "alice", Vec::new()).unwrap();
+
+        tokens
+            .set_keys(vec![
+                SigningKey::from_pkcs8_pem("new", Algorithm::EdDSA, &ed25519_pem()).unwrap(),
+                SigningKey::from_pkcs8_pem("old", Algorithm::EdDSA, &old_pem).unwrap(),
+            ])
+            .unwrap();
+        let new_token = tokens.issue("bob", "bob", Vec::new()).unwrap();
+        assert_eq!(tokens.verify(&old_token).unwrap().sub, "alice");
+        assert_eq!(tokens.verify(&new_token).unwrap().sub, "bob");
+        assert_eq!(tokens.jwks().keys.len(), 2);
+
+        // Once the old key is retired its tokens are refused
+        tokens
+            .set_keys(vec![SigningKey::from_pkcs8_pem("new", Algorithm::EdDSA, &ed25519_pem()).unwrap()])
+            .unwrap();
+        assert!(matches!(tokens.verify(&old_token), Err(TokenError::UnknownKey(kid)) if kid == "old"));
+    }
+
+    #[test]
+    fn test_rejects_foreign_tokens() {
+        let tokens = TokenService::development();
+
+        // Right secret, but no issuer or audience
+        let now = chrono::Utc::now().timestamp() as usize;
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(SECRET_KID.to_string());
+        let bare = encode(
+            &header,
+            &serde_json::json!({ "sub": "alice", "roles": [], "exp": now + 60, "iat": now }),
+            &EncodingKey::from_secret(DEV_JWT_SECRET.as_bytes()),
+        )
+        .unwrap();
+        assert!(matches!(tokens.verify(&bare), Err(TokenError::Invalid(_))));
+
+        // Another audience's token
+        let other = TokenService::new(
+            vec![SigningKey::hmac(SECRET_KID, DEV_JWT_SECRET.as_bytes())],
+            DEFAULT_ISSUER,
+            "billing",
+            DEFAULT_TTL,
+        )
+        .unwrap();
+        let token = other.issue("alice", "alice", Vec::new()).unwrap();
+        assert!(tokens.verify(&token).is_err());
+
+        assert!(matches!(
+            TokenService::new(Vec::new(), DEFAULT_ISSUER, DEFAULT_AUDIENCE, DEFAULT_TTL),
+            Err(TokenError::NoKeys)
+        ));
+    }
+
+    #[test]
+    fn test_challenges_are_not_access_tokens() {
+        let service = TokenService::development();
+        let (challenge, claims) = service.issue_challenge("user-1").unwrap();
+        assert_eq!(service.verify_challenge(&challenge).unwrap(), claims);
+        assert!(service.verify(&challenge).is_err());
+
+        let access = service.issue("user-1", "ace", vec!["user".to_string()]).unwrap();
+        assert!(service.verify_challenge(&access).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rotates_and_detects_reuse() {
+        let tokens = TokenService::development();
+        let shared = SharedState::in_process();
+        let mut revocations = tokens.subscribe_revocations();
+
+        let accounts = Accounts::default();
+        accounts.set("u-alice", "alice", &["user"]);
+
+        let login = tokens.start_session(&shared, "u-alice", "alice", vec!["user".to_string()]).await.unwrap();
+        let refreshed = tokens.refresh(&shared, &accounts, &login.refresh_token).await.unwrap();
+        assert_eq!(refreshed.claims.sid, login.claims.sid);
+        assert_eq!(refreshed.claims.name, "alice");
+        assert_eq!(refreshed.claims.roles, vec!["user"]);
+        assert_ne!(refreshed.refresh_token, login.refresh_token);
+        assert!(tokens.verify(&login.access_token).is_ok());
+
+        // Replaying the old refresh token revokes the whole session
+        assert!(matches!(tokens.refresh(&shared, &accounts, &login.refresh_token).await, Err(TokenError::RefreshTokenReused)));
+        assert!(matches!(tokens.verify(&refreshed.access_token), Err(TokenError::Revoked)));
+        assert!(matches!(tokens.refresh(&shared, &accounts, &refreshed.refresh_token).await, Err(TokenError::InvalidRefreshToken)));
+        assert!(matches!(revocations.recv().await.unwrap(), Revocation::Session { sid, .. } if sid == login.claims.sid));
+
+        assert!(matches!(tokens.refresh(&shared, &accounts, "made-up").await, Err(TokenError::InvalidRefreshToken)));
+    }
+
+    #[tokio::test]
+    async fn test_revoke_user() {
+        let tokens = TokenService::development();
+        let shared = SharedState::in_process();
+        let accounts = Accounts::default();
+        accounts.set("u-alice", "alice", &[]);
+        accounts.set("u-bob", "bob", &[]);
+        let alice = tokens.start_session(&shared, "u-alice", "alice", Vec::new()).await.unwrap();
+        let bob = tokens.start_session(&shared, "u-bob", "bob", Vec::new()).await.unwrap();
+
+        tokens.revoke_user(&shared, "u-alice").await.unwrap();
+        assert!(matches!(tokens.verify(&alice.access_token), Err(TokenError::Revoked)));
+        assert!(matches!(tokens.refresh(&shared, &accounts, &alice.refresh_token).await, Err(TokenError::InvalidRefreshToken)));
+        assert!(tokens.verify(&bob.access_token).is_ok());
+        assert!(tokens.refresh(&shared, &accounts, &bob.refresh_token).await.is_ok());
+
+        // Tokens issued afterwards are not covered, even within the same second
+        let again = tokens.start_session(&shared, "u-alice", "alice", Vec::new()).await.unwrap();
+        assert!(tokens.verify(&again.access_token).is_ok());
+        let revoked_at = |issued_before: f64| Revocation::User {
+            user_id: "u-alice".to_string(),
+            issued_before,
+            expires_at: again.claims.exp as i64,
+        };
+        assert!(again.claims.token_ref().is_revoked_by(&revoked_at(again.claims.iat)));
+        assert!(!again.claims.token_ref().is_revoked_by(&revoked_at(again.claims.iat - 0.000_001)));
+    }
+
+    #[tokio::test]
+    async fn test_revocations_reach_other_instances() {
+        let backend: std::sync::Arc<dyn crate::state::StateBackend> = std::sync::Arc::new(crate::state::InProcessBackend::new());
+        let eu = SharedState::new(backend.clone());
+        let na = SharedState::new(backend);
+        let eu_tokens = TokenService::development();
+        let na_tokens = TokenService::development();
+
+        let first = eu_tokens.start_session(&eu, "alice", "alice", Vec::new()).await.unwrap();
+        let second = eu_tokens.start_session(&eu, "alice", "alice", Vec::new()).await.unwrap();
+        eu_tokens.revoke_token(&eu, &first.claims).await.unwrap();
+        assert!(matches!(eu_tokens.verify(&first.access_token), Err(TokenError::Revoked)));
+        assert!(eu_tokens.verify(&second.access_token).is_ok());
+
+        // An instance starting later loads the revocation
+        for revocation in na.load_revocations().await.unwrap() {
+            na_tokens.apply_revocation(revocation);
+        }
+        assert!(matches!(na_tokens.verify(&first.access_token), Err(TokenError::Revoked)));
+        assert!(na_tokens.verify(&second.access_token).is_ok());
+
+        // Refresh tokens work through any instance
+        let accounts = Accounts::default();
+        accounts.set("alice", "alice", &[]);
+        assert!(na_tokens.refresh(&na, &accounts, &second.refresh_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_uses_current_roles() {
+        let tokens = TokenService::development();
+        let shared = SharedState::in_process();
+        let accounts = Accounts::default();
+        accounts.set("u-alice", "alice", &["admin", "user"]);
+
+        let login = tokens.start_session(&shared, "u-alice", "alice", vec!["admin".to_string(), "user".to_string()]).await.unwrap();
+        accounts.set("u-alice", "alice.b", &["user"]);
+        let refreshed = tokens.refresh(&shared, &accounts, &login.refresh_token).await.unwrap();
+        assert_eq!(refreshed.claims.name, "alice.b");
+        assert_eq!(refreshed.claims.roles, vec!["user"]);
+
+        // A deleted account's session ends at its next refresh
+        accounts.remove("u-alice");
+        assert!(matches!(
+            tokens.refresh(&shared, &accounts, &refreshed.refresh_token).await,
+            Err(TokenError::InvalidRefreshToken)
+        ));
+        assert!(matches!(tokens.verify(&refreshed.access_token), Err(TokenError::Revoked)));
+    }
+}
@@ -0,0 +1,2 @@
+#[allow(dead_code)] // general helpers; the servers use the token buckets, DoS detector and block list
+pub mod security;
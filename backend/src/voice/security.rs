@@ -1,25 +1,22 @@
-//! Security hardening middleware and helpers for Rocket backend.
+//! Security helpers for the HTTP, WebSocket and UDP servers.
 //!
 //! Features:
-//! - Rate limiting (per IP/user, configurable)
+//! - Rate limiting (fixed windows and token buckets, per IP/user/session)
 //! - Input validation/sanitization
-//! - JWT validation and revocation
 //! - WebSocket origin checks
-//! - UDP packet authentication (token/key)
-//! - Logging and DoS detection
+//! - Logging, DoS detection and address blocking
 //!
 //! # Configuration
 //! See README for setup and deployment instructions.
 
-use rocket::{Request, Data, Response, http::Status, fairing::{Fairing, Info, Kind}, outcome::Outcome, request::{self, FromRequest}, State};
-use rocket::serde::json::Json;
-use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use jsonwebtoken::{decode, DecodingKey, Validation, TokenData, Algorithm, errors::Error as JwtError};
-use log::{warn, info, error};
-use rocket::tokio::sync::RwLock;
-use rocket::tokio::time::sleep;
+use tracing::warn;
 
 // --- Rate Limiting ---
 #[derive(Clone)]
@@ -56,23 +53,78 @@ impl RateLimiter {
     }
 }
 
-// Rocket Fairing for Rate Limiting
-pub struct RateLimitFairing {
-    pub limiter: RateLimiter,
+// --- Token Buckets ---
+/// Budget of a token bucket: `rate` tokens a second, at most `burst` saved up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketConfig {
+    pub rate: f64,
+    pub burst: f64,
 }
 
-#[rocket::async_trait]
-impl Fairing for RateLimitFairing {
-    fn info(&self) -> Info {
-        Info { name: "Rate Limiting", kind: Kind::Request }
+impl BucketConfig {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self { rate, burst }
     }
-    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
-        let ip = req.client_ip().map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string());
-        if !self.limiter.check(&ip) {
-            warn!("Rate limit exceeded for IP: {}", ip);
-            req.local_cache(|| Some(Status::TooManyRequests));
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token buckets by key, e.g. source address or session.
+///
+/// Unlike `RateLimiter` this allows short bursts without letting a sender
+/// double its rate across a window boundary.
+pub struct TokenBucketLimiter<K> {
+    config: Mutex<BucketConfig>,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Eq + Hash> TokenBucketLimiter<K> {
+    pub fn new(config: BucketConfig) -> Self {
+        Self { config: Mutex::new(config), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn config(&self) -> BucketConfig {
+        *self.config.lock().unwrap()
+    }
+
+    /// Change the budget; existing buckets keep their tokens up to the new burst
+    pub fn set_config(&self, config: BucketConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    /// Take `cost` tokens from `key`'s bucket, false if it holds too few
+    pub fn check<Q>(&self, key: &Q, cost: f64) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ToOwned<Owned = K> + ?Sized,
+    {
+        let config = self.config();
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+        if !buckets.contains_key(key) {
+            buckets.insert(key.to_owned(), Bucket { tokens: config.burst, refilled_at: now });
+        }
+        let bucket = buckets.get_mut(key).unwrap();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * config.rate).min(config.burst);
+        bucket.refilled_at = now;
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            true
+        } else {
+            false
         }
     }
+
+    /// Forget buckets untouched for `idle`; they would have refilled anyway
+    /// once `idle` covers a full refill
+    pub fn retain_active(&self, idle: Duration) {
+        let now = Instant::now();
+        self.buckets.lock().unwrap().retain(|_, bucket| now.duration_since(bucket.refilled_at) < idle);
+    }
 }
 
 // --- Input Validation and Sanitization ---
@@ -92,37 +144,12 @@ pub fn validate_token(token: &str) -> Result<(), &'static str> {
     }
 }
 
-// --- JWT Handling ---
-pub struct JwtConfig {
-    pub secret: String,
-    pub issuer: String,
-    pub audience: String,
-    pub blacklist: Arc<RwLock<HashSet<String>>>,
-}
-
-pub async fn is_token_revoked(token: &str, config: &JwtConfig) -> bool {
-    let blacklist = config.blacklist.read().await;
-    blacklist.contains(token)
-}
-
-pub fn validate_jwt(token: &str, config: &JwtConfig) -> Result<TokenData<serde_json::Value>, JwtError> {
-    let mut validation = Validation::new(Algorithm::HS256);
-    validation.set_audience(&[&config.audience]);
-    validation.set_issuer(&[&config.issuer]);
-    decode::<serde_json::Value>(token, &DecodingKey::from_secret(config.secret.as_bytes()), &validation)
-}
-
 // --- WebSocket Origin Check ---
 pub fn check_ws_origin(origin: &str, allowed_origins: &[&str]) -> bool {
-    allowed_origins.iter().any(|&o| o == origin)
+    allowed_origins.contains(&origin)
 }
 
-// --- UDP Packet Authentication ---
-pub fn validate_udp_token(token: &str, config: &JwtConfig) -> bool {
-    // Could use JWT or a shared secret
-    validate_jwt(token, config).is_ok()
-}
-// Suggestion: For future, use DTLS for full encryption/authentication
+// Tokens are issued and verified by `crate::tokens::TokenService`
 
 // --- Logging and Monitoring ---
 pub fn log_auth_failure(ip: &str, reason: &str) {
@@ -160,11 +187,128 @@ impl DosDetector {
             entry.0 > self.threshold
         }
     }
+
+    /// Start counting `ip` from zero again
+    pub fn reset(&self, ip: &str) {
+        self.hits.lock().unwrap().remove(ip);
+    }
+
+    /// Forget addresses whose window has passed
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let window = self.window;
+        self.hits.lock().unwrap().retain(|_, (_, start)| now.duration_since(*start) <= window);
+    }
 }
 
-// --- Rocket Integration Example ---
-// Attach RateLimitFairing and use guards for JWT, input validation, etc.
-// See README for full integration details.
+// --- Address Blocking ---
+/// A blocked address, as listed to administrators
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockedAddress {
+    pub ip: IpAddr,
+    pub reason: String,
+    /// Blocks in a row, each twice as long as the last
+    pub offences: u32,
+    /// Unix time of the latest block
+    pub blocked_at: u64,
+    /// Unix time the block lapses
+    pub expires_at: u64,
+}
+
+struct BlockEntry {
+    info: BlockedAddress,
+    until: Instant,
+}
+
+/// Addresses blocked for an escalating period after repeated abuse
+pub struct BlockList {
+    /// Length of a first block
+    pub base: Duration,
+    /// Longest block, however often an address offends
+    pub max: Duration,
+    /// Offences are forgotten once an address behaves for this long after a block
+    pub forgive_after: Duration,
+    entries: Mutex<HashMap<IpAddr, BlockEntry>>,
+}
+
+impl BlockList {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            forgive_after: max,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_blocked(&self, ip: &IpAddr) -> bool {
+        match self.entries.lock().unwrap().get(ip) {
+            Some(entry) => Instant::now() < entry.until,
+            None => false,
+        }
+    }
+
+    /// Block `ip`, doubling the period for each recent offence
+    pub fn block(&self, ip: IpAddr, reason: &str) -> Duration {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        let offences = match entries.get(&ip) {
+            Some(entry) if now < entry.until + self.forgive_after => entry.info.offences + 1,
+            _ => 1,
+        };
+        let period = self
+            .base
+            .checked_mul(1u32.checked_shl(offences - 1).unwrap_or(u32::MAX))
+            .map_or(self.max, |period| period.min(self.max));
+
+        let blocked_at = chrono::Utc::now().timestamp() as u64;
+        entries.insert(ip, BlockEntry {
+            info: BlockedAddress {
+                ip,
+                reason: reason.to_string(),
+                offences,
+                blocked_at,
+                expires_at: blocked_at + period.as_secs(),
+            },
+            until: now + period,
+        });
+        period
+    }
+
+    /// Addresses blocked right now
+    pub fn list(&self) -> Vec<BlockedAddress> {
+        let now = Instant::now();
+        let mut blocked: Vec<BlockedAddress> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| now < entry.until)
+            .map(|entry| entry.info.clone())
+            .collect();
+        blocked.sort_by_key(|blocked| blocked.ip);
+        blocked
+    }
+
+    /// Lift the block on `ip` and forget its offences
+    pub fn unblock(&self, ip: &IpAddr) -> bool {
+        self.entries.lock().unwrap().remove(ip).is_some()
+    }
+
+    /// Lift every block, returning how many were active
+    pub fn clear(&self) -> usize {
+        let blocked = self.list().len();
+        self.entries.lock().unwrap().clear();
+        blocked
+    }
+
+    /// Forget addresses whose offences have been forgiven
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let forgive_after = self.forgive_after;
+        self.entries.lock().unwrap().retain(|_, entry| now < entry.until + forgive_after);
+    }
+}
 
 // --- Tests ---
 #[cfg(test)]
@@ -195,4 +339,30 @@ mod tests {
         assert!(!dos.check("ip1"));
         assert!(dos.check("ip1"));
     }
+    #[test]
+    fn test_token_bucket_allows_bursts_up_to_budget() {
+        let limiter = TokenBucketLimiter::new(BucketConfig::new(1.0, 1000.0));
+        assert!(limiter.check(&"addr1", 600.0));
+        assert!(!limiter.check(&"addr1", 600.0));
+        assert!(limiter.check(&"addr2", 600.0));
+        // A forgotten bucket starts full again
+        limiter.retain_active(Duration::ZERO);
+        assert!(limiter.check(&"addr1", 600.0));
+    }
+    #[test]
+    fn test_block_list_escalates() {
+        let blocks = BlockList::new(Duration::from_secs(60), Duration::from_secs(200));
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(blocks.block(ip, "flood"), Duration::from_secs(60));
+        assert_eq!(blocks.block(ip, "flood"), Duration::from_secs(120));
+        assert_eq!(blocks.block(ip, "flood"), Duration::from_secs(200));
+        assert!(blocks.is_blocked(&ip));
+        assert_eq!(blocks.list()[0].offences, 3);
+
+        assert!(blocks.unblock(&ip));
+        assert!(!blocks.is_blocked(&ip));
+        assert_eq!(blocks.block(ip, "flood"), Duration::from_secs(60));
+        assert_eq!(blocks.clear(), 1);
+        assert!(blocks.list().is_empty());
+    }
 } 
\ No newline at end of file
@@ -6,71 +6,20 @@ use axum::{
     response::IntoResponse,
 };
 use futures::{sink::SinkExt, stream::StreamExt};
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
-use uuid::Uuid;
+use std::sync::{Arc, Weak};
+use tokio::sync::{broadcast, mpsc, Notify, RwLock};
 use std::time::{Duration, Instant};
-use log::{info, warn};
-
-// JWT Claims structure (reused from auth)
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    roles: Vec<String>,
-    exp: usize,
-    iat: usize,
-}
-
-// WebSocket message types
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "type")]
-pub enum WsMessage {
-    #[serde(rename = "join_channel")]
-    JoinChannel {
-        channel_id: String,
-    },
-    #[serde(rename = "leave_channel")]
-    LeaveChannel,
-    #[serde(rename = "mute")]
-    Mute,
-    #[serde(rename = "unmute")]
-    Unmute,
-    #[serde(rename = "user_joined")]
-    UserJoined {
-        user_id: String,
-        username: String,
-        is_muted: bool,
-    },
-    #[serde(rename = "user_left")]
-    UserLeft {
-        user_id: String,
-    },
-    #[serde(rename = "user_state_update")]
-    UserStateUpdate {
-        user_id: String,
-        is_muted: bool,
-    },
-    #[serde(rename = "error")]
-    Error {
-        message: String,
-    },
-    #[serde(rename = "channel_info")]
-    ChannelInfo {
-        channel_id: String,
-        users: Vec<UserInfo>,
-    },
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct UserInfo {
-    pub user_id: String,
-    pub username: String,
-    pub is_muted: bool,
-    pub is_speaking: bool,
-}
+use tracing::{info, warn};
+use crate::audio::state::StateError as VoiceStateError;
+use crate::events::{ChannelEvent, EventBus, RemovalReason};
+use crate::metrics::Metrics;
+use crate::routes::channels::AppState as ChannelAppState;
+use crate::routes::user::UNVERIFIED_ROLE;
+use crate::state::{Presence, SharedState, StateChange};
+use crate::tokens::{Claims, Revocation, TokenRef, TokenService};
+pub use whisper_fleet_protocol::signaling::{UserInfo, WsMessage};
 
 // User connection state
 #[derive(Debug, Clone)]
@@ -81,65 +30,71 @@ pub struct UserConnection {
     pub is_muted: bool,
     pub is_speaking: bool,
     pub tx: broadcast::Sender<WsMessage>,
+    /// Token the socket was opened with
+    pub token: TokenRef,
+    /// Notified to close the socket, e.g. once its token is revoked
+    pub close: Arc<Notify>,
 }
 
 // Each channel gets an mpsc sender for batched state updates
+#[derive(Debug)]
 pub struct ChannelBroadcaster {
     pub tx: mpsc::UnboundedSender<WsMessage>,
 }
 
-fn spawn_channel_broadcaster(channel: Arc<RwLock<VoiceChannel>>) -> ChannelBroadcaster {
-    let (tx, mut rx) = mpsc::unbounded_channel::<WsMessage>();
-    let channel_clone = channel.clone();
+// Runs until the channel is dropped, which also closes `rx`
+fn spawn_channel_broadcaster(
+    channel: Weak<RwLock<VoiceChannel>>,
+    mut rx: mpsc::UnboundedReceiver<WsMessage>,
+    metrics: Arc<Metrics>,
+) {
     tokio::spawn(async move {
-        let mut last_sent = Instant::now();
-        let mut pending: Option<WsMessage> = None;
+        // Message and when it was queued
+        let mut pending: Option<(WsMessage, Instant)> = None;
         loop {
             tokio::select! {
-                Some(msg) = rx.recv() => {
-                    pending = Some(msg);
+                msg = rx.recv() => {
+                    let Some(msg) = msg else { break };
+                    // A newer state update replaces one still waiting
+                    let queued_at = match pending.take() {
+                        Some((_, queued_at)) => {
+                            metrics.ws_message_dropped("superseded");
+                            queued_at
+                        }
+                        None => Instant::now(),
+                    };
+                    pending = Some((msg, queued_at));
                 }
                 _ = tokio::time::sleep(Duration::from_millis(BROADCAST_BATCH_MS)) => {
-                    if let Some(msg) = pending.take() {
-                        let channel = channel_clone.read().await;
+                    if let Some((msg, queued_at)) = pending.take() {
+                        let Some(channel) = channel.upgrade() else { break };
+                        let channel = channel.read().await;
                         for user in channel.users.values() {
                             let _ = user.tx.send(msg.clone());
                         }
-                        last_sent = Instant::now();
+                        metrics.ws_broadcast_lag_seconds.observe(queued_at.elapsed().as_secs_f64());
                     }
                 }
             }
         }
     });
-    ChannelBroadcaster { tx }
 }
 
 // Voice channel state
 #[derive(Debug)]
 pub struct VoiceChannel {
-    pub id: String,
-    pub name: String,
     pub users: HashMap<String, UserConnection>,
-    pub tx: broadcast::Sender<WsMessage>,
     pub broadcaster: ChannelBroadcaster,
 }
 
 // Helper to create a new channel with broadcaster
-fn create_voice_channel(channel_id: &str) -> Arc<RwLock<VoiceChannel>> {
-    let (tx, _) = broadcast::channel::<WsMessage>(100);
+fn create_voice_channel(metrics: &Arc<Metrics>) -> Arc<RwLock<VoiceChannel>> {
+    let (broadcast_tx, broadcast_rx) = mpsc::unbounded_channel::<WsMessage>();
     let channel = Arc::new(RwLock::new(VoiceChannel {
-        id: channel_id.to_string(),
-        name: format!("Voice Channel {}", channel_id),
         users: HashMap::new(),
-        tx,
-        broadcaster: ChannelBroadcaster { tx: mpsc::unbounded_channel().0 }, // placeholder, will be replaced
+        broadcaster: ChannelBroadcaster { tx: broadcast_tx },
     }));
-    // Now spawn the broadcaster and set it
-    let broadcaster = spawn_channel_broadcaster(channel.clone());
-    {
-        let mut channel_mut = futures::executor::block_on(channel.write());
-        channel_mut.broadcaster = broadcaster;
-    }
+    spawn_channel_broadcaster(Arc::downgrade(&channel), broadcast_rx, metrics.clone());
     channel
 }
 
@@ -147,18 +102,279 @@ fn create_voice_channel(channel_id: &str) -> Arc<RwLock<VoiceChannel>> {
 #[derive(Clone)]
 pub struct WsAppState {
     pub connections: Arc<RwLock<HashMap<String, UserConnection>>>,
-    pub channels: Arc<RwLock<HashMap<String, VoiceChannel>>>,
+    pub channels: Arc<RwLock<HashMap<String, Arc<RwLock<VoiceChannel>>>>>,
+    /// Active recordings by channel, replayed to users joining mid-recording
+    pub recordings: Arc<RwLock<HashMap<String, WsMessage>>>,
+    /// Moderator restrictions by channel, then user, replayed to joining users
+    pub voice_moderation: Arc<RwLock<HashMap<String, HashMap<String, WsMessage>>>>,
+    /// Users connected through other instances, by user ID
+    pub remote_presence: Arc<RwLock<HashMap<String, Presence>>>,
+    pub shared: SharedState,
+    pub metrics: Arc<Metrics>,
+    /// Channel records, for capacity limits; joins are unrestricted without them
+    pub channel_state: Option<ChannelAppState>,
+    /// Verifies the tokens of connecting users
+    pub tokens: Arc<TokenService>,
 }
 
 impl WsAppState {
-    pub fn new() -> Self {
+    pub fn with_shared_state(shared: SharedState) -> Self {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
             channels: Arc::new(RwLock::new(HashMap::new())),
+            recordings: Arc::new(RwLock::new(HashMap::new())),
+            voice_moderation: Arc::new(RwLock::new(HashMap::new())),
+            remote_presence: Arc::new(RwLock::new(HashMap::new())),
+            shared,
+            metrics: Arc::new(Metrics::new()),
+            channel_state: None,
+            tokens: Arc::new(TokenService::development()),
+        }
+    }
+
+    /// Report into `metrics` instead of a private registry
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Enforce the capacity limits of `channel_state`'s channels on joins
+    pub fn with_channel_state(mut self, channel_state: ChannelAppState) -> Self {
+        self.channel_state = Some(channel_state);
+        self
+    }
+
+    pub fn with_tokens(mut self, tokens: Arc<TokenService>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Forward channel events and other instances' presence changes to the
+    /// connected members of each channel
+    pub fn listen(&self, events: &EventBus) {
+        let mut rx = events.subscribe();
+        let state = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("WebSocket event listener skipped {} channel events", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                handle_channel_event(event, &state).await;
+            }
+        });
+
+        let mut changes = self.shared.subscribe_remote();
+        let state = self.clone();
+        tokio::spawn(async move {
+            while let Some(change) = changes.recv().await {
+                handle_remote_change(change, &state).await;
+            }
+        });
+
+        let mut revocations = self.tokens.subscribe_revocations();
+        let state = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let revocation = match revocations.recv().await {
+                    Ok(revocation) => revocation,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("WebSocket revocation listener skipped {} revocations", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                close_revoked(&revocation, &state).await;
+            }
+        });
+    }
+}
+
+// Close the sockets opened with a revoked token
+async fn close_revoked(revocation: &Revocation, state: &WsAppState) {
+    let connections = state.connections.read().await;
+    if let Some(connection) = connections.get(revocation.user_id()) {
+        if connection.token.is_revoked_by(revocation) {
+            info!("Closing WebSocket of {}: token revoked", connection.user_id);
+            connection.close.notify_one();
+        }
+    }
+}
+
+// Send a message directly to the connected members of a channel
+async fn send_to_channel(state: &WsAppState, channel_id: &str, msg: WsMessage) {
+    let connections = state.connections.read().await;
+    for connection in connections.values() {
+        if connection.channel_id.as_deref() == Some(channel_id) {
+            let _ = connection.tx.send(msg.clone());
+        }
+    }
+}
+
+// Tell local channel members about users joining, muting and leaving on other instances
+async fn handle_remote_change(change: StateChange, state: &WsAppState) {
+    match change {
+        StateChange::PresenceUpdated { presence } => {
+            let previous = state
+                .remote_presence
+                .write()
+                .await
+                .insert(presence.user_id.clone(), presence.clone());
+            let msg = match previous {
+                Some(previous) if previous.channel_id == presence.channel_id => WsMessage::UserStateUpdate {
+                    user_id: presence.user_id,
+                    is_muted: presence.is_muted,
+                },
+                _ => WsMessage::UserJoined {
+                    user_id: presence.user_id,
+                    username: presence.username,
+                    is_muted: presence.is_muted,
+                },
+            };
+            send_to_channel(state, &presence.channel_id, msg).await;
+        }
+        StateChange::PresenceRemoved { user_id, channel_id } => {
+            {
+                let mut remote_presence = state.remote_presence.write().await;
+                if remote_presence.get(&user_id).is_some_and(|presence| presence.channel_id == channel_id) {
+                    remote_presence.remove(&user_id);
+                }
+            }
+            send_to_channel(state, &channel_id, WsMessage::UserLeft { user_id }).await;
+        }
+        // Channel records, events and revocations are applied by the HTTP state
+        StateChange::ChannelUpdated { .. }
+        | StateChange::ChannelRemoved { .. }
+        | StateChange::Event { .. }
+        | StateChange::TokenRevoked { .. } => {}
+    }
+}
+
+// Share a user's current channel and mute state with other instances
+async fn share_presence(user_id: &str, state: &WsAppState) {
+    let connection = state.connections.read().await.get(user_id).cloned();
+    let result = match connection {
+        Some(UserConnection { channel_id: Some(channel_id), username, is_muted, .. }) => {
+            state.shared.set_presence(user_id, &username, &channel_id, is_muted).await
+        }
+        _ => state.shared.clear_presence(user_id).await,
+    };
+    if let Err(e) = result {
+        warn!("Failed to share presence of {}: {}", user_id, e);
+    }
+}
+
+// Channel members connected through other instances
+async fn remote_channel_users(channel_id: &str, state: &WsAppState) -> Vec<UserInfo> {
+    match state.shared.remote_presence(channel_id).await {
+        Ok(presence) => presence
+            .into_iter()
+            .map(|presence| UserInfo {
+                user_id: presence.user_id,
+                username: presence.username,
+                is_muted: presence.is_muted,
+                is_speaking: false,
+            })
+            .collect(),
+        Err(e) => {
+            warn!("Failed to load remote presence for channel {}: {}", channel_id, e);
+            Vec::new()
         }
     }
 }
 
+// Notify channel members of a channel event
+async fn handle_channel_event(event: ChannelEvent, state: &WsAppState) {
+    let channel_id = event.channel_id().to_string();
+    let msg = match event {
+        ChannelEvent::RecordingStarted { channel_id, recording_id, started_by } => {
+            let msg = WsMessage::RecordingState {
+                channel_id: channel_id.clone(),
+                active: true,
+                recording_id: Some(recording_id),
+                started_by: Some(started_by),
+            };
+            state.recordings.write().await.insert(channel_id, msg.clone());
+            msg
+        }
+        ChannelEvent::RecordingStopped { channel_id, recording_id } => {
+            state.recordings.write().await.remove(&channel_id);
+            WsMessage::RecordingState {
+                channel_id,
+                active: false,
+                recording_id: Some(recording_id),
+                started_by: None,
+            }
+        }
+        ChannelEvent::VoiceModerationChanged { channel_id, user_id, server_muted, server_deafened, .. } => {
+            let msg = WsMessage::UserServerState {
+                user_id: user_id.clone(),
+                server_muted,
+                server_deafened,
+            };
+            let mut voice_moderation = state.voice_moderation.write().await;
+            if server_muted || server_deafened {
+                voice_moderation.entry(channel_id).or_default().insert(user_id, msg.clone());
+            } else if let Some(users) = voice_moderation.get_mut(&channel_id) {
+                users.remove(&user_id);
+                if users.is_empty() {
+                    voice_moderation.remove(&channel_id);
+                }
+            }
+            msg
+        }
+        ChannelEvent::MemberRemoved { channel_id, user_id, reason, note, .. } => {
+            remove_from_channel(&user_id, &channel_id, reason, note.as_deref(), state).await;
+            return;
+        }
+        ChannelEvent::RoleChanged { channel_id, user_id, role, .. } => {
+            let msg = WsMessage::RoleChanged {
+                channel_id: channel_id.clone(),
+                user_id: user_id.clone(),
+                role: role.as_str().to_string(),
+            };
+            // The user hears about it even when not in the channel right now
+            if let Some(connection) = state.connections.read().await.get(&user_id) {
+                if connection.channel_id.as_deref() != Some(channel_id.as_str()) {
+                    let _ = connection.tx.send(msg.clone());
+                }
+            }
+            msg
+        }
+    };
+
+    // Sent directly rather than batched, so it cannot be superseded
+    send_to_channel(state, &channel_id, msg).await;
+}
+
+// Take a user a moderator removed out of the channel and tell them why
+async fn remove_from_channel(
+    user_id: &str,
+    channel_id: &str,
+    reason: RemovalReason,
+    note: Option<&str>,
+    state: &WsAppState,
+) {
+    let connection = state.connections.read().await.get(user_id).cloned();
+    let Some(connection) = connection.filter(|connection| connection.channel_id.as_deref() == Some(channel_id)) else {
+        return;
+    };
+
+    if leave_voice_channel(user_id, state).await.is_ok() {
+        let _ = connection.tx.send(WsMessage::RemovedFromChannel {
+            channel_id: channel_id.to_string(),
+            reason: reason.as_str().to_string(),
+            message: reason.message(note),
+        });
+        share_presence(user_id, state).await;
+        info!("Removed {} from channel {} ({})", user_id, channel_id, reason.as_str());
+    }
+}
+
 // Query parameters for WebSocket upgrade
 #[derive(Debug, Deserialize)]
 pub struct WsQuery {
@@ -176,8 +392,8 @@ pub async fn ws_handler(
     State(state): State<WsAppState>,
 ) -> impl IntoResponse {
     // Authenticate JWT token
-    let user_id = match authenticate_token(&query.token) {
-        Ok(user_id) => user_id,
+    let claims = match authenticate_token(&state.tokens, &query.token) {
+        Ok(claims) => claims,
         Err(_) => {
             return ws.on_upgrade(|socket| async {
                 let _ = handle_ws_connection(socket, None, state).await;
@@ -186,31 +402,23 @@ pub async fn ws_handler(
     };
 
     ws.on_upgrade(move |socket| async move {
-        handle_ws_connection(socket, Some(user_id), state).await;
+        handle_ws_connection(socket, Some(claims), state).await;
     })
 }
 
 // Authenticate JWT token
-fn authenticate_token(token: &str) -> Result<String, ()> {
-    let secret = "your-secret-key"; // Should match auth.rs
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_ref()),
-        &Validation::default(),
-    )
-    .map_err(|_| ())?;
-
-    Ok(token_data.claims.sub)
+fn authenticate_token(tokens: &TokenService, token: &str) -> Result<Claims, ()> {
+    tokens.verify(token).map_err(|_| ())
 }
 
 // Handle WebSocket connection
 async fn handle_ws_connection(
     mut socket: WebSocket,
-    user_id: Option<String>,
+    claims: Option<Claims>,
     state: WsAppState,
 ) {
-    let user_id = match user_id {
-        Some(id) => id,
+    let claims = match claims {
+        Some(claims) => claims,
         None => {
             let error_msg = WsMessage::Error {
                 message: "Authentication failed".to_string(),
@@ -222,8 +430,11 @@ async fn handle_ws_connection(
         }
     };
 
+    let user_id = claims.sub.clone();
+
     // Create broadcast channel for this user
     let (tx, mut rx) = broadcast::channel::<WsMessage>(100);
+    let close = Arc::new(Notify::new());
     // Add per-user rate limiter
     let mut msg_count = 0usize;
     let mut last_msg_time = Instant::now();
@@ -231,11 +442,13 @@ async fn handle_ws_connection(
     // Store user connection
     let user_connection = UserConnection {
         user_id: user_id.clone(),
-        username: user_id.clone(), // In real app, get from database
+        username: claims.name.clone(),
         channel_id: None,
         is_muted: false,
         is_speaking: false,
         tx: tx.clone(),
+        token: claims.token_ref(),
+        close: close.clone(),
     };
 
     {
@@ -243,6 +456,7 @@ async fn handle_ws_connection(
         let mut connections = state.connections.write().await;
         connections.insert(user_id.clone(), user_connection);
     }
+    state.metrics.ws_connections.inc();
 
     // Send welcome message
     let welcome_msg = WsMessage::ChannelInfo {
@@ -254,13 +468,12 @@ async fn handle_ws_connection(
     }
 
     // Handle incoming messages
-    let mut socket_rx = socket.split();
-    let mut socket_tx = socket_rx.1;
+    let (mut socket_tx, mut socket_rx) = socket.split();
 
     loop {
         tokio::select! {
             // Handle incoming WebSocket messages
-            msg = socket_rx.0.next() => {
+            msg = socket_rx.next() => {
                 match msg {
                     Some(Ok(Message::Text(text))) => {
                         // Rate limiting: max USER_MSG_RATE_LIMIT per second
@@ -272,6 +485,7 @@ async fn handle_ws_connection(
                         msg_count += 1;
                         if msg_count > USER_MSG_RATE_LIMIT {
                             warn!("User {} exceeded rate limit", user_id);
+                            state.metrics.ws_message_dropped("rate_limited");
                             let error_msg = WsMessage::Error { message: "Rate limit exceeded".to_string() };
                             if let Ok(msg) = serde_json::to_string(&error_msg) {
                                 let _ = socket_tx.send(Message::Text(msg)).await;
@@ -279,7 +493,15 @@ async fn handle_ws_connection(
                             continue;
                         }
                         if let Ok(ws_msg) = serde_json::from_str::<WsMessage>(&text) {
-                            if let Err(_) = handle_ws_message(ws_msg, &user_id, &state).await {
+                            // Voice needs a verified email address
+                            if matches!(ws_msg, WsMessage::JoinChannel { .. }) && claims.has_role(UNVERIFIED_ROLE) {
+                                let error_msg = WsMessage::Error { message: "Verify your email address to join voice channels".to_string() };
+                                if let Ok(msg) = serde_json::to_string(&error_msg) {
+                                    let _ = socket_tx.send(Message::Text(msg)).await;
+                                }
+                                continue;
+                            }
+                            if handle_ws_message(ws_msg, &user_id, &state).await.is_err() {
                                 break;
                             }
                         }
@@ -296,17 +518,30 @@ async fn handle_ws_connection(
                     _ => {}
                 }
             }
+            // The token the socket was opened with was revoked
+            _ = close.notified() => {
+                let error_msg = WsMessage::Error { message: "Session revoked".to_string() };
+                if let Ok(msg) = serde_json::to_string(&error_msg) {
+                    let _ = socket_tx.send(Message::Text(msg)).await;
+                }
+                let _ = socket_tx.send(Message::Close(None)).await;
+                break;
+            }
             // Handle broadcast messages
             msg = rx.recv() => {
                 match msg {
                     Ok(ws_msg) => {
                         if let Ok(msg_str) = serde_json::to_string(&ws_msg) {
-                            if let Err(_) = socket_tx.send(Message::Text(msg_str)).await {
+                            if socket_tx.send(Message::Text(msg_str)).await.is_err() {
                                 break;
                             }
                         }
                     }
-                    Err(_) => {
+                    Err(e) => {
+                        if let broadcast::error::RecvError::Lagged(skipped) = e {
+                            warn!("User {} fell {} messages behind, disconnecting", user_id, skipped);
+                            state.metrics.ws_messages_dropped.with_label_values(&["lagged"]).inc_by(skipped);
+                        }
                         break;
                     }
                 }
@@ -316,6 +551,10 @@ async fn handle_ws_connection(
 
     // Cleanup on disconnect
     cleanup_user_connection(&user_id, &state).await;
+    state.metrics.ws_connections.dec();
+    if let Err(e) = state.shared.clear_presence(&user_id).await {
+        warn!("Failed to clear presence of {}: {}", user_id, e);
+    }
 }
 
 // Handle WebSocket messages
@@ -327,15 +566,19 @@ async fn handle_ws_message(
     match msg {
         WsMessage::JoinChannel { channel_id } => {
             join_voice_channel(user_id, &channel_id, state).await?;
+            share_presence(user_id, state).await;
         }
         WsMessage::LeaveChannel => {
             leave_voice_channel(user_id, state).await?;
+            share_presence(user_id, state).await;
         }
         WsMessage::Mute => {
             set_user_mute_state(user_id, true, state).await?;
+            share_presence(user_id, state).await;
         }
         WsMessage::Unmute => {
             set_user_mute_state(user_id, false, state).await?;
+            share_presence(user_id, state).await;
         }
         _ => {
             // Ignore other message types
@@ -350,12 +593,15 @@ async fn join_voice_channel(
     channel_id: &str,
     state: &WsAppState,
 ) -> Result<(), ()> {
+    // Looked up before taking the locks, the backend may be remote
+    let remote_users = remote_channel_users(channel_id, state).await;
+
     let mut channels = state.channels.write().await;
     let mut connections = state.connections.write().await;
 
     // Use Arc<RwLock<VoiceChannel>> for channel batching
     let channel_arc = channels.entry(channel_id.to_string()).or_insert_with(|| {
-        create_voice_channel(channel_id)
+        create_voice_channel(&state.metrics)
     }).clone();
     let mut channel = channel_arc.write().await;
 
@@ -364,11 +610,27 @@ async fn join_voice_channel(
         .get_mut(user_id)
         .ok_or(())?;
 
+    // Turn the user away from a full channel, keeping them where they are
+    if let Some(channel_state) = &state.channel_state {
+        let occupied = channel.users.keys().filter(|id| id.as_str() != user_id).count();
+        if let Err(VoiceStateError::ChannelFull { limit, position }) = channel_state.admit_voice(channel_id, user_id, occupied) {
+            info!("Channel {} is full, turned away {} at position {:?}", channel_id, user_id, position);
+            let _ = user_connection.tx.send(WsMessage::ChannelFull {
+                channel_id: channel_id.to_string(),
+                limit,
+                position,
+            });
+            return Ok(());
+        }
+    }
+
     // Leave current channel if any
-    if let Some(current_channel_id) = &user_connection.channel_id {
-        if let Some(current_channel) = channels.get_mut(current_channel_id) {
+    if let Some(current_channel_id) = user_connection.channel_id.as_deref().filter(|current| *current != channel_id) {
+        if let Some(current_channel) = channels.get(current_channel_id) {
+            let mut current_channel = current_channel.write().await;
             current_channel.users.remove(user_id);
-            broadcast_user_left(&mut *current_channel, user_id).await;
+            state.metrics.set_ws_channel_users(current_channel_id, current_channel.users.len());
+            broadcast_user_left(&mut current_channel, user_id).await;
         }
     }
 
@@ -377,14 +639,8 @@ async fn join_voice_channel(
     user_connection.is_muted = false;
     user_connection.is_speaking = false;
 
-    let user_info = UserInfo {
-        user_id: user_id.to_string(),
-        username: user_connection.username.clone(),
-        is_muted: user_connection.is_muted,
-        is_speaking: user_connection.is_speaking,
-    };
-
     channel.users.insert(user_id.to_string(), user_connection.clone());
+    state.metrics.set_ws_channel_users(channel_id, channel.users.len());
 
     // Broadcast user joined to channel
     let join_msg = WsMessage::UserJoined {
@@ -397,7 +653,7 @@ async fn join_voice_channel(
     let _ = channel.broadcaster.tx.send(join_msg);
 
     // Send channel info to joining user
-    let channel_users: Vec<UserInfo> = channel
+    let mut channel_users: Vec<UserInfo> = channel
         .users
         .values()
         .map(|conn| UserInfo {
@@ -407,6 +663,7 @@ async fn join_voice_channel(
             is_speaking: conn.is_speaking,
         })
         .collect();
+    channel_users.extend(remote_users.into_iter().filter(|user| !channel.users.contains_key(&user.user_id)));
 
     let channel_info = WsMessage::ChannelInfo {
         channel_id: channel_id.to_string(),
@@ -416,12 +673,24 @@ async fn join_voice_channel(
     // Send channel info directly to joining user (not batched)
     let _ = user_connection.tx.send(channel_info);
 
+    // Let the joining user know the channel is being recorded
+    if let Some(recording_state) = state.recordings.read().await.get(channel_id) {
+        let _ = user_connection.tx.send(recording_state.clone());
+    }
+
+    // And who is muted or deafened by a moderator
+    if let Some(users) = state.voice_moderation.read().await.get(channel_id) {
+        for server_state in users.values() {
+            let _ = user_connection.tx.send(server_state.clone());
+        }
+    }
+
     Ok(())
 }
 
 // Leave voice channel
 async fn leave_voice_channel(user_id: &str, state: &WsAppState) -> Result<(), ()> {
-    let mut channels = state.channels.write().await;
+    let channels = state.channels.read().await;
     let mut connections = state.connections.write().await;
 
     let user_connection = connections
@@ -429,9 +698,11 @@ async fn leave_voice_channel(user_id: &str, state: &WsAppState) -> Result<(), ()
         .ok_or(())?;
 
     if let Some(channel_id) = &user_connection.channel_id {
-        if let Some(channel) = channels.get_mut(channel_id) {
+        if let Some(channel) = channels.get(channel_id) {
+            let mut channel = channel.write().await;
             channel.users.remove(user_id);
-            broadcast_user_left(&mut *channel, user_id).await;
+            state.metrics.set_ws_channel_users(channel_id, channel.users.len());
+            broadcast_user_left(&mut channel, user_id).await;
         }
         user_connection.channel_id = None;
     }
@@ -445,7 +716,7 @@ async fn set_user_mute_state(
     is_muted: bool,
     state: &WsAppState,
 ) -> Result<(), ()> {
-    let mut channels = state.channels.write().await;
+    let channels = state.channels.read().await;
     let mut connections = state.connections.write().await;
 
     let user_connection = connections
@@ -455,7 +726,8 @@ async fn set_user_mute_state(
     user_connection.is_muted = is_muted;
 
     if let Some(channel_id) = &user_connection.channel_id {
-        if let Some(channel) = channels.get_mut(channel_id) {
+        if let Some(channel) = channels.get(channel_id) {
+            let mut channel = channel.write().await;
             if let Some(channel_user) = channel.users.get_mut(user_id) {
                 channel_user.is_muted = is_muted;
             }
@@ -492,34 +764,25 @@ async fn cleanup_user_connection(user_id: &str, state: &WsAppState) {
     if let Some(connection) = user_connection {
         // Remove from channel
         if let Some(channel_id) = connection.channel_id {
-            if let Some(channel_arc) = channels.get_mut(&channel_id) {
-                let mut channel = channel_arc.write().await;
-                channel.users.remove(user_id);
-                broadcast_user_left(&mut *channel, user_id).await;
-                
-                // Remove empty channels and drop broadcaster
-                if channel.users.is_empty() {
-                    // Dropping the Arc will stop the broadcaster task
-                    channels.remove(&channel_id);
+            let empty = match channels.get(&channel_id) {
+                Some(channel) => {
+                    let mut channel = channel.write().await;
+                    channel.users.remove(user_id);
+                    state.metrics.set_ws_channel_users(&channel_id, channel.users.len());
+                    broadcast_user_left(&mut channel, user_id).await;
+                    channel.users.is_empty()
                 }
+                None => false,
+            };
+            // Dropping the last reference to an empty channel stops its broadcaster
+            if empty {
+                channels.remove(&channel_id);
             }
         }
     }
 }
 
-// Prepare for UDP audio packet forwarding (placeholder)
-pub async fn handle_audio_packet(
-    _channel_id: &str,
-    _user_id: &str,
-    _audio_data: Vec<u8>,
-) {
-    // TODO: Implement UDP audio packet forwarding
-    // This will be implemented in a future update
-    // For now, this is a placeholder for the audio handling system
-}
-
 // TODO: Integrate ChannelBroadcaster into join_voice_channel, leave_voice_channel, and state update broadcasts.
-// TODO: Add metrics for dropped messages, rate limit violations, and broadcast latency.
 
 // TODO: Insert rate limiting and profiling hooks here (e.g., count messages per user, log slow/busy locks)
 // TODO: Add metrics/logging integration for dropped messages, lock contention, and message rates 
\ No newline at end of file